@@ -0,0 +1,340 @@
+use std::io::{self, Cursor, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use pdfmcr::jpeg::{Block, Image};
+
+
+/// Lists the blocks (marker segments) of a JPEG file.
+#[derive(Parser)]
+struct Opts {
+    /// The JPEG file to examine.
+    pub jpeg_file: PathBuf,
+}
+
+
+/// Returns the abbreviated name of the marker of the given kind.
+fn marker_name(kind: u8) -> String {
+    match kind {
+        0x01 => "TEM".to_owned(),
+        0xC4 => "DHT".to_owned(),
+        0xC8 => "JPG".to_owned(),
+        0xCC => "DAC".to_owned(),
+        0xC0..=0xCF => format!("SOF{}", kind - 0xC0),
+        0xD0..=0xD7 => format!("RST{}", kind - 0xD0),
+        0xD8 => "SOI".to_owned(),
+        0xD9 => "EOI".to_owned(),
+        0xDA => "SOS".to_owned(),
+        0xDB => "DQT".to_owned(),
+        0xDC => "DNL".to_owned(),
+        0xDD => "DRI".to_owned(),
+        0xDE => "DHP".to_owned(),
+        0xDF => "EXP".to_owned(),
+        0xE0..=0xEF => format!("APP{}", kind - 0xE0),
+        0xF0..=0xFD => format!("JPG{}", kind - 0xF0),
+        0xFE => "COM".to_owned(),
+        _ => "RES".to_owned(),
+    }
+}
+
+/// Returns the identifier at the start of an application-specific segment, e.g. `JFIF` or `Exif`,
+/// if it is printable.
+fn printable_app_identifier(block: &Block) -> Option<&str> {
+    let identifier = block.app_identifier()?;
+    if identifier.is_empty() || !identifier.iter().all(|b| b.is_ascii_graphic()) {
+        return None;
+    }
+    std::str::from_utf8(identifier).ok()
+}
+
+/// Returns a short description of the contents of a block.
+fn describe_block(block: &Block) -> String {
+    let data = block.data();
+    let kind = block.kind();
+    match kind {
+        0xE0..=0xEF => {
+            if kind == 0xEE && data.starts_with(b"Adobe") && data.len() >= 12 {
+                // the identifier is not NUL-terminated; the version follows directly
+                return format!("Adobe, color transform {}", data[11]);
+            }
+            let Some(identifier) = printable_app_identifier(block) else {
+                return String::new();
+            };
+            if block.is_app_with_identifier(0, b"JFIF") && data.len() >= 12 {
+                let unit = match data[7] {
+                    0 => "(aspect ratio)",
+                    1 => "dpi",
+                    2 => "dpcm",
+                    _ => "(unknown unit)",
+                };
+                format!(
+                    "JFIF {}.{:02}, density {}x{} {}",
+                    data[5], data[6],
+                    u16::from_be_bytes([data[8], data[9]]),
+                    u16::from_be_bytes([data[10], data[11]]),
+                    unit,
+                )
+            } else {
+                identifier.to_owned()
+            }
+        },
+        _ if block.is_sof() => {
+            if data.len() < 6 {
+                return "(too short)".to_owned();
+            }
+            // only SOF0 has the lowest two bits unset
+            let process = match kind & 0x03 {
+                0 => "baseline",
+                1 => "extended sequential",
+                2 => "progressive",
+                _ => "lossless",
+            };
+            let differential = if kind & 0x04 != 0 { "differential " } else { "" };
+            let coding = if block.is_arithmetic_sof() { "arithmetic" } else { "Huffman" };
+            format!(
+                "{}{} {}, {}x{}, {} components, {} bits",
+                differential, process, coding,
+                u16::from_be_bytes([data[3], data[4]]),
+                u16::from_be_bytes([data[1], data[2]]),
+                data[5], data[0],
+            )
+        },
+        0xC4 => {
+            // class and ID, then 16 counts followed by the values
+            let mut tables = Vec::new();
+            let mut rest = data;
+            while rest.len() >= 17 {
+                let class = if rest[0] >> 4 == 0 { "DC" } else { "AC" };
+                tables.push(format!("{} {}", class, rest[0] & 0x0F));
+                let value_count: usize = rest[1..17].iter().map(|c| usize::from(*c)).sum();
+                rest = rest.get(17 + value_count..).unwrap_or(&[]);
+            }
+            format!("Huffman tables: {}", tables.join(", "))
+        },
+        0xDB => {
+            // precision and ID, then 64 values of 8 or 16 bits
+            let mut tables = Vec::new();
+            let mut rest = data;
+            while !rest.is_empty() {
+                let sixteen_bit = rest[0] >> 4 != 0;
+                tables.push(format!("{} ({}-bit)", rest[0] & 0x0F, if sixteen_bit { 16 } else { 8 }));
+                let table_len = if sixteen_bit { 129 } else { 65 };
+                rest = rest.get(table_len..).unwrap_or(&[]);
+            }
+            format!("quantization tables: {}", tables.join(", "))
+        },
+        0xDA => {
+            match data.first() {
+                Some(component_count) => format!("{} components", component_count),
+                None => "(too short)".to_owned(),
+            }
+        },
+        0xDD => {
+            if data.len() < 2 {
+                return "(too short)".to_owned();
+            }
+            format!("restart interval {}", u16::from_be_bytes([data[0], data[1]]))
+        },
+        0xFE => {
+            let comment = String::from_utf8_lossy(data);
+            let mut shortened: String = comment.chars().take(40).collect();
+            if shortened.len() < comment.len() {
+                shortened.push('…');
+            }
+            format!("{:?}", shortened)
+        },
+        _ => String::new(),
+    }
+}
+
+/// Finds the end of the entropy-coded data at the start of the given slice.
+///
+/// Returns the length of the entropy-coded data, which ends at the next marker that is neither a
+/// stuffed 0xFF byte nor a restart marker, and the number of restart markers within it.
+fn entropy_coded_length(data: &[u8]) -> (usize, usize) {
+    let mut restart_count = 0;
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != 0xFF {
+            i += 1;
+            continue;
+        }
+
+        // skip fill bytes
+        let mut j = i + 1;
+        while j < data.len() && data[j] == 0xFF {
+            j += 1;
+        }
+        match data.get(j) {
+            None => break,
+            Some(0x00) => {},
+            Some(0xD0..=0xD7) => restart_count += 1,
+            Some(_) => return (j - 1, restart_count),
+        }
+        i = j + 1;
+    }
+    (data.len(), restart_count)
+}
+
+
+/// Writes the table of the blocks of the given JPEG data.
+///
+/// Returns whether the data is terminated correctly, i.e. ends with End-of-Image.
+fn dump<W: Write>(data: &[u8], out: &mut W) -> Result<bool, io::Error> {
+    let data_len: u64 = data.len().try_into().unwrap();
+    let mut cursor = Cursor::new(data);
+
+    writeln!(out, "{:<10}  {:<6}  {:<5}  {:>6}  description", "offset", "marker", "name", "length")?;
+    let mut terminated = false;
+    while cursor.position() < data_len {
+        let offset = cursor.position();
+        let block = match Block::try_read(&mut cursor) {
+            Ok(b) => b,
+            Err(e) => {
+                writeln!(out, "{:#010X}  failed to read block: {}", offset, e)?;
+                break;
+            },
+        };
+        let length = match &block {
+            Block::Short { .. } => "-".to_owned(),
+            Block::Long { data, .. } => (data.len() + 2).to_string(),
+        };
+        writeln!(
+            out,
+            "{:#010X}  FF{:02X}    {:<5}  {:>6}  {}",
+            offset, block.kind(), marker_name(block.kind()), length, describe_block(&block),
+        )?;
+
+        match block.kind() {
+            0xD9 => {
+                terminated = true;
+                break;
+            },
+            0xDA => {
+                let scan_start: usize = cursor.position().try_into().unwrap();
+                let (scan_length, restart_count) = entropy_coded_length(&data[scan_start..]);
+                writeln!(
+                    out,
+                    "{:#010X}  {:<6}  {:<5}  {:>6}  entropy-coded data, {} restart markers",
+                    scan_start, "", "", scan_length, restart_count,
+                )?;
+                cursor.set_position((scan_start + scan_length).try_into().unwrap());
+            },
+            _ => {},
+        }
+    }
+
+    let trailing_count = data_len - cursor.position();
+    let terminated_correctly = terminated && trailing_count == 0;
+    if terminated_correctly {
+        writeln!(out, "the data is terminated correctly by EOI")?;
+    } else if terminated {
+        writeln!(out, "{} bytes follow EOI", trailing_count)?;
+    } else {
+        writeln!(out, "the data is not terminated by EOI")?;
+    }
+
+    match Image::try_read(data) {
+        Ok(_) => writeln!(out, "pdfmcr accepts the image")?,
+        Err(e) => writeln!(out, "pdfmcr refuses the image: {}", e)?,
+    }
+
+    Ok(terminated_correctly)
+}
+
+
+fn main() -> ExitCode {
+    let opts = Opts::parse();
+
+    let data = std::fs::read(&opts.jpeg_file)
+        .expect("failed to read JPEG file");
+    let terminated_correctly = dump(&data, &mut io::stdout().lock())
+        .expect("failed to write block table");
+
+    if terminated_correctly {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles a baseline JFIF image of 8x8 grayscale pixels at 300 dpi.
+    fn jfif_fixture() -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        // JFIF 1.02, 300x300 dpi, no thumbnail
+        data.extend_from_slice(b"\xFF\xE0\x00\x10JFIF\x00\x01\x02\x01\x01\x2C\x01\x2C\x00\x00");
+        // quantization table 0, all ones
+        data.extend_from_slice(&[0xFF, 0xDB, 0x00, 0x43, 0x00]);
+        data.extend_from_slice(&[0x01; 64]);
+        // baseline frame, 8 bits, 8x8 pixels, one component using table 0
+        data.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00]);
+        // DC table 0 with a single code of length 1 for category 0
+        data.extend_from_slice(&[0xFF, 0xC4, 0x00, 0x14, 0x00, 0x01]);
+        data.extend_from_slice(&[0x00; 15]);
+        data.push(0x00);
+        // AC table 0 with a single code of length 1 for end-of-block
+        data.extend_from_slice(&[0xFF, 0xC4, 0x00, 0x14, 0x10, 0x01]);
+        data.extend_from_slice(&[0x00; 15]);
+        data.push(0x00);
+        // scan of the single component, followed by its entropy-coded data (DC 0, end-of-block)
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+        data.push(0x3F);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    fn dump_to_string(data: &[u8]) -> (bool, String) {
+        let mut out = Vec::new();
+        let terminated_correctly = dump(data, &mut out).unwrap();
+        (terminated_correctly, String::from_utf8(out).unwrap())
+    }
+
+    /// Returns the names of the markers listed in a block table.
+    fn marker_names(table: &str) -> Vec<&str> {
+        table.lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut columns = line.split_whitespace().skip(1);
+                let marker = columns.next()?;
+                if !marker.starts_with("FF") {
+                    return None;
+                }
+                columns.next()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lists_blocks_of_jfif_image() {
+        let (terminated_correctly, table) = dump_to_string(&jfif_fixture());
+        assert!(terminated_correctly);
+        assert_eq!(marker_names(&table), ["SOI", "APP0", "DQT", "SOF0", "DHT", "DHT", "SOS", "EOI"]);
+        assert!(table.contains("JFIF 1.02, density 300x300 dpi"));
+        assert!(table.contains("baseline Huffman, 8x8, 1 components, 8 bits"));
+        assert!(table.contains("entropy-coded data, 0 restart markers"));
+        assert!(table.contains("the data is terminated correctly by EOI"));
+        assert!(table.contains("pdfmcr accepts the image"));
+    }
+
+    #[test]
+    fn reports_missing_end_of_image() {
+        let mut data = jfif_fixture();
+        data.truncate(data.len() - 2);
+        let (terminated_correctly, table) = dump_to_string(&data);
+        assert!(!terminated_correctly);
+        assert!(!marker_names(&table).contains(&"EOI"));
+        assert!(table.contains("the data is not terminated by EOI"));
+
+        let mut data = jfif_fixture();
+        data.extend_from_slice(b"junk");
+        let (terminated_correctly, table) = dump_to_string(&data);
+        assert!(!terminated_correctly);
+        assert!(table.contains("4 bytes follow EOI"));
+    }
+}
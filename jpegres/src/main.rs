@@ -61,6 +61,90 @@ fn copy_over<R: Read, W: Write>(source: &mut R, destination: &mut W, mut byte_co
 }
 
 
+/// Reads the next marker, returning its type.
+///
+/// Any additional 0xFF bytes preceding the marker type are copied over as-is.
+fn read_marker<R: Read, W: Write>(source: &mut R, destination: &mut W) -> u8 {
+    let mut buf1 = [0u8; 1];
+    source.read_exact(&mut buf1)
+        .expect("failed to read marker start");
+    if buf1[0] != 0xFF {
+        panic!("marker starts with invalid byte 0x{:02X}", buf1[0]);
+    }
+    loop {
+        source.read_exact(&mut buf1)
+            .expect("failed to read marker type");
+        if buf1[0] != 0xFF {
+            return buf1[0];
+        }
+        destination.write_all(&buf1)
+            .expect("failed to write fill byte");
+    }
+}
+
+
+/// Copies over a block with the given marker type whose marker has already been read.
+fn copy_block<R: Read, W: Write>(marker_type: u8, source: &mut R, destination: &mut W) {
+    destination.write_all(&[0xFF, marker_type])
+        .expect("failed to write block header");
+
+    let mut length_buf = [0u8; 2];
+    source.read_exact(&mut length_buf)
+        .expect("failed to read block length");
+    destination.write_all(&length_buf)
+        .expect("failed to write block length");
+    let block_length = u16::from_be_bytes(length_buf);
+    if block_length < 2 {
+        panic!("invalid block length; must be at least 2 to accommodate the length bytes we just read");
+    }
+
+    // copy that
+    let copy_count: u64 = (block_length - 2).into();
+    copy_over(source, destination, copy_count);
+}
+
+
+/// Copies over the entropy-coded data following a Start of Scan block.
+///
+/// Byte-stuffed 0xFF values and restart markers are part of the data. Returns the type of the
+/// marker that ends the data, which has been read but not yet written.
+fn copy_entropy_coded_data<R: Read, W: Write>(source: &mut R, destination: &mut W) -> u8 {
+    let mut buf1 = [0u8; 1];
+    loop {
+        // copy until we see 0xFF
+        source.read_exact(&mut buf1)
+            .expect("failed to read data byte");
+        if buf1[0] != 0xFF {
+            destination.write_all(&buf1)
+                .expect("failed to write data byte");
+            continue;
+        }
+
+        // marker! what kind?
+        let mut fill_count: usize = 0;
+        let marker_type = loop {
+            source.read_exact(&mut buf1)
+                .expect("failed to read marker byte type");
+            if buf1[0] != 0xFF {
+                break buf1[0];
+            }
+            fill_count += 1;
+        };
+        destination.write_all(&vec![0xFF; fill_count])
+            .expect("failed to write fill bytes");
+
+        if marker_type == 0x00 || (0xD0..=0xD7).contains(&marker_type) {
+            // byte-stuffed non-marker or restart marker; part of data
+            destination.write_all(&[0xFF, marker_type])
+                .expect("failed to write data bytes");
+        } else {
+            // end of this scan
+            return marker_type;
+        }
+    }
+}
+
+
 fn handle_app0<R: Read, W: Write>(source: &mut R, destination: &mut W) {
     let mut length_buf = [0u8; 2];
     source.read_exact(&mut length_buf)
@@ -92,90 +176,68 @@ fn handle_app0<R: Read, W: Write>(source: &mut R, destination: &mut W) {
 }
 
 
-fn main() {
-    let opts = Opts::parse();
+fn handle_app1<R: Read, W: Write>(source: &mut R, destination: &mut W) {
+    // the density recorded in Exif is not rewritten; copy it over
+    copy_block(0xE1, source, destination);
+}
 
-    // find the basic metadata of the JPEG file
-    let mut input_file = File::open(&opts.input_file)
-        .expect("failed to open input file");
-    let mut output_file = File::create(&opts.output_file)
-        .expect("failed to create output file");
 
+/// Copies a JPEG image from the source to the destination, handling its metadata blocks.
+fn copy_jpeg<R: Read, W: Write>(source: &mut R, destination: &mut W) {
     // image must start with Start of Image
     let mut buf2 = [0u8; 2];
-    input_file.read_exact(&mut buf2)
+    source.read_exact(&mut buf2)
         .expect("failed to read Start of Image");
     if buf2 != [0xFF, 0xD8] {
         panic!("invalid Start of Image -- expected 0xFF 0xD8, obtained 0x{:02X} 0x{:02X}", buf2[0], buf2[1]);
     }
-    output_file.write_all(&buf2)
+    destination.write_all(&buf2)
         .expect("failed to write Start of Image");
 
+    // what's the next block?
+    let mut marker_type = read_marker(source, destination);
     loop {
-        // what's the next block?
-        input_file.read_exact(&mut buf2)
-            .expect("failed to read next block");
-        if buf2 == [0xFF, 0xE0] {
+        if marker_type == 0xE0 {
             // APP0, possibly JFIF?
-            handle_app0(&mut input_file, &mut output_file);
-        } else if buf2 == [0xFF, 0xE1] {
+            handle_app0(source, destination);
+        } else if marker_type == 0xE1 {
             // APP1, possibly Exif?
-            handle_app1(&mut input_file, &mut output_file);
-        } else if buf2 == [0xFF, 0xDA] {
-            // Start of Scan; this one has no length following it
+            handle_app1(source, destination);
+        } else if marker_type == 0xDA {
+            // Start of Scan; the block is followed by entropy-coded data, which is ended by the
+            // next marker (progressive images have multiple scans, interspersed with e.g. new
+            // Huffman tables)
+            copy_block(marker_type, source, destination);
+            marker_type = copy_entropy_coded_data(source, destination);
+            continue;
+        } else if marker_type == 0xD9 {
+            // end of image; break out
+            destination.write_all(&[0xFF, marker_type])
+                .expect("failed to write End of Image");
             break;
         } else {
-            if buf2[0] != 0xFF {
-                panic!("header starts with invalid byte 0x{:02X}", buf2[0]);
-            }
-
             // some other kind of header
-            output_file.write_all(&buf2)
-                .expect("failed to write block header");
-
-            input_file.read_exact(&mut buf2)
-                .expect("failed to read block length");
-            let block_length = u16::from_be_bytes(buf2);
-            if block_length < 2 {
-                panic!("invalid block length; must be at least 2 to accommodate the length bytes we just read");
-            }
-
-            // copy that
-            let copy_count: u64 = (block_length - 2).into();
-            copy_over(&mut input_file, &mut output_file, copy_count);
-
-            // next header
+            copy_block(marker_type, source, destination);
         }
+
+        // next header
+        marker_type = read_marker(source, destination);
     }
+}
 
-    loop {
-        // copy until we see 0xFF
-        let mut buf1 = [0u8; 1];
-        input_file.read_exact(&mut buf1)
-            .expect("failed to read data byte");
-        output_file.write_all(&buf1)
-            .expect("failed to write data byte");
 
-        if buf1[0] == 0xFF {
-            // marker!
+fn main() {
+    let opts = Opts::parse();
 
-            // what kind?
-            input_file.read_exact(&mut buf1)
-                .expect("failed to read marker byte type");
-            output_file.write_all(&buf1)
-                .expect("failed to write marker byte type");
-
-            if buf1[0] == 0x00 {
-                // byte-stuffed non-marker; part of data
-                // go again
-            } else if buf1[0] == 0xD9 {
-                // end of data; break out
-                break;
-            } else {
-                panic!("unknown marker sequence 0xFF 0x{:02X} in image data", buf1[0]);
-            }
-        }
-    }
+    // find the basic metadata of the JPEG file
+    let input_file = File::open(&opts.input_file)
+        .expect("failed to open input file");
+    let output_file = File::create(&opts.output_file)
+        .expect("failed to create output file");
+    let mut input_file = BufReader::new(input_file);
+    let mut output_file = BufWriter::new(output_file);
+
+    copy_jpeg(&mut input_file, &mut output_file);
 
     // ensure we wrote it all
     output_file.flush()
@@ -183,3 +245,91 @@ fn main() {
 
     // that's it
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles the blocks of a progressive image with three scans, separated by Huffman tables.
+    ///
+    /// The entropy-coded data is not meaningful, but contains byte-stuffed 0xFF values, restart
+    /// markers and fill bytes before the markers following it.
+    fn progressive_fixture() -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        // Exif block without tags
+        data.extend_from_slice(b"\xFF\xE1\x00\x10Exif\x00\x00MM\x00\x2A\x00\x00\x00\x08");
+        // quantization table 0, all ones
+        data.extend_from_slice(&[0xFF, 0xDB, 0x00, 0x43, 0x00]);
+        data.extend_from_slice(&[0x01; 64]);
+        // progressive frame, 8 bits, 16x16 pixels, one component using table 0
+        data.extend_from_slice(&[0xFF, 0xC2, 0x00, 0x0B, 0x08, 0x00, 0x10, 0x00, 0x10, 0x01, 0x01, 0x11, 0x00]);
+        // restart interval of 1 MCU
+        data.extend_from_slice(&[0xFF, 0xDD, 0x00, 0x04, 0x00, 0x01]);
+        let huffman_table = |class_and_id: u8| {
+            let mut table = vec![0xFF, 0xC4, 0x00, 0x14, class_and_id, 0x01];
+            table.extend_from_slice(&[0x00; 15]);
+            table.push(0x00);
+            table
+        };
+        // DC scan
+        data.extend_from_slice(&huffman_table(0x00));
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD1, 0x78]);
+        // first AC scan, preceded by fill bytes
+        data.extend_from_slice(&[0xFF, 0xFF]);
+        data.extend_from_slice(&huffman_table(0x10));
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x01, 0x05, 0x00]);
+        data.extend_from_slice(&[0x9A, 0xFF, 0x00, 0xBC, 0xFF, 0xD0, 0xDE]);
+        // second AC scan, directly following the first
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x06, 0x3F, 0x00]);
+        data.extend_from_slice(&[0xF0, 0xFF, 0xD0, 0x0F]);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    /// Counts the Start-of-Scan blocks of an image by walking its blocks.
+    fn count_scans(data: &[u8]) -> usize {
+        let mut scan_count = 0;
+        let mut i = 2;
+        while i + 1 < data.len() {
+            if data[i] != 0xFF || data[i + 1] == 0xFF {
+                // entropy-coded data or fill byte
+                i += 1;
+                continue;
+            }
+            match data[i + 1] {
+                0x00|0xD0..=0xD7 => i += 2,
+                0xD9 => break,
+                marker_type => {
+                    if marker_type == 0xDA {
+                        scan_count += 1;
+                    }
+                    let length = usize::from(u16::from_be_bytes([data[i + 2], data[i + 3]]));
+                    i += 2 + length;
+                },
+            }
+        }
+        scan_count
+    }
+
+    #[test]
+    fn copies_all_scans() {
+        let input = progressive_fixture();
+        assert_eq!(count_scans(&input), 3);
+
+        let mut output = Vec::new();
+        copy_jpeg(&mut input.as_slice(), &mut output);
+        assert_eq!(count_scans(&output), 3);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to read data byte")]
+    fn refuses_truncated_scan() {
+        let mut input = progressive_fixture();
+        // cut off within the last scan
+        input.truncate(input.len() - 5);
+        copy_jpeg(&mut input.as_slice(), &mut Vec::new());
+    }
+}
@@ -0,0 +1,58 @@
+use std::borrow::Cow;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use tracing::warn;
+
+use crate::config::CONFIG;
+
+
+/// The name of the header carrying the administrator secret.
+pub(crate) const ADMIN_HEADER: &str = "X-Pdfmcr-Admin";
+
+
+/// Proof that a request has been made by an administrator.
+///
+/// A request is made by an administrator if it carries the configured administrator secret in the
+/// header named [`ADMIN_HEADER`]. If no administrator secret is configured, administrative requests
+/// are refused altogether.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Admin;
+
+/// Compares two byte strings in time that depends only on their lengths.
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right.iter())
+        .fold(0u8, |difference, (l, r)| difference | (l ^ r))
+        == 0
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = Cow<'static, str>;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let admin_secret = {
+            let config_guard = CONFIG
+                .get().expect("CONFIG not set?!")
+                .read().await;
+            config_guard.admin_secret.clone()
+        };
+        let Some(admin_secret) = admin_secret else {
+            return Outcome::Error((Status::Forbidden, Cow::Borrowed("administration is disabled")));
+        };
+
+        let Some(header_value) = request.headers().get_one(ADMIN_HEADER) else {
+            return Outcome::Error((Status::Unauthorized, Cow::Borrowed("admin header missing")));
+        };
+        if constant_time_eq(header_value.as_bytes(), admin_secret.as_bytes()) {
+            Outcome::Success(Self)
+        } else {
+            warn!("rejecting administrative request with invalid secret");
+            Outcome::Error((Status::Forbidden, Cow::Borrowed("invalid admin header")))
+        }
+    }
+}
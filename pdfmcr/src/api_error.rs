@@ -0,0 +1,180 @@
+use std::fmt;
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use serde::Serialize;
+
+
+/// An error returned by a web handler.
+///
+/// Errors are delivered to the client as a JSON object with a machine-readable `code` and a
+/// human-readable `message`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum ApiError {
+    /// The page with the given index does not exist.
+    NoSuchPage { page: usize },
+
+    /// The page with the given index is text-only and therefore has no image.
+    PageHasNoImage { page: usize },
+
+    /// The uploaded file is too small to possibly be a JPEG image.
+    UploadTooSmall,
+
+    /// The request body exceeds the configured size limit.
+    UploadTooLarge,
+
+    /// The uploaded file is not a JPEG or TIFF image usable by pdfmcr.
+    InvalidImage { reason: String },
+
+    /// The annotations with the given indices lie outside of their page.
+    AnnotationsOutOfBounds { page: usize, indices: Vec<usize> },
+
+    /// The requested reading order is not a permutation of the annotations of the page.
+    InvalidReadingOrder { page: usize },
+
+    /// The given language is not a valid BCP 47 language tag.
+    InvalidLanguage { language: String },
+
+    /// An annotation or artifact of the page with the given index has an unacceptable font size or
+    /// spacing.
+    InvalidTypography { page: usize, reason: String },
+
+    /// The request body is not valid JSON or does not have the expected structure.
+    MalformedBody { reason: String },
+
+    /// The page with the given index would have more annotations and artifacts than allowed.
+    TooManyAnnotations { page: usize, limit: usize },
+
+    /// The submitted document is not usable, e.g. because a page has no known size.
+    InvalidDocument { reason: String },
+
+    /// The given dimension of the page with the given index cannot be converted into points.
+    UnknownPageSize { page: usize, dimension: &'static str },
+
+    /// The uploaded OCR output cannot be read or placed onto the page with the given index.
+    InvalidOcr { page: usize, reason: String },
+
+    /// An error occurred on the server side; the details have been logged.
+    Internal { message: &'static str },
+}
+impl ApiError {
+    /// The HTTP status with which the error is returned.
+    pub fn status(&self) -> Status {
+        match self {
+            Self::NoSuchPage { .. } => Status::NotFound,
+            Self::PageHasNoImage { .. } => Status::NotFound,
+            Self::UploadTooSmall => Status::BadRequest,
+            Self::UploadTooLarge => Status::PayloadTooLarge,
+            Self::InvalidImage { .. } => Status::BadRequest,
+            Self::AnnotationsOutOfBounds { .. } => Status::BadRequest,
+            Self::InvalidReadingOrder { .. } => Status::BadRequest,
+            Self::InvalidLanguage { .. } => Status::BadRequest,
+            Self::InvalidTypography { .. } => Status::BadRequest,
+            Self::MalformedBody { .. } => Status::BadRequest,
+            Self::TooManyAnnotations { .. } => Status::UnprocessableEntity,
+            Self::InvalidDocument { .. } => Status::BadRequest,
+            Self::UnknownPageSize { .. } => Status::BadRequest,
+            Self::InvalidOcr { .. } => Status::BadRequest,
+            Self::Internal { .. } => Status::InternalServerError,
+        }
+    }
+
+    /// The machine-readable code identifying the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NoSuchPage { .. } => "no_such_page",
+            Self::PageHasNoImage { .. } => "page_has_no_image",
+            Self::UploadTooSmall => "upload_too_small",
+            Self::UploadTooLarge => "upload_too_large",
+            Self::InvalidImage { .. } => "invalid_image",
+            Self::AnnotationsOutOfBounds { .. } => "annotations_out_of_bounds",
+            Self::InvalidReadingOrder { .. } => "invalid_reading_order",
+            Self::InvalidLanguage { .. } => "invalid_language",
+            Self::InvalidTypography { .. } => "invalid_typography",
+            Self::MalformedBody { .. } => "malformed_body",
+            Self::TooManyAnnotations { .. } => "too_many_annotations",
+            Self::InvalidDocument { .. } => "invalid_document",
+            Self::UnknownPageSize { .. } => "unknown_page_size",
+            Self::InvalidOcr { .. } => "invalid_ocr",
+            Self::Internal { .. } => "internal",
+        }
+    }
+}
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchPage { page }
+                => write!(f, "page {} does not exist", page),
+            Self::PageHasNoImage { page }
+                => write!(f, "page {} has no image", page),
+            Self::UploadTooSmall
+                => write!(f, "uploaded file is too small to be a JPEG image"),
+            Self::UploadTooLarge
+                => write!(f, "uploaded data exceeds the size limit"),
+            Self::InvalidImage { reason }
+                => write!(f, "uploaded file is not a usable image: {}", reason),
+            Self::AnnotationsOutOfBounds { page, indices }
+                => write!(f, "annotations {:?} lie outside of page {}", indices, page),
+            Self::InvalidReadingOrder { page }
+                => write!(f, "reading order does not contain each annotation of page {} exactly once", page),
+            Self::InvalidLanguage { language }
+                => write!(f, "{:?} is not a valid language tag", language),
+            Self::InvalidTypography { page, reason }
+                => write!(f, "page {}: {}", page, reason),
+            Self::MalformedBody { reason }
+                => write!(f, "request body is malformed: {}", reason),
+            Self::TooManyAnnotations { page, limit }
+                => write!(f, "page {} would have more than {} annotations and artifacts", page, limit),
+            Self::InvalidDocument { reason }
+                => write!(f, "document is not usable: {}", reason),
+            Self::UnknownPageSize { page, dimension }
+                => write!(f, "the {} of page {} is not known in absolute units", dimension, page),
+            Self::InvalidOcr { page, reason }
+                => write!(f, "OCR output for page {} is not usable: {}", page, reason),
+            Self::Internal { message }
+                => write!(f, "{}", message),
+        }
+    }
+}
+impl std::error::Error for ApiError {
+}
+impl<'r> From<rocket::serde::json::Error<'r>> for ApiError {
+    /// Reports a JSON request body that Rocket could not decode, including what is wrong with it
+    /// (e.g. a font size of 0).
+    fn from(value: rocket::serde::json::Error<'r>) -> Self {
+        match value {
+            rocket::serde::json::Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof
+                => Self::UploadTooLarge,
+            rocket::serde::json::Error::Io(e)
+                => Self::MalformedBody { reason: e.to_string() },
+            rocket::serde::json::Error::Parse(_, e)
+                => Self::MalformedBody { reason: e.to_string() },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = ApiErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        };
+        Custom(self.status(), Json(body)).respond_to(request)
+    }
+}
+
+
+/// Reports request bodies rejected by Rocket's size limits as an [`ApiError`].
+#[rocket::catch(413)]
+pub(crate) fn payload_too_large() -> ApiError {
+    ApiError::UploadTooLarge
+}
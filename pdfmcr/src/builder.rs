@@ -0,0 +1,355 @@
+//! Programmatic assembly of pdfmcr files.
+
+
+use std::fmt;
+
+use crate::image_path::ImagePath;
+use crate::language::is_valid_language_tag;
+use crate::model::{Annotation, Artifact, File, ImageInfoError, JpegImage, JpegImageInfo, Page};
+
+
+/// An error that can occur while assembling a pdfmcr file.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Error {
+    /// The image intended for the page with the given index cannot be used.
+    Image { page_index: usize, error: ImageInfoError },
+
+    /// The text-only page with the given index has a width or height of 0.
+    ZeroPageSize { page_index: usize },
+
+    /// The file does not contain any pages.
+    NoPages,
+
+    /// The default language of the file has been set to an empty string.
+    EmptyDefaultLanguage,
+
+    /// The default language of the file is not a valid BCP 47 language tag.
+    InvalidDefaultLanguage { language: String },
+
+    /// A text chunk on the page with the given index has a language that is not a valid BCP 47
+    /// language tag.
+    InvalidChunkLanguage { page_index: usize, language: String },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image { page_index, error }
+                => write!(f, "unusable image for page {}: {}", page_index, error),
+            Self::ZeroPageSize { page_index }
+                => write!(f, "page {} has a width or height of 0", page_index),
+            Self::NoPages
+                => write!(f, "file does not contain any pages"),
+            Self::EmptyDefaultLanguage
+                => write!(f, "default language is empty"),
+            Self::InvalidDefaultLanguage { language }
+                => write!(f, "default language {:?} is not a valid language tag", language),
+            Self::InvalidChunkLanguage { page_index, language }
+                => write!(f, "language {:?} of a text chunk on page {} is not a valid language tag", language, page_index),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Image { error, .. } => Some(error),
+            Self::ZeroPageSize { .. } => None,
+            Self::NoPages => None,
+            Self::EmptyDefaultLanguage => None,
+            Self::InvalidDefaultLanguage { .. } => None,
+            Self::InvalidChunkLanguage { .. } => None,
+        }
+    }
+}
+
+
+/// Assembles a pdfmcr [`File`] page by page.
+///
+/// This allows constructing a file without going through the web interface, e.g. from an automated
+/// OCR pipeline.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct FileBuilder {
+    pages: Vec<Page>,
+    default_language: Option<String>,
+    title: Option<String>,
+    visible_text: bool,
+}
+impl FileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the default language of the file, as a BCP 47 language tag.
+    pub fn default_language<S: Into<String>>(&mut self, language: S) -> &mut Self {
+        self.default_language = Some(language.into());
+        self
+    }
+
+    /// Sets the title of the file; see [`File::title`].
+    pub fn title<S: Into<String>>(&mut self, title: S) -> &mut Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets whether the text of the annotations is drawn visibly; see [`File::visible_text`].
+    pub fn visible_text(&mut self, visible_text: bool) -> &mut Self {
+        self.visible_text = visible_text;
+        self
+    }
+
+    /// Appends a page with the given scanned image, annotations and artifacts.
+    ///
+    /// `file_path` is the path, relative to the image directory, at which the full data of the image
+    /// is stored. The image itself is only consulted for its metadata.
+    pub fn add_page(
+        &mut self,
+        image: &crate::jpeg::Image,
+        file_path: ImagePath,
+        annotations: Vec<Annotation>,
+        artifacts: Vec<Artifact>,
+    ) -> Result<&mut Self, Error> {
+        self.add_image_page(image, file_path, annotations, artifacts)
+    }
+
+    /// Appends a page with the given bilevel scanned image compressed using CCITT Group 4,
+    /// annotations and artifacts.
+    ///
+    /// `file_path` is the path, relative to the image directory, at which the compressed data of
+    /// the image (as returned by [`ccitt_group4_data`](crate::tiff::Image::ccitt_group4_data)) is
+    /// stored.
+    pub fn add_bilevel_page(
+        &mut self,
+        image: &crate::tiff::Image,
+        file_path: ImagePath,
+        annotations: Vec<Annotation>,
+        artifacts: Vec<Artifact>,
+    ) -> Result<&mut Self, Error> {
+        self.add_image_page(image, file_path, annotations, artifacts)
+    }
+
+    /// Appends a page with a scanned image of any kind from which image information can be derived.
+    fn add_image_page<I>(
+        &mut self,
+        image: I,
+        file_path: ImagePath,
+        annotations: Vec<Annotation>,
+        artifacts: Vec<Artifact>,
+    ) -> Result<&mut Self, Error>
+    where
+        JpegImageInfo: TryFrom<I, Error = ImageInfoError>,
+    {
+        let info = JpegImageInfo::try_from(image)
+            .map_err(|error| Error::Image { page_index: self.pages.len(), error })?;
+        let mut page = Page::new(JpegImage {
+            info,
+            file_path,
+            data_filters: None,
+        });
+        page.annotations = annotations;
+        page.artifacts = artifacts;
+        self.pages.push(page);
+        Ok(self)
+    }
+
+    /// Appends a page without a scanned image, with the given size in points (1/72 in),
+    /// annotations and artifacts.
+    pub fn add_text_only_page(
+        &mut self,
+        width_pt: u64,
+        height_pt: u64,
+        annotations: Vec<Annotation>,
+        artifacts: Vec<Artifact>,
+    ) -> Result<&mut Self, Error> {
+        if width_pt == 0 || height_pt == 0 {
+            return Err(Error::ZeroPageSize { page_index: self.pages.len() });
+        }
+        let mut page = Page::new_text_only(width_pt as f64, height_pt as f64);
+        page.annotations = annotations;
+        page.artifacts = artifacts;
+        self.pages.push(page);
+        Ok(self)
+    }
+
+    /// Validates the assembled data and returns the finished file.
+    pub fn build(self) -> Result<File, Error> {
+        if self.pages.is_empty() {
+            return Err(Error::NoPages);
+        }
+        if let Some(default_language) = self.default_language.as_ref() {
+            if default_language.is_empty() {
+                return Err(Error::EmptyDefaultLanguage);
+            }
+            if !is_valid_language_tag(default_language) {
+                return Err(Error::InvalidDefaultLanguage { language: default_language.clone() });
+            }
+        }
+        for (page_index, page) in self.pages.iter().enumerate() {
+            let invalid_language = page.text_chunks()
+                .filter_map(|chunk| chunk.language.as_deref())
+                .find(|language| !is_valid_language_tag(language));
+            if let Some(language) = invalid_language {
+                return Err(Error::InvalidChunkLanguage { page_index, language: language.to_owned() });
+            }
+        }
+        Ok(File {
+            pages: self.pages,
+            default_language: self.default_language,
+            title: self.title,
+            visible_text: self.visible_text,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+    use super::*;
+    use crate::file_to_pdf::{ConversionOptions, file_to_pdf};
+    use crate::jpeg::DensityUnit;
+    use crate::model::{FontVariant, TextChunk};
+    use crate::tiff::{Compression, PhotometricInterpretation};
+
+    fn annotation_in_language(language: &str) -> Annotation {
+        Annotation {
+            left: 10,
+            bottom: 10,
+            font_size: NonZeroPositiveF64::new(12.0).unwrap(),
+            leading: FiniteF64::new(0.0).unwrap(),
+            elements: vec![
+                TextChunk {
+                    text: "text".to_owned(),
+                    font_variant: FontVariant::Regular,
+                    character_spacing: FiniteF64::new(0.0).unwrap(),
+                    word_spacing: FiniteF64::new(0.0).unwrap(),
+                    horizontal_scale: None,
+                    text_rise: None,
+                    language: Some(language.to_owned()),
+                    alternate_text: None,
+                    actual_text: None,
+                    expansion: None,
+                },
+            ],
+            order: None,
+            block_role: None,
+        }
+    }
+
+    #[test]
+    fn accepts_valid_languages() {
+        let mut builder = FileBuilder::new();
+        builder.default_language("de-CH");
+        builder.add_text_only_page(100, 100, vec![annotation_in_language("zh-Hant-TW")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+        assert_eq!(file.default_language.as_deref(), Some("de-CH"));
+    }
+
+    #[test]
+    fn refuses_invalid_default_language() {
+        let mut builder = FileBuilder::new();
+        builder.default_language("english");
+        builder.add_text_only_page(100, 100, Vec::new(), Vec::new()).unwrap();
+        assert_eq!(builder.build(), Err(Error::InvalidDefaultLanguage { language: "english".to_owned() }));
+    }
+
+    #[test]
+    fn refuses_invalid_chunk_language() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(100, 100, Vec::new(), Vec::new()).unwrap();
+        builder.add_text_only_page(100, 100, vec![annotation_in_language("english")], Vec::new()).unwrap();
+        assert_eq!(builder.build(), Err(Error::InvalidChunkLanguage { page_index: 1, language: "english".to_owned() }));
+    }
+
+    /// A bilevel image of 16x16 pixels at 300 dpi compressed using CCITT Group 4.
+    ///
+    /// The strip is not valid Group 4 data; it is only embedded, never decoded.
+    fn bilevel_image() -> crate::tiff::Image {
+        crate::tiff::Image {
+            width: 16,
+            height: 16,
+            bits_per_sample: vec![1],
+            compression: Compression::CcittGroup4,
+            photometric_interpretation: PhotometricInterpretation::WhiteIsZero,
+            rows_per_strip: 16,
+            least_significant_bit_first: false,
+            density_unit: DensityUnit::DotsPerInch,
+            density_x: 300,
+            density_y: 300,
+            strips: vec![vec![0x26, 0xA0, 0x00, 0x10, 0x01]],
+        }
+    }
+
+    fn jpeg_image() -> crate::jpeg::Image {
+        let mut jpeg_data = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg_data, 90);
+        encoder.set_density(jpeg_encoder::Density::Inch { x: 300, y: 300 });
+        encoder.encode(&[0x80; 16 * 16], 16, 16, jpeg_encoder::ColorType::Luma).unwrap();
+        crate::jpeg::Image::try_read(jpeg_data.as_slice()).unwrap()
+    }
+
+    /// Converts the file to PDF, reading the images from the given directory, and loads the result.
+    fn convert(file: &File, image_dir: &Path) -> lopdf::Document {
+        let document = file_to_pdf(file, image_dir, &ConversionOptions::default()).unwrap();
+        let mut pdf_bytes = Vec::new();
+        document.write_pdf_to_stream(&mut pdf_bytes).unwrap();
+        lopdf::Document::load_mem(&pdf_bytes).unwrap()
+    }
+
+    #[test]
+    fn builds_file_with_jpeg_and_bilevel_pages() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-builder-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let jpeg = jpeg_image();
+        let mut jpeg_data = Vec::new();
+        jpeg.write(&mut jpeg_data).unwrap();
+        std::fs::write(dir.join("page1.jpeg"), &jpeg_data).unwrap();
+        let bilevel = bilevel_image();
+        std::fs::write(dir.join("page2.g4"), bilevel.ccitt_group4_data().unwrap()).unwrap();
+
+        let mut builder = FileBuilder::new();
+        builder.default_language("de-AT").title("Zwei Seiten");
+        builder.add_page(&jpeg, "page1.jpeg".parse().unwrap(), Vec::new(), Vec::new()).unwrap();
+        builder.add_bilevel_page(&bilevel, "page2.g4".parse().unwrap(), Vec::new(), Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+        assert_eq!(file.pages.len(), 2);
+        assert_eq!(file.default_language.as_deref(), Some("de-AT"));
+
+        let pdf = convert(&file, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(pdf.get_pages().len(), 2);
+
+        let images: Vec<&lopdf::Stream> = pdf.objects.values()
+            .filter_map(|object| object.as_stream().ok())
+            .filter(|stream| stream.dict.get(b"Subtype").and_then(|v| v.as_name()).is_ok_and(|name| name == b"Image"))
+            .collect();
+        assert_eq!(images.len(), 2);
+        let bilevel_xobject = images.iter()
+            .find(|image| image.dict.get(b"BitsPerComponent").and_then(|v| v.as_i64()).is_ok_and(|bpc| bpc == 1))
+            .unwrap();
+        assert_eq!(bilevel_xobject.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceGray");
+        let filters = bilevel_xobject.dict.get(b"Filter").unwrap().as_array().unwrap();
+        assert_eq!(filters.len(), 1);
+        assert_eq!(filters[0].as_name().unwrap(), b"CCITTFaxDecode");
+        let decode_parms = bilevel_xobject.dict.get(b"DecodeParms").unwrap().as_array().unwrap()[0]
+            .as_dict().unwrap();
+        assert_eq!(decode_parms.get(b"K").unwrap().as_i64().unwrap(), -1);
+        assert_eq!(decode_parms.get(b"Columns").unwrap().as_i64().unwrap(), 16);
+        assert_eq!(bilevel_xobject.content, bilevel.strips[0]);
+    }
+
+    #[test]
+    fn refuses_uncompressed_bilevel_page() {
+        let mut image = bilevel_image();
+        image.compression = Compression::Uncompressed;
+        let mut builder = FileBuilder::new();
+        let result = builder.add_bilevel_page(&image, "page.tiff".parse().unwrap(), Vec::new(), Vec::new());
+        assert!(matches!(result, Err(Error::Image { page_index: 0, error: ImageInfoError::UnsupportedEncoding })));
+    }
+
+    #[test]
+    fn refuses_empty_file() {
+        assert_eq!(FileBuilder::new().build(), Err(Error::NoPages));
+    }
+}
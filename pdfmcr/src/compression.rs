@@ -0,0 +1,104 @@
+use std::io::{self, Cursor, Write};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rocket::{Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use tracing::error;
+
+
+/// The minimum size of a response body, in bytes, for compressing it to be worthwhile.
+const MIN_COMPRESSED_SIZE: usize = 256;
+
+
+/// Compresses textual responses using gzip if the client accepts it.
+///
+/// Only HTML, JSON, JavaScript, CSS and plain-text responses are compressed. In particular, images
+/// and exported documents are left alone, as are event streams, which must not be buffered.
+pub(crate) struct GzipCompression;
+
+/// Whether the given `Accept-Encoding` header value allows gzip-encoded responses.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut pieces = coding.split(';').map(|piece| piece.trim());
+        let name = pieces.next().unwrap_or("");
+        if !name.eq_ignore_ascii_case("gzip") && !name.eq_ignore_ascii_case("x-gzip") {
+            return false;
+        }
+        // "gzip;q=0" explicitly refuses gzip
+        let refused = pieces
+            .filter_map(|parameter| parameter.strip_prefix("q="))
+            .any(|quality| quality.parse::<f64>().map(|q| q == 0.0).unwrap_or(false));
+        !refused
+    })
+}
+
+/// Whether responses of the given content type benefit from being compressed.
+fn is_compressible(content_type: &ContentType) -> bool {
+    content_type == &ContentType::HTML
+        || content_type == &ContentType::JSON
+        || content_type == &ContentType::JavaScript
+        || content_type == &ContentType::CSS
+        || content_type == &ContentType::Plain
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[rocket::async_trait]
+impl Fairing for GzipCompression {
+    fn info(&self) -> Info {
+        Info {
+            name: "gzip compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.headers().contains("Content-Encoding") {
+            return;
+        }
+        let compressible = response.content_type()
+            .map(|content_type| is_compressible(&content_type))
+            .unwrap_or(false);
+        if !compressible {
+            return;
+        }
+
+        // caches must not hand the compressed variant to clients that cannot decode it
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+
+        let client_accepts_gzip = request.headers()
+            .get("Accept-Encoding")
+            .any(accepts_gzip);
+        if !client_accepts_gzip {
+            return;
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(b) => b,
+            Err(e) => {
+                error!("failed to read response body for compression: {}", e);
+                return;
+            },
+        };
+        if body.len() < MIN_COMPRESSED_SIZE {
+            response.set_sized_body(body.len(), Cursor::new(body));
+            return;
+        }
+        match gzip(&body) {
+            Ok(compressed) => {
+                response.set_header(Header::new("Content-Encoding", "gzip"));
+                response.set_sized_body(compressed.len(), Cursor::new(compressed));
+            },
+            Err(e) => {
+                error!("failed to compress response body: {}", e);
+                response.set_sized_body(body.len(), Cursor::new(body));
+            },
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 use std::sync::OnceLock;
 
+use pdfmcr::image_path::ContentHashAlgorithm;
+use pdfmcr::pdf::PdfVersion;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tracing::error;
@@ -12,11 +14,136 @@ pub(crate) static CONFIG: OnceLock<RwLock<Config>> = OnceLock::new();
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct Config {
+    /// The path to the file in which the state is stored.
+    ///
+    /// If the name of the file ends in `.json`, the state is written as JSON; otherwise, it is written
+    /// as CBOR. Either format is accepted when the state is loaded.
     pub state_file_path: String,
     pub image_dir: String,
+
+    /// The number of directory levels into which images are sharded within `image_dir`.
+    ///
+    /// Each level is named after the next two hexadecimal digits of the image's checksum, e.g. with
+    /// two levels, an image whose checksum starts with `abcd` is stored at `ab/cd/abcd...jpeg`.
+    #[serde(default)]
+    pub image_shard_levels: u8,
+
+    /// The hash algorithm with which the names of newly stored images are derived from their data,
+    /// either `sha3-512` (the default) or `sha-512`.
+    ///
+    /// Changing the algorithm does not affect images that have already been stored; the names of
+    /// images are not recomputed. Uploading the same image before and after a change stores it
+    /// twice, however.
+    #[serde(default)]
+    pub content_hash_algorithm: ContentHashAlgorithm,
+
+    /// Whether the images referenced by a state are checked for existence when the state is
+    /// loaded, i.e. at startup and when the state of a tenant is first accessed.
+    ///
+    /// Missing images are logged as warnings; the state is used regardless.
+    #[serde(default)]
+    pub check_images_on_load: bool,
+
+    /// The secret with which tenant headers are signed, enabling multi-tenancy if set.
+    ///
+    /// If set, every request must carry a valid tenant header, and each tenant receives its own
+    /// state file and its own subdirectory of `image_dir`. See [`crate::tenant::TENANT_HEADER`].
+    #[serde(default)]
+    pub tenant_secret: Option<String>,
+
+    /// The secret that must be passed in the admin header to perform administrative tasks.
+    ///
+    /// Administrative tasks are disabled if not set. See [`crate::admin::ADMIN_HEADER`].
+    #[serde(default)]
+    pub admin_secret: Option<String>,
+
+    /// Whether viewers are asked to interpolate the scanned images of exported documents when
+    /// scaling them.
+    #[serde(default = "default_true")]
+    pub interpolate_images: bool,
+
+    /// Whether the scanned RGB images of exported documents are declared to be in the sRGB color
+    /// space (using an embedded ICC profile) instead of in device-dependent RGB.
+    #[serde(default)]
+    pub srgb_images: bool,
+
+    /// Whether annotations whose origin lies outside of their page are rejected.
+    #[serde(default)]
+    pub strict_annotation_bounds: bool,
+
+    /// The directory from which static files are served.
+    ///
+    /// Defaults to `static` within the current working directory.
+    #[serde(default)]
+    pub static_dir: Option<String>,
+
+    /// The directory from which the compiled scripts are served.
+    ///
+    /// Defaults to `ts/dist` within the current working directory.
+    #[serde(default)]
+    pub js_dir: Option<String>,
+
+    /// Whether textual responses (HTML pages and JSON data) are compressed for clients that accept
+    /// gzip-encoded responses.
+    ///
+    /// Images and exported documents are never compressed.
+    #[serde(default = "default_true")]
+    pub compress_responses: bool,
+
+    /// The quality (1 to 100) at which uploaded JPEG images are re-encoded before they are stored.
+    ///
+    /// If not set, uploaded JPEG images are stored unchanged. Re-encoding only takes place if it
+    /// makes the image smaller; images that cannot be decoded are stored unchanged as well. Uploaded
+    /// TIFF images are always transcoded, at this quality if set.
+    #[serde(default)]
+    pub import_jpeg_quality: Option<u8>,
+
+    /// The horizontal pixel density, in dots per inch, assumed for JPEG images that only specify
+    /// their pixel aspect ratio instead of an absolute density.
+    #[serde(default = "default_density_dpi")]
+    pub default_density_dpi: u16,
+
+    /// The maximum number of annotations and artifacts (taken together) on a single page.
+    ///
+    /// Requests setting more annotations on a page are refused. If not set, the number is
+    /// unlimited.
+    #[serde(default)]
+    pub max_annotations_per_page: Option<usize>,
+
+    /// The comment written into the header of exported PDF files, following the version.
+    ///
+    /// If it does not contain at least four non-ASCII bytes, the conventional binary detection
+    /// comment is written before it. If not set, only the conventional comment is written.
+    #[serde(default)]
+    pub pdf_header_comment: Option<String>,
+
+    /// The name of the producing software stored in the metadata of exported PDF files.
+    ///
+    /// If not set, pdfmcr and its version are stored.
+    #[serde(default)]
+    pub pdf_producer: Option<String>,
+
+    /// The path to the TrueType font with which exported PDF files draw text that the standard
+    /// fonts cannot encode (e.g. Greek or Cyrillic text).
+    ///
+    /// Only the glyphs actually used are embedded. If not set, exporting documents containing such
+    /// text fails.
+    #[serde(default)]
+    pub fallback_font_path: Option<String>,
+
+    /// The version of PDF to which exported files conform, e.g. `"1.7"`.
+    ///
+    /// Exporting fails if a document uses features that this version does not support. Defaults to
+    /// 1.5.
+    #[serde(default)]
+    pub pdf_version: PdfVersion,
 }
 
 
+fn default_true() -> bool { true }
+fn default_density_dpi() -> u16 { pdfmcr::jpeg::DEFAULT_DENSITY_DPI }
+
+
 pub(crate) fn load_config() -> Option<Config> {
     let config_path = CONFIG_PATH.get()
         .expect("CONFIG_PATH not set?!");
@@ -34,5 +161,9 @@ pub(crate) fn load_config() -> Option<Config> {
             return None;
         },
     };
+    if config.pdf_header_comment.as_ref().is_some_and(|comment| comment.contains(['\r', '\n'])) {
+        error!("invalid config in {}: pdf_header_comment must not contain line breaks", config_path.display());
+        return None;
+    }
     Some(config)
 }
@@ -0,0 +1,52 @@
+use std::sync::OnceLock;
+
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+
+use crate::tenant::Tenant;
+
+
+/// The number of events buffered for each subscriber before it starts missing events.
+const EVENT_CAPACITY: usize = 64;
+
+static EVENT_SENDER: OnceLock<broadcast::Sender<(Tenant, DocumentEvent)>> = OnceLock::new();
+
+
+/// A change to a document, announced to all clients viewing it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum DocumentEvent {
+    /// A page has been appended to the document.
+    PageAdded { page: usize },
+
+    /// The image of a page has been replaced.
+    PageImageReplaced { page: usize },
+
+    /// The annotations or artifacts of a page have been changed.
+    AnnotationsChanged { page: usize },
+
+    /// A page has been marked as reviewed or not reviewed.
+    ReviewedChanged { page: usize },
+
+    /// A page has been moved to another position; the pages in between have shifted by one.
+    PageMoved { from: usize, to: usize },
+
+    /// The whole document has been replaced.
+    DocumentReplaced,
+}
+
+
+fn sender() -> &'static broadcast::Sender<(Tenant, DocumentEvent)> {
+    EVENT_SENDER.get_or_init(|| broadcast::channel(EVENT_CAPACITY).0)
+}
+
+/// Announces a change to the document of the given tenant.
+pub(crate) fn publish(tenant: &Tenant, event: DocumentEvent) {
+    // fails only if nobody is listening, which is fine
+    let _ = sender().send((tenant.clone(), event));
+}
+
+/// Subscribes to the changes of the documents of all tenants.
+pub(crate) fn subscribe() -> broadcast::Receiver<(Tenant, DocumentEvent)> {
+    sender().subscribe()
+}
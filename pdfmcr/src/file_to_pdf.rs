@@ -1,132 +1,1961 @@
 //! Conversion from pdfmcr files to PDF files.
 
 
-use std::collections::BTreeMap;
-use std::io::Write;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::path::Path;
 
-use crate::model::File;
+use strict_num::FiniteF64;
+use time::OffsetDateTime;
+
+use crate::image_path::{ContentHashAlgorithm, ImagePath};
+use crate::model::{BlockRole, ColorSpace, DEBUG_TEXT_GRAPHICS_STATE, DrawingContext, File, FontFamily, FontVariant, ImageEncoding};
 use crate::pdf::{
-    Catalog, Content, Document, ImageXObject, Page, PageContents, Pages, PdfId, StandardFont,
+    Catalog, CidFont, CidToGidMap, CompositeFont, Content, Document, ExtGState, FilterParameters, FontDescriptor,
+    FontFile2, IccProfile, ImageXObject, Info, OutputIntent, Page, PageContents, Pages, ParentTree, PdfId,
+    PdfVersion, StandardFont, StructElem, StructKid, StructTreeRoot, TextAnnotation, ToUnicodeCMap, XmpMetadata,
+    encode_win_ansi,
 };
+use crate::truetype;
 
 
-/// Converts a pdfmcr file to PDF.
-pub(crate) fn file_to_pdf(file: &File, image_base_path: &Path) -> Document {
-    // we'll go for the following structure:
-    // 1 = catalog
-    // 2 = page tree root with all pages
-    // 3 = Times Regular font
-    // 4 = Times Italic font
-    // 5 = Times Bold font
-    // 6 = Times BoldItalic font
-    // 7+3i = page
-    // 7+3i+1 = page content
-    // 7+3i+2 = scanned page background image
-
-    const COMMON_IDS: u64 = 6;
-    const IDS_PER_PAGE: u64 = 3;
+/// The name of the producing software stored in the metadata of documents by default.
+pub const DEFAULT_PRODUCER: &str = concat!("pdfmcr ", env!("CARGO_PKG_VERSION"));
 
-    let mut document = Document {
-        objects: BTreeMap::new(),
-    };
 
-    let catalog = Catalog {
-        root_pages_id: PdfId(2),
-        lang: file.default_language.clone(),
+/// Options influencing the conversion of a pdfmcr file to PDF.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ConversionOptions {
+    /// Whether each annotation is additionally emitted as an interactive text annotation.
+    ///
+    /// The text of the annotations is always rendered into the content stream of the page; this
+    /// option only controls whether viewers also show it as separate, interactive annotations.
+    pub interactive_annotations: bool,
+
+    /// The font family in which the text is rendered.
+    pub font_family: FontFamily,
+
+    /// Whether viewers are asked to interpolate the scanned images when scaling them.
+    ///
+    /// Disabling interpolation keeps the edges of scanned text crisp. Enabled by default.
+    pub interpolate_images: bool,
+
+    /// Whether RGB images are declared to be in the sRGB color space, using an embedded ICC
+    /// profile, instead of in the device-dependent RGB color space.
+    ///
+    /// The profile is generated by [`crate::icc::srgb_profile`]; it is only embedded if the
+    /// document contains RGB images.
+    pub srgb_images: bool,
+
+    /// Whether the data of each image is checked against the checksum encoded in its file name.
+    ///
+    /// Images whose file names do not follow the checksum naming scheme are not checked.
+    pub verify_image_checksums: bool,
+
+    /// The output intent embedded into the document, if any.
+    ///
+    /// PDF/A requires an output intent to be present.
+    pub output_intent: Option<OutputIntentProfile>,
+
+    /// The creation date stored in the metadata of the document.
+    ///
+    /// Omitted by default, so that converting the same file always yields the same document.
+    pub creation_date: Option<OffsetDateTime>,
+
+    /// The modification date stored in the metadata of the document.
+    ///
+    /// Omitted by default, so that converting the same file always yields the same document.
+    pub modification_date: Option<OffsetDateTime>,
+
+    /// The name of the producing software stored in the metadata of the document.
+    ///
+    /// Defaults to [`DEFAULT_PRODUCER`], which only changes between versions of pdfmcr and thereby
+    /// keeps the output reproducible. If `None`, no producer is stored.
+    pub producer: Option<String>,
+
+    /// Whether the text of the annotations is drawn visibly, for proofing their placement.
+    ///
+    /// The text is drawn in semi-transparent red over the scanned image.
+    pub debug_text: bool,
+
+    /// The maximum number of annotations and artifacts (taken together) on a single page.
+    ///
+    /// The content stream of each page is assembled in memory; the limit prevents pathological
+    /// pages from exhausting it. Unlimited by default.
+    pub max_annotations_per_page: Option<usize>,
+
+    /// The comment written into the header of the document; see [`Document::header_comment`].
+    pub header_comment: Option<Vec<u8>>,
+
+    /// Whether the placement of the scanned image and the text of each page are written into
+    /// separate content streams.
+    ///
+    /// This allows tools to replace the text layer of a page without touching the placement of its
+    /// image. Pages without a scanned image always have a single content stream.
+    pub separate_text_stream: bool,
+
+    /// The data of the TrueType font with which text is drawn that the standard fonts cannot encode.
+    ///
+    /// Only the glyphs actually used are embedded into the document. Converting such text fails if
+    /// no fallback font is given.
+    pub fallback_font: Option<Vec<u8>>,
+
+    /// The version of PDF to which the document conforms.
+    ///
+    /// Writing the document fails if it uses features that are not supported by this version.
+    pub pdf_version: PdfVersion,
+}
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            interactive_annotations: false,
+            font_family: FontFamily::default(),
+            interpolate_images: true,
+            srgb_images: false,
+            verify_image_checksums: false,
+            output_intent: None,
+            creation_date: None,
+            modification_date: None,
+            producer: Some(DEFAULT_PRODUCER.to_owned()),
+            debug_text: false,
+            max_annotations_per_page: None,
+            header_comment: None,
+            separate_text_stream: false,
+            fallback_font: None,
+            pdf_version: PdfVersion::default(),
+        }
+    }
+}
+
+
+/// An ICC profile describing the device on which a document is intended to be reproduced.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct OutputIntentProfile {
+    /// The identifier of the output condition, e.g. `sRGB IEC61966-2.1`.
+    pub identifier: String,
+
+    /// The number of color components described by the profile (1, 3 or 4).
+    pub components: u8,
+
+    /// The data of the ICC profile.
+    pub profile_data: Vec<u8>,
+}
+
+
+/// An error that can occur while converting a pdfmcr file to PDF.
+#[derive(Debug)]
+pub enum ConversionError {
+    /// The image of the page with the given index could not be read.
+    ImageIo { page_index: usize, error: io::Error },
+
+    /// The data of the image of the page with the given index does not match its checksum.
+    ImageChecksumMismatch { page_index: usize, file_path: ImagePath },
+
+    /// The page with the given index has more annotations and artifacts than the configured limit.
+    TooManyAnnotations { page_index: usize, count: usize, limit: usize },
+
+    /// The given dimension of the page with the given index cannot be converted into points, e.g.
+    /// because the density unit of its image is [`NoUnit`](crate::model::DensityUnit::NoUnit).
+    UnknownPageSize { page_index: usize, dimension: &'static str },
+
+    /// The text which cannot be encoded for the standard fonts contains more distinct characters
+    /// than the fallback fonts can hold.
+    TooManyFallbackCharacters { count: usize },
+
+    /// Some text cannot be encoded for the standard fonts, but no fallback font has been given.
+    NoFallbackFont,
+
+    /// The fallback font could not be read.
+    FallbackFont(truetype::Error),
+}
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ImageIo { page_index, error }
+                => write!(f, "failed to read image of page {}: {}", page_index, error),
+            Self::ImageChecksumMismatch { page_index, file_path }
+                => write!(f, "image {:?} of page {} does not match its checksum", file_path.as_str(), page_index),
+            Self::TooManyAnnotations { page_index, count, limit }
+                => write!(f, "page {} has {} annotations and artifacts, more than the limit of {}", page_index, count, limit),
+            Self::UnknownPageSize { page_index, dimension }
+                => write!(f, "the {} of page {} cannot be converted into points", dimension, page_index),
+            Self::TooManyFallbackCharacters { count }
+                => write!(f, "{} distinct characters need the fallback font, more than the limit of {}", count, u16::MAX),
+            Self::NoFallbackFont
+                => write!(f, "some text cannot be encoded for the standard fonts and no fallback font is configured"),
+            Self::FallbackFont(e)
+                => write!(f, "failed to read fallback font: {}", e),
+        }
+    }
+}
+impl std::error::Error for ConversionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ImageIo { error, .. } => Some(error),
+            Self::ImageChecksumMismatch { .. } => None,
+            Self::TooManyAnnotations { .. } => None,
+            Self::UnknownPageSize { .. } => None,
+            Self::TooManyFallbackCharacters { .. } => None,
+            Self::NoFallbackFont => None,
+            Self::FallbackFont(e) => Some(e),
+        }
+    }
+}
+
+
+/// Derives the tag identifying a font subset from the glyphs it contains.
+///
+/// The tag consists of six uppercase letters; it is prefixed to the name of the font, separated by
+/// a plus sign, so that viewers do not confuse the subset with the full font.
+fn subset_tag(glyph_ids: &BTreeSet<u16>) -> String {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    for glyph_id in glyph_ids {
+        hasher.update(glyph_id.to_be_bytes());
+    }
+    hasher.finalize()[..6].iter()
+        .map(|b| char::from(b'A' + b % 26))
+        .collect()
+}
+
+
+/// Checks whether the data of the image at the given path matches the checksum encoded in its name.
+///
+/// As the name does not record the algorithm with which the checksum was computed, the checksum
+/// may have been computed with any of them; all are computed in a single pass over the data.
+fn image_matches_checksum(file_path: &ImagePath, os_path: &Path) -> Result<bool, io::Error> {
+    let Ok(content_name) = file_path.content_name() else {
+        // nothing to check against
+        return Ok(true);
     };
-    document.objects.insert(PdfId(1), Content::Catalog(catalog));
 
-    let pages_children = (0..file.pages.len()).into_iter()
-        .map(|page_index| u64::try_from(page_index).unwrap())
-        .map(|page_index| PdfId(1 + COMMON_IDS + IDS_PER_PAGE*page_index))
+    let mut image_file = std::fs::File::open(os_path)?;
+    if image_file.metadata()?.len() != content_name.size {
+        return Ok(false);
+    }
+
+    let mut hashers: Vec<_> = ContentHashAlgorithm::ALL.iter()
+        .map(|algorithm| algorithm.new_hasher())
         .collect();
-    let root_pages = Pages {
-        children: pages_children,
-    };
-    document.objects.insert(PdfId(2), Content::Pages(root_pages));
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let bytes_read = image_file.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        for hasher in &mut hashers {
+            hasher.update(&buf[..bytes_read]);
+        }
+    }
 
-    let times_regular = StandardFont { name: "Times-Regular".to_owned() };
-    let times_italic = StandardFont { name: "Times-Italic".to_owned() };
-    let times_bold = StandardFont { name: "Times-Bold".to_owned() };
-    let times_bold_italic = StandardFont { name: "Times-BoldItalic".to_owned() };
-    document.objects.insert(PdfId(3), Content::StandardFont(times_regular));
-    document.objects.insert(PdfId(4), Content::StandardFont(times_italic));
-    document.objects.insert(PdfId(5), Content::StandardFont(times_bold));
-    document.objects.insert(PdfId(6), Content::StandardFont(times_bold_italic));
+    let mut digest = [0u8; 64];
+    for hasher in &mut hashers {
+        hasher.finalize_into_reset(&mut digest)
+            .expect("digest has unexpected length");
+        if digest == content_name.digest {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Adds the structure element of a list with the given items to the document.
+fn insert_list(document: &mut Document, list_id: PdfId, parent: PdfId, items: Vec<StructKid>) {
+    document.objects.insert(
+        list_id,
+        Content::StructElem(StructElem {
+            structure_type: "L".to_owned(),
+            parent,
+            page: None,
+            kids: items,
+            lang: None,
+        }),
+    );
+}
 
-    let mut font_refs = BTreeMap::new();
-    font_refs.insert("F0".to_owned(), PdfId(3));
-    font_refs.insert("F1".to_owned(), PdfId(4));
-    font_refs.insert("F2".to_owned(), PdfId(5));
-    font_refs.insert("F3".to_owned(), PdfId(6));
+
+/// The objects shared by all pages of a document.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SharedObjects<'a> {
+    /// The ID of the page tree node to which the pages belong.
+    pub pages_root: PdfId,
+
+    /// The fonts available to the page contents, by resource name (`F0` to `F3` for the standard
+    /// fonts by font variant and `FU0` to `FU3`, which all refer to the fallback font).
+    pub font_refs: BTreeMap<String, PdfId>,
+
+    /// The graphics state parameter dictionaries available to the page contents, by resource name.
+    pub ext_g_state_refs: BTreeMap<String, PdfId>,
+
+    /// The ID of the structure element to which the structure elements of the annotations belong.
+    pub structure_parent: PdfId,
+
+    /// The ID of the ICC profile with which RGB images are interpreted, if any.
+    pub rgb_icc_profile: Option<PdfId>,
+
+    /// The document-wide settings influencing how annotations are drawn.
+    pub drawing_context: DrawingContext<'a>,
+}
+
+
+/// The PDF objects into which the pages of a pdfmcr file have been converted.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ConvertedPages {
+    /// The IDs of the pages, in order.
+    pub page_ids: Vec<PdfId>,
+
+    /// The IDs of the topmost structure elements of the annotations, in reading order.
+    ///
+    /// These are the elements of the annotations themselves, except for list items, which are
+    /// represented by the lists containing them.
+    pub structure_elements: Vec<PdfId>,
+
+    /// The entries of the parent tree for the pages, keyed by the `/StructParents` value of each page.
+    pub parent_tree_entries: BTreeMap<u64, Vec<PdfId>>,
+
+    /// The next ID that has not been allocated.
+    pub next_free_id: u64,
+}
+
+
+/// Converts the pages of a pdfmcr file to PDF objects, allocating IDs starting at `id_base`.
+///
+/// The objects are added to `document`; the pages are not added to the page tree, this is the
+/// responsibility of the caller. Likewise, the structure elements of the annotations are returned
+/// to be attached to the structure tree and the parent tree by the caller.
+///
+/// This allows combining the pages of multiple files into one document.
+pub fn file_to_pdf_with_id_base(
+    file: &File,
+    image_base_path: &Path,
+    options: &ConversionOptions,
+    shared: &SharedObjects,
+    document: &mut Document,
+    id_base: u64,
+) -> Result<ConvertedPages, ConversionError> {
+    // we'll go for the following structure:
+    // base+3i = page
+    // base+3i+1 = page content (or only the placement of the image, if the text is separate)
+    // base+3i+2 = scanned page background image (unless the page is text-only)
+    // base+3n+j = interactive annotation (if requested), structure element of each annotation and
+    //             text content stream (if separate)
+    //
+    // the ID of each page doubles as its /StructParents key, which keeps the keys unique even if
+    // the pages of multiple files are combined
+
+    const IDS_PER_PAGE: u64 = 3;
+
+    let page_count: u64 = file.pages.len().try_into().unwrap();
+    let page_ids = (0..page_count)
+        .map(|page_index| PdfId(id_base + IDS_PER_PAGE*page_index))
+        .collect();
+    let mut next_free_id = id_base + IDS_PER_PAGE*page_count;
+    let mut structure_elements = Vec::new();
+    let mut parent_tree_entries = BTreeMap::new();
+
+    if let Some(limit) = options.max_annotations_per_page {
+        for (page_index, page) in file.pages.iter().enumerate() {
+            let count = page.annotations.len() + page.artifacts.len();
+            if count > limit {
+                return Err(ConversionError::TooManyAnnotations { page_index, count, limit });
+            }
+        }
+    }
+
+    // whether text is visible is a property of each file
+    let drawing_context = DrawingContext {
+        visible_text: file.visible_text,
+        ..shared.drawing_context
+    };
 
     for (page_index_usize, page) in file.pages.iter().enumerate() {
         let page_index: u64 = page_index_usize.try_into().unwrap();
-        let page_pdf_id = 1 + COMMON_IDS + IDS_PER_PAGE*page_index;
+        let page_pdf_id = id_base + IDS_PER_PAGE*page_index;
 
-        let width_pt = page.scanned_image.info.density_unit.try_to_points(
-            page.scanned_image.info.width,
-            page.scanned_image.info.density_x,
-        ).unwrap();
-        let height_pt = page.scanned_image.info.density_unit.try_to_points(
-            page.scanned_image.info.height,
-            page.scanned_image.info.density_y,
-        ).unwrap();
+        let width_pt = page.width_pt()
+            .ok_or(ConversionError::UnknownPageSize { page_index: page_index_usize, dimension: "width" })?;
+        let height_pt = page.height_pt()
+            .ok_or(ConversionError::UnknownPageSize { page_index: page_index_usize, dimension: "height" })?;
+        let margins = page.margins_pt.unwrap_or_default();
+        let media_width_pt = FiniteF64::new(width_pt + (margins.left + margins.right) as f64)
+            .ok_or(ConversionError::UnknownPageSize { page_index: page_index_usize, dimension: "width" })?;
+        let media_height_pt = FiniteF64::new(height_pt + (margins.bottom + margins.top) as f64)
+            .ok_or(ConversionError::UnknownPageSize { page_index: page_index_usize, dimension: "height" })?;
 
         let mut xobject_refs = BTreeMap::new();
-        xobject_refs.insert(
-            "Im0".to_owned(),
-            PdfId(page_pdf_id + 2),
-        );
+        if page.scanned_image.is_some() {
+            xobject_refs.insert(
+                "Im0".to_owned(),
+                PdfId(page_pdf_id + 2),
+            );
+        }
+
+        let annotations = page.annotations_in_reading_order();
+
+        let mut annotation_ids = Vec::new();
+        if options.interactive_annotations {
+            for annotation in &annotations {
+                let font_size_pt = annotation.font_size.get().ceil() as u64;
+                let text_annotation = TextAnnotation {
+                    rect: [
+                        margins.left + annotation.left,
+                        margins.bottom + annotation.bottom,
+                        margins.left + annotation.left + font_size_pt,
+                        margins.bottom + annotation.bottom + font_size_pt,
+                    ],
+                    contents: annotation.plain_text(),
+                };
+                let annotation_id = PdfId(next_free_id);
+                next_free_id += 1;
+                document.objects.insert(
+                    annotation_id,
+                    Content::TextAnnotation(text_annotation),
+                );
+                annotation_ids.push(annotation_id);
+            }
+        }
+
+        // each annotation is a structure element of its block role (a paragraph unless specified),
+        // referencing its marked content; consecutive list items are gathered into a list
+        let mut page_structure_elements = Vec::with_capacity(annotations.len());
+        let mut page_top_elements = Vec::new();
+        let mut open_list: Option<(PdfId, Vec<StructKid>)> = None;
+        for (mcid, annotation) in annotations.iter().enumerate() {
+            let mcid: u64 = mcid.try_into().unwrap();
+            let block_role = annotation.block_role.unwrap_or(BlockRole::Paragraph);
+            // if all the chunks share a language, announce it on the element as well; the chunks
+            // keep their own language spans
+            let structure_language = annotation.common_language().map(|l| l.to_owned());
+            let element_id = PdfId(next_free_id);
+            next_free_id += 1;
+
+            if block_role != BlockRole::ListItem {
+                if let Some((list_id, list_kids)) = open_list.take() {
+                    insert_list(document, list_id, shared.structure_parent, list_kids);
+                }
+                document.objects.insert(
+                    element_id,
+                    Content::StructElem(StructElem {
+                        structure_type: block_role.as_structure_type().to_owned(),
+                        parent: shared.structure_parent,
+                        page: Some(PdfId(page_pdf_id)),
+                        kids: vec![StructKid::MarkedContent(mcid)],
+                        lang: structure_language,
+                    }),
+                );
+                page_top_elements.push(element_id);
+                page_structure_elements.push(element_id);
+                continue;
+            }
+
+            // list item: L > LI > LBody > marked content
+            let (list_id, list_kids) = open_list.get_or_insert_with(|| {
+                let list_id = PdfId(next_free_id);
+                next_free_id += 1;
+                page_top_elements.push(list_id);
+                (list_id, Vec::new())
+            });
+            let item_id = PdfId(next_free_id);
+            next_free_id += 1;
+            document.objects.insert(
+                item_id,
+                Content::StructElem(StructElem {
+                    structure_type: block_role.as_structure_type().to_owned(),
+                    parent: *list_id,
+                    page: None,
+                    kids: vec![StructKid::Element(element_id)],
+                    lang: None,
+                }),
+            );
+            document.objects.insert(
+                element_id,
+                Content::StructElem(StructElem {
+                    structure_type: block_role.as_marked_content_tag().to_owned(),
+                    parent: item_id,
+                    page: Some(PdfId(page_pdf_id)),
+                    kids: vec![StructKid::MarkedContent(mcid)],
+                    lang: structure_language,
+                }),
+            );
+            list_kids.push(StructKid::Element(item_id));
+            page_structure_elements.push(element_id);
+        }
+        if let Some((list_id, list_kids)) = open_list.take() {
+            insert_list(document, list_id, shared.structure_parent, list_kids);
+        }
+        let struct_parents = if !page_structure_elements.is_empty() {
+            structure_elements.extend(page_top_elements);
+            parent_tree_entries.insert(page_pdf_id, page_structure_elements);
+            Some(page_pdf_id)
+        } else {
+            None
+        };
+
+        let separate_text_id = if options.separate_text_stream && page.scanned_image.is_some() {
+            let text_id = PdfId(next_free_id);
+            next_free_id += 1;
+            Some(text_id)
+        } else {
+            None
+        };
+        let mut contents = vec![PdfId(page_pdf_id + 1)];
+        contents.extend(separate_text_id);
 
         let pdf_page = Page {
-            parent: PdfId(2),
-            width_pt,
-            height_pt,
-            contents: Some(PdfId(page_pdf_id + 1)),
+            parent: shared.pages_root,
+            width_pt: media_width_pt,
+            height_pt: media_height_pt,
+            contents,
             xobject_refs,
-            font_refs: font_refs.clone(),
+            font_refs: shared.font_refs.clone(),
+            ext_g_state_refs: shared.ext_g_state_refs.clone(),
+            annotations: annotation_ids,
+            struct_parents,
         };
         document.objects.insert(
             PdfId(page_pdf_id),
             Content::Page(pdf_page),
         );
 
+        // the text of chunks with marked-content properties is wrapped in a Span sequence
+        let text_version = if page.text_chunks().any(|chunk| chunk.needs_span(drawing_context.document_language)) {
+            PdfVersion::V1_5
+        } else {
+            PdfVersion::V1_4
+        };
+
         let mut commands = Vec::new();
-        // place the image, then the annotations, then the artifacts
-        write!(commands, "q {} 0 0 {} 0 0 cm/Im0 Do Q", width_pt, height_pt).unwrap();
-        for annotation in &page.annotations {
-            annotation.write_drawing_commands(&mut commands).unwrap();
+        // place the image (if any), then the annotations, then the artifacts
+        // (the scanned image is not part of the logical structure; the annotations describe it)
+        // (the margins move everything inward; the annotations are translated as a whole)
+        if page.scanned_image.is_some() {
+            write!(
+                commands, "/Artifact BMC q {} 0 0 {} {} {} cm/Im0 Do Q EMC",
+                width_pt, height_pt, margins.left, margins.bottom,
+            ).unwrap();
+        }
+        if separate_text_id.is_some() {
+            let image_content = PageContents {
+                commands: std::mem::take(&mut commands),
+                minimum_version: PdfVersion::V1_4,
+            };
+            document.objects.insert(
+                PdfId(page_pdf_id + 1),
+                Content::PageContents(image_content),
+            );
+        }
+        let translate_annotations = margins.left != 0 || margins.bottom != 0;
+        if translate_annotations {
+            write!(commands, " q 1 0 0 1 {} {} cm", margins.left, margins.bottom).unwrap();
+        }
+        for (mcid, annotation) in annotations.iter().enumerate() {
+            let tag = annotation.block_role.unwrap_or(BlockRole::Paragraph).as_marked_content_tag();
+            write!(commands, "/{}<</MCID {}>>BDC", tag, mcid).unwrap();
+            annotation.write_drawing_commands(&mut commands, &drawing_context).unwrap();
+            commands.extend_from_slice(b" EMC");
         }
         for artifact in &page.artifacts {
-            artifact.write_drawing_commands(&mut commands).unwrap();
+            artifact.write_drawing_commands(&mut commands, &drawing_context).unwrap();
+        }
+        if translate_annotations {
+            commands.extend_from_slice(b" Q");
         }
         let content = PageContents {
             commands,
+            minimum_version: text_version,
         };
         document.objects.insert(
-            PdfId(page_pdf_id + 1),
+            separate_text_id.unwrap_or(PdfId(page_pdf_id + 1)),
             Content::PageContents(content),
         );
 
+        let Some(scanned_image) = page.scanned_image.as_ref() else {
+            // text-only page
+            continue;
+        };
+
         // convert the image path into an operating system path
-        let os_path = page.scanned_image.file_path.to_os_path(image_base_path);
+        let os_path = scanned_image.file_path.to_os_path(image_base_path);
+
+        if options.verify_image_checksums {
+            let matches = image_matches_checksum(&scanned_image.file_path, &os_path)
+                .map_err(|error| ConversionError::ImageIo { page_index: page_index_usize, error })?;
+            if !matches {
+                return Err(ConversionError::ImageChecksumMismatch {
+                    page_index: page_index_usize,
+                    file_path: scanned_image.file_path.clone(),
+                });
+            }
+        }
 
+        // the metadata was obtained when the image was uploaded; trust it instead of parsing the
+        // image again
+        let decode = if scanned_image.info.inverted {
+            [1, 0].repeat(scanned_image.info.color_space.component_count())
+        } else {
+            Vec::new()
+        };
+        let encoding_parameters = match scanned_image.info.encoding {
+            ImageEncoding::Jpeg => None,
+            ImageEncoding::CcittGroup4 { black_is_1 } => Some(FilterParameters::CcittFax {
+                k: -1,
+                columns: scanned_image.info.width.into(),
+                rows: scanned_image.info.height.into(),
+                black_is_1,
+            }),
+        };
+        let data_filters = scanned_image.effective_data_filters();
+        // the parameters derived from the encoding belong to the filter decoding it
+        let filter_parameters = data_filters.iter()
+            .map(|data_filter|
+                if data_filter == scanned_image.info.encoding.pdf_filter_name() {
+                    encoding_parameters.clone()
+                } else {
+                    None
+                }
+            )
+            .collect();
         let image = ImageXObject {
-            width: page.scanned_image.info.width.into(),
-            height: page.scanned_image.info.height.into(),
-            color_space: page.scanned_image.info.color_space.as_pdf_name(),
-            bits_per_component: page.scanned_image.info.bit_depth,
-            interpolate: true,
-            data_filters: vec!["DCTDecode".to_owned()],
+            width: scanned_image.info.width.into(),
+            height: scanned_image.info.height.into(),
+            color_space: scanned_image.info.color_space.as_pdf_name(),
+            icc_profile: match scanned_image.info.color_space {
+                ColorSpace::Rgb => shared.rgb_icc_profile,
+                _ => None,
+            },
+            image_mask: false,
+            bits_per_component: scanned_image.info.bit_depth,
+            decode,
+            interpolate: options.interpolate_images,
+            data_filters,
+            filter_parameters,
             os_path,
         };
         document.objects.insert(
-            PdfId(page_pdf_id + 1),
+            PdfId(page_pdf_id + 2),
             Content::ImageXObject(image),
         );
     }
 
-    document
+    Ok(ConvertedPages {
+        page_ids,
+        structure_elements,
+        parent_tree_entries,
+        next_free_id,
+    })
+}
+
+
+/// Converts a pdfmcr file to PDF.
+pub fn file_to_pdf(file: &File, image_base_path: &Path, options: &ConversionOptions) -> Result<Document, ConversionError> {
+    merge_files_to_pdf(&[(file, image_base_path)], options)
+}
+
+
+/// Converts multiple pdfmcr files into one PDF, appending their pages one after the other.
+///
+/// Each file is given with the path of the directory containing its images. The document language
+/// is only declared if all files share the same default language. The standard fonts are written
+/// once and shared by the pages of all the files.
+pub fn merge_files_to_pdf(files: &[(&File, &Path)], options: &ConversionOptions) -> Result<Document, ConversionError> {
+    // we'll go for the following structure:
+    // 1 = catalog
+    // 2 = page tree root with all pages
+    // 3 = regular font
+    // 4 = italic font
+    // 5 = bold font
+    // 6 = bold italic font
+    // 7 = structure tree root
+    // 8 = document structure element
+    // 9 = parent tree
+    // 10 = graphics state for debugging text (if requested)
+    // 10 or 11... = ToUnicode map, composite font, CID font, font descriptor, embedded font program
+    //               and CIDToGIDMap of the fallback font (if any text cannot be encoded for the
+    //               standard fonts)
+    // followed by the objects of the pages of each file (see file_to_pdf_with_id_base)
+    // followed by the ICC profile and the output intent (if requested)
+    // followed by the XMP metadata stream and the document information dictionary (if any
+    // metadata is given)
+
+    const COMMON_IDS: u64 = 9;
+
+    let mut document = Document {
+        version: options.pdf_version,
+        header_comment: options.header_comment.clone(),
+        objects: BTreeMap::new(),
+    };
+
+    let mut languages = files.iter()
+        .map(|(file, _image_base_path)| file.effective_default_language());
+    let first_language = languages.next().flatten();
+    let document_language = if languages.all(|language| language == first_language) {
+        first_language
+    } else {
+        None
+    };
+
+    // likewise, the title is only declared if all files share it
+    let mut titles = files.iter()
+        .map(|(file, _image_base_path)| file.title.as_deref());
+    let first_title = titles.next().flatten();
+    let title = if titles.all(|title| title == first_title) {
+        first_title
+    } else {
+        None
+    };
+
+    let mut font_refs = BTreeMap::new();
+    for variant in FontVariant::ALL {
+        let font_id = PdfId(3 + u64::from(variant.as_index()));
+        let font = StandardFont {
+            name: options.font_family.standard_font_name(variant).to_owned(),
+        };
+        document.objects.insert(font_id, Content::StandardFont(font));
+        font_refs.insert(format!("F{}", variant.as_index()), font_id);
+    }
+
+    let mut next_free_id = 1 + COMMON_IDS;
+
+    let mut ext_g_state_refs = BTreeMap::new();
+    if options.debug_text {
+        let state_id = PdfId(next_free_id);
+        next_free_id += 1;
+        document.objects.insert(
+            state_id,
+            Content::ExtGState(ExtGState {
+                fill_opacity_percent: Some(50),
+            }),
+        );
+        ext_g_state_refs.insert(DEBUG_TEXT_GRAPHICS_STATE.to_owned(), state_id);
+    }
+
+    // the characters of text chunks which the standard fonts cannot encode are numbered for the
+    // fallback fonts; 0 is the .notdef glyph
+    let fallback_chars: BTreeSet<char> = files.iter()
+        .flat_map(|(file, _image_base_path)| file.pages.iter())
+        .flat_map(|page| page.text_chunks())
+        .filter(|chunk| encode_win_ansi(&chunk.text).is_none())
+        .flat_map(|chunk| chunk.text.chars())
+        .collect();
+    if fallback_chars.len() > usize::from(u16::MAX) {
+        return Err(ConversionError::TooManyFallbackCharacters { count: fallback_chars.len() });
+    }
+    let fallback_codes: BTreeMap<char, u16> = fallback_chars.into_iter()
+        .zip(1..)
+        .collect();
+    if !fallback_codes.is_empty() {
+        let to_unicode_id = PdfId(next_free_id);
+        next_free_id += 1;
+        document.objects.insert(
+            to_unicode_id,
+            Content::ToUnicodeCMap(ToUnicodeCMap {
+                mappings: fallback_codes.iter()
+                    .map(|(c, code)| (*code, *c))
+                    .collect(),
+            }),
+        );
+
+        let font_data = options.fallback_font.as_deref()
+            .ok_or(ConversionError::NoFallbackFont)?;
+        let font = truetype::Font::parse(font_data)
+            .map_err(ConversionError::FallbackFont)?;
+
+        // the codes are assigned in the order of the characters; characters missing from the font
+        // are drawn as .notdef
+        let glyph_ids: Vec<u16> = std::iter::once(0)
+            .chain(fallback_codes.keys().map(|c| font.glyph_id(*c).unwrap_or(0)))
+            .collect();
+        let used_glyph_ids: BTreeSet<u16> = glyph_ids.iter().copied().collect();
+        let subset_data = font.subset(&used_glyph_ids)
+            .map_err(ConversionError::FallbackFont)?;
+        let name = format!("{}+{}", subset_tag(&used_glyph_ids), font.postscript_name);
+
+        let font_id = PdfId(next_free_id);
+        let cid_font_id = PdfId(next_free_id + 1);
+        let descriptor_id = PdfId(next_free_id + 2);
+        let font_file_id = PdfId(next_free_id + 3);
+        let cid_to_gid_map_id = PdfId(next_free_id + 4);
+        next_free_id += 5;
+
+        let mut flags = 4; // symbolic
+        if font.is_fixed_pitch {
+            flags |= 1;
+        }
+        if font.italic_angle != 0 {
+            flags |= 64;
+        }
+        // estimated from the weight as there is no better source in TrueType fonts
+        let stem_v = 10 + 220 * (i32::from(font.weight_class) - 50).max(0) / 900;
+
+        document.objects.insert(
+            font_id,
+            Content::CompositeFont(CompositeFont {
+                name: name.clone(),
+                descendant: cid_font_id,
+                to_unicode: to_unicode_id,
+            }),
+        );
+        document.objects.insert(
+            cid_font_id,
+            Content::CidFont(CidFont {
+                name: name.clone(),
+                descriptor: descriptor_id,
+                cid_to_gid_map: cid_to_gid_map_id,
+                default_width: font.advance_width(0),
+                widths: glyph_ids[1..].iter()
+                    .map(|glyph_id| font.advance_width(*glyph_id))
+                    .collect(),
+            }),
+        );
+        document.objects.insert(
+            descriptor_id,
+            Content::FontDescriptor(FontDescriptor {
+                name,
+                flags,
+                bounding_box: font.bounding_box.map(|value| font.to_glyph_space(value.into())),
+                italic_angle: font.italic_angle,
+                ascent: font.to_glyph_space(font.ascent.into()),
+                descent: font.to_glyph_space(font.descent.into()),
+                cap_height: font.to_glyph_space(font.cap_height.into()),
+                stem_v,
+                font_file: font_file_id,
+            }),
+        );
+        document.objects.insert(
+            font_file_id,
+            Content::FontFile2(FontFile2 {
+                data: subset_data,
+            }),
+        );
+        document.objects.insert(
+            cid_to_gid_map_id,
+            Content::CidToGidMap(CidToGidMap {
+                glyph_ids,
+            }),
+        );
+
+        // one font serves all variants
+        for variant in FontVariant::ALL {
+            font_refs.insert(format!("FU{}", variant.as_index()), font_id);
+        }
+    }
+
+    let has_rgb_images = files.iter()
+        .flat_map(|(file, _image_base_path)| file.pages.iter())
+        .filter_map(|page| page.scanned_image.as_ref())
+        .any(|scanned_image| scanned_image.info.color_space == ColorSpace::Rgb);
+    let rgb_icc_profile = if options.srgb_images && has_rgb_images {
+        let profile_id = PdfId(next_free_id);
+        next_free_id += 1;
+        document.objects.insert(
+            profile_id,
+            Content::IccProfile(IccProfile {
+                components: 3,
+                data: crate::icc::srgb_profile(),
+            }),
+        );
+        Some(profile_id)
+    } else {
+        None
+    };
+
+    let shared = SharedObjects {
+        pages_root: PdfId(2),
+        font_refs,
+        ext_g_state_refs,
+        structure_parent: PdfId(8),
+        rgb_icc_profile,
+        drawing_context: DrawingContext {
+            document_language,
+            debug_text: options.debug_text,
+            visible_text: false,
+            fallback_codes: Some(&fallback_codes),
+        },
+    };
+
+    let mut pages_children = Vec::new();
+    let mut structure_elements = Vec::new();
+    let mut parent_tree = ParentTree::default();
+    for (file, image_base_path) in files {
+        let converted = file_to_pdf_with_id_base(
+            file,
+            image_base_path,
+            options,
+            &shared,
+            &mut document,
+            next_free_id,
+        )?;
+        pages_children.extend(converted.page_ids);
+        structure_elements.extend(converted.structure_elements.into_iter().map(StructKid::Element));
+        parent_tree.entries.extend(converted.parent_tree_entries);
+        next_free_id = converted.next_free_id;
+    }
+
+    let root_pages = Pages {
+        page_count: pages_children.len().try_into().unwrap(),
+        children: pages_children,
+    };
+    document.objects.insert(PdfId(2), Content::Pages(root_pages));
+
+    let parent_tree_next_key = parent_tree.entries.keys()
+        .next_back()
+        .map(|key| key + 1)
+        .unwrap_or(0);
+    document.objects.insert(
+        PdfId(7),
+        Content::StructTreeRoot(StructTreeRoot {
+            kids: vec![PdfId(8)],
+            parent_tree: PdfId(9),
+            parent_tree_next_key,
+        }),
+    );
+    document.objects.insert(
+        PdfId(8),
+        Content::StructElem(StructElem {
+            structure_type: "Document".to_owned(),
+            parent: PdfId(7),
+            page: None,
+            kids: structure_elements,
+            lang: None,
+        }),
+    );
+    document.objects.insert(PdfId(9), Content::ParentTree(parent_tree));
+
+    let mut output_intents = Vec::new();
+    if let Some(output_intent) = options.output_intent.as_ref() {
+        let profile_id = PdfId(next_free_id);
+        let intent_id = PdfId(next_free_id + 1);
+        document.objects.insert(
+            profile_id,
+            Content::IccProfile(IccProfile {
+                components: output_intent.components,
+                data: output_intent.profile_data.clone(),
+            }),
+        );
+        document.objects.insert(
+            intent_id,
+            Content::OutputIntent(OutputIntent {
+                subtype: "GTS_PDFA1".to_owned(),
+                output_condition_identifier: output_intent.identifier.clone(),
+                dest_output_profile: profile_id,
+            }),
+        );
+        output_intents.push(intent_id);
+        next_free_id += 2;
+    }
+
+    let mut metadata = None;
+    if title.is_some() || options.creation_date.is_some() || options.modification_date.is_some() || options.producer.is_some() {
+        let info = Info {
+            title: title.map(|t| t.to_owned()),
+            creation_date: options.creation_date,
+            mod_date: options.modification_date,
+            producer: options.producer.clone(),
+        };
+        let metadata_id = PdfId(next_free_id);
+        let info_id = PdfId(next_free_id + 1);
+        document.objects.insert(metadata_id, Content::XmpMetadata(XmpMetadata { info: info.clone() }));
+        document.objects.insert(info_id, Content::Info(info));
+        metadata = Some(metadata_id);
+    }
+
+    let catalog = Catalog {
+        root_pages_id: PdfId(2),
+        lang: document_language.map(|l| l.to_owned()),
+        output_intents,
+        structure_tree_root: Some(PdfId(7)),
+        metadata,
+        display_doc_title: title.is_some(),
+    };
+    document.objects.insert(PdfId(1), Content::Catalog(catalog));
+
+    Ok(document)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use sha3::{Digest, Sha3_512};
+    use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+    use super::*;
+    use crate::builder::FileBuilder;
+    use crate::image_path::ContentName;
+    use crate::model::{
+        Annotation, BlockRole, ColorSpace, DensityUnit, FontVariant, ImageEncoding, JpegImage, JpegImageInfo,
+        PageMargins, TextChunk,
+    };
+    use crate::pdf::Object;
+
+    fn sample_page() -> crate::model::Page {
+        crate::model::Page::new(JpegImage {
+            info: JpegImageInfo {
+                bit_depth: 8,
+                width: 600,
+                height: 900,
+                color_space: ColorSpace::Grayscale,
+                density_unit: DensityUnit::DotsPerInch,
+                density_x: 300,
+                density_y: 300,
+                inverted: false,
+                encoding: ImageEncoding::Jpeg,
+            },
+            file_path: "page.jpeg".parse().unwrap(),
+            data_filters: None,
+        })
+    }
+
+    fn sample_annotation(text: &str) -> Annotation {
+        Annotation {
+            left: 10,
+            bottom: 20,
+            font_size: NonZeroPositiveF64::new(12.0).unwrap(),
+            leading: FiniteF64::new(0.0).unwrap(),
+            elements: vec![
+                TextChunk {
+                    text: text.to_owned(),
+                    font_variant: FontVariant::Regular,
+                    character_spacing: FiniteF64::new(0.0).unwrap(),
+                    word_spacing: FiniteF64::new(0.0).unwrap(),
+                    horizontal_scale: None,
+                    text_rise: None,
+                    language: None,
+                    alternate_text: None,
+                    actual_text: None,
+                    expansion: None,
+                },
+            ],
+            order: None,
+            block_role: None,
+        }
+    }
+
+    fn write_to_lopdf(document: &Document) -> lopdf::Document {
+        let mut pdf_bytes = std::io::Cursor::new(Vec::new());
+        document.write_pdf(&mut pdf_bytes).unwrap();
+        lopdf::Document::load_mem(pdf_bytes.get_ref()).unwrap()
+    }
+
+    fn written_object(document: &Document, id: u64) -> String {
+        let mut buf = Vec::new();
+        document.objects[&PdfId(id)].write_content(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    #[test]
+    fn emits_interactive_annotations_only_if_requested() {
+        let mut page = sample_page();
+        page.annotations.push(sample_annotation("Hello"));
+        page.annotations.push(sample_annotation("World"));
+        let file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+
+        // flattened: the text is only in the content stream
+        let options = ConversionOptions::default();
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        assert!(!written_object(&document, 10).contains("/Annots"));
+        assert!(!document.objects.values().any(|o| matches!(o, Content::TextAnnotation(_))));
+
+        // interactive: additionally as text annotations
+        let options = ConversionOptions {
+            interactive_annotations: true,
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        assert!(written_object(&document, 10).contains("/Annots[13 0 R 14 0 R]"));
+        assert!(written_object(&document, 13).contains("/Subtype/Text"));
+        assert!(written_object(&document, 14).contains("/Subtype/Text"));
+    }
+
+    #[test]
+    fn detects_corrupted_images() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-checksum-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let image_data = b"pretend this is a JPEG image".to_vec();
+        let file_name = ContentName {
+            digest: Sha3_512::digest(&image_data).into(),
+            size: image_data.len().try_into().unwrap(),
+        }.to_string();
+        std::fs::write(dir.join(&file_name), &image_data).unwrap();
+
+        let mut page = sample_page();
+        page.scanned_image.as_mut().unwrap().file_path = file_name.parse().unwrap();
+        let file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+        let options = ConversionOptions {
+            verify_image_checksums: true,
+            ..ConversionOptions::default()
+        };
+        assert!(file_to_pdf(&file, &dir, &options).is_ok());
+
+        // flip a bit of the stored image
+        let mut corrupted_data = image_data.clone();
+        corrupted_data[0] ^= 0x01;
+        std::fs::write(dir.join(&file_name), &corrupted_data).unwrap();
+        let result = file_to_pdf(&file, &dir, &options);
+        // only checked if requested
+        let unchecked_result = file_to_pdf(&file, &dir, &ConversionOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(result, Err(ConversionError::ImageChecksumMismatch { page_index: 0, .. })));
+        assert!(unchecked_result.is_ok());
+    }
+
+    #[test]
+    fn accepts_checksums_of_each_algorithm() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-checksum-algorithm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut pages = Vec::new();
+        for (algorithm, image_data) in [
+            (ContentHashAlgorithm::Sha3_512, b"pretend this is a JPEG image".as_slice()),
+            (ContentHashAlgorithm::Sha512, b"pretend this is another JPEG image".as_slice()),
+        ] {
+            let mut hasher = algorithm.new_hasher();
+            hasher.update(image_data);
+            let mut digest = [0u8; 64];
+            hasher.finalize_into_reset(&mut digest).unwrap();
+            let file_name = ContentName {
+                digest,
+                size: image_data.len().try_into().unwrap(),
+            }.to_string();
+            std::fs::write(dir.join(&file_name), image_data).unwrap();
+
+            let mut page = sample_page();
+            page.scanned_image.as_mut().unwrap().file_path = file_name.parse().unwrap();
+            pages.push(page);
+        }
+        let file = File {
+            pages,
+            ..File::default()
+        };
+        let options = ConversionOptions {
+            verify_image_checksums: true,
+            ..ConversionOptions::default()
+        };
+        let result = file_to_pdf(&file, &dir, &options);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn infers_document_language_from_chunks() {
+        let mut annotation = sample_annotation("Hello");
+        annotation.elements[0].language = Some("en-US".to_owned());
+        let mut page = sample_page();
+        page.annotations.push(annotation.clone());
+        page.annotations.push(annotation.clone());
+        let mut file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let Content::Catalog(catalog) = &document.objects[&PdfId(1)] else {
+            panic!("object 1 is not the catalog");
+        };
+        assert_eq!(catalog.lang.as_deref(), Some("en-US"));
+
+        // the chunks do not repeat the language of the document
+        let context = DrawingContext {
+            document_language: file.effective_default_language(),
+            debug_text: false,
+            visible_text: false,
+            fallback_codes: None,
+        };
+        let mut commands = Vec::new();
+        annotation.write_drawing_commands(&mut commands, &context).unwrap();
+        assert!(!String::from_utf8_lossy(&commands).contains("/Lang"));
+
+        // a chunk without a language prevents the inference
+        file.pages[0].annotations[1].elements[0].language = None;
+        assert_eq!(file.effective_default_language(), None);
+    }
+
+    #[test]
+    fn converts_text_only_page() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("Title")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let page = written_object(&document, 10);
+        assert!(page.contains("/MediaBox[0 0 612 792]"));
+        assert!(!page.contains("/XObject"));
+        let Content::PageContents(contents) = &document.objects[&PdfId(11)] else {
+            panic!("object 11 is not the page contents");
+        };
+        let commands = String::from_utf8_lossy(&contents.commands);
+        assert!(!commands.contains(" cm"));
+        assert!(!commands.contains(" Do"));
+        assert!(commands.contains(" BT"));
+        // no image
+        assert!(!document.objects.contains_key(&PdfId(12)));
+    }
+
+    #[test]
+    fn references_output_intent_from_catalog() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("Title")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+        let options = ConversionOptions {
+            output_intent: Some(OutputIntentProfile {
+                identifier: "sRGB IEC61966-2.1".to_owned(),
+                components: 3,
+                profile_data: b"pretend this is an ICC profile".to_vec(),
+            }),
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        let pdf = write_to_lopdf(&document);
+
+        let output_intents = pdf.catalog().unwrap()
+            .get(b"OutputIntents").unwrap()
+            .as_array().unwrap();
+        assert_eq!(output_intents.len(), 1);
+        let output_intent = pdf.get_dictionary(output_intents[0].as_reference().unwrap()).unwrap();
+        assert_eq!(output_intent.get(b"Type").unwrap().as_name().unwrap(), b"OutputIntent");
+        assert_eq!(output_intent.get(b"S").unwrap().as_name().unwrap(), b"GTS_PDFA1");
+
+        let profile = pdf.get_object(output_intent.get(b"DestOutputProfile").unwrap().as_reference().unwrap())
+            .unwrap()
+            .as_stream().unwrap();
+        assert_eq!(profile.dict.get(b"N").unwrap().as_i64().unwrap(), 3);
+        assert_eq!(profile.content, b"pretend this is an ICC profile");
+    }
+
+    #[test]
+    fn omits_output_intent_by_default() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, Vec::new(), Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        assert!(pdf.catalog().unwrap().get(b"OutputIntents").is_err());
+    }
+
+    #[test]
+    fn counts_remaining_pages_after_delete() {
+        let mut builder = FileBuilder::new();
+        for title in ["One", "Two", "Three"] {
+            builder.add_text_only_page(612, 792, vec![sample_annotation(title)], Vec::new()).unwrap();
+        }
+        let mut file = builder.build().unwrap();
+        file.pages.remove(1);
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let pages = pdf.get_dictionary(pdf.catalog().unwrap().get(b"Pages").unwrap().as_reference().unwrap())
+            .unwrap();
+        assert_eq!(pages.get(b"Count").unwrap().as_i64().unwrap(), 2);
+        assert_eq!(pages.get(b"Kids").unwrap().as_array().unwrap().len(), 2);
+        assert_eq!(pdf.get_pages().len(), 2);
+    }
+
+    #[test]
+    fn uses_configured_font_family() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("Title")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+
+        let options = ConversionOptions {
+            font_family: FontFamily::Helvetica,
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        let fonts: Vec<String> = (3..7)
+            .map(|id| written_object(&document, id))
+            .collect();
+        assert!(fonts[0].contains("/BaseFont/Helvetica/"));
+        assert!(fonts[1].contains("/BaseFont/Helvetica-Oblique"));
+        assert!(fonts[2].contains("/BaseFont/Helvetica-Bold/"));
+        assert!(fonts[3].contains("/BaseFont/Helvetica-BoldOblique"));
+
+        // Times remains the default
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        assert!(written_object(&document, 3).contains("/BaseFont/Times-Roman"));
+    }
+
+    #[test]
+    fn disables_interpolation_if_requested() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-interpolate-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("page.jpeg"), b"pretend this is a JPEG image").unwrap();
+        let file = File {
+            pages: vec![sample_page()],
+            ..File::default()
+        };
+
+        let options = ConversionOptions {
+            interpolate_images: false,
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, &dir, &options).unwrap();
+        let image = written_object(&document, 12);
+        let default_document = file_to_pdf(&file, &dir, &ConversionOptions::default()).unwrap();
+        let default_image = written_object(&default_document, 12);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(image.contains("/Interpolate false"));
+        assert!(default_image.contains("/Interpolate true"));
+    }
+
+    #[test]
+    fn merges_files_into_one_document() {
+        let mut files = Vec::new();
+        for (title, width) in [("First", 612), ("Second", 595)] {
+            let mut builder = FileBuilder::new();
+            builder.add_text_only_page(width, 792, vec![sample_annotation(title)], Vec::new()).unwrap();
+            files.push(builder.build().unwrap());
+        }
+        let files_with_paths: Vec<(&File, &Path)> = files.iter()
+            .map(|file| (file, Path::new("images")))
+            .collect();
+
+        let document = merge_files_to_pdf(&files_with_paths, &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let pages = pdf.get_pages();
+        assert_eq!(pages.len(), 2);
+
+        let media_box_widths: Vec<i64> = pages.values()
+            .map(|page_id| {
+                let page = pdf.get_dictionary(*page_id).unwrap();
+                let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+                media_box[2].as_i64().unwrap()
+            })
+            .collect();
+        assert_eq!(media_box_widths, [612, 595]);
+
+        // both pages hang off the same page tree root
+        let parents: Vec<lopdf::ObjectId> = pages.values()
+            .map(|page_id| pdf.get_dictionary(*page_id).unwrap().get(b"Parent").unwrap().as_reference().unwrap())
+            .collect();
+        assert_eq!(parents, [(2, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn uses_data_filters_of_image() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-filter-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("page.jpeg"), b"pretend this is a JPEG 2000 image").unwrap();
+        let mut page = sample_page();
+        page.scanned_image.as_mut().unwrap().data_filters = Some(vec!["JPXDecode".to_owned()]);
+        let file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+
+        let document = file_to_pdf(&file, &dir, &ConversionOptions::default()).unwrap();
+        let image = written_object(&document, 12);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(image.contains("/Filter[/JPXDecode]"));
+        assert!(!image.contains("/DCTDecode"));
+    }
+
+    #[test]
+    fn maps_struct_parents_through_parent_tree() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("One"), sample_annotation("Two")], Vec::new()).unwrap();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("Three")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+
+        let struct_tree_root = pdf.get_dictionary(
+            pdf.catalog().unwrap().get(b"StructTreeRoot").unwrap().as_reference().unwrap()
+        ).unwrap();
+        let parent_tree = pdf.get_dictionary(
+            struct_tree_root.get(b"ParentTree").unwrap().as_reference().unwrap()
+        ).unwrap();
+        let nums = parent_tree.get(b"Nums").unwrap().as_array().unwrap();
+        let parent_tree_entries: BTreeMap<i64, &Vec<lopdf::Object>> = nums.chunks(2)
+            .map(|pair| (pair[0].as_i64().unwrap(), pair[1].as_array().unwrap()))
+            .collect();
+
+        let mut mcid_counts = Vec::new();
+        for page_id in pdf.get_pages().values() {
+            let page = pdf.get_dictionary(*page_id).unwrap();
+            let struct_parents = page.get(b"StructParents").unwrap().as_i64().unwrap();
+            let elements = parent_tree_entries[&struct_parents];
+            // each element refers back to the page
+            for element in elements {
+                let element = pdf.get_dictionary(element.as_reference().unwrap()).unwrap();
+                assert_eq!(element.get(b"Pg").unwrap().as_reference().unwrap(), *page_id);
+            }
+            mcid_counts.push(elements.len());
+        }
+        assert_eq!(mcid_counts, [2, 1]);
+        assert!(struct_tree_root.get(b"ParentTreeNextKey").unwrap().as_i64().unwrap() > *parent_tree_entries.keys().last().unwrap());
+    }
+
+    #[test]
+    fn emits_annotations_in_reading_order() {
+        let annotations = [10, 20, 30]
+            .map(|left| Annotation { left, ..sample_annotation("Text") })
+            .to_vec();
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, annotations, Vec::new()).unwrap();
+        let mut file = builder.build().unwrap();
+        assert!(file.pages[0].set_reading_order(&[2, 0, 1]));
+        // not a permutation
+        assert!(!file.pages[0].set_reading_order(&[2, 2, 1]));
+
+        let options = ConversionOptions {
+            interactive_annotations: true,
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        assert!(written_object(&document, 10).contains("/Annots[13 0 R 14 0 R 15 0 R]"));
+        assert!(written_object(&document, 13).contains("/Rect[30 "));
+        assert!(written_object(&document, 14).contains("/Rect[10 "));
+        assert!(written_object(&document, 15).contains("/Rect[20 "));
+    }
+
+    #[test]
+    fn draws_text_visibly_for_debugging() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("Title")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let page_id = pdf.page_iter().next().unwrap();
+        let commands = String::from_utf8_lossy(&pdf.get_page_content(page_id).unwrap()).into_owned();
+        assert!(commands.contains(" 3 Tr"));
+        assert!(!commands.contains(" 0 Tr"));
+
+        let options = ConversionOptions {
+            debug_text: true,
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let page_id = pdf.page_iter().next().unwrap();
+        let commands = String::from_utf8_lossy(&pdf.get_page_content(page_id).unwrap()).into_owned();
+        assert!(commands.contains(" 0 Tr"));
+        assert!(!commands.contains(" 3 Tr"));
+        assert!(commands.contains("/GSDebug gs"));
+
+        // the page provides the fonts and the graphics state
+        let page = pdf.get_dictionary(page_id).unwrap();
+        let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+        assert!(resources.get(b"Font").unwrap().as_dict().unwrap().has(b"F0"));
+        assert!(resources.get(b"ExtGState").unwrap().as_dict().unwrap().has(b"GSDebug"));
+    }
+
+    #[test]
+    fn draws_text_visibly_per_file_if_requested() {
+        let mut files = Vec::new();
+        for visible_text in [true, false] {
+            let mut builder = FileBuilder::new();
+            builder.visible_text(visible_text);
+            builder.add_text_only_page(612, 792, vec![sample_annotation("Title")], Vec::new()).unwrap();
+            files.push(builder.build().unwrap());
+        }
+        let files_with_paths: Vec<(&File, &Path)> = files.iter()
+            .map(|file| (file, Path::new("images")))
+            .collect();
+
+        let document = merge_files_to_pdf(&files_with_paths, &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let page_commands: Vec<String> = pdf.page_iter()
+            .map(|page_id| String::from_utf8_lossy(&pdf.get_page_content(page_id).unwrap()).into_owned())
+            .collect();
+        assert!(page_commands[0].contains(" 0 Tr"));
+        assert!(!page_commands[0].contains(" 3 Tr"));
+        // unlike debugging output, the text is not drawn in a special color
+        assert!(!page_commands[0].contains("/GSDebug gs"));
+        assert!(page_commands[1].contains(" 3 Tr"));
+        assert!(!page_commands[1].contains(" 0 Tr"));
+    }
+
+    #[test]
+    fn shares_fonts_between_merged_files() {
+        let mut files = Vec::new();
+        for title in ["First", "Second"] {
+            let mut builder = FileBuilder::new();
+            builder.add_text_only_page(612, 792, vec![sample_annotation(title)], Vec::new()).unwrap();
+            files.push(builder.build().unwrap());
+        }
+        let files_with_paths: Vec<(&File, &Path)> = files.iter()
+            .map(|file| (file, Path::new("images")))
+            .collect();
+
+        let document = merge_files_to_pdf(&files_with_paths, &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let font_ids: BTreeSet<lopdf::ObjectId> = pdf.objects.iter()
+            .filter(|(_id, object)| object.as_dict().ok().and_then(|dict| dict.get(b"Type").ok()).and_then(|t| t.as_name().ok()) == Some(b"Font".as_slice()))
+            .map(|(id, _object)| *id)
+            .collect();
+        assert_eq!(font_ids.len(), 4);
+
+        // the pages of both files refer to the same font objects
+        for page_id in pdf.get_pages().values() {
+            let page = pdf.get_dictionary(*page_id).unwrap();
+            let resources = page.get(b"Resources").unwrap().as_dict().unwrap();
+            let page_font_ids: BTreeSet<lopdf::ObjectId> = resources.get(b"Font").unwrap().as_dict().unwrap()
+                .iter()
+                .map(|(_name, font)| font.as_reference().unwrap())
+                .collect();
+            assert_eq!(page_font_ids, font_ids);
+        }
+    }
+
+    #[test]
+    fn limits_annotations_per_page() {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("one")], Vec::new()).unwrap();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("two"), sample_annotation("three")], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+
+        let options = ConversionOptions {
+            max_annotations_per_page: Some(2),
+            ..ConversionOptions::default()
+        };
+        assert!(file_to_pdf(&file, Path::new("images"), &options).is_ok());
+
+        let options = ConversionOptions {
+            max_annotations_per_page: Some(1),
+            ..ConversionOptions::default()
+        };
+        assert!(matches!(
+            file_to_pdf(&file, Path::new("images"), &options),
+            Err(ConversionError::TooManyAnnotations { page_index: 1, count: 2, limit: 1 }),
+        ));
+    }
+
+    #[test]
+    fn emits_structure_elements_for_block_roles() {
+        let mut heading = sample_annotation("Chapter One");
+        heading.block_role = Some(BlockRole::Heading1);
+        let paragraph = sample_annotation("It was a dark and stormy night.");
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![heading, paragraph], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let structure_types: Vec<&[u8]> = pdf.objects.values()
+            .filter_map(|object| object.as_dict().ok())
+            .filter(|dict| dict.get(b"Type").and_then(|t| t.as_name()).ok() == Some(b"StructElem".as_slice()))
+            .filter_map(|dict| dict.get(b"S").and_then(|s| s.as_name()).ok())
+            .collect();
+        assert_eq!(structure_types, [b"Document".as_slice(), b"H1".as_slice(), b"P".as_slice()]);
+    }
+
+    #[test]
+    fn moves_content_inward_by_margins() {
+        let mut page = sample_page();
+        page.margins_pt = Some(PageMargins::uniform(10));
+        page.annotations.push(sample_annotation("Hello"));
+        let file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let page_object = written_object(&document, 10);
+        let contents = written_object(&document, 11);
+        assert!(page_object.contains("/MediaBox[0 0 164 236]"), "{}", page_object);
+        assert!(contents.contains("q 144 0 0 216 10 10 cm/Im0 Do Q"), "{}", contents);
+        assert!(contents.contains("q 1 0 0 1 10 10 cm"), "{}", contents);
+    }
+
+    #[test]
+    fn overrides_image_size_with_media_box() {
+        let mut page = sample_page();
+        page.media_box_pt = Some((595.28, 841.89));
+        let file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let page_object = written_object(&document, 10);
+        let contents = written_object(&document, 11);
+        assert!(page_object.contains("/MediaBox[0 0 595.28 841.89]"), "{}", page_object);
+        assert!(contents.contains("q 595.28 0 0 841.89 0 0 cm/Im0 Do Q"), "{}", contents);
+    }
+
+    #[test]
+    fn separates_text_stream_if_requested() {
+        fn page_contents(document: &Document, page_id: u64) -> Vec<PdfId> {
+            match &document.objects[&PdfId(page_id)] {
+                Content::Page(page) => page.contents.clone(),
+                other => panic!("not a page: {:?}", other),
+            }
+        }
+
+        let mut page = sample_page();
+        page.annotations.push(sample_annotation("Title"));
+        let file = File {
+            pages: vec![page],
+            ..File::default()
+        };
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        assert_eq!(page_contents(&document, 10), [PdfId(11)]);
+        assert!(written_object(&document, 10).contains("/Contents 11 0 R"));
+
+        let options = ConversionOptions {
+            separate_text_stream: true,
+            ..ConversionOptions::default()
+        };
+        let document = file_to_pdf(&file, Path::new("images"), &options).unwrap();
+        let contents = page_contents(&document, 10);
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0], PdfId(11));
+        let page_object = written_object(&document, 10);
+        assert!(page_object.contains(&format!("/Contents[11 0 R {} 0 R]", contents[1].0)), "{}", page_object);
+
+        let image_commands = written_object(&document, 11);
+        let text_commands = written_object(&document, contents[1].0);
+        assert!(image_commands.contains("/Im0 Do"), "{}", image_commands);
+        assert!(!image_commands.contains("BT"), "{}", image_commands);
+        assert!(text_commands.contains("BT"), "{}", text_commands);
+        assert!(!text_commands.contains("Do"), "{}", text_commands);
+
+        // text-only pages keep a single stream
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation("Title")], Vec::new()).unwrap();
+        let document = file_to_pdf(&builder.build().unwrap(), Path::new("images"), &options).unwrap();
+        assert_eq!(page_contents(&document, 10), [PdfId(11)]);
+    }
+
+    #[test]
+    fn reports_pages_of_unknown_size() {
+        let mut aspect_ratio_page = sample_page();
+        aspect_ratio_page.scanned_image.as_mut().unwrap().info.density_unit = DensityUnit::NoUnit;
+        let mut zero_density_page = sample_page();
+        zero_density_page.scanned_image.as_mut().unwrap().info.density_y = 0;
+        let file = File {
+            pages: vec![sample_page(), aspect_ratio_page, zero_density_page],
+            ..File::default()
+        };
+
+        let result = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default());
+        assert!(matches!(result, Err(ConversionError::UnknownPageSize { page_index: 1, dimension: "width" })));
+
+        let file = File {
+            pages: vec![file.pages[2].clone()],
+            ..File::default()
+        };
+        let result = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default());
+        assert!(matches!(result, Err(ConversionError::UnknownPageSize { page_index: 0, dimension: "height" })));
+    }
+
+    #[test]
+    fn declares_title_in_info_and_metadata() {
+        let mut builder = FileBuilder::new();
+        builder.title("Minutes & Notes");
+        builder.add_text_only_page(612, 792, Vec::new(), Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+
+        let info_id = pdf.trailer.get(b"Info").unwrap().as_reference().unwrap();
+        let info = pdf.get_dictionary(info_id).unwrap();
+        assert_eq!(lopdf::decode_text_string(info.get(b"Title").unwrap()).unwrap(), "Minutes & Notes");
+
+        let catalog = pdf.catalog().unwrap();
+        let viewer_preferences = catalog.get(b"ViewerPreferences").unwrap().as_dict().unwrap();
+        assert!(viewer_preferences.get(b"DisplayDocTitle").unwrap().as_bool().unwrap());
+
+        let metadata_id = catalog.get(b"Metadata").unwrap().as_reference().unwrap();
+        let metadata = pdf.get_object(metadata_id).unwrap().as_stream().unwrap();
+        assert_eq!(metadata.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"XML");
+        let xmp = String::from_utf8(metadata.content.clone()).unwrap();
+        assert!(xmp.contains("<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Minutes &amp; Notes</rdf:li></rdf:Alt></dc:title>"), "{}", xmp);
+
+        // without a title, viewers show the file name
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, Vec::new(), Vec::new()).unwrap();
+        let document = file_to_pdf(&builder.build().unwrap(), Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        assert!(pdf.catalog().unwrap().get(b"ViewerPreferences").is_err());
+        let info_id = pdf.trailer.get(b"Info").unwrap().as_reference().unwrap();
+        assert!(pdf.get_dictionary(info_id).unwrap().get(b"Title").is_err());
+    }
+
+    #[test]
+    fn stores_producer_in_info() {
+        fn written_producer(options: &ConversionOptions) -> Option<String> {
+            let mut builder = FileBuilder::new();
+            builder.add_text_only_page(612, 792, Vec::new(), Vec::new()).unwrap();
+            let file = builder.build().unwrap();
+            let document = file_to_pdf(&file, Path::new("images"), options).unwrap();
+            let pdf = write_to_lopdf(&document);
+            let info_id = pdf.trailer.get(b"Info").ok()?.as_reference().unwrap();
+            let info = pdf.get_dictionary(info_id).unwrap();
+            info.get(b"Producer").ok()
+                .map(|producer| lopdf::decode_text_string(producer).unwrap())
+        }
+
+        assert_eq!(
+            written_producer(&ConversionOptions::default()),
+            Some(format!("pdfmcr {}", env!("CARGO_PKG_VERSION"))),
+        );
+        let options = ConversionOptions {
+            producer: Some("Scanning Department".to_owned()),
+            ..ConversionOptions::default()
+        };
+        assert_eq!(written_producer(&options), Some("Scanning Department".to_owned()));
+        let options = ConversionOptions {
+            producer: None,
+            ..ConversionOptions::default()
+        };
+        assert_eq!(written_producer(&options), None);
+    }
+
+    #[test]
+    fn announces_common_language_on_structure_elements() {
+        fn in_languages(text: &str, languages: &[Option<&str>]) -> Annotation {
+            let mut annotation = sample_annotation(text);
+            let chunk = annotation.elements.pop().unwrap();
+            for language in languages {
+                let mut language_chunk = chunk.clone();
+                language_chunk.language = language.map(|l| l.to_owned());
+                annotation.elements.push(language_chunk);
+            }
+            annotation
+        }
+
+        let german = in_languages("Guten Tag", &[Some("de"), Some("DE")]);
+        let mixed = in_languages("Hello Tag", &[Some("en"), Some("de")]);
+        let default = in_languages("Hello", &[None]);
+        let mut list_item = in_languages("Servus", &[Some("de-AT")]);
+        list_item.block_role = Some(BlockRole::ListItem);
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![german, mixed, default, list_item], Vec::new()).unwrap();
+        let file = builder.build().unwrap();
+
+        let document = file_to_pdf(&file, Path::new("images"), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        let mut structure_languages: Vec<(String, Option<String>)> = pdf.objects.values()
+            .filter_map(|object| object.as_dict().ok())
+            .filter(|dict| dict.get(b"Type").and_then(|t| t.as_name()).ok() == Some(b"StructElem".as_slice()))
+            .map(|dict| (
+                String::from_utf8(dict.get(b"S").unwrap().as_name().unwrap().to_vec()).unwrap(),
+                dict.get(b"Lang").ok().map(|lang| lopdf::decode_text_string(lang).unwrap()),
+            ))
+            .collect();
+        structure_languages.sort();
+        assert_eq!(
+            structure_languages,
+            [
+                ("Document".to_owned(), None),
+                ("L".to_owned(), None),
+                ("LBody".to_owned(), Some("de-AT".to_owned())),
+                ("LI".to_owned(), None),
+                ("P".to_owned(), None),
+                ("P".to_owned(), None),
+                ("P".to_owned(), Some("de".to_owned())),
+            ],
+        );
+    }
+
+    #[test]
+    fn declares_rgb_images_as_srgb_if_requested() {
+        fn image_color_spaces(options: &ConversionOptions) -> Vec<lopdf::Object> {
+            let dir = std::env::temp_dir().join(format!("pdfmcr-srgb-test-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("page.jpeg"), b"pretend this is a JPEG image").unwrap();
+            let gray_page = sample_page();
+            let mut rgb_page = sample_page();
+            rgb_page.scanned_image.as_mut().unwrap().info.color_space = ColorSpace::Rgb;
+            let file = File {
+                pages: vec![gray_page, rgb_page],
+                ..File::default()
+            };
+
+            let document = file_to_pdf(&file, &dir, options).unwrap();
+            let pdf = write_to_lopdf(&document);
+            std::fs::remove_dir_all(&dir).unwrap();
+
+            let mut color_spaces: Vec<(lopdf::ObjectId, lopdf::Object)> = pdf.objects.iter()
+                .filter_map(|(id, object)| object.as_stream().ok().map(|stream| (*id, &stream.dict)))
+                .filter(|(_, dict)| dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image".as_slice()))
+                .map(|(id, dict)| (id, dict.get(b"ColorSpace").unwrap().clone()))
+                .collect();
+            color_spaces.sort_by_key(|(id, _)| *id);
+            color_spaces.into_iter()
+                .map(|(_, color_space)| match color_space {
+                    // resolve the profile to check it
+                    lopdf::Object::Array(elements) => {
+                        assert_eq!(elements[0].as_name().unwrap(), b"ICCBased");
+                        let profile = pdf.get_object(elements[1].as_reference().unwrap()).unwrap()
+                            .as_stream().unwrap();
+                        assert_eq!(profile.dict.get(b"N").unwrap().as_i64().unwrap(), 3);
+                        assert_eq!(profile.content, crate::icc::srgb_profile());
+                        lopdf::Object::Name(b"ICCBased".to_vec())
+                    },
+                    other => other,
+                })
+                .collect()
+        }
+
+        assert_eq!(
+            image_color_spaces(&ConversionOptions::default()),
+            [lopdf::Object::Name(b"DeviceGray".to_vec()), lopdf::Object::Name(b"DeviceRGB".to_vec())],
+        );
+        let options = ConversionOptions {
+            srgb_images: true,
+            ..ConversionOptions::default()
+        };
+        assert_eq!(
+            image_color_spaces(&options),
+            [lopdf::Object::Name(b"DeviceGray".to_vec()), lopdf::Object::Name(b"ICCBased".to_vec())],
+        );
+    }
+
+    fn text_only_file(text: &str) -> File {
+        let mut builder = FileBuilder::new();
+        builder.add_text_only_page(612, 792, vec![sample_annotation(text)], Vec::new()).unwrap();
+        builder.build().unwrap()
+    }
+
+    fn objects_of_type<'a>(document: &'a lopdf::Document, key: &[u8], value: &[u8]) -> Vec<&'a lopdf::Dictionary> {
+        document.objects.values()
+            .filter_map(|object| match object {
+                lopdf::Object::Dictionary(dict) => Some(dict),
+                lopdf::Object::Stream(stream) => Some(&stream.dict),
+                _ => None,
+            })
+            .filter(|dict| dict.get(key).and_then(|v| v.as_name()).is_ok_and(|name| name == value))
+            .collect()
+    }
+
+    #[test]
+    fn embeds_fallback_font() {
+        let options = ConversionOptions {
+            fallback_font: Some(truetype::test_font()),
+            ..ConversionOptions::default()
+        };
+        let file = text_only_file("\u{03A9}\u{0436}");
+        let document = file_to_pdf(&file, Path::new("."), &options).unwrap();
+        let pdf = write_to_lopdf(&document);
+
+        let type0_fonts = objects_of_type(&pdf, b"Subtype", b"Type0");
+        assert_eq!(type0_fonts.len(), 1);
+        assert!(type0_fonts[0].get(b"ToUnicode").is_ok());
+
+        let cid_fonts = objects_of_type(&pdf, b"Subtype", b"CIDFontType2");
+        assert_eq!(cid_fonts.len(), 1);
+        let cid_font = cid_fonts[0];
+        let base_font = cid_font.get(b"BaseFont").unwrap().as_name().unwrap();
+        assert!(base_font.ends_with(b"+TestFont"));
+        let widths = cid_font.get(b"W").unwrap().as_array().unwrap();
+        assert_eq!(widths[0].as_i64().unwrap(), 1);
+        let widths: Vec<i64> = widths[1].as_array().unwrap().iter()
+            .map(|width| width.as_i64().unwrap())
+            .collect();
+        assert_eq!(widths, [600, 700]);
+
+        let cid_to_gid_map = pdf.get_object(cid_font.get(b"CIDToGIDMap").unwrap().as_reference().unwrap())
+            .unwrap().as_stream().unwrap();
+        // CID 1 is U+03A9 (glyph 1), CID 2 is U+0436 (glyph 2)
+        assert_eq!(cid_to_gid_map.content, [0, 0, 0, 1, 0, 2]);
+
+        let descriptor = pdf.get_dictionary(cid_font.get(b"FontDescriptor").unwrap().as_reference().unwrap())
+            .unwrap();
+        assert_eq!(descriptor.get(b"Type").unwrap().as_name().unwrap(), b"FontDescriptor");
+        for key in [&b"Flags"[..], b"FontBBox", b"Ascent", b"Descent", b"CapHeight", b"StemV", b"ItalicAngle"] {
+            assert!(descriptor.get(key).is_ok(), "descriptor lacks {}", String::from_utf8_lossy(key));
+        }
+        let font_file = pdf.get_object(descriptor.get(b"FontFile2").unwrap().as_reference().unwrap())
+            .unwrap().as_stream().unwrap();
+        let subset = truetype::Font::parse(&font_file.content).unwrap_err();
+        // the subset omits the character map, which PDF does not need with a CIDToGIDMap
+        assert_eq!(subset, truetype::Error::MissingTable { tag: *b"cmap" });
+    }
+
+    #[test]
+    fn fallback_text_requires_font() {
+        let file = text_only_file("\u{03A9}");
+        let result = file_to_pdf(&file, Path::new("."), &ConversionOptions::default());
+        assert!(matches!(result, Err(ConversionError::NoFallbackFont)));
+    }
+
+    #[test]
+    fn win_ansi_text_needs_no_fallback_font() {
+        let file = text_only_file("Gr\u{00FC}\u{00DF}e");
+        let document = file_to_pdf(&file, Path::new("."), &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        assert!(objects_of_type(&pdf, b"Subtype", b"Type0").is_empty());
+    }
+
+    #[test]
+    fn marked_content_properties_require_pdf_1_5() {
+        let options = ConversionOptions {
+            pdf_version: PdfVersion::V1_4,
+            ..ConversionOptions::default()
+        };
+        let mut pdf_bytes = Vec::new();
+
+        let file = text_only_file("Nacken");
+        let document = file_to_pdf(&file, Path::new("."), &options).unwrap();
+        document.write_pdf_to_stream(&mut pdf_bytes).unwrap();
+        assert!(pdf_bytes.starts_with(b"%PDF-1.4\n"));
+
+        let mut file = text_only_file("Nak-ken");
+        file.pages[0].annotations[0].elements[0].actual_text = Some("Nacken".to_owned());
+        let document = file_to_pdf(&file, Path::new("."), &options).unwrap();
+        assert!(document.write_pdf_to_stream(&mut Vec::new()).is_err());
+    }
+
+    /// Encodes a 16x16 scan in the given color type (with the given number of components), stores it
+    /// in the given directory and appends a page displaying it.
+    fn add_scanned_page(builder: &mut FileBuilder, dir: &Path, file_name: &str, color_type: jpeg_encoder::ColorType, component_count: usize) {
+        let pixels = vec![0x40; 16 * 16 * component_count];
+        let mut jpeg_data = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg_data, 90)
+            .encode(&pixels, 16, 16, color_type)
+            .unwrap();
+        std::fs::write(dir.join(file_name), &jpeg_data).unwrap();
+
+        let image = crate::jpeg::Image::try_read(jpeg_data.as_slice()).unwrap();
+        builder.add_page(&image, file_name.parse().unwrap(), Vec::new(), Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn inverts_adobe_cmyk_scans() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-cmyk-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut builder = FileBuilder::new();
+        // the encoder marks CMYK images with an Adobe block with color transform 0
+        add_scanned_page(&mut builder, &dir, "cmyk.jpeg", jpeg_encoder::ColorType::Cmyk, 4);
+        add_scanned_page(&mut builder, &dir, "rgb.jpeg", jpeg_encoder::ColorType::Rgb, 3);
+        let file = builder.build().unwrap();
+        assert!(file.pages[0].scanned_image.as_ref().unwrap().info.inverted);
+        assert!(!file.pages[1].scanned_image.as_ref().unwrap().info.inverted);
+
+        let document = file_to_pdf(&file, &dir, &ConversionOptions::default()).unwrap();
+        let pdf = write_to_lopdf(&document);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let images = objects_of_type(&pdf, b"Subtype", b"Image");
+        let cmyk_image = images.iter()
+            .find(|image| image.get(b"ColorSpace").unwrap().as_name().is_ok_and(|name| name == b"DeviceCMYK"))
+            .unwrap();
+        let decode: Vec<i64> = cmyk_image.get(b"Decode").unwrap().as_array().unwrap().iter()
+            .map(|value| value.as_i64().unwrap())
+            .collect();
+        assert_eq!(decode, [1, 0, 1, 0, 1, 0, 1, 0]);
+
+        let rgb_image = images.iter()
+            .find(|image| image.get(b"ColorSpace").unwrap().as_name().is_ok_and(|name| name == b"DeviceRGB"))
+            .unwrap();
+        assert!(rgb_image.get(b"Decode").is_err());
+    }
 }
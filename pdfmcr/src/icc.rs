@@ -0,0 +1,199 @@
+//! Generation of a minimal sRGB ICC profile.
+//!
+//! The profile is a version 2 display device profile consisting of the primaries and white point
+//! of sRGB (chromatically adapted to D50, as required for the profile connection space) and the
+//! sRGB tone reproduction curve, sampled at 256 points. This is enough for PDF viewers to interpret
+//! RGB images as sRGB instead of device-dependent RGB.
+
+
+/// The description stored in the profile.
+const DESCRIPTION: &str = "sRGB (pdfmcr minimal profile)";
+
+/// The copyright notice stored in the profile.
+const COPYRIGHT: &str = "No copyright, use freely";
+
+/// The D50 illuminant of the profile connection space, as XYZ.
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// The sRGB primaries chromatically adapted to D50, as XYZ.
+const RED: [f64; 3] = [0.4360747, 0.2225045, 0.0139322];
+const GREEN: [f64; 3] = [0.3850649, 0.7168786, 0.0971045];
+const BLUE: [f64; 3] = [0.1430804, 0.0606169, 0.7141733];
+
+/// The number of samples of the tone reproduction curve.
+const CURVE_SAMPLES: usize = 256;
+
+
+fn push_s15_fixed16(value: f64, data: &mut Vec<u8>) {
+    let fixed = (value * 65536.0).round() as i32;
+    data.extend_from_slice(&fixed.to_be_bytes());
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(20);
+    data.extend_from_slice(b"XYZ \0\0\0\0");
+    for value in xyz {
+        push_s15_fixed16(value, &mut data);
+    }
+    data
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"text\0\0\0\0");
+    data.extend_from_slice(text.as_bytes());
+    data.push(0);
+    data
+}
+
+fn description_tag(text: &str) -> Vec<u8> {
+    let ascii_count = u32::try_from(text.len() + 1).unwrap();
+    let mut data = Vec::new();
+    data.extend_from_slice(b"desc\0\0\0\0");
+    data.extend_from_slice(&ascii_count.to_be_bytes());
+    data.extend_from_slice(text.as_bytes());
+    data.push(0);
+    // empty Unicode description: language code and count
+    data.extend_from_slice(&[0; 8]);
+    // empty ScriptCode description: code, count and the fixed-size buffer
+    data.extend_from_slice(&[0; 2 + 1 + 67]);
+    data
+}
+
+/// Applies the sRGB transfer function, converting an encoded value into linear light.
+fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn curve_tag() -> Vec<u8> {
+    let mut data = Vec::with_capacity(12 + 2 * CURVE_SAMPLES);
+    data.extend_from_slice(b"curv\0\0\0\0");
+    data.extend_from_slice(&u32::try_from(CURVE_SAMPLES).unwrap().to_be_bytes());
+    for i in 0..CURVE_SAMPLES {
+        let encoded = i as f64 / (CURVE_SAMPLES - 1) as f64;
+        let sample = (srgb_to_linear(encoded) * 65535.0).round() as u16;
+        data.extend_from_slice(&sample.to_be_bytes());
+    }
+    data
+}
+
+
+/// Generates the data of a minimal sRGB ICC profile.
+///
+/// The output is always the same, keeping documents embedding it reproducible.
+pub fn srgb_profile() -> Vec<u8> {
+    let curve = curve_tag();
+    // tags with the same data share it
+    let tags: [(&[u8; 4], Vec<u8>); 9] = [
+        (b"desc", description_tag(DESCRIPTION)),
+        (b"cprt", text_tag(COPYRIGHT)),
+        (b"wtpt", xyz_tag(D50)),
+        (b"rXYZ", xyz_tag(RED)),
+        (b"gXYZ", xyz_tag(GREEN)),
+        (b"bXYZ", xyz_tag(BLUE)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    const HEADER_SIZE: usize = 128;
+    let tag_table_size = 4 + 12 * tags.len();
+    let mut tag_table = Vec::with_capacity(tag_table_size);
+    tag_table.extend_from_slice(&u32::try_from(tags.len()).unwrap().to_be_bytes());
+    let mut tag_data: Vec<u8> = Vec::new();
+    let mut written: Vec<(usize, &[u8])> = Vec::new();
+    for (signature, data) in &tags {
+        let offset = match written.iter().find(|(_, existing)| *existing == data.as_slice()) {
+            Some((offset, _)) => *offset,
+            None => {
+                let offset = HEADER_SIZE + tag_table_size + tag_data.len();
+                tag_data.extend_from_slice(data);
+                // each tag starts on a four-byte boundary
+                tag_data.resize(tag_data.len().next_multiple_of(4), 0);
+                written.push((offset, data.as_slice()));
+                offset
+            },
+        };
+        tag_table.extend_from_slice(*signature);
+        tag_table.extend_from_slice(&u32::try_from(offset).unwrap().to_be_bytes());
+        tag_table.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+    }
+
+    let profile_size = HEADER_SIZE + tag_table.len() + tag_data.len();
+    let mut profile = Vec::with_capacity(profile_size);
+    profile.extend_from_slice(&u32::try_from(profile_size).unwrap().to_be_bytes());
+    // preferred CMM: none
+    profile.extend_from_slice(&[0; 4]);
+    // version 2.1
+    profile.extend_from_slice(&[0x02, 0x10, 0x00, 0x00]);
+    profile.extend_from_slice(b"mntr");
+    profile.extend_from_slice(b"RGB ");
+    profile.extend_from_slice(b"XYZ ");
+    // creation date and time: 2000-01-01 00:00:00, fixed for reproducibility
+    for value in [2000u16, 1, 1, 0, 0, 0] {
+        profile.extend_from_slice(&value.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    // platform, flags, manufacturer, model, attributes (8 bytes), rendering intent (perceptual)
+    profile.extend_from_slice(&[0; 4 + 4 + 4 + 4 + 8 + 4]);
+    for value in D50 {
+        push_s15_fixed16(value, &mut profile);
+    }
+    // creator: none; the rest is reserved
+    profile.resize(HEADER_SIZE, 0);
+
+    profile.extend_from_slice(&tag_table);
+    profile.extend_from_slice(&tag_data);
+    debug_assert_eq!(profile.len(), profile_size);
+    profile
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset+4].try_into().unwrap())
+    }
+
+    #[test]
+    fn generates_consistent_profile() {
+        let profile = srgb_profile();
+        assert_eq!(profile, srgb_profile());
+        assert_eq!(usize::try_from(read_u32(&profile, 0)).unwrap(), profile.len());
+        assert_eq!(&profile[12..20], b"mntrRGB ");
+        assert_eq!(&profile[36..40], b"acsp");
+
+        // each tag lies within the profile, starting on a four-byte boundary
+        let tag_count = usize::try_from(read_u32(&profile, 128)).unwrap();
+        assert_eq!(tag_count, 9);
+        let mut tags = Vec::new();
+        for i in 0..tag_count {
+            let entry = 132 + 12 * i;
+            let signature = &profile[entry..entry+4];
+            let offset = usize::try_from(read_u32(&profile, entry + 4)).unwrap();
+            let size = usize::try_from(read_u32(&profile, entry + 8)).unwrap();
+            assert_eq!(offset % 4, 0);
+            assert!(offset + size <= profile.len());
+            tags.push((signature, offset, size));
+        }
+
+        // the curves are shared and run from black to white
+        let curves: Vec<_> = tags.iter()
+            .filter(|(signature, _, _)| signature.ends_with(b"TRC"))
+            .map(|(_, offset, size)| (*offset, *size))
+            .collect();
+        assert_eq!(curves.len(), 3);
+        assert!(curves.iter().all(|curve| *curve == curves[0]));
+        let (curve_offset, curve_size) = curves[0];
+        assert_eq!(&profile[curve_offset..curve_offset+4], b"curv");
+        assert_eq!(curve_size, 12 + 2 * CURVE_SAMPLES);
+        assert_eq!(profile[curve_offset+12..curve_offset+14], [0x00, 0x00]);
+        assert_eq!(profile[curve_offset+curve_size-2..curve_offset+curve_size], [0xFF, 0xFF]);
+    }
+}
@@ -4,6 +4,7 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::Error as _;
+use sha3::digest::{Digest, DynDigest};
 
 
 /// An error pertaining to an image path.
@@ -72,6 +73,16 @@ impl ImagePath {
     pub fn to_relative_os_path(&self) -> String {
         self.as_str().replace("/", std::path::MAIN_SEPARATOR_STR)
     }
+
+    /// The last component of the path, i.e. the name of the file itself.
+    pub fn file_name(&self) -> &str {
+        self.as_str().rsplit('/').next().unwrap()
+    }
+
+    /// Attempts to decode the file name of this path as a [`ContentName`].
+    pub fn content_name(&self) -> Result<ContentName, ContentNameError> {
+        self.file_name().parse()
+    }
 }
 impl fmt::Display for ImagePath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -119,3 +130,263 @@ impl Serialize for ImagePath {
         str.serialize(serializer)
     }
 }
+
+
+/// An error pertaining to a content-derived file name.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ContentNameError {
+    /// The file name does not end with the `.jpeg` extension.
+    WrongExtension,
+
+    /// The file name does not contain a hyphen separating the digest from the size.
+    MissingSize,
+
+    /// The digest does not consist of the expected number of hexadecimal digits.
+    DigestLength { expected: usize, obtained: usize },
+
+    /// The digest contains a character that is not a lowercase hexadecimal digit.
+    DigestNotHex,
+
+    /// The size is not a decimal number.
+    SizeNotNumeric,
+}
+impl fmt::Display for ContentNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongExtension
+                => write!(f, "file name does not end with \".jpeg\""),
+            Self::MissingSize
+                => write!(f, "file name does not contain a size"),
+            Self::DigestLength { expected, obtained }
+                => write!(f, "digest has {} hex digits, expected {}", obtained, expected),
+            Self::DigestNotHex
+                => write!(f, "digest contains a character that is not a lowercase hex digit"),
+            Self::SizeNotNumeric
+                => write!(f, "size is not a decimal number"),
+        }
+    }
+}
+impl std::error::Error for ContentNameError {
+}
+
+/// The hash algorithm with which the digest in a [`ContentName`] is computed.
+///
+/// All algorithms produce 512-bit digests, so the resulting file names are indistinguishable; the
+/// algorithm is not recorded in the name. Files named using any of the algorithms can therefore be
+/// mixed in the same image directory.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum ContentHashAlgorithm {
+    /// SHA3-512, the algorithm originally used by pdfmcr.
+    #[default]
+    #[serde(rename = "sha3-512")]
+    Sha3_512,
+
+    /// SHA-512 from the SHA-2 family, which is considerably faster when computed in software.
+    #[serde(rename = "sha-512")]
+    Sha512,
+}
+impl ContentHashAlgorithm {
+    /// All the supported algorithms.
+    pub const ALL: [Self; 2] = [Self::Sha3_512, Self::Sha512];
+
+    /// Creates a hasher computing a digest using this algorithm.
+    pub fn new_hasher(&self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Self::Sha3_512 => Box::new(sha3::Sha3_512::new()),
+            Self::Sha512 => Box::new(sha2::Sha512::new()),
+        }
+    }
+}
+impl fmt::Display for ContentHashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha3_512 => write!(f, "SHA3-512"),
+            Self::Sha512 => write!(f, "SHA-512"),
+        }
+    }
+}
+
+/// A file name derived from the contents of an image file.
+///
+/// Such a file name consists of the digest of the file's data (see [`ContentHashAlgorithm`]) as
+/// 128 lowercase hexadecimal digits, a hyphen (`-`), the size of the file in bytes as a decimal
+/// number and the extension `.jpeg`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ContentName {
+    /// The 512-bit digest of the file's data.
+    pub digest: [u8; 64],
+
+    /// The size of the file, in bytes.
+    pub size: u64,
+}
+impl ContentName {
+    /// Returns the path at which an image with this name is stored when sharded into the given
+    /// number of directory levels.
+    ///
+    /// Each directory level is named after the next two hexadecimal digits of the digest, so the
+    /// number of levels is limited by the length of the digest.
+    pub fn to_sharded_path(&self, levels: usize) -> ImagePath {
+        let mut path = String::new();
+        for b in self.digest.iter().take(levels) {
+            path.push_str(&format!("{:02x}/", b));
+        }
+        path.push_str(&self.to_string());
+        ImagePath(path)
+    }
+}
+impl fmt::Display for ContentName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in &self.digest {
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, "-{}.jpeg", self.size)
+    }
+}
+impl FromStr for ContentName {
+    type Err = ContentNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stem = s.strip_suffix(".jpeg")
+            .ok_or(ContentNameError::WrongExtension)?;
+        let (hex_digest, size_str) = stem.split_once('-')
+            .ok_or(ContentNameError::MissingSize)?;
+
+        let mut digest = [0u8; 64];
+        if hex_digest.len() != 2 * digest.len() {
+            return Err(ContentNameError::DigestLength { expected: 2 * digest.len(), obtained: hex_digest.len() });
+        }
+        if !hex_digest.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            return Err(ContentNameError::DigestNotHex);
+        }
+        for (i, b) in digest.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex_digest[2*i..2*i+2], 16).unwrap();
+        }
+
+        if size_str.is_empty() || !size_str.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ContentNameError::SizeNotNumeric);
+        }
+        let size = size_str.parse()
+            .map_err(|_| ContentNameError::SizeNotNumeric)?;
+
+        Ok(Self {
+            digest,
+            size,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST_HEX: &str = concat!(
+        "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff",
+        "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff",
+    );
+
+    #[test]
+    fn parses_and_formats_content_names() {
+        let name = format!("{}-1234.jpeg", DIGEST_HEX);
+        let content_name: ContentName = name.parse().unwrap();
+        assert_eq!(content_name.digest[0..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(content_name.digest[63], 0xFF);
+        assert_eq!(content_name.size, 1234);
+        assert_eq!(content_name.to_string(), name);
+
+        let image_path: ImagePath = format!("a/b/{}", name).parse().unwrap();
+        assert_eq!(image_path.content_name(), Ok(content_name));
+    }
+
+    #[test]
+    fn refuses_malformed_content_names() {
+        fn parse(name: &str) -> Result<ContentName, ContentNameError> {
+            name.parse()
+        }
+
+        assert_eq!(parse(&format!("{}-1234.jpg", DIGEST_HEX)), Err(ContentNameError::WrongExtension));
+        assert_eq!(parse(&format!("{}.jpeg", DIGEST_HEX)), Err(ContentNameError::MissingSize));
+        assert_eq!(
+            parse(&format!("{}-1234.jpeg", &DIGEST_HEX[2..])),
+            Err(ContentNameError::DigestLength { expected: 128, obtained: 126 }),
+        );
+        assert_eq!(
+            parse(&format!("{}-1234.jpeg", DIGEST_HEX.to_uppercase())),
+            Err(ContentNameError::DigestNotHex),
+        );
+        assert_eq!(
+            parse(&format!("{}g-1234.jpeg", &DIGEST_HEX[1..])),
+            Err(ContentNameError::DigestNotHex),
+        );
+        assert_eq!(parse(&format!("{}-.jpeg", DIGEST_HEX)), Err(ContentNameError::SizeNotNumeric));
+        assert_eq!(parse(&format!("{}-+12.jpeg", DIGEST_HEX)), Err(ContentNameError::SizeNotNumeric));
+        assert_eq!(
+            parse(&format!("{}-99999999999999999999.jpeg", DIGEST_HEX)),
+            Err(ContentNameError::SizeNotNumeric),
+        );
+    }
+
+    #[test]
+    fn hashes_with_each_algorithm() {
+        fn hex_digest(algorithm: ContentHashAlgorithm, data: &[u8]) -> String {
+            let mut hasher = algorithm.new_hasher();
+            hasher.update(data);
+            let mut digest = [0u8; 64];
+            hasher.finalize_into_reset(&mut digest).unwrap();
+            ContentName { digest, size: 0 }.to_string()
+        }
+
+        assert_eq!(
+            hex_digest(ContentHashAlgorithm::Sha3_512, b"abc"),
+            concat!(
+                "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e",
+                "10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0",
+                "-0.jpeg",
+            ),
+        );
+        assert_eq!(
+            hex_digest(ContentHashAlgorithm::Sha512, b"abc"),
+            concat!(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a",
+                "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+                "-0.jpeg",
+            ),
+        );
+    }
+
+    fn content_name() -> ContentName {
+        let mut digest = [0u8; 64];
+        for (i, b) in digest.iter_mut().enumerate() {
+            *b = 0xAB_u8.wrapping_add(u8::try_from(i).unwrap());
+        }
+        ContentName { digest, size: 12345 }
+    }
+
+    #[test]
+    fn produces_sharded_path() {
+        let name = content_name();
+        let name_string = name.to_string();
+        assert!(name_string.starts_with("abacad"));
+
+        assert_eq!(name.to_sharded_path(0).as_str(), name_string);
+        let sharded = name.to_sharded_path(2);
+        assert_eq!(sharded.as_str(), format!("ab/ac/{}", name_string));
+        assert_eq!(sharded.file_name(), name_string);
+        assert_eq!(sharded.content_name(), Ok(name));
+
+        // the number of levels is limited by the length of the digest
+        assert_eq!(name.to_sharded_path(100).as_str().matches('/').count(), 64);
+    }
+
+    #[test]
+    fn sharded_path_is_valid_and_resolvable() {
+        let sharded = content_name().to_sharded_path(2);
+        let parsed: ImagePath = sharded.as_str().parse().unwrap();
+        assert_eq!(parsed, sharded);
+
+        let base_path = Path::new("images");
+        let os_path = sharded.to_os_path(base_path);
+        assert!(os_path.starts_with(base_path));
+        assert_eq!(os_path, base_path.join("ab").join("ac").join(sharded.file_name()));
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{self, Cursor, SeekFrom};
+use std::path::Path;
+
+use rocket::{Request, Response};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder};
+use rocket::tokio::fs::File;
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+
+/// The request headers influencing how an image is delivered.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct ImageRequestHeaders {
+    /// The value of the `Range` header, if any.
+    pub range: Option<String>,
+
+    /// The value of the `If-None-Match` header, if any.
+    pub if_none_match: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ImageRequestHeaders {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Self {
+            range: request.headers().get_one("Range").map(|r| r.to_owned()),
+            if_none_match: request.headers().get_one("If-None-Match").map(|inm| inm.to_owned()),
+        })
+    }
+}
+
+
+/// The outcome of interpreting a `Range` header.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum ByteRange {
+    /// The whole file is to be delivered.
+    ///
+    /// This is also the case if the header cannot be parsed or requests multiple ranges, both of
+    /// which servers are free to ignore.
+    Whole,
+
+    /// The bytes from the first to the last offset, inclusive, are to be delivered.
+    Partial { first: u64, last: u64 },
+
+    /// The requested range lies outside of the file.
+    Unsatisfiable,
+}
+
+/// Interprets the value of a `Range` header for a file of the given size.
+fn parse_byte_range(range: &str, size: u64) -> ByteRange {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Whole;
+    };
+    if spec.contains(',') {
+        // multiple ranges would require a multipart response; send everything instead
+        return ByteRange::Whole;
+    }
+    let Some((first_str, last_str)) = spec.trim().split_once('-') else {
+        return ByteRange::Whole;
+    };
+
+    if first_str.is_empty() {
+        // suffix range: the last n bytes
+        let Ok(suffix_length) = last_str.parse::<u64>() else {
+            return ByteRange::Whole;
+        };
+        if suffix_length == 0 || size == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        let first = size.saturating_sub(suffix_length);
+        return ByteRange::Partial { first, last: size - 1 };
+    }
+
+    let Ok(first) = first_str.parse::<u64>() else {
+        return ByteRange::Whole;
+    };
+    let last = if last_str.is_empty() {
+        size.saturating_sub(1)
+    } else {
+        match last_str.parse::<u64>() {
+            Ok(l) => l.min(size.saturating_sub(1)),
+            Err(_) => return ByteRange::Whole,
+        }
+    };
+    if first >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    if last < first {
+        // syntactically invalid; ignore it
+        return ByteRange::Whole;
+    }
+    ByteRange::Partial { first, last }
+}
+
+
+/// Whether the value of an `If-None-Match` header matches the given entity tag.
+///
+/// The comparison is weak, as required for `If-None-Match`: a `W/` prefix is disregarded.
+fn if_none_match_matches(if_none_match: &str, entity_tag: &str) -> bool {
+    let strip_weak = |tag: &str| tag.trim().strip_prefix("W/").unwrap_or(tag.trim()).to_owned();
+    let entity_tag = strip_weak(entity_tag);
+    if_none_match.split(',')
+        .any(|tag| tag.trim() == "*" || strip_weak(tag) == entity_tag)
+}
+
+
+/// A stored image, delivered in whole or in part.
+#[derive(Debug)]
+pub(crate) struct ImageResponse {
+    /// The entity tag of the image, including the quotes, if it is known.
+    entity_tag: Option<String>,
+
+    /// The data to deliver.
+    body: ImageBody,
+}
+
+#[derive(Debug)]
+enum ImageBody {
+    /// The whole image.
+    Whole { content_type: ContentType, file: File },
+
+    /// A range of bytes of the image, answering a `Range` request.
+    Partial { content_type: ContentType, data: Vec<u8>, first: u64, size: u64 },
+
+    /// The requested range lies outside of the image.
+    Unsatisfiable { size: u64 },
+
+    /// The client already has the current version of the image.
+    NotModified,
+}
+
+impl ImageResponse {
+    /// Opens the image at the given path, honoring the range and the entity tags requested in the
+    /// headers.
+    ///
+    /// `entity_tag` uniquely identifies the content of the image (e.g. by its checksum); it is
+    /// delivered within quotes as the `ETag` header.
+    pub async fn open(path: &Path, content_type: ContentType, entity_tag: Option<&str>, headers: &ImageRequestHeaders) -> Result<Self, io::Error> {
+        let entity_tag = entity_tag.map(|et| format!("\"{}\"", et));
+        let body = Self::open_body(path, content_type, entity_tag.as_deref(), headers).await?;
+        Ok(Self {
+            entity_tag,
+            body,
+        })
+    }
+
+    async fn open_body(path: &Path, content_type: ContentType, entity_tag: Option<&str>, headers: &ImageRequestHeaders) -> Result<ImageBody, io::Error> {
+        let not_modified = entity_tag.zip(headers.if_none_match.as_deref())
+            .is_some_and(|(entity_tag, if_none_match)| if_none_match_matches(if_none_match, entity_tag));
+        if not_modified {
+            return Ok(ImageBody::NotModified);
+        }
+
+        let mut file = File::open(path).await?;
+        let Some(range) = headers.range.as_deref() else {
+            return Ok(ImageBody::Whole { content_type, file });
+        };
+
+        let size = file.metadata().await?.len();
+        match parse_byte_range(range, size) {
+            ByteRange::Whole => Ok(ImageBody::Whole { content_type, file }),
+            ByteRange::Unsatisfiable => Ok(ImageBody::Unsatisfiable { size }),
+            ByteRange::Partial { first, last } => {
+                file.seek(SeekFrom::Start(first)).await?;
+                let length = usize::try_from(last - first + 1).unwrap();
+                let mut data = vec![0u8; length];
+                file.read_exact(&mut data).await?;
+                Ok(ImageBody::Partial { content_type, data, first, size })
+            },
+        }
+    }
+}
+impl<'r> Responder<'r, 'static> for ImageResponse {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let mut builder = Response::build();
+        builder.header(Header::new("Accept-Ranges", "bytes"));
+        if let Some(entity_tag) = self.entity_tag {
+            // the URL of a page image stays the same when the image is replaced, so the response
+            // must be revalidated every time; thanks to the entity tag, that's cheap
+            builder.header(Header::new("ETag", entity_tag));
+            builder.header(Header::new("Cache-Control", "private, no-cache"));
+        }
+        match self.body {
+            ImageBody::Whole { content_type, file } => {
+                builder
+                    .header(content_type)
+                    .sized_body(None, file);
+            },
+            ImageBody::Partial { content_type, data, first, size } => {
+                let last = first + u64::try_from(data.len()).unwrap() - 1;
+                builder
+                    .status(Status::PartialContent)
+                    .header(content_type)
+                    .header(Header::new("Content-Range", format!("bytes {}-{}/{}", first, last, size)))
+                    .sized_body(data.len(), Cursor::new(data));
+            },
+            ImageBody::Unsatisfiable { size } => {
+                builder
+                    .status(Status::RangeNotSatisfiable)
+                    .header(Header::new("Content-Range", format!("bytes */{}", size)));
+            },
+            ImageBody::NotModified => {
+                builder.status(Status::NotModified);
+            },
+        }
+        builder.ok()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteRange, if_none_match_matches, parse_byte_range};
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_byte_range("bytes=0-99", 1000), ByteRange::Partial { first: 0, last: 99 });
+        assert_eq!(parse_byte_range("bytes=900-", 1000), ByteRange::Partial { first: 900, last: 999 });
+        assert_eq!(parse_byte_range("bytes=-100", 1000), ByteRange::Partial { first: 900, last: 999 });
+        assert_eq!(parse_byte_range("bytes=500-5000", 1000), ByteRange::Partial { first: 500, last: 999 });
+    }
+
+    #[test]
+    fn ignores_unsupported_ranges() {
+        assert_eq!(parse_byte_range("bytes=0-9,20-29", 1000), ByteRange::Whole);
+        assert_eq!(parse_byte_range("lines=0-9", 1000), ByteRange::Whole);
+        assert_eq!(parse_byte_range("bytes=9-0", 1000), ByteRange::Whole);
+        assert_eq!(parse_byte_range("bytes=a-b", 1000), ByteRange::Whole);
+    }
+
+    #[test]
+    fn refuses_ranges_outside_of_file() {
+        assert_eq!(parse_byte_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_byte_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn matches_entity_tags() {
+        assert!(if_none_match_matches("\"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("W/\"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("\"xyz\", \"abc\"", "\"abc\""));
+        assert!(if_none_match_matches("*", "\"abc\""));
+        assert!(!if_none_match_matches("\"xyz\"", "\"abc\""));
+    }
+}
@@ -0,0 +1,160 @@
+//! Splitting multi-page sources into the scanned images of individual pages.
+
+
+use std::fmt;
+use std::io::Cursor;
+
+
+/// An error that can occur while splitting a multi-page source into pages.
+#[derive(Debug)]
+pub enum Error {
+    /// The source is neither a TIFF nor a PDF file.
+    UnknownFormat,
+
+    /// The TIFF file cannot be read or one of its images cannot be transcoded.
+    Tiff(crate::tiff::Error),
+
+    /// The PDF file cannot be read.
+    Pdf(lopdf::Error),
+
+    /// The page with the given index of the PDF file does not contain an image.
+    PageWithoutImage { page_index: usize },
+
+    /// The image on the page with the given index of the PDF file is not stored as a JPEG image.
+    NotJpeg { page_index: usize, filters: Option<Vec<String>> },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat
+                => write!(f, "source is neither a TIFF nor a PDF file"),
+            Self::Tiff(e)
+                => write!(f, "TIFF error: {}", e),
+            Self::Pdf(e)
+                => write!(f, "PDF error: {}", e),
+            Self::PageWithoutImage { page_index }
+                => write!(f, "page {} does not contain an image", page_index),
+            Self::NotJpeg { page_index, filters }
+                => write!(f, "image on page {} is not a JPEG image (filters {:?})", page_index, filters),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UnknownFormat => None,
+            Self::Tiff(e) => Some(e),
+            Self::Pdf(e) => Some(e),
+            Self::PageWithoutImage { .. } => None,
+            Self::NotJpeg { .. } => None,
+        }
+    }
+}
+impl From<crate::tiff::Error> for Error {
+    fn from(value: crate::tiff::Error) -> Self { Self::Tiff(value) }
+}
+impl From<lopdf::Error> for Error {
+    fn from(value: lopdf::Error) -> Self { Self::Pdf(value) }
+}
+
+
+/// The scanned image of a single page obtained from a multi-page source.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ImportedPage {
+    /// The image, as a JPEG file.
+    pub jpeg_data: Vec<u8>,
+
+    /// The size of the page in points (1/72 in), as `(width, height)`, if the source specifies it
+    /// independently of the image.
+    pub size_pt: Option<(u64, u64)>,
+}
+
+
+/// Whether the given data starts with a PDF header.
+pub fn is_pdf(data: &[u8]) -> bool {
+    data.starts_with(b"%PDF-")
+}
+
+
+/// Splits a multi-page TIFF or PDF file into its pages.
+pub fn split_pages(data: &[u8]) -> Result<Vec<ImportedPage>, Error> {
+    if crate::tiff::is_tiff(data) {
+        split_tiff(data)
+    } else if is_pdf(data) {
+        split_pdf(data)
+    } else {
+        Err(Error::UnknownFormat)
+    }
+}
+
+
+/// Splits a multi-frame TIFF file into its frames, transcoding each of them into a JPEG image.
+pub fn split_tiff(data: &[u8]) -> Result<Vec<ImportedPage>, Error> {
+    let images = crate::tiff::Image::try_read_all(Cursor::new(data))?;
+    let mut pages = Vec::with_capacity(images.len());
+    for image in &images {
+        pages.push(ImportedPage {
+            jpeg_data: image.to_jpeg()?,
+            size_pt: None,
+        });
+    }
+    Ok(pages)
+}
+
+
+/// Splits a PDF file into its pages, extracting the JPEG image of each page.
+///
+/// Every page must contain at least one JPEG image; if it contains multiple images, the largest one
+/// is taken as the scan of the page. The size of each page is taken from its media box.
+pub fn split_pdf(data: &[u8]) -> Result<Vec<ImportedPage>, Error> {
+    let document = lopdf::Document::load_mem(data)?;
+    let mut pages = Vec::new();
+    for (page_index, page_id) in document.page_iter().enumerate() {
+        let Some(jpeg_data) = pdf_page_jpeg_data(&document, page_index, page_id)? else {
+            return Err(Error::PageWithoutImage { page_index });
+        };
+        pages.push(ImportedPage {
+            jpeg_data,
+            size_pt: pdf_page_size_pt(&document, page_id),
+        });
+    }
+    Ok(pages)
+}
+
+
+/// Extracts the JPEG data of the largest image on a PDF page.
+///
+/// Returns `None` if the page does not contain any images.
+pub(crate) fn pdf_page_jpeg_data(document: &lopdf::Document, page_index: usize, page_id: lopdf::ObjectId) -> Result<Option<Vec<u8>>, Error> {
+    let images = document.get_page_images(page_id)
+        .unwrap_or_default();
+    let Some(image) = images.iter().max_by_key(|image| image.width.saturating_mul(image.height)) else {
+        return Ok(None);
+    };
+    let is_jpeg = image.filters.as_ref()
+        .is_some_and(|filters| filters.len() == 1 && filters[0] == "DCTDecode");
+    if !is_jpeg {
+        return Err(Error::NotJpeg { page_index, filters: image.filters.clone() });
+    }
+    Ok(Some(image.content.to_vec()))
+}
+
+
+/// Obtains the size of a PDF page in points from its media box.
+pub(crate) fn pdf_page_size_pt(document: &lopdf::Document, page_id: lopdf::ObjectId) -> Option<(u64, u64)> {
+    let page_dict = document.get_dictionary(page_id).ok()?;
+    let media_box = page_dict.get(b"MediaBox").ok()?.as_array().ok()?;
+    if media_box.len() != 4 {
+        return None;
+    }
+    let mut coordinates = [0.0f32; 4];
+    for (coordinate, value) in coordinates.iter_mut().zip(media_box.iter()) {
+        *coordinate = value.as_float().ok()?;
+    }
+    let width = (coordinates[2] - coordinates[0]).abs().round();
+    let height = (coordinates[3] - coordinates[1]).abs().round();
+    if width < 1.0 || height < 1.0 {
+        return None;
+    }
+    Some((width as u64, height as u64))
+}
@@ -0,0 +1,852 @@
+//! Decoding and re-encoding of baseline JPEG images.
+//!
+//! Only sequential images using Huffman coding with 8 bits per sample and one (grayscale) or three
+//! (YCbCr or RGB) components are supported, which covers the output of practically all scanners.
+
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::jpeg::{DensityUnit, Image};
+
+
+/// The order in which the coefficients of a block are stored in the entropy-coded data.
+const ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The factors of the inverse discrete cosine transform, indexed by sample position and frequency.
+static IDCT_FACTORS: OnceLock<[[f64; 8]; 8]> = OnceLock::new();
+
+/// The largest number of samples decoded for a single component, limiting the memory taken up by
+/// images with absurd dimensions.
+const MAX_COMPONENT_SAMPLES: usize = 1 << 28;
+
+
+/// An error that can occur while decoding or re-encoding a JPEG image.
+#[derive(Debug)]
+pub enum Error {
+    /// The image is not a sequential image using Huffman coding.
+    UnsupportedFrameType { kind: u8 },
+
+    /// The image does not use 8 bits per sample.
+    UnsupportedBitDepth { bit_depth: u8 },
+
+    /// The image has a number of components that cannot be decoded.
+    UnsupportedComponentCount { count: u8 },
+
+    /// The image is too large to be decoded in memory.
+    ImageTooLarge { width: usize, height: usize },
+
+    /// A segment of the given kind is too short for its contents.
+    TruncatedSegment { kind: u8 },
+
+    /// A segment of the given kind contains an invalid value.
+    InvalidSegment { kind: u8 },
+
+    /// The image has no frame header.
+    MissingFrameHeader,
+
+    /// A scan references a component that is not defined by the frame header.
+    UnknownComponent { id: u8 },
+
+    /// A scan references a quantization table that has not been defined.
+    MissingQuantizationTable { index: u8 },
+
+    /// A scan references a Huffman table that has not been defined.
+    MissingHuffmanTable { class: u8, index: u8 },
+
+    /// The entropy-coded data contains a code that is not part of its Huffman table.
+    InvalidHuffmanCode,
+
+    /// The image has no scan header.
+    MissingScanHeader,
+
+    /// A restart marker was expected but not found in the entropy-coded data.
+    MissingRestartMarker,
+
+    /// The re-encoded image could not be encoded.
+    Encoding(jpeg_encoder::EncodingError),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFrameType { kind }
+                => write!(f, "unsupported frame type 0x{:02X}; only sequential Huffman-coded images are supported", kind),
+            Self::UnsupportedBitDepth { bit_depth }
+                => write!(f, "unsupported bit depth {}; only 8 bits per sample are supported", bit_depth),
+            Self::UnsupportedComponentCount { count }
+                => write!(f, "unsupported number of components {}", count),
+            Self::ImageTooLarge { width, height }
+                => write!(f, "image of {}x{} pixels is too large to be decoded", width, height),
+            Self::TruncatedSegment { kind }
+                => write!(f, "segment 0x{:02X} is truncated", kind),
+            Self::InvalidSegment { kind }
+                => write!(f, "segment 0x{:02X} contains an invalid value", kind),
+            Self::MissingFrameHeader
+                => write!(f, "image has no frame header"),
+            Self::UnknownComponent { id }
+                => write!(f, "scan references unknown component {}", id),
+            Self::MissingQuantizationTable { index }
+                => write!(f, "quantization table {} is not defined", index),
+            Self::MissingHuffmanTable { class, index }
+                => write!(f, "Huffman table {} of class {} is not defined", index, class),
+            Self::InvalidHuffmanCode
+                => write!(f, "invalid Huffman code in entropy-coded data"),
+            Self::MissingScanHeader
+                => write!(f, "image has no scan header"),
+            Self::MissingRestartMarker
+                => write!(f, "expected restart marker not found"),
+            Self::Encoding(e)
+                => write!(f, "encoding error: {}", e),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UnsupportedFrameType { .. } => None,
+            Self::UnsupportedBitDepth { .. } => None,
+            Self::UnsupportedComponentCount { .. } => None,
+            Self::ImageTooLarge { .. } => None,
+            Self::TruncatedSegment { .. } => None,
+            Self::InvalidSegment { .. } => None,
+            Self::MissingFrameHeader => None,
+            Self::UnknownComponent { .. } => None,
+            Self::MissingQuantizationTable { .. } => None,
+            Self::MissingHuffmanTable { .. } => None,
+            Self::InvalidHuffmanCode => None,
+            Self::MissingScanHeader => None,
+            Self::MissingRestartMarker => None,
+            Self::Encoding(e) => Some(e),
+        }
+    }
+}
+impl From<jpeg_encoder::EncodingError> for Error {
+    fn from(value: jpeg_encoder::EncodingError) -> Self { Self::Encoding(value) }
+}
+
+
+/// The samples of a decoded image.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DecodedImage {
+    pub width: u16,
+    pub height: u16,
+
+    /// The number of components of each pixel.
+    pub component_count: u8,
+
+    /// The samples of the image, row by row, with the components of each pixel next to each other.
+    ///
+    /// The components are given in the color space in which they are stored, i.e. usually YCbCr for
+    /// color images.
+    pub samples: Vec<u8>,
+}
+
+
+/// A Huffman table, prepared for decoding.
+#[derive(Clone, Debug, Default)]
+struct HuffmanTable {
+    /// For each code length (1 through 16), the largest code of that length, or -1 if there is none.
+    max_code: [i32; 17],
+
+    /// For each code length, the index into `values` of the first code of that length minus that
+    /// code.
+    value_offset: [i32; 17],
+
+    values: Vec<u8>,
+}
+impl HuffmanTable {
+    fn new(counts: &[u8; 16], values: Vec<u8>) -> Self {
+        let mut max_code = [-1; 17];
+        let mut value_offset = [0; 17];
+        let mut code: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=16 {
+            let count = i32::from(counts[length - 1]);
+            if count > 0 {
+                value_offset[length] = index - code;
+                code += count;
+                index += count;
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        Self {
+            max_code,
+            value_offset,
+            values,
+        }
+    }
+}
+
+/// A component of the frame.
+#[derive(Clone, Debug)]
+struct Component {
+    id: u8,
+    horizontal_sampling: usize,
+    vertical_sampling: usize,
+    quantization_table: u8,
+
+    /// The width of the sample plane, in blocks.
+    width_blocks: usize,
+
+    /// The height of the sample plane, in blocks.
+    height_blocks: usize,
+
+    /// The samples, `8 * width_blocks` per row.
+    samples: Vec<u8>,
+}
+
+/// Reads bits from entropy-coded data, skipping stuffed bytes.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u32,
+    bit_count: u32,
+
+    /// Whether a marker has been reached; no further bytes are consumed until the reader is reset.
+    at_marker: bool,
+}
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self {
+            data,
+            position,
+            bits: 0,
+            bit_count: 0,
+            at_marker: false,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.bit_count <= 24 {
+            let byte = if self.at_marker {
+                // pad with ones, as encoders do; a well-formed scan never needs these bits
+                0xFF
+            } else if self.position >= self.data.len() {
+                // the data ends without a marker; treat the end like one
+                self.at_marker = true;
+                0xFF
+            } else if self.data[self.position] == 0xFF {
+                match self.data.get(self.position + 1) {
+                    Some(0x00) => {
+                        self.position += 2;
+                        0xFF
+                    },
+                    _ => {
+                        self.at_marker = true;
+                        0xFF
+                    },
+                }
+            } else {
+                self.position += 1;
+                self.data[self.position - 1]
+            };
+            self.bits |= u32::from(byte) << (24 - self.bit_count);
+            self.bit_count += 8;
+        }
+    }
+
+    fn read_bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        self.fill();
+        let value = self.bits >> (32 - count);
+        self.bits <<= count;
+        self.bit_count -= count;
+        value
+    }
+
+    fn decode_huffman(&mut self, table: &HuffmanTable) -> Result<u8, Error> {
+        let mut code: i32 = 0;
+        for length in 1..=16 {
+            code = (code << 1) | i32::try_from(self.read_bits(1)).unwrap();
+            if code <= table.max_code[length] {
+                let index = usize::try_from(table.value_offset[length] + code)
+                    .map_err(|_| Error::InvalidHuffmanCode)?;
+                return table.values.get(index)
+                    .copied()
+                    .ok_or(Error::InvalidHuffmanCode);
+            }
+        }
+        Err(Error::InvalidHuffmanCode)
+    }
+
+    /// Reads a value of the given bit length and extends its sign.
+    fn receive_extend(&mut self, length: u8) -> Result<i32, Error> {
+        if length == 0 {
+            return Ok(0);
+        }
+        if length > 16 {
+            return Err(Error::InvalidHuffmanCode);
+        }
+        let length = u32::from(length);
+        let value = i32::try_from(self.read_bits(length)).unwrap();
+        if value < (1 << (length - 1)) {
+            Ok(value - (1 << length) + 1)
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Skips to the next marker, returning its kind and moving past it.
+    fn take_marker(&mut self) -> Option<u8> {
+        // drop any buffered bits, then find the marker
+        self.bits = 0;
+        self.bit_count = 0;
+        self.at_marker = false;
+        while self.position + 1 < self.data.len() {
+            if self.data[self.position] == 0xFF && !matches!(self.data[self.position + 1], 0x00|0xFF) {
+                let kind = self.data[self.position + 1];
+                self.position += 2;
+                return Some(kind);
+            }
+            self.position += 1;
+        }
+        None
+    }
+}
+
+/// The state of the decoder, collected from the segments of the image.
+#[derive(Default)]
+struct Decoder {
+    width: usize,
+    height: usize,
+    max_horizontal_sampling: usize,
+    max_vertical_sampling: usize,
+    components: Vec<Component>,
+    quantization_tables: [Option<[u16; 64]>; 4],
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    restart_interval: u16,
+}
+impl Decoder {
+    fn process_segment(&mut self, kind: u8, data: &[u8]) -> Result<(), Error> {
+        let truncated = || Error::TruncatedSegment { kind };
+        let invalid = || Error::InvalidSegment { kind };
+        match kind {
+            0xC0|0xC1 => {
+                if !self.components.is_empty() {
+                    // only the first frame header describes the image
+                    return Ok(());
+                }
+                if data.len() < 6 {
+                    return Err(truncated());
+                }
+                if data[0] != 8 {
+                    return Err(Error::UnsupportedBitDepth { bit_depth: data[0] });
+                }
+                self.height = usize::from(u16::from_be_bytes(data[1..3].try_into().unwrap()));
+                self.width = usize::from(u16::from_be_bytes(data[3..5].try_into().unwrap()));
+                let component_count = data[5];
+                if component_count != 1 && component_count != 3 {
+                    return Err(Error::UnsupportedComponentCount { count: component_count });
+                }
+                let component_data = data.get(6..6 + 3*usize::from(component_count))
+                    .ok_or_else(truncated)?;
+                for chunk in component_data.chunks(3) {
+                    let horizontal_sampling = usize::from(chunk[1] >> 4);
+                    let vertical_sampling = usize::from(chunk[1] & 0x0F);
+                    if !(1..=4).contains(&horizontal_sampling) || !(1..=4).contains(&vertical_sampling) || chunk[2] > 3 {
+                        return Err(invalid());
+                    }
+                    self.components.push(Component {
+                        id: chunk[0],
+                        horizontal_sampling,
+                        vertical_sampling,
+                        quantization_table: chunk[2],
+                        width_blocks: 0,
+                        height_blocks: 0,
+                        samples: Vec::new(),
+                    });
+                }
+                if self.width == 0 || self.height == 0 {
+                    // the height might be defined later using a DNL segment; we do not support this
+                    return Err(invalid());
+                }
+
+                self.max_horizontal_sampling = self.components.iter().map(|c| c.horizontal_sampling).max().unwrap();
+                self.max_vertical_sampling = self.components.iter().map(|c| c.vertical_sampling).max().unwrap();
+                let (mcus_x, mcus_y) = self.mcu_counts();
+                for component in &mut self.components {
+                    component.width_blocks = mcus_x * component.horizontal_sampling;
+                    component.height_blocks = mcus_y * component.vertical_sampling;
+                    let sample_count = component.width_blocks.checked_mul(component.height_blocks)
+                        .and_then(|blocks| blocks.checked_mul(64))
+                        .filter(|count| *count <= MAX_COMPONENT_SAMPLES)
+                        .ok_or(Error::ImageTooLarge { width: self.width, height: self.height })?;
+                    component.samples = vec![0; sample_count];
+                }
+            },
+            0xC2|0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF => {
+                return Err(Error::UnsupportedFrameType { kind });
+            },
+            0xC4 => {
+                let mut rest = data;
+                while !rest.is_empty() {
+                    if rest.len() < 17 {
+                        return Err(truncated());
+                    }
+                    let class = rest[0] >> 4;
+                    let index = usize::from(rest[0] & 0x0F);
+                    if class > 1 || index > 3 {
+                        return Err(invalid());
+                    }
+                    let counts: [u8; 16] = rest[1..17].try_into().unwrap();
+                    let value_count: usize = counts.iter().map(|c| usize::from(*c)).sum();
+                    let values = rest.get(17..17 + value_count)
+                        .ok_or_else(truncated)?
+                        .to_vec();
+                    let table = HuffmanTable::new(&counts, values);
+                    if class == 0 {
+                        self.dc_tables[index] = Some(table);
+                    } else {
+                        self.ac_tables[index] = Some(table);
+                    }
+                    rest = &rest[17 + value_count..];
+                }
+            },
+            0xDB => {
+                let mut rest = data;
+                while !rest.is_empty() {
+                    let sixteen_bit = rest[0] >> 4 != 0;
+                    let index = usize::from(rest[0] & 0x0F);
+                    if index > 3 {
+                        return Err(invalid());
+                    }
+                    let mut table = [0u16; 64];
+                    if sixteen_bit {
+                        let values = rest.get(1..129).ok_or_else(truncated)?;
+                        for (entry, bytes) in table.iter_mut().zip(values.chunks(2)) {
+                            *entry = u16::from_be_bytes([bytes[0], bytes[1]]);
+                        }
+                        rest = &rest[129..];
+                    } else {
+                        let values = rest.get(1..65).ok_or_else(truncated)?;
+                        for (entry, value) in table.iter_mut().zip(values) {
+                            *entry = (*value).into();
+                        }
+                        rest = &rest[65..];
+                    }
+                    self.quantization_tables[index] = Some(table);
+                }
+            },
+            0xDD => {
+                if data.len() < 2 {
+                    return Err(truncated());
+                }
+                self.restart_interval = u16::from_be_bytes(data[0..2].try_into().unwrap());
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// The number of MCUs of an interleaved scan, horizontally and vertically.
+    fn mcu_counts(&self) -> (usize, usize) {
+        (
+            self.width.div_ceil(8 * self.max_horizontal_sampling),
+            self.height.div_ceil(8 * self.max_vertical_sampling),
+        )
+    }
+
+    /// Decodes a scan whose header is given, starting at the given position of the data.
+    ///
+    /// Returns the position of the first byte after the scan.
+    fn decode_scan(&mut self, header: &[u8], data: &[u8], position: usize) -> Result<usize, Error> {
+        if self.components.is_empty() {
+            return Err(Error::MissingFrameHeader);
+        }
+        let scan_component_count = usize::from(*header.first().ok_or(Error::TruncatedSegment { kind: 0xDA })?);
+        let scan_component_data = header.get(1..1 + 2*scan_component_count)
+            .ok_or(Error::TruncatedSegment { kind: 0xDA })?;
+
+        // resolve the components and tables of the scan
+        let mut scan_components = Vec::with_capacity(scan_component_count);
+        for chunk in scan_component_data.chunks(2) {
+            let component_index = self.components.iter()
+                .position(|c| c.id == chunk[0])
+                .ok_or(Error::UnknownComponent { id: chunk[0] })?;
+            let dc_index = chunk[1] >> 4;
+            let ac_index = chunk[1] & 0x0F;
+            if self.dc_tables.get(usize::from(dc_index)).and_then(|t| t.as_ref()).is_none() {
+                return Err(Error::MissingHuffmanTable { class: 0, index: dc_index });
+            }
+            if self.ac_tables.get(usize::from(ac_index)).and_then(|t| t.as_ref()).is_none() {
+                return Err(Error::MissingHuffmanTable { class: 1, index: ac_index });
+            }
+            let quantization_index = self.components[component_index].quantization_table;
+            if self.quantization_tables[usize::from(quantization_index)].is_none() {
+                return Err(Error::MissingQuantizationTable { index: quantization_index });
+            }
+            scan_components.push((component_index, usize::from(dc_index), usize::from(ac_index)));
+        }
+
+        // list the blocks making up each MCU of the scan, as (scan component, block x, block y)
+        let mut mcus: Vec<Vec<(usize, usize, usize)>> = Vec::new();
+        if scan_components.len() == 1 {
+            // non-interleaved: each MCU is a single block; only blocks within the image are coded
+            let component = &self.components[scan_components[0].0];
+            let component_width = (self.width * component.horizontal_sampling).div_ceil(self.max_horizontal_sampling);
+            let component_height = (self.height * component.vertical_sampling).div_ceil(self.max_vertical_sampling);
+            for block_y in 0..component_height.div_ceil(8) {
+                for block_x in 0..component_width.div_ceil(8) {
+                    mcus.push(vec![(0, block_x, block_y)]);
+                }
+            }
+        } else {
+            let (mcus_x, mcus_y) = self.mcu_counts();
+            for mcu_y in 0..mcus_y {
+                for mcu_x in 0..mcus_x {
+                    let mut blocks = Vec::new();
+                    for (scan_index, (component_index, _, _)) in scan_components.iter().enumerate() {
+                        let component = &self.components[*component_index];
+                        for v in 0..component.vertical_sampling {
+                            for h in 0..component.horizontal_sampling {
+                                blocks.push((
+                                    scan_index,
+                                    mcu_x * component.horizontal_sampling + h,
+                                    mcu_y * component.vertical_sampling + v,
+                                ));
+                            }
+                        }
+                    }
+                    mcus.push(blocks);
+                }
+            }
+        }
+
+        let mut reader = BitReader::new(data, position);
+        let mut predictors = vec![0i32; scan_components.len()];
+        let restart_interval = usize::from(self.restart_interval);
+        let mut coefficients = [0i32; 64];
+        for (mcu_index, blocks) in mcus.iter().enumerate() {
+            if restart_interval > 0 && mcu_index > 0 && mcu_index % restart_interval == 0 {
+                match reader.take_marker() {
+                    Some(0xD0..=0xD7) => {},
+                    _ => return Err(Error::MissingRestartMarker),
+                }
+                predictors.fill(0);
+            }
+
+            for &(scan_index, block_x, block_y) in blocks {
+                let (component_index, dc_index, ac_index) = scan_components[scan_index];
+                let dc_table = self.dc_tables[dc_index].as_ref().unwrap();
+                let ac_table = self.ac_tables[ac_index].as_ref().unwrap();
+                let quantization_table = self.quantization_tables[usize::from(self.components[component_index].quantization_table)]
+                    .as_ref().unwrap();
+
+                coefficients.fill(0);
+                let dc_length = reader.decode_huffman(dc_table)?;
+                // corrupt data can drive the predictor arbitrarily far; its value is clamped below
+                predictors[scan_index] = predictors[scan_index].wrapping_add(reader.receive_extend(dc_length)?);
+                coefficients[0] = dequantize(predictors[scan_index], quantization_table[0]);
+                let mut k = 1;
+                while k < 64 {
+                    let run_size = reader.decode_huffman(ac_table)?;
+                    let run = usize::from(run_size >> 4);
+                    let size = run_size & 0x0F;
+                    if size == 0 {
+                        if run == 15 {
+                            // sixteen zeros
+                            k += 16;
+                            continue;
+                        }
+                        // end of block
+                        break;
+                    }
+                    k += run;
+                    if k >= 64 {
+                        return Err(Error::InvalidHuffmanCode);
+                    }
+                    coefficients[ZIGZAG[k]] = dequantize(reader.receive_extend(size)?, quantization_table[k]);
+                    k += 1;
+                }
+
+                let component = &mut self.components[component_index];
+                if block_x < component.width_blocks && block_y < component.height_blocks {
+                    let stride = 8 * component.width_blocks;
+                    let offset = 8 * block_y * stride + 8 * block_x;
+                    inverse_dct(&coefficients, &mut component.samples[offset..], stride);
+                }
+            }
+        }
+
+        // the scan ends at the next marker that is not a restart marker
+        loop {
+            match reader.take_marker() {
+                Some(0xD0..=0xD7) => continue,
+                Some(_) => return Ok(reader.position - 2),
+                None => return Ok(data.len()),
+            }
+        }
+    }
+
+    /// Combines the sample planes of the components into one buffer, upsampling as necessary.
+    fn into_decoded_image(self) -> DecodedImage {
+        let component_count = self.components.len();
+        let mut samples = Vec::with_capacity(self.width * self.height * component_count);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for component in &self.components {
+                    let component_x = x * component.horizontal_sampling / self.max_horizontal_sampling;
+                    let component_y = y * component.vertical_sampling / self.max_vertical_sampling;
+                    let stride = 8 * component.width_blocks;
+                    samples.push(component.samples[component_y * stride + component_x]);
+                }
+            }
+        }
+        DecodedImage {
+            width: self.width.try_into().unwrap(),
+            height: self.height.try_into().unwrap(),
+            component_count: component_count.try_into().unwrap(),
+            samples,
+        }
+    }
+}
+
+/// Multiplies a coefficient by its quantizer, clamping the result to the range of valid
+/// coefficients.
+fn dequantize(value: i32, quantizer: u16) -> i32 {
+    value.saturating_mul(quantizer.into())
+        .clamp(i16::MIN.into(), i16::MAX.into())
+}
+
+/// Transforms a block of dequantized coefficients into samples, storing them with the given stride.
+fn inverse_dct(coefficients: &[i32; 64], output: &mut [u8], stride: usize) {
+    let factors = IDCT_FACTORS.get_or_init(|| {
+        // cos((2x + 1) u pi / 16), scaled by C(u) / 2
+        let mut factors = [[0f64; 8]; 8];
+        for (x, row) in factors.iter_mut().enumerate() {
+            for (u, factor) in row.iter_mut().enumerate() {
+                let scale = if u == 0 { std::f64::consts::FRAC_1_SQRT_2 } else { 1.0 };
+                *factor = scale / 2.0 * ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.0).cos();
+            }
+        }
+        factors
+    });
+
+    // transform the rows, then the columns
+    let mut intermediate = [0f64; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            intermediate[v * 8 + x] = (0..8)
+                .map(|u| factors[x][u] * f64::from(coefficients[v * 8 + u]))
+                .sum();
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let value: f64 = (0..8)
+                .map(|v| factors[y][v] * intermediate[v * 8 + x])
+                .sum();
+            output[y * stride + x] = (value.round() + 128.0).clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+
+/// Decodes the samples of a JPEG image.
+pub fn decode(image: &Image) -> Result<DecodedImage, Error> {
+    let mut decoder = Decoder::default();
+
+    // the leading blocks end with the header of the first scan
+    let mut scan_header = None;
+    for block in &image.leading_blocks {
+        if block.kind() == 0xDA {
+            scan_header = Some(block.data());
+        } else {
+            decoder.process_segment(block.kind(), block.data())?;
+        }
+    }
+    let Some(mut scan_header) = scan_header else {
+        return Err(Error::MissingScanHeader);
+    };
+
+    // the image data contains the first scan, possibly followed by further tables and scans
+    let data = image.image_data.as_slice();
+    let mut position = 0;
+    loop {
+        position = decoder.decode_scan(scan_header, data, position)?;
+        if position >= data.len() {
+            break;
+        }
+
+        // process the segments up to the next scan
+        let mut next_scan_header = None;
+        while position + 4 <= data.len() && data[position] == 0xFF {
+            let kind = data[position + 1];
+            let length = usize::from(u16::from_be_bytes([data[position + 2], data[position + 3]]));
+            let segment = data.get(position + 4..position + 2 + length)
+                .ok_or(Error::TruncatedSegment { kind })?;
+            position += 2 + length;
+            if kind == 0xDA {
+                next_scan_header = Some(segment);
+                break;
+            }
+            decoder.process_segment(kind, segment)?;
+        }
+        match next_scan_header {
+            Some(h) => scan_header = h,
+            None => break,
+        }
+    }
+
+    Ok(decoder.into_decoded_image())
+}
+
+/// Decodes a JPEG image and encodes it again with the given quality (1 to 100).
+///
+/// The dimensions and pixel density of the image are retained, as are its application-specific
+/// segments (e.g. Exif metadata and ICC profiles) except for the JFIF and Adobe segments, which are
+/// generated anew.
+pub fn reencode(image: &Image, quality: u8) -> Result<Vec<u8>, Error> {
+    let decoded = decode(image)?;
+    let color_type = match (decoded.component_count, image.adobe_transform) {
+        (1, _) => jpeg_encoder::ColorType::Luma,
+        // Adobe transform 0 means that the components are stored without conversion
+        (3, Some(0)) => jpeg_encoder::ColorType::Rgb,
+        (3, _) => jpeg_encoder::ColorType::Ycbcr,
+        (count, _) => return Err(Error::UnsupportedComponentCount { count }),
+    };
+    let density = match image.density_unit {
+        DensityUnit::DotsPerInch => jpeg_encoder::Density::Inch { x: image.density_x, y: image.density_y },
+        DensityUnit::DotsPerCentimeter => jpeg_encoder::Density::Centimeter { x: image.density_x, y: image.density_y },
+        _ => jpeg_encoder::Density::None,
+    };
+
+    let mut jpeg_data = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg_data, quality);
+    encoder.set_density(density);
+    for block in &image.leading_blocks {
+        let Some(segment_number) = block.app_marker_number() else {
+            continue;
+        };
+        if segment_number == 0 || segment_number == 14 {
+            continue;
+        }
+        encoder.add_app_segment(segment_number, block.data())?;
+    }
+    encoder.encode(&decoded.samples, decoded.width, decoded.height, color_type)?;
+    Ok(jpeg_data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(samples: &[u8], width: u16, height: u16, color_type: jpeg_encoder::ColorType, configure: impl FnOnce(&mut jpeg_encoder::Encoder<&mut Vec<u8>>)) -> Image {
+        let mut jpeg_data = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg_data, 100);
+        configure(&mut encoder);
+        encoder.encode(samples, width, height, color_type).unwrap();
+        Image::try_read(jpeg_data.as_slice()).unwrap()
+    }
+
+    fn assert_samples_close(decoded: &[u8], expected: &[u8], tolerance: u8) {
+        assert_eq!(decoded.len(), expected.len());
+        for (index, (d, e)) in decoded.iter().zip(expected).enumerate() {
+            assert!(d.abs_diff(*e) <= tolerance, "sample {} is {} instead of {}", index, d, e);
+        }
+    }
+
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        let mut samples = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                samples.push((x * 6 + y * 2).min(255) as u8);
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn decodes_grayscale() {
+        // dimensions that are not multiples of the block size
+        let expected = gradient(37, 21);
+        let image = encode(&expected, 37, 21, jpeg_encoder::ColorType::Luma, |_| {});
+        let decoded = decode(&image).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.component_count), (37, 21, 1));
+        assert_samples_close(&decoded.samples, &expected, 3);
+    }
+
+    #[test]
+    fn decodes_subsampled_color() {
+        let luma = gradient(20, 12);
+        let expected: Vec<u8> = luma.iter()
+            .flat_map(|y| [*y, 90, 170])
+            .collect();
+        let image = encode(&expected, 20, 12, jpeg_encoder::ColorType::Ycbcr, |encoder| {
+            encoder.set_sampling_factor(jpeg_encoder::SamplingFactor::F_2_2);
+        });
+        let decoded = decode(&image).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.component_count), (20, 12, 3));
+        assert_samples_close(&decoded.samples, &expected, 4);
+    }
+
+    #[test]
+    fn decodes_with_restart_markers() {
+        let expected = gradient(40, 16);
+        let image = encode(&expected, 40, 16, jpeg_encoder::ColorType::Luma, |encoder| {
+            encoder.set_restart_interval(1);
+        });
+        let decoded = decode(&image).unwrap();
+        assert_samples_close(&decoded.samples, &expected, 3);
+    }
+
+    #[test]
+    fn refuses_progressive() {
+        let samples = gradient(16, 16);
+        let image = encode(&samples, 16, 16, jpeg_encoder::ColorType::Luma, |encoder| {
+            encoder.set_progressive(true);
+        });
+        assert!(matches!(decode(&image), Err(Error::UnsupportedFrameType { kind: 0xC2 })));
+    }
+
+    #[test]
+    fn refuses_truncated_frame_header() {
+        let mut decoder = Decoder::default();
+        let result = decoder.process_segment(0xC0, &[8, 0, 16, 0, 16]);
+        assert!(matches!(result, Err(Error::TruncatedSegment { kind: 0xC0 })));
+
+        // three components announced, only one given
+        let mut decoder = Decoder::default();
+        let result = decoder.process_segment(0xC0, &[8, 0, 16, 0, 16, 3, 1, 0x11, 0]);
+        assert!(matches!(result, Err(Error::TruncatedSegment { kind: 0xC0 })));
+    }
+
+    #[test]
+    fn refuses_truncated_tables() {
+        let mut decoder = Decoder::default();
+        let result = decoder.process_segment(0xC4, &[0x00, 1, 0, 0]);
+        assert!(matches!(result, Err(Error::TruncatedSegment { kind: 0xC4 })));
+
+        let mut decoder = Decoder::default();
+        let result = decoder.process_segment(0xDB, &[0x00, 1, 2, 3]);
+        assert!(matches!(result, Err(Error::TruncatedSegment { kind: 0xDB })));
+    }
+
+    #[test]
+    fn refuses_oversize_frame() {
+        let mut decoder = Decoder::default();
+        let result = decoder.process_segment(0xC0, &[8, 0xFF, 0xFF, 0xFF, 0xFF, 1, 1, 0x44, 0]);
+        assert!(matches!(result, Err(Error::ImageTooLarge { width: 65535, height: 65535 })));
+    }
+
+    #[test]
+    fn clamps_dequantized_coefficients() {
+        assert_eq!(dequantize(i32::MAX, u16::MAX), i32::from(i16::MAX));
+        assert_eq!(dequantize(i32::MIN, u16::MAX), i32::from(i16::MIN));
+        assert_eq!(dequantize(-3, 7), -21);
+    }
+}
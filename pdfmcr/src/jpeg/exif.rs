@@ -1,538 +1,204 @@
 //! Parsing the Extensible Image File Format (Exif).
 
 
-use std::fmt;
-use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::io::Cursor;
 
-use from_to_repr::from_to_other;
+use crate::jpeg::DensityUnit;
+use crate::tiff::Values;
 
-use crate::jpeg::{DensityUnit, ImageBuilder};
 
+/// The pixel density assumed by Exif if an image does not record its resolution, in dots per inch.
+pub(crate) const DEFAULT_RESOLUTION_DPI: u16 = 72;
 
-#[derive(Debug)]
-pub enum Error {
-    ByteOrderIndicator { bytes: [u8; 2] },
-    Version { version: u16 },
-    BigPointerSize { size: u16 },
-    BigReserved { value: u16 },
-    UnknownType { data_type: ValueType },
-}
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::ByteOrderIndicator { bytes }
-                => write!(f, "unrecognized byte order indicator 0x{:02X} 0x{:02X}", bytes[0], bytes[1]),
-            Self::Version { version }
-                => write!(f, "unknown TIFF version {}", version),
-            Self::BigPointerSize { size }
-                => write!(f, "unexpected BigTIFF pointer size {}", size),
-            Self::BigReserved { value }
-                => write!(f, "unexpected BigTIFF reserved value {}", value),
-            Self::UnknownType { data_type }
-                => write!(f, "unknown data type {:?}", data_type),
-        }
-    }
-}
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Self::ByteOrderIndicator { .. } => None,
-            Self::Version { .. } => None,
-            Self::BigPointerSize { .. } => None,
-            Self::BigReserved { .. } => None,
-            Self::UnknownType { .. } => None,
-        }
-    }
-}
 
+/// Reads the pixel density recorded in an Exif block, as `(unit, x, y)`.
+///
+/// Returns `None` if the block does not record a horizontal resolution; the resolution defined by
+/// Exif for this case ([`DEFAULT_RESOLUTION_DPI`]) is only a default, which should not take
+/// precedence over a density recorded elsewhere.
+pub(crate) fn read_density(app1_data: &[u8]) -> Result<Option<(DensityUnit, u16, u16)>, crate::jpeg::Error> {
+    assert!(app1_data.starts_with(b"Exif\0\0"));
+    let exif_tiff = &app1_data[6..];
+    let tiff_cursor = Cursor::new(exif_tiff);
+    let ifds_values = crate::tiff::read_ifds(tiff_cursor)?;
 
-macro_rules! impl_read {
-    ($name:ident, $type:ty, $buf_size:expr) => {
-        fn $name(&mut self) -> Result<$type, crate::jpeg::Error> {
-            let mut buf = [0u8; $buf_size];
-            self.reader.read_exact(&mut buf)?;
-            Ok(if self.big_endian {
-                <$type>::from_be_bytes(buf)
-            } else {
-                <$type>::from_le_bytes(buf)
-            })
-        }
+    // process what we know
+    // IFD0 = image itself, IFD1 = thumbnail
+    // => ignore IFD1
+    let Some(ifd0) = ifds_values.first() else {
+        // nothing to learn from this Exif block
+        return Ok(None);
     };
-}
-
 
-struct Reader<R: Read + Seek> {
-    reader: R,
-    big_endian: bool,
-    ptr64: bool,
-}
-impl<R: Read + Seek> Reader<R> {
-    pub fn new(mut reader: R) -> Result<Self, crate::jpeg::Error> {
-        let mut byte_order_buf = [0u8; 2];
-        reader.read_exact(&mut byte_order_buf)?;
-        let big_endian = match (byte_order_buf[0], byte_order_buf[1]) {
-            (0x4D, 0x4D) => true, // MM (Motorola Motorola)
-            (0x49, 0x49) => false, // II (Intel Intel)
-            _ => return Err(Error::ByteOrderIndicator { bytes: byte_order_buf }.into()),
-        };
-        let mut tiff_reader = Self {
-            reader,
-            big_endian,
-            ptr64: false,
-        };
-
-        // read 16 bytes
-        let version = tiff_reader.read_u16()?;
-        match version {
-            42 => {
-                // standard TIFF
-            },
-            43 => {
-                // BigTIFF
-                tiff_reader.ptr64 = true;
-            },
-            other => return Err(Error::Version { version: other }.into()),
-        }
-
-        if tiff_reader.ptr64 {
-            // we have a few more fields in the header
-            let pointer_size = tiff_reader.read_u16()?;
-            if pointer_size != 8 {
-                return Err(Error::BigPointerSize { size: pointer_size }.into());
-            }
-
-            let reserved = tiff_reader.read_u16()?;
-            if reserved != 0 {
-                return Err(Error::BigReserved { value: reserved }.into());
-            }
-        }
-
-        // where is the first directory?
-        let dir_offset = tiff_reader.read_offset()?;
-
-        // go there
-        tiff_reader.reader.seek(SeekFrom::Start(dir_offset))?;
-
-        Ok(tiff_reader)
-    }
-
-    fn read_i8(&mut self) -> Result<i8, crate::jpeg::Error> {
-        let mut buf = [0u8];
-        self.reader.read_exact(&mut buf)?;
-        Ok(i8::from_ne_bytes(buf))
-    }
+    // resolutions are single nonzero rational numbers that must fit into 16 bits; anything else is
+    // treated as if it were absent
+    let single_rational = |tag| match crate::tiff::find_values(ifd0, tag) {
+        Some(Values::Rational(vals)) => match vals.as_slice() {
+            [(numerator, denominator)] if *denominator != 0 => u16::try_from(numerator / denominator).ok()
+                .filter(|resolution| *resolution > 0),
+            _ => None,
+        },
+        _ => None,
+    };
 
-    impl_read!(read_u16, u16, 2);
-    impl_read!(read_u32, u32, 4);
-    impl_read!(read_u64, u64, 8);
-    impl_read!(read_i16, i16, 2);
-    impl_read!(read_i32, i32, 4);
-    impl_read!(read_i64, i64, 8);
-    impl_read!(read_f32, f32, 4);
-    impl_read!(read_f64, f64, 8);
+    // do we have an X resolution?
+    let Some(x_resolution) = single_rational(0x011A) else {
+        return Ok(None);
+    };
 
-    fn read_offset(&mut self) -> Result<u64, crate::jpeg::Error> {
-        if self.ptr64 {
-            self.read_u64()
-        } else {
-            self.read_u32()
-                .map(|i| i.into())
-        }
-    }
+    // do we have a Y resolution? fall back to X resolution if not
+    let y_resolution = single_rational(0x011B).unwrap_or(x_resolution);
 
-    fn read_ifd_entry_count(&mut self) -> Result<u64, crate::jpeg::Error> {
-        if self.ptr64 {
-            self.read_u64()
-        } else {
-            self.read_u16()
-                .map(|i| i.into())
-        }
-    }
+    // find the unit (fall back to inches)
+    let unit = crate::tiff::find_values(ifd0, 0x0128)
+        .and_then(|values| values.as_single_u64())
+        .unwrap_or(2);
 
-    fn read_type(&mut self) -> Result<ValueType, crate::jpeg::Error> {
-        let base_value = self.read_u16()?;
-        Ok(ValueType::from_base_type(base_value))
-    }
+    let density_unit = match unit {
+        // no absolute unit; the values only specify the aspect ratio
+        1 => DensityUnit::NoUnit,
+        2 => DensityUnit::DotsPerInch,
+        3 => DensityUnit::DotsPerCentimeter,
+        _ => DensityUnit::DotsPerInch,
+    };
+    Ok(Some((density_unit, x_resolution, y_resolution)))
+}
 
-    fn read_value_or_pointer(&mut self, tag: u16, kind: ValueType, count: u32) -> Result<ValueOrPointer, crate::jpeg::Error> {
-        let mut inline_buf = [0u8; 8];
-        let inline_slice = if self.ptr64 {
-            self.reader.read_exact(&mut inline_buf)?;
-            &inline_buf[..]
-        } else {
-            self.reader.read_exact(&mut inline_buf[0..4])?;
-            &inline_buf[0..4]
-        };
 
-        // how much space does this value need?
-        let single_value_size = match kind.single_element_size() {
-            Some(svs) => svs,
-            None => {
-                // unknown value type; return the inline bytes raw
-                return Ok(ValueOrPointer::Unknown {
-                    tag,
-                    value_type: kind,
-                    count,
-                    inline_data: inline_buf,
-                });
-            },
-        };
+/// Overwrites the pixel density recorded in an Exif block in place.
+///
+/// Only the resolution and resolution unit tags already present in IFD0 as single values are
+/// changed; no tags are added. Returns whether the block records a horizontal resolution, i.e.
+/// whether the new density is read back from it.
+pub(crate) fn write_density(app1_data: &mut [u8], unit: DensityUnit, density_x: u16, density_y: u16) -> bool {
+    assert!(app1_data.starts_with(b"Exif\0\0"));
+    let exif_tiff = &mut app1_data[6..];
+    write_density_to_tiff(exif_tiff, unit, density_x, density_y)
+        .unwrap_or(false)
+}
 
-        // how much space do all the values need?
-        let total_size = single_value_size * usize::try_from(count).unwrap();
-        if total_size > inline_slice.len() {
-            // it's a pointer
-            let pointer_value: u64 = match (self.ptr64, self.big_endian) {
-                (false, false) => u32::from_le_bytes(inline_buf[0..4].try_into().unwrap()).into(),
-                (false, true) => u32::from_be_bytes(inline_buf[0..4].try_into().unwrap()).into(),
-                (true, false) => u64::from_le_bytes(inline_buf).into(),
-                (true, true) => u64::from_be_bytes(inline_buf).into(),
-            };
-            Ok(ValueOrPointer::Pointer {
-                tag,
-                value_type: kind,
-                count,
-                pointer: pointer_value,
-            })
-        } else {
-            // it fits inline
-            let inline_cursor = Cursor::new(inline_slice);
-            let mut inline_reader = Reader {
-                reader: inline_cursor,
-                big_endian: self.big_endian,
-                ptr64: self.ptr64,
-            };
-            let values = inline_reader.read_values(kind, count)?;
-            Ok(ValueOrPointer::Value {
-                tag,
-                values,
-            })
-        }
-    }
+fn write_density_to_tiff(tiff: &mut [u8], unit: DensityUnit, density_x: u16, density_y: u16) -> Option<bool> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    };
+    let u16_bytes = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+    let u32_bytes = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+
+    let exif_unit: u16 = match unit {
+        // no absolute unit; the values only specify the aspect ratio
+        DensityUnit::NoUnit => 1,
+        DensityUnit::DotsPerCentimeter => 3,
+        _ => 2,
+    };
 
-    fn read_values(&mut self, kind: ValueType, count: u32) -> Result<Values, crate::jpeg::Error> {
-        let count_usize: usize = count.try_into().unwrap();
-        match kind {
-            ValueType::Byte|ValueType::Ascii|ValueType::Undefined => {
-                let mut buf = vec![0; count_usize];
-                self.reader.read_exact(buf.as_mut_slice())?;
-                match kind {
-                    ValueType::Byte => Ok(Values::Byte(buf)),
-                    ValueType::Ascii => Ok(Values::Ascii(buf)),
-                    ValueType::Undefined => Ok(Values::Undefined(buf)),
-                    _ => unreachable!(),
-                }
-            },
-            ValueType::Short => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_u16()?;
-                    buf.push(value);
+    let ifd0_offset = usize::try_from(read_u32(tiff.get(4..8)?)).ok()?;
+    let entry_count = usize::from(read_u16(tiff.get(ifd0_offset..ifd0_offset+2)?));
+    let mut has_x_resolution = false;
+    for index in 0..entry_count {
+        let entry_offset = ifd0_offset + 2 + 12*index;
+        let entry = tiff.get(entry_offset..entry_offset+12)?;
+        let tag = read_u16(&entry[0..2]);
+        let value_type = read_u16(&entry[2..4]);
+        let count = read_u32(&entry[4..8]);
+        if count != 1 {
+            continue;
+        }
+        match (tag, value_type) {
+            (0x011A|0x011B, 5) => {
+                // a rational takes up 8 bytes and is therefore stored outside of the entry
+                let density = if tag == 0x011A { density_x } else { density_y };
+                let Ok(value_offset) = usize::try_from(read_u32(&entry[8..12])) else {
+                    continue;
+                };
+                let Some(value) = tiff.get_mut(value_offset..value_offset+8) else {
+                    continue;
+                };
+                value[0..4].copy_from_slice(&u32_bytes(density.into()));
+                value[4..8].copy_from_slice(&u32_bytes(1));
+                if tag == 0x011A {
+                    has_x_resolution = true;
                 }
-                Ok(Values::Short(buf))
             },
-            ValueType::Long => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_u32()?;
-                    buf.push(value);
-                }
-                Ok(Values::Long(buf))
-            },
-            ValueType::Rational => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let numerator = self.read_u32()?;
-                    let denominator = self.read_u32()?;
-                    buf.push((numerator, denominator));
-                }
-                Ok(Values::Rational(buf))
-            },
-            ValueType::SByte => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_i8()?;
-                    buf.push(value);
-                }
-                Ok(Values::SByte(buf))
-            },
-            ValueType::SShort => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_i16()?;
-                    buf.push(value);
-                }
-                Ok(Values::SShort(buf))
-            },
-            ValueType::SLong => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_i32()?;
-                    buf.push(value);
-                }
-                Ok(Values::SLong(buf))
+            (0x0128, 3) => {
+                // a short is stored within the entry
+                tiff[entry_offset+8..entry_offset+10].copy_from_slice(&u16_bytes(exif_unit));
             },
-            ValueType::SRational => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let numerator = self.read_i32()?;
-                    let denominator = self.read_i32()?;
-                    buf.push((numerator, denominator));
-                }
-                Ok(Values::SRational(buf))
-            },
-            ValueType::Float => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_f32()?;
-                    buf.push(value);
-                }
-                Ok(Values::Float(buf))
-            },
-            ValueType::Double => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_f64()?;
-                    buf.push(value);
-                }
-                Ok(Values::Double(buf))
-            },
-            ValueType::Ifd => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_u32()?;
-                    buf.push(value);
-                }
-                Ok(Values::Ifd(buf))
-            },
-            ValueType::Long8 => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_u64()?;
-                    buf.push(value);
-                }
-                Ok(Values::Long8(buf))
-            },
-            ValueType::SLong8 => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_i64()?;
-                    buf.push(value);
-                }
-                Ok(Values::SLong8(buf))
-            },
-            ValueType::Ifd8 => {
-                let mut buf = Vec::with_capacity(count_usize);
-                for _ in 0..count_usize {
-                    let value = self.read_u64()?;
-                    buf.push(value);
-                }
-                Ok(Values::Ifd8(buf))
-            },
-            ValueType::Other(_) => Err(Error::UnknownType { data_type: kind }.into()),
+            _ => {},
         }
     }
+    Some(has_x_resolution)
 }
 
 
-#[derive(Clone, Copy, Debug)]
-#[from_to_other(base_type = u16, derive_compare = "as_int")]
-pub enum ValueType {
-    Byte = 1,
-    Ascii = 2,
-    Short = 3,
-    Long = 4,
-    Rational = 5,
-    SByte = 6,
-    Undefined = 7,
-    SShort = 8,
-    SLong = 9,
-    SRational = 10,
-    Float = 11,
-    Double = 12,
-    Ifd = 13,
-    // Unicode = 14, Complex = 15, Adobe-internal assignments
-    Long8 = 16,
-    SLong8 = 17,
-    Ifd8 = 18,
-    Other(u16),
-}
-impl ValueType {
-    pub fn single_element_size(&self) -> Option<usize> {
-        match self {
-            Self::Byte|Self::Ascii|Self::SByte|Self::Undefined => Some(1),
-            Self::Short|Self::SShort => Some(2),
-            Self::Long|Self::SLong|Self::Float|Self::Ifd => Some(4),
-            Self::Rational|Self::SRational|Self::Double|Self::Long8|Self::SLong8|Self::Ifd8 => Some(8),
-            Self::Other(_) => None,
-        }
-    }
-}
-
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub enum Values {
-    Byte(Vec<u8>),
-    Ascii(Vec<u8>),
-    Short(Vec<u16>),
-    Long(Vec<u32>),
-    Rational(Vec<(u32, u32)>),
-    SByte(Vec<i8>),
-    Undefined(Vec<u8>),
-    SShort(Vec<i16>),
-    SLong(Vec<i32>),
-    SRational(Vec<(i32, i32)>),
-    Float(Vec<f32>),
-    Double(Vec<f64>),
-    Ifd(Vec<u32>),
-    Long8(Vec<u64>),
-    SLong8(Vec<i64>),
-    Ifd8(Vec<u64>),
-}
-
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub enum ValueOrPointer {
-    Value { tag: u16, values: Values },
-    Pointer { tag: u16, value_type: ValueType, count: u32, pointer: u64 },
-    Unknown { tag: u16, value_type: ValueType, count: u32, inline_data: [u8; 8] },
-}
-impl ValueOrPointer {
-    pub fn tag(&self) -> u16 {
-        match self {
-            Self::Value { tag, .. } => *tag,
-            Self::Pointer { tag, .. } => *tag,
-            Self::Unknown { tag, .. } => *tag,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles an Exif block whose IFD0 contains the given rational-valued tags.
+    fn exif_block(tags: &[(u16, &[(u32, u32)])]) -> Vec<u8> {
+        let mut data = b"Exif\0\0II\x2A\x00\x08\x00\x00\x00".to_vec();
+        let mut rational_data = Vec::new();
+        let mut rational_offset = 8 + 2 + 12 * tags.len() + 4;
+
+        data.extend_from_slice(&u16::try_from(tags.len()).unwrap().to_le_bytes());
+        for (tag, rationals) in tags {
+            data.extend_from_slice(&tag.to_le_bytes());
+            // RATIONAL
+            data.extend_from_slice(&5u16.to_le_bytes());
+            data.extend_from_slice(&u32::try_from(rationals.len()).unwrap().to_le_bytes());
+            if rationals.is_empty() {
+                data.extend_from_slice(&[0; 4]);
+            } else {
+                data.extend_from_slice(&u32::try_from(rational_offset).unwrap().to_le_bytes());
+            }
+            for (numerator, denominator) in *rationals {
+                rational_data.extend_from_slice(&numerator.to_le_bytes());
+                rational_data.extend_from_slice(&denominator.to_le_bytes());
+                rational_offset += 8;
+            }
         }
+        // no next IFD
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&rational_data);
+        data
     }
 
-    pub fn value(&self) -> Option<&Values> {
-        match self {
-            Self::Value { values, .. } => Some(values),
-            _ => None,
-        }
+    fn read_resolution(tags: &[(u16, &[(u32, u32)])]) -> Option<(u16, u16)> {
+        read_density(&exif_block(tags)).unwrap()
+            .map(|(unit, x, y)| {
+                assert_eq!(unit, DensityUnit::DotsPerInch);
+                (x, y)
+            })
     }
-}
-
-
-pub(crate) fn process(app1_data: &[u8], builder: &mut ImageBuilder) -> Result<(), crate::jpeg::Error> {
-    assert!(app1_data.starts_with(b"Exif\0\0"));
-    let exif_tiff = &app1_data[6..];
-    let tiff_cursor = Cursor::new(exif_tiff);
-    let mut tiff = Reader::new(tiff_cursor)?;
-
-    let mut ifds_values = Vec::new();
 
-    loop {
-        // how many entries in the IFD do we have?
-        let ifd_entry_count = tiff.read_ifd_entry_count()?;
-        let mut values = Vec::new();
-
-        // run through them, collecting the values
-        for _ in 0..ifd_entry_count {
-            let tag = tiff.read_u16()?;
-            let kind = tiff.read_type()?;
-            let count = tiff.read_u32()?;
-
-            let value_or_pointer = tiff.read_value_or_pointer(tag, kind, count)?;
-            values.push(value_or_pointer);
-        }
-
-        ifds_values.push(values);
-
-        // the next value is the pointer to the next IFD
-        let next_ifd_offset = tiff.read_offset()?;
-        if next_ifd_offset == 0 {
-            // we are done
-            break;
-        }
-
-        tiff.reader.seek(SeekFrom::Start(next_ifd_offset))?;
+    #[test]
+    fn reads_resolutions() {
+        assert_eq!(read_resolution(&[(0x011A, &[(300, 1)]), (0x011B, &[(600, 2)])]), Some((300, 300)));
+        assert_eq!(read_resolution(&[(0x011A, &[(150, 1)])]), Some((150, 150)));
+        assert_eq!(read_resolution(&[]), None);
     }
 
-    // dereference the pointers
-    for values in &mut ifds_values {
-        for value in values {
-            if let ValueOrPointer::Pointer { tag, value_type, count, pointer } = value {
-                tiff.reader.seek(SeekFrom::Start(*pointer))?;
-                let values = tiff.read_values(*value_type, *count)?;
-                *value = ValueOrPointer::Value { tag: *tag, values };
-            }
-        }
+    #[test]
+    fn ignores_unusable_resolutions() {
+        // zero count and zero denominator
+        assert_eq!(read_resolution(&[(0x011A, &[]), (0x011B, &[(300, 0)])]), None);
+        // too large for 16 bits
+        assert_eq!(read_resolution(&[(0x011A, &[(300_000, 1)])]), None);
+        // zero
+        assert_eq!(read_resolution(&[(0x011A, &[(0, 1)])]), None);
+        // multiple values
+        assert_eq!(read_resolution(&[(0x011A, &[(300, 1), (300, 1)])]), None);
+        // zero count for Y only
+        assert_eq!(read_resolution(&[(0x011A, &[(600, 2)]), (0x011B, &[])]), Some((300, 300)));
     }
-
-    // process what we know
-    // IFD0 = image itself, IFD1 = thumbnail
-    // => ignore IFD1
-
-    // do we have an X resolution? fall back to 72 if not
-    let x_resolution_values_opt = ifds_values[0]
-        .iter()
-        .filter(|v| v.tag() == 0x011A)
-        .filter_map(|v| v.value())
-        .nth(0);
-    let x_resolution_opt = if let Some(x_resolution_values) = x_resolution_values_opt {
-        if let Values::Rational(vals) = x_resolution_values {
-            if vals.len() == 1 {
-                Some(vals[0].0 / vals[0].1)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-    let x_resolution = x_resolution_opt.unwrap_or(72);
-
-    // do we have a Y resolution? fall back to X resolution if not
-    let y_resolution_values_opt = ifds_values[0]
-        .iter()
-        .filter(|v| v.tag() == 0x011B)
-        .filter_map(|v| v.value())
-        .nth(0);
-    let y_resolution_opt = if let Some(y_resolution_values) = y_resolution_values_opt {
-        if let Values::Rational(vals) = y_resolution_values {
-            if vals.len() == 1 {
-                Some(vals[0].0 / vals[0].1)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-    let y_resolution = y_resolution_opt.unwrap_or(x_resolution);
-
-    // find the unit (fall back to inches)
-    let unit_values_opt = ifds_values[0]
-        .iter()
-        .filter(|v| v.tag() == 0x0128)
-        .filter_map(|v| v.value())
-        .nth(0);
-    let unit_opt = if let Some(unit_values) = unit_values_opt {
-        if let Values::Short(vals) = unit_values {
-            if vals.len() == 1 {
-                Some(vals[0])
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-    let unit = unit_opt.unwrap_or(2);
-
-    builder.density_x = Some(x_resolution.try_into().unwrap());
-    builder.density_y = Some(y_resolution.try_into().unwrap());
-    builder.density_unit = Some(match unit {
-        2 => DensityUnit::DotsPerInch,
-        3 => DensityUnit::DotsPerCentimeter,
-        _ => DensityUnit::DotsPerInch,
-    });
-
-    Ok(())
 }
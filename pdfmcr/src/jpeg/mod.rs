@@ -1,6 +1,7 @@
 //! Parsing Joint Photographics Expert Group image files.
 
 
+pub mod decode;
 mod exif;
 
 
@@ -8,17 +9,18 @@ use std::fmt;
 use std::io::{self, Read, Write};
 
 use from_to_repr::from_to_other;
+use tracing::warn;
 
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Block {
-    Short { kind: u8 },
-    Long { kind: u8, data: Vec<u8> },
+    Short { kind: u8, fill: usize },
+    Long { kind: u8, data: Vec<u8>, fill: usize },
 }
 impl Block {
     pub fn kind(&self) -> u8 {
         match self {
-            Self::Short { kind } => *kind,
+            Self::Short { kind, .. } => *kind,
             Self::Long { kind, .. } => *kind,
         }
     }
@@ -30,19 +32,89 @@ impl Block {
         }
     }
 
+    /// The number of fill bytes (0xFF) preceding the marker of this block.
+    ///
+    /// JPEG permits any number of fill bytes before each marker. They carry no meaning but are
+    /// retained so that an image is written exactly as it was read.
+    pub fn fill(&self) -> usize {
+        match self {
+            Self::Short { fill, .. } => *fill,
+            Self::Long { fill, .. } => *fill,
+        }
+    }
+
     pub fn is_required(&self) -> bool {
         let kind = self.kind();
         kind < 0xE0 || kind > 0xFE
     }
 
+    /// Whether this block is an application-specific segment (APP0 through APP15).
+    pub fn is_appn(&self) -> bool {
+        self.app_marker_number().is_some()
+    }
+
+    /// The number of this application-specific segment (0 for APP0 through 15 for APP15), or
+    /// `None` if this block is not an application-specific segment.
+    pub fn app_marker_number(&self) -> Option<u8> {
+        match self.kind() {
+            kind @ 0xE0..=0xEF => Some(kind - 0xE0),
+            _ => None,
+        }
+    }
+
+    /// The identifier at the start of this application-specific segment, without the terminating
+    /// NUL byte, e.g. `JFIF`, `Exif` or `ICC_PROFILE`.
+    ///
+    /// Returns `None` if this block is not an application-specific segment or its data does not
+    /// contain a NUL byte.
+    pub fn app_identifier(&self) -> Option<&[u8]> {
+        self.app_marker_number()?;
+        let data = self.data();
+        let nul_pos = data.iter().position(|b| *b == 0x00)?;
+        Some(&data[..nul_pos])
+    }
+
+    /// Whether this block is the given application-specific segment and starts with the given
+    /// identifier.
+    pub fn is_app_with_identifier(&self, number: u8, identifier: &[u8]) -> bool {
+        self.app_marker_number() == Some(number)
+            && self.app_identifier() == Some(identifier)
+    }
+
+    /// The data of this application-specific segment following its identifier and the terminating
+    /// NUL byte, or `None` if there is no identifier.
+    pub fn app_payload(&self) -> Option<&[u8]> {
+        let identifier_len = self.app_identifier()?.len();
+        Some(&self.data()[identifier_len+1..])
+    }
+
+    /// Whether this block is a start-of-frame segment.
+    ///
+    /// The markers 0xC4 (define Huffman tables), 0xC8 (reserved for JPEG extensions) and 0xCC
+    /// (define arithmetic coding conditioning) fall within the range of start-of-frame markers but
+    /// are not start-of-frame segments.
+    pub fn is_sof(&self) -> bool {
+        matches!(self.kind(), 0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF)
+    }
+
+    /// Whether this block is a start-of-frame segment of an image using arithmetic coding.
+    ///
+    /// All other start-of-frame segments describe images using Huffman coding.
+    pub fn is_arithmetic_sof(&self) -> bool {
+        matches!(self.kind(), 0xC9..=0xCB|0xCD..=0xCF)
+    }
+
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        for _ in 0..self.fill() {
+            writer.write_all(&[0xFF])?;
+        }
         match self {
-            Self::Short { kind } => {
+            Self::Short { kind, .. } => {
                 let buf2 = [0xFF, *kind];
                 writer.write_all(&buf2)?;
                 Ok(())
             },
-            Self::Long { kind, data } => {
+            Self::Long { kind, data, .. } => {
                 if data.len() > 0xFFFF {
                     return Err(Error::BlockTooLong { max_allowed: 0xFFFF, obtained: data.len() });
                 }
@@ -66,14 +138,20 @@ impl Block {
             return Err(Error::NotABlock { start_byte: buf1[0] });
         }
 
+        // any further 0xFF bytes are fill bytes; the last 0xFF byte is the start of the marker
+        let mut fill = 0;
         reader.read_exact(&mut buf1)?;
+        while buf1[0] == 0xFF {
+            fill += 1;
+            reader.read_exact(&mut buf1)?;
+        }
         let block_kind = buf1[0];
 
         match block_kind {
-            0xD0..=0xD9 => {
-                // restart 0 through 7, start-of-image, end-of-image, start-of-scan
+            0x01|0xD0..=0xD9 => {
+                // temporary arithmetic coding use, restart 0 through 7, start-of-image, end-of-image
                 // short blocks
-                Ok(Block::Short { kind: block_kind })
+                Ok(Block::Short { kind: block_kind, fill })
             },
             _ => {
                 // long blocks
@@ -87,7 +165,7 @@ impl Block {
 
                 let mut data = vec![0u8; block_len];
                 reader.read_exact(&mut data)?;
-                Ok(Block::Long { kind: block_kind, data })
+                Ok(Block::Long { kind: block_kind, data, fill })
             },
         }
     }
@@ -106,7 +184,14 @@ pub enum Error {
     UnexpectedJfifVersion { expected: u16, obtained: u16 },
     JfifTooShort { min_expected: usize, obtained: usize },
     SofTooShort { min_expected: usize, obtained: usize },
-    Exif(crate::jpeg::exif::Error),
+    DriTooShort { min_expected: usize, obtained: usize },
+    UnexpectedRestartMarker { expected_kind: u8, obtained_kind: u8 },
+    RestartMarkerCount { expected: u64, obtained: u64 },
+    Exif(crate::tiff::Error),
+    MissingStartOfImage,
+    StartOfScanCount { obtained: usize },
+    StartOfScanNotLast,
+    MissingEndOfImage,
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -120,7 +205,7 @@ impl fmt::Display for Error {
             Self::BlockTooLong { max_allowed, obtained }
                 => write!(f, "block too long -- max allowed {} bytes, obtained {} bytes", max_allowed, obtained),
             Self::IncompleteData { builder }
-                => write!(f, "incomplete data in header: {:?}", builder),
+                => write!(f, "incomplete data in header; missing {}", builder.missing_fields().join(", ")),
             Self::UnexpectedBlock { expected_kind, obtained_kind  }
                 => write!(f, "unexpected block 0x{:02X} (expected 0x{:02X})", obtained_kind, expected_kind),
             Self::IncorrectImageDataTermination
@@ -133,8 +218,22 @@ impl fmt::Display for Error {
                 => write!(f, "JFIF header too short; expected at least {} bytes, obtained {}", min_expected, obtained),
             Self::SofTooShort { min_expected, obtained }
                 => write!(f, "Start-of-Frame too short; expected at least {} bytes, obtained {}", min_expected, obtained),
+            Self::DriTooShort { min_expected, obtained }
+                => write!(f, "Define-Restart-Interval too short; expected at least {} bytes, obtained {}", min_expected, obtained),
+            Self::UnexpectedRestartMarker { expected_kind, obtained_kind }
+                => write!(f, "unexpected restart marker 0x{:02X} (expected 0x{:02X})", obtained_kind, expected_kind),
+            Self::RestartMarkerCount { expected, obtained }
+                => write!(f, "expected {} restart markers in the first scan, obtained {}", expected, obtained),
             Self::Exif(e)
                 => write!(f, "Exif-specific error: {}", e),
+            Self::MissingStartOfImage
+                => write!(f, "first block is not Start-of-Image"),
+            Self::StartOfScanCount { obtained }
+                => write!(f, "expected exactly one Start-of-Scan block before the image data, obtained {}", obtained),
+            Self::StartOfScanNotLast
+                => write!(f, "Start-of-Scan is not the last block before the image data"),
+            Self::MissingEndOfImage
+                => write!(f, "last block is not End-of-Image"),
         }
     }
 }
@@ -152,15 +251,39 @@ impl std::error::Error for Error {
             Self::UnexpectedJfifVersion { .. } => None,
             Self::JfifTooShort { .. } => None,
             Self::SofTooShort { .. } => None,
+            Self::DriTooShort { .. } => None,
+            Self::UnexpectedRestartMarker { .. } => None,
+            Self::RestartMarkerCount { .. } => None,
             Self::Exif(e) => Some(e),
+            Self::MissingStartOfImage => None,
+            Self::StartOfScanCount { .. } => None,
+            Self::StartOfScanNotLast => None,
+            Self::MissingEndOfImage => None,
         }
     }
 }
+impl Error {
+    /// If the image data was incomplete, returns the builder holding the data that was found.
+    ///
+    /// This allows inspecting the blocks that were encountered.
+    pub fn incomplete_builder(&self) -> Option<&ImageBuilder> {
+        match self {
+            Self::IncompleteData { builder } => Some(builder),
+            _ => None,
+        }
+    }
+
+    /// If the image data was incomplete, returns the names of the fields that were missing.
+    pub fn missing_fields(&self) -> Option<Vec<&'static str>> {
+        self.incomplete_builder()
+            .map(|builder| builder.missing_fields())
+    }
+}
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self { Self::Io(value) }
 }
-impl From<crate::jpeg::exif::Error> for Error {
-    fn from(value: crate::jpeg::exif::Error) -> Self { Self::Exif(value) }
+impl From<crate::tiff::Error> for Error {
+    fn from(value: crate::tiff::Error) -> Self { Self::Exif(value) }
 }
 
 
@@ -173,13 +296,54 @@ pub struct Image {
     pub density_unit: DensityUnit,
     pub density_x: u16,
     pub density_y: u16,
+
+    /// The number of MCUs between restart markers in the first scan, as defined by the DRI block
+    /// preceding it, or `None` if there is no such block.
+    ///
+    /// An interval of 0 disables restart markers.
+    pub restart_interval: Option<u16>,
+
+    /// The color transform declared by an Adobe APP14 block, or `None` if there is no such block.
+    ///
+    /// Adobe applications store CMYK images with inverted components and mark them with this block.
+    pub adobe_transform: Option<u8>,
+
     pub leading_blocks: Vec<Block>,
     pub image_data: Vec<u8>,
     pub trailing_blocks: Vec<Block>,
 }
 impl Image {
-    pub fn try_read<R: Read>(mut reader: R) -> Result<Self, Error> {
+    /// Reads a JPEG image.
+    ///
+    /// All blocks between Start-of-Image and the first Start-of-Scan are collected, regardless of
+    /// the order in which they appear; everything following the first Start-of-Scan (including any
+    /// further scans and tables of progressive images) is taken as image data. If multiple
+    /// start-of-frame blocks are present, the first one describes the image.
+    ///
+    /// The pixel density is taken from the first of the following sources that provides it:
+    ///
+    /// 1. the resolution recorded in an Exif (APP1) block,
+    /// 2. the density recorded in the JFIF (APP0) block,
+    /// 3. the default resolution of Exif (72 dpi), if there is an Exif block without a resolution,
+    /// 4. [`DEFAULT_DENSITY_DPI`], if there is an Exif block that cannot be parsed.
+    ///
+    /// An Exif block that cannot be parsed is otherwise ignored with a warning. If the image only
+    /// specifies its pixel aspect ratio, [`DEFAULT_DENSITY_DPI`] is assumed.
+    ///
+    /// Restart markers that do not match the restart interval are reported with a warning as well;
+    /// many decoders resynchronize on them, so such images usually remain displayable. Use
+    /// [`Image::validate_restart_markers`] to refuse them.
+    pub fn try_read<R: Read>(reader: R) -> Result<Self, Error> {
+        Self::try_read_with_default_density(reader, DEFAULT_DENSITY_DPI)
+    }
+
+    /// Reads a JPEG image, assuming the given horizontal pixel density (in dots per inch) if the
+    /// image only specifies its pixel aspect ratio.
+    ///
+    /// Otherwise, this behaves like [`Image::try_read`].
+    pub fn try_read_with_default_density<R: Read>(mut reader: R, default_density_dpi: u16) -> Result<Self, Error> {
         let mut builder = ImageBuilder::new();
+        builder.default_density_dpi = default_density_dpi;
         loop {
             let block = Block::try_read(&mut reader)?;
             builder.leading_blocks.push(block);
@@ -206,20 +370,26 @@ impl Image {
         if image_data.ends_with(&[0xFF, 0xD9]) {
             // ends with end-of-input, perfect
             image_data.drain(image_data.len()-2..);
-            builder.trailing_blocks.push(Block::Short { kind: 0xD9 });
+            // any fill bytes preceding it remain part of the image data
+            builder.trailing_blocks.push(Block::Short { kind: 0xD9, fill: 0 });
         } else {
             return Err(Error::IncorrectImageDataTermination);
         }
 
         builder.image_data = image_data;
 
-        let leading_blocks_clone = builder.leading_blocks.clone();
-        for block in &leading_blocks_clone {
+        // take the blocks out of the builder so that the builder can be modified while processing
+        let leading_blocks = std::mem::take(&mut builder.leading_blocks);
+        let mut jfif_density = None;
+        let mut exif_density = None;
+        let mut exif_seen = false;
+        let mut exif_failed = false;
+        for block in &leading_blocks {
             let data = block.data();
             match block.kind() {
                 0xE0 => {
                     // APP0
-                    if !data.starts_with(b"JFIF\0") {
+                    if !block.is_app_with_identifier(0, b"JFIF") {
                         return Err(Error::NotJfif);
                     }
                     if data.len() < 12 {
@@ -227,26 +397,38 @@ impl Image {
                     }
 
                     let version = u16::from_be_bytes(data[5..7].try_into().unwrap());
-                    if version != 0x0101 {
-                        return Err(Error::UnexpectedJfifVersion { expected: 0x0101, obtained: version });
+                    // versions 1.00 through 1.02 share the same layout
+                    if !(0x0100..=0x0102).contains(&version) {
+                        return Err(Error::UnexpectedJfifVersion { expected: 0x0102, obtained: version });
                     }
 
                     let unit = DensityUnit::from_base_type(data[7]);
                     let density_x = u16::from_be_bytes(data[8..10].try_into().unwrap());
                     let density_y = u16::from_be_bytes(data[10..12].try_into().unwrap());
 
-                    builder.density_unit = Some(unit);
-                    builder.density_x = Some(density_x);
-                    builder.density_y = Some(density_y);
+                    jfif_density = Some((unit, density_x, density_y));
                 },
-                0xE1 => {
+                0xE1 if data.starts_with(b"Exif\0\0") => {
                     // APP1
-                    if data.starts_with(b"Exif\0\0") {
-                        crate::jpeg::exif::process(data, &mut builder)?;
+                    // the identifier is followed by a padding byte
+                    // Exif blocks are often mangled by editing tools; the image remains usable
+                    match crate::jpeg::exif::read_density(data) {
+                        Ok(density) => {
+                            exif_seen = true;
+                            exif_density = exif_density.or(density);
+                        },
+                        Err(e) => {
+                            warn!("ignoring unreadable Exif block: {}", e);
+                            exif_failed = true;
+                        },
                     }
                 },
-                0xC0..=0xC3|0xC5..=0xC7|0xC9..=0xCB|0xCD..=0xCF => {
+                _ if block.is_sof() => {
                     // start of frame
+                    if builder.bit_depth.is_some() {
+                        // only the first frame header describes the image
+                        continue;
+                    }
                     if data.len() < 6 {
                         return Err(Error::SofTooShort { min_expected: 6, obtained: data.len() });
                     }
@@ -259,14 +441,243 @@ impl Image {
                     builder.width = Some(width);
                     builder.color_space = Some(color_space);
                 },
+                0xEE if data.starts_with(b"Adobe") && data.len() >= 12 => {
+                    // APP14
+                    // "Adobe", version (2 bytes), flags (2 * 2 bytes), color transform (1 byte)
+                    // other applications might use APP14 differently; ignore those blocks
+                    builder.adobe_transform = Some(data[11]);
+                },
+                0xDD => {
+                    // define restart interval
+                    if data.len() < 2 {
+                        return Err(Error::DriTooShort { min_expected: 2, obtained: data.len() });
+                    }
+                    let restart_interval = u16::from_be_bytes(data[0..2].try_into().unwrap());
+                    builder.restart_interval = Some(restart_interval);
+                },
                 _ => {},
             }
         }
+        builder.leading_blocks = leading_blocks;
+
+        // a resolution recorded in Exif takes precedence over the density in the JFIF block: Exif
+        // is written by the scanner or camera, whereas JFIF often just carries an encoder's default
+        let exif_default_density = exif_seen.then_some((
+            DensityUnit::DotsPerInch,
+            crate::jpeg::exif::DEFAULT_RESOLUTION_DPI,
+            crate::jpeg::exif::DEFAULT_RESOLUTION_DPI,
+        ));
+        let unreadable_exif_density = exif_failed.then_some((DensityUnit::NoUnit, 1, 1));
+        let density = exif_density
+            .or(jfif_density)
+            .or(exif_default_density)
+            .or(unreadable_exif_density);
+        if let Some((density_unit, density_x, density_y)) = density {
+            builder.density_unit = Some(density_unit);
+            builder.density_x = Some(density_x);
+            builder.density_y = Some(density_y);
+        }
+
+        let image: Image = builder.try_into()?;
+        if let Err(e) = image.validate_restart_markers() {
+            warn!("image has invalid restart markers: {}", e);
+        }
+        Ok(image)
+    }
+
+    /// Whether the image uses Huffman coding but does not define any Huffman tables before its
+    /// first scan.
+    ///
+    /// Such images cannot be decoded by most decoders. Some encoders (notably those producing Motion
+    /// JPEG frames) nevertheless omit the tables, relying on decoders to substitute the example
+    /// tables from the standard, so this is not treated as an error.
+    ///
+    /// Images using arithmetic coding are never flagged, since arithmetic coding conditioning
+    /// (marker 0xCC) has default values and therefore need not be defined.
+    pub fn lacks_huffman_tables(&self) -> bool {
+        let Some(frame_header) = self.leading_blocks.iter().find(|b| b.is_sof()) else {
+            return false;
+        };
+        if frame_header.is_arithmetic_sof() {
+            return false;
+        }
+        !self.leading_blocks.iter().any(|b| b.kind() == 0xC4)
+    }
+
+    /// Returns the number of MCUs (minimum coded units) in the first scan, if it can be determined.
+    ///
+    /// The number cannot be determined if the frame or scan header is missing or malformed, or if
+    /// the height of the image is only defined later using a DNL block.
+    fn first_scan_mcu_count(&self) -> Option<u64> {
+        let frame_header = self.leading_blocks.iter().find(|b| b.is_sof())?.data();
+        let scan_header = self.leading_blocks.iter().find(|b| b.kind() == 0xDA)?.data();
+
+        // frame header: precision, height, width, component count,
+        // then (ID, sampling factors, quantization table) per component
+        let height = u64::from(u16::from_be_bytes(frame_header.get(1..3)?.try_into().unwrap()));
+        let width = u64::from(u16::from_be_bytes(frame_header.get(3..5)?.try_into().unwrap()));
+        let component_count = usize::from(*frame_header.get(5)?);
+        let components = frame_header.get(6..6+3*component_count)?;
+        let sampling_factors = |component: &[u8]| (u64::from(component[1] >> 4), u64::from(component[1] & 0x0F));
+        let h_max = components.chunks(3).map(|c| sampling_factors(c).0).max()?;
+        let v_max = components.chunks(3).map(|c| sampling_factors(c).1).max()?;
+        if width == 0 || height == 0 || h_max == 0 || v_max == 0 {
+            return None;
+        }
+
+        // scan header: component count, then (ID, entropy coding tables) per component
+        let mcu_count = if *scan_header.first()? == 1 {
+            // non-interleaved: each MCU is one 8x8 data unit of the single component
+            let component_id = *scan_header.get(1)?;
+            let component = components.chunks(3).find(|c| c[0] == component_id)?;
+            let (h, v) = sampling_factors(component);
+            let component_width = (width * h).div_ceil(h_max);
+            let component_height = (height * v).div_ceil(v_max);
+            component_width.div_ceil(8) * component_height.div_ceil(8)
+        } else {
+            // interleaved: each MCU covers the area of the largest sampling factors
+            width.div_ceil(8 * h_max) * height.div_ceil(8 * v_max)
+        };
+        if mcu_count == 0 {
+            None
+        } else {
+            Some(mcu_count)
+        }
+    }
+
+    /// Checks whether the restart markers in the first scan match the restart interval.
+    ///
+    /// The restart markers must cycle through RST0 to RST7 in order. If the number of MCUs in the
+    /// first scan can be determined, the scan must also contain exactly as many restart markers as
+    /// the restart interval implies; without a (nonzero) restart interval, it must contain none.
+    pub fn validate_restart_markers(&self) -> Result<(), Error> {
+        // find the restart markers in the entropy-coded data of the first scan
+        let mut obtained_count: u64 = 0;
+        let mut i = 0;
+        while i + 1 < self.image_data.len() {
+            if self.image_data[i] != 0xFF {
+                i += 1;
+                continue;
+            }
+            match self.image_data[i+1] {
+                0x00 => {
+                    // stuffed zero byte
+                    i += 2;
+                },
+                0xFF => {
+                    // fill byte
+                    i += 1;
+                },
+                marker @ 0xD0..=0xD7 => {
+                    let expected_marker = 0xD0 + u8::try_from(obtained_count % 8).unwrap();
+                    if marker != expected_marker {
+                        return Err(Error::UnexpectedRestartMarker {
+                            expected_kind: expected_marker,
+                            obtained_kind: marker,
+                        });
+                    }
+                    obtained_count += 1;
+                    i += 2;
+                },
+                _ => {
+                    // any other marker ends the first scan
+                    break;
+                },
+            }
+        }
 
-        builder.try_into()
+        let expected_count = match self.restart_interval {
+            None|Some(0) => Some(0),
+            Some(interval) => self.first_scan_mcu_count()
+                // no restart marker follows the last interval
+                .map(|mcu_count| (mcu_count - 1) / u64::from(interval)),
+        };
+        if let Some(expected_count) = expected_count && obtained_count != expected_count {
+            return Err(Error::RestartMarkerCount { expected: expected_count, obtained: obtained_count });
+        }
+        Ok(())
+    }
+
+    /// Changes the pixel density of the image, updating the blocks recording it in place.
+    ///
+    /// The density is updated in the JFIF (APP0) block and in the resolution tags of each Exif
+    /// (APP1) block. If neither records a density, a JFIF block is inserted after Start-of-Image.
+    /// All other data of the image remains untouched.
+    pub fn set_density(&mut self, unit: DensityUnit, density_x: u16, density_y: u16) {
+        let mut density_recorded = false;
+        for block in &mut self.leading_blocks {
+            let is_jfif = block.is_app_with_identifier(0, b"JFIF");
+            let Block::Long { kind, data, .. } = block else {
+                continue;
+            };
+            if is_jfif && data.len() >= 12 {
+                data[7] = unit.to_base_type();
+                data[8..10].copy_from_slice(&density_x.to_be_bytes());
+                data[10..12].copy_from_slice(&density_y.to_be_bytes());
+                density_recorded = true;
+            } else if *kind == 0xE1 && data.starts_with(b"Exif\0\0")
+                    && crate::jpeg::exif::write_density(data, unit, density_x, density_y) {
+                density_recorded = true;
+            }
+        }
+
+        if !density_recorded {
+            // "JFIF", version 1.02, density, no thumbnail
+            let mut data = Vec::with_capacity(14);
+            data.extend_from_slice(b"JFIF\0\x01\x02");
+            data.push(unit.to_base_type());
+            data.extend_from_slice(&density_x.to_be_bytes());
+            data.extend_from_slice(&density_y.to_be_bytes());
+            data.extend_from_slice(&[0, 0]);
+            // the first block is always Start-of-Image
+            self.leading_blocks.insert(1, Block::Long { kind: 0xE0, data, fill: 0 });
+        }
+
+        self.density_unit = unit;
+        self.density_x = density_x;
+        self.density_y = density_y;
+    }
+
+    /// Returns all blocks of the given kind, leading blocks first, in the order in which they appear
+    /// in the image.
+    pub fn blocks_of_kind(&self, kind: u8) -> impl Iterator<Item = &Block> {
+        self.leading_blocks.iter()
+            .chain(self.trailing_blocks.iter())
+            .filter(move |block| block.kind() == kind)
+    }
+
+    /// Returns the first block of the given kind, if any.
+    pub fn first_block_of_kind(&self, kind: u8) -> Option<&Block> {
+        self.blocks_of_kind(kind).next()
+    }
+
+    /// Checks whether the blocks of this image are arranged in a way that produces a valid file.
+    ///
+    /// The first leading block must be Start-of-Image, the last leading block must be the only
+    /// Start-of-Scan among the leading blocks and the last trailing block must be End-of-Image.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.leading_blocks.first().map(|b| b.kind()) != Some(0xD8) {
+            return Err(Error::MissingStartOfImage);
+        }
+
+        let sos_count = self.blocks_of_kind(0xDA).count();
+        if sos_count != 1 {
+            return Err(Error::StartOfScanCount { obtained: sos_count });
+        }
+        if self.leading_blocks.last().map(|b| b.kind()) != Some(0xDA) {
+            return Err(Error::StartOfScanNotLast);
+        }
+
+        if self.trailing_blocks.last().map(|b| b.kind()) != Some(0xD9) {
+            return Err(Error::MissingEndOfImage);
+        }
+
+        Ok(())
     }
 
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        self.validate()?;
+
         for leading_block in &self.leading_blocks {
             leading_block.write(&mut writer)?;
         }
@@ -276,8 +687,86 @@ impl Image {
         }
         Ok(())
     }
+
+    /// Reads a JPEG image like [`Image::try_read`], additionally retaining the data as it was read.
+    ///
+    /// The image is then held in memory twice: once as originally read and once split into its
+    /// blocks.
+    pub fn try_read_retaining_original<R: Read>(mut reader: R) -> Result<RetainedImage, Error> {
+        let mut original = Vec::new();
+        reader.read_to_end(&mut original)?;
+        let image = Self::try_read(original.as_slice())?;
+        Ok(RetainedImage {
+            image,
+            original: Some(original),
+        })
+    }
+}
+
+/// A JPEG image along with the data from which it was read, as long as it has not been modified.
+///
+/// The image can only be modified through [`RetainedImage::image_mut`], which discards the original
+/// data; this way, the original data is never written in place of a modified image.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RetainedImage {
+    image: Image,
+    original: Option<Vec<u8>>,
+}
+impl RetainedImage {
+    /// The image.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// The image, for modification.
+    ///
+    /// The original data is discarded; afterwards, [`RetainedImage::write_original`] reconstructs
+    /// the image from its blocks.
+    pub fn image_mut(&mut self) -> &mut Image {
+        self.original = None;
+        &mut self.image
+    }
+
+    /// The data from which the image was read, if the image has not been modified since.
+    pub fn original(&self) -> Option<&[u8]> {
+        self.original.as_deref()
+    }
+
+    /// Takes the image, discarding the original data.
+    pub fn into_image(self) -> Image {
+        self.image
+    }
+
+    /// Writes the image.
+    ///
+    /// If the image has not been modified, the original data is written verbatim; otherwise, this
+    /// is equivalent to [`Image::write`].
+    pub fn write_original<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        match &self.original {
+            Some(original) => {
+                writer.write_all(original)?;
+                Ok(())
+            },
+            None => self.image.write(writer),
+        }
+    }
+}
+
+/// The pixel density, in dots per inch, assumed if an image does not specify an absolute density.
+pub const DEFAULT_DENSITY_DPI: u16 = 72;
+
+/// The size, in bytes, of the smallest possible JPEG image.
+///
+/// This consists of a Start of Image marker, a Start of Frame block with a single component, a
+/// Start of Scan block with a single component (and no scan data) and an End of Image marker.
+pub const MIN_IMAGE_SIZE: u64 = 2 + (4 + 6 + 3) + (4 + 1 + 2 + 3) + 2;
+
+/// Whether the given data starts with a Start of Image marker, as every JPEG image does.
+pub fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8])
 }
 
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct ImageBuilder {
     pub bit_depth: Option<u8>,
@@ -287,9 +776,15 @@ pub struct ImageBuilder {
     pub density_unit: Option<DensityUnit>,
     pub density_x: Option<u16>,
     pub density_y: Option<u16>,
+    pub restart_interval: Option<u16>,
+    pub adobe_transform: Option<u8>,
     pub leading_blocks: Vec<Block>,
     pub image_data: Vec<u8>,
     pub trailing_blocks: Vec<Block>,
+
+    /// The horizontal pixel density, in dots per inch, assumed if the density fields only specify
+    /// the pixel aspect ratio (density unit 0).
+    pub default_density_dpi: u16,
 }
 impl ImageBuilder {
     pub fn new() -> Self {
@@ -301,10 +796,48 @@ impl ImageBuilder {
             density_unit: None,
             density_x: None,
             density_y: None,
+            restart_interval: None,
+            adobe_transform: None,
             leading_blocks: Vec::new(),
             image_data: Vec::new(),
             trailing_blocks: Vec::new(),
+            default_density_dpi: DEFAULT_DENSITY_DPI,
+        }
+    }
+
+    /// Returns the names of the fields required to build an image that have not been set.
+    ///
+    /// The bit depth, width, height and color space are taken from the Start-of-Frame block; the
+    /// density fields are taken from the JFIF (APP0) or Exif (APP1) block.
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.bit_depth.is_none() {
+            missing.push("bit_depth");
+        }
+        if self.width.is_none() {
+            missing.push("width");
+        }
+        if self.height.is_none() {
+            missing.push("height");
+        }
+        if self.color_space.is_none() {
+            missing.push("color_space");
+        }
+        if self.density_unit.is_none() {
+            missing.push("density_unit");
+        }
+        if self.density_x.is_none() {
+            missing.push("density_x");
+        }
+        if self.density_y.is_none() {
+            missing.push("density_y");
         }
+        missing
+    }
+
+    /// Returns the kinds of the leading blocks encountered so far, in order.
+    pub fn leading_block_kinds(&self) -> impl Iterator<Item = u8> {
+        self.leading_blocks.iter().map(|block| block.kind())
     }
 
     fn build(&self) -> Option<Image> {
@@ -312,9 +845,25 @@ impl ImageBuilder {
         let width = self.width?;
         let height = self.height?;
         let color_space = self.color_space?;
-        let density_unit = self.density_unit?;
-        let density_x = self.density_x?;
-        let density_y = self.density_y?;
+        let mut density_unit = self.density_unit?;
+        let mut density_x = self.density_x?;
+        let mut density_y = self.density_y?;
+        if density_unit == DensityUnit::NoUnit {
+            // the density values only encode the pixel aspect ratio
+            // assume the default density horizontally and derive the vertical density
+            let default_density = self.default_density_dpi.max(1);
+            let aspect_density_y = if density_x == 0 || density_y == 0 {
+                default_density
+            } else {
+                let scaled = u32::from(default_density) * u32::from(density_y) / u32::from(density_x);
+                scaled.try_into().unwrap_or(u16::MAX).max(1)
+            };
+            density_unit = DensityUnit::DotsPerInch;
+            density_x = default_density;
+            density_y = aspect_density_y;
+        }
+        let restart_interval = self.restart_interval;
+        let adobe_transform = self.adobe_transform;
         let leading_blocks = self.leading_blocks.clone();
         let image_data = self.image_data.clone();
         let trailing_blocks = self.trailing_blocks.clone();
@@ -326,6 +875,8 @@ impl ImageBuilder {
             density_unit,
             density_x,
             density_y,
+            restart_interval,
+            adobe_transform,
             leading_blocks,
             image_data,
             trailing_blocks,
@@ -359,3 +910,429 @@ pub enum ColorSpace {
     Cmyk = 4,
     Other(u8),
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles a small grayscale JPEG file with the given blocks between the start-of-image and
+    /// the start-of-frame blocks.
+    fn jpeg_file(leading_blocks: &[Block]) -> Vec<u8> {
+        let mut blocks = vec![Block::Short { kind: 0xD8, fill: 0 }];
+        blocks.extend_from_slice(leading_blocks);
+        // 8 bits, 16x16 pixels, one component
+        blocks.push(Block::Long { kind: 0xC0, data: vec![8, 0, 16, 0, 16, 1, 1, 0x11, 0], fill: 0 });
+        blocks.push(Block::Long { kind: 0xDA, data: vec![1, 1, 0x00, 0, 63, 0], fill: 0 });
+
+        let mut file = Vec::new();
+        for block in &blocks {
+            block.write(&mut file).unwrap();
+        }
+        file.extend_from_slice(&[0x12, 0x34, 0x56]);
+        Block::Short { kind: 0xD9, fill: 0 }.write(&mut file).unwrap();
+        file
+    }
+
+    /// Assembles an Exif block whose first directory contains the given entries, each consisting
+    /// of a tag, a value type, a value count and the encoded values.
+    fn exif_block(entries: &[(u16, u16, u32, Vec<u8>)]) -> Block {
+        let mut tiff = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        let entry_count: u16 = entries.len().try_into().unwrap();
+        tiff.extend_from_slice(&entry_count.to_le_bytes());
+
+        // values that do not fit into an entry follow the directory
+        let mut outside_values = Vec::new();
+        let outside_offset = tiff.len() + 12*entries.len() + 4;
+        for (tag, value_type, count, values) in entries {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&value_type.to_le_bytes());
+            tiff.extend_from_slice(&count.to_le_bytes());
+            if values.len() <= 4 {
+                let mut inline_values = [0u8; 4];
+                inline_values[..values.len()].copy_from_slice(values);
+                tiff.extend_from_slice(&inline_values);
+            } else {
+                let offset: u32 = (outside_offset + outside_values.len()).try_into().unwrap();
+                tiff.extend_from_slice(&offset.to_le_bytes());
+                outside_values.extend_from_slice(values);
+            }
+        }
+        // no further directories
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        tiff.extend_from_slice(&outside_values);
+
+        let mut data = b"Exif\0\0".to_vec();
+        data.extend_from_slice(&tiff);
+        Block::Long { kind: 0xE1, data, fill: 0 }
+    }
+
+    /// Assembles an Exif block specifying the given resolutions and resolution unit.
+    fn exif_resolution_block(x_resolution: u32, y_resolution: u32, unit: u16) -> Block {
+        let rational = |value: u32| {
+            let mut bytes = value.to_le_bytes().to_vec();
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes
+        };
+        exif_block(&[
+            (0x011A, 5, 1, rational(x_resolution)),
+            (0x011B, 5, 1, rational(y_resolution)),
+            (0x0128, 3, 1, unit.to_le_bytes().to_vec()),
+        ])
+    }
+
+    #[test]
+    fn reads_exif_resolution() {
+        let file = jpeg_file(&[exif_resolution_block(300, 300, 2)]);
+        let image = Image::try_read(file.as_slice()).unwrap();
+        assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!(image.density_x, 300);
+        assert_eq!(image.density_y, 300);
+    }
+
+    #[test]
+    fn falls_back_to_default_density_for_exif_aspect_ratio() {
+        // resolution unit 1 means that only the aspect ratio is known
+        let file = jpeg_file(&[exif_resolution_block(1, 2, 1)]);
+        let image = Image::try_read(file.as_slice()).unwrap();
+        assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!(image.density_x, DEFAULT_DENSITY_DPI);
+        assert_eq!(image.density_y, 2*DEFAULT_DENSITY_DPI);
+    }
+
+    #[test]
+    fn sizes_jfif_aspect_ratio_images_using_default_density() {
+        // JFIF 1.01, density unit 0 (aspect ratio only), pixels twice as high as wide
+        let jfif_block = Block::Long {
+            kind: 0xE0,
+            data: b"JFIF\x00\x01\x01\x00\x00\x01\x00\x02\x00\x00".to_vec(),
+            fill: 0,
+        };
+        let file = jpeg_file(&[jfif_block]);
+        let image = Image::try_read_with_default_density(file.as_slice(), 144).unwrap();
+        assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!(image.density_x, 144);
+        assert_eq!(image.density_y, 288);
+
+        // the page size can be derived
+        let info = crate::model::JpegImageInfo::try_from(&image).unwrap();
+        assert_eq!(info.width_pt(), Some(8));
+        assert_eq!(info.height_pt(), Some(4));
+    }
+
+    /// A JFIF block declaring 72 dpi.
+    fn jfif_block() -> Block {
+        Block::Long { kind: 0xE0, data: b"JFIF\x00\x01\x01\x01\x00\x48\x00\x48\x00\x00".to_vec(), fill: 0 }
+    }
+
+    #[test]
+    fn flags_missing_huffman_tables() {
+        let file = jpeg_file(&[jfif_block()]);
+        let mut image = Image::try_read(file.as_slice()).unwrap();
+        assert!(image.lacks_huffman_tables());
+
+        // arithmetic coding does not need any tables
+        for block in &mut image.leading_blocks {
+            if let Block::Long { kind: kind @ 0xC0, .. } = block {
+                *kind = 0xC9;
+            }
+        }
+        assert!(!image.lacks_huffman_tables());
+    }
+
+    #[test]
+    fn accepts_defined_huffman_tables() {
+        // a DC table with a single code of length 1 for category 0
+        let mut table = vec![0x00, 1];
+        table.extend_from_slice(&[0; 15]);
+        table.push(0);
+        let file = jpeg_file(&[jfif_block(), Block::Long { kind: 0xC4, data: table, fill: 0 }]);
+        let image = Image::try_read(file.as_slice()).unwrap();
+        assert!(!image.lacks_huffman_tables());
+    }
+
+    #[test]
+    fn ignores_unreadable_exif_blocks() {
+        // the first directory lies beyond the end of the block
+        let broken_exif = Block::Long { kind: 0xE1, data: b"Exif\0\0II\x2A\x00\x00\x01\x00\x00".to_vec(), fill: 0 };
+
+        // without any other density information, the default density is assumed
+        let file = jpeg_file(std::slice::from_ref(&broken_exif));
+        let image = Image::try_read_with_default_density(file.as_slice(), 144).unwrap();
+        assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!((image.density_x, image.density_y), (144, 144));
+
+        // the JFIF block still counts
+        let file = jpeg_file(&[jfif_block(), broken_exif]);
+        let image = Image::try_read_with_default_density(file.as_slice(), 144).unwrap();
+        assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!((image.density_x, image.density_y), (72, 72));
+    }
+
+    #[test]
+    fn prefers_exif_resolution_over_jfif_density() {
+        // regardless of the order of the blocks
+        for blocks in [[jfif_block(), exif_resolution_block(300, 300, 2)], [exif_resolution_block(300, 300, 2), jfif_block()]] {
+            let file = jpeg_file(&blocks);
+            let image = Image::try_read(file.as_slice()).unwrap();
+            assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+            assert_eq!((image.density_x, image.density_y), (300, 300));
+        }
+
+        // an Exif block without a resolution does not override the JFIF density...
+        let jfif_150 = Block::Long { kind: 0xE0, data: b"JFIF\x00\x01\x01\x01\x00\x96\x00\x96\x00\x00".to_vec(), fill: 0 };
+        let file = jpeg_file(&[exif_block(&[]), jfif_150]);
+        let image = Image::try_read_with_default_density(file.as_slice(), 144).unwrap();
+        assert_eq!((image.density_x, image.density_y), (150, 150));
+
+        // ...but its default resolution takes precedence over the default density
+        let file = jpeg_file(&[exif_block(&[])]);
+        let image = Image::try_read_with_default_density(file.as_slice(), 144).unwrap();
+        assert_eq!((image.density_x, image.density_y), (72, 72));
+    }
+
+    #[test]
+    fn classifies_app_segments() {
+        let jfif = Block::Long { kind: 0xE0, data: b"JFIF\x00\x01\x01".to_vec(), fill: 0 };
+        assert_eq!(jfif.app_marker_number(), Some(0));
+        assert_eq!(jfif.app_identifier(), Some(b"JFIF".as_slice()));
+        assert_eq!(jfif.app_payload(), Some(b"\x01\x01".as_slice()));
+        assert!(jfif.is_app_with_identifier(0, b"JFIF"));
+        assert!(!jfif.is_app_with_identifier(1, b"JFIF"));
+
+        let exif = Block::Long { kind: 0xE1, data: b"Exif\x00\x00II".to_vec(), fill: 0 };
+        assert_eq!(exif.app_marker_number(), Some(1));
+        assert_eq!(exif.app_identifier(), Some(b"Exif".as_slice()));
+
+        let icc = Block::Long { kind: 0xE2, data: b"ICC_PROFILE\x00\x01\x01".to_vec(), fill: 0 };
+        assert_eq!(icc.app_marker_number(), Some(2));
+        assert!(icc.is_app_with_identifier(2, b"ICC_PROFILE"));
+
+        let adobe = Block::Long { kind: 0xEE, data: b"Adobe".to_vec(), fill: 0 };
+        assert_eq!(adobe.app_marker_number(), Some(14));
+        assert!(adobe.is_appn());
+        assert_eq!(adobe.app_identifier(), None);
+
+        let last = Block::Long { kind: 0xEF, data: Vec::new(), fill: 0 };
+        assert_eq!(last.app_marker_number(), Some(15));
+
+        let comment = Block::Long { kind: 0xFE, data: b"JFIF\x00".to_vec(), fill: 0 };
+        assert_eq!(comment.app_marker_number(), None);
+        assert!(!comment.is_appn());
+        assert_eq!(comment.app_identifier(), None);
+    }
+
+    #[test]
+    fn writes_unmodified_image_verbatim() {
+        let file = jpeg_file(&[jfif_block()]);
+        let mut retained = Image::try_read_retaining_original(file.as_slice()).unwrap();
+        assert_eq!(retained.original(), Some(file.as_slice()));
+        let mut passthrough = Vec::new();
+        retained.write_original(&mut passthrough).unwrap();
+        assert_eq!(passthrough, file);
+
+        // once modified, the image is reconstructed
+        retained.image_mut().leading_blocks.retain(|block| !block.is_appn());
+        assert_eq!(retained.original(), None);
+        let mut reconstructed = Vec::new();
+        retained.write_original(&mut reconstructed).unwrap();
+        let mut expected = Vec::new();
+        retained.image().write(&mut expected).unwrap();
+        assert_eq!(reconstructed, expected);
+        assert_ne!(reconstructed, file);
+    }
+
+    /// Encodes a grayscale gradient of 64x32 pixels (32 MCUs), allowing the encoder to be
+    /// configured first.
+    fn encode_jpeg(configure: impl FnOnce(&mut jpeg_encoder::Encoder<&mut Vec<u8>>)) -> Vec<u8> {
+        let samples: Vec<u8> = (0..64*32)
+            .map(|i| (i % 64 * 2 + i / 64 * 4) as u8)
+            .collect();
+        let mut jpeg_data = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg_data, 90);
+        configure(&mut encoder);
+        encoder.encode(&samples, 64, 32, jpeg_encoder::ColorType::Luma).unwrap();
+        jpeg_data
+    }
+
+    /// Assembles the data of an Exif (APP1) block recording the given resolution in dots per inch.
+    fn exif_data(x_resolution: u32, y_resolution: u32) -> Vec<u8> {
+        let mut data = b"Exif\0\0".to_vec();
+        // big-endian TIFF header, IFD0 at offset 8
+        data.extend_from_slice(b"MM\0\x2A\0\0\0\x08");
+        data.extend_from_slice(&3u16.to_be_bytes());
+        // XResolution and YResolution (rationals stored after the IFD), ResolutionUnit (inches)
+        data.extend_from_slice(&[0x01, 0x1A, 0, 5, 0, 0, 0, 1, 0, 0, 0, 50]);
+        data.extend_from_slice(&[0x01, 0x1B, 0, 5, 0, 0, 0, 1, 0, 0, 0, 58]);
+        data.extend_from_slice(&[0x01, 0x28, 0, 3, 0, 0, 0, 1, 0, 2, 0, 0]);
+        // no further IFDs
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        for resolution in [x_resolution, y_resolution] {
+            data.extend_from_slice(&resolution.to_be_bytes());
+            data.extend_from_slice(&1u32.to_be_bytes());
+        }
+        data
+    }
+
+    /// Inserts two fill bytes before each marker following Start-of-Image up to and including
+    /// Start-of-Scan as well as before End-of-Image.
+    fn add_fill_bytes(jpeg_data: &[u8]) -> Vec<u8> {
+        let mut filled = jpeg_data[..2].to_vec();
+        let mut position = 2;
+        loop {
+            assert_eq!(jpeg_data[position], 0xFF);
+            let kind = jpeg_data[position + 1];
+            let length = usize::from(u16::from_be_bytes([jpeg_data[position + 2], jpeg_data[position + 3]]));
+            filled.extend_from_slice(&[0xFF, 0xFF]);
+            filled.extend_from_slice(&jpeg_data[position..position + 2 + length]);
+            position += 2 + length;
+            if kind == 0xDA {
+                break;
+            }
+        }
+        let end = jpeg_data.len() - 2;
+        filled.extend_from_slice(&jpeg_data[position..end]);
+        filled.extend_from_slice(&[0xFF, 0xFF]);
+        filled.extend_from_slice(&jpeg_data[end..]);
+        filled
+    }
+
+    fn write_image(image: &Image) -> Vec<u8> {
+        let mut written = Vec::new();
+        image.write(&mut written).unwrap();
+        written
+    }
+
+    #[test]
+    fn round_trips_exif_and_fill_bytes() {
+        let jpeg_data = add_fill_bytes(&encode_jpeg(|encoder| {
+            encoder.add_app_segment(1, &exif_data(300, 300)).unwrap();
+        }));
+        let image = Image::try_read(jpeg_data.as_slice()).unwrap();
+        assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!((image.density_x, image.density_y), (300, 300));
+
+        let exif_block = image.first_block_of_kind(0xE1).unwrap();
+        assert_eq!(exif_block.fill(), 2);
+        assert_eq!(exif_block.data(), exif_data(300, 300));
+        assert_eq!(image.first_block_of_kind(0xDA).unwrap().fill(), 2);
+
+        assert_eq!(write_image(&image), jpeg_data);
+    }
+
+    #[test]
+    fn reads_restart_interval() {
+        let jpeg_data = encode_jpeg(|encoder| encoder.set_restart_interval(5));
+        let image = Image::try_read(jpeg_data.as_slice()).unwrap();
+        assert_eq!(image.restart_interval, Some(5));
+        // 32 MCUs in intervals of 5
+        let marker_count = image.image_data.windows(2)
+            .filter(|pair| pair[0] == 0xFF && (0xD0..=0xD7).contains(&pair[1]))
+            .count();
+        assert_eq!(marker_count, 6);
+        image.validate_restart_markers().unwrap();
+    }
+
+    #[test]
+    fn reads_image_with_misnumbered_restart_markers() {
+        let mut jpeg_data = encode_jpeg(|encoder| encoder.set_restart_interval(5));
+        // the entropy-coded data only contains 0xFF followed by 0x00 or a marker
+        let second_marker = jpeg_data.windows(2)
+            .position(|pair| pair == [0xFF, 0xD1])
+            .unwrap();
+        jpeg_data[second_marker + 1] = 0xD2;
+
+        let image = Image::try_read(jpeg_data.as_slice()).unwrap();
+        assert!(matches!(
+            image.validate_restart_markers(),
+            Err(Error::UnexpectedRestartMarker { expected_kind: 0xD1, obtained_kind: 0xD2 }),
+        ));
+        // the image is retained unchanged
+        assert_eq!(write_image(&image), jpeg_data);
+    }
+
+    #[test]
+    fn counts_missing_restart_markers() {
+        let jpeg_data = encode_jpeg(|encoder| encoder.set_restart_interval(5));
+        let mut image = Image::try_read(jpeg_data.as_slice()).unwrap();
+        image.restart_interval = Some(4);
+        assert!(matches!(
+            image.validate_restart_markers(),
+            Err(Error::RestartMarkerCount { expected: 7, obtained: 6 }),
+        ));
+    }
+
+    #[test]
+    fn sets_density_in_jfif_and_exif() {
+        let jpeg_data = encode_jpeg(|encoder| {
+            encoder.set_density(jpeg_encoder::Density::Inch { x: 300, y: 300 });
+            encoder.add_app_segment(1, &exif_data(300, 300)).unwrap();
+        });
+        let mut image = Image::try_read(jpeg_data.as_slice()).unwrap();
+        assert_eq!((image.density_x, image.density_y), (300, 300));
+        image.set_density(DensityUnit::DotsPerInch, 600, 600);
+
+        let written = write_image(&image);
+        // only the density values change
+        assert_eq!(written.len(), jpeg_data.len());
+        let reread = Image::try_read(written.as_slice()).unwrap();
+        assert_eq!(reread.density_unit, DensityUnit::DotsPerInch);
+        assert_eq!((reread.density_x, reread.density_y), (600, 600));
+        assert_eq!(reread.first_block_of_kind(0xE1).unwrap().data(), exif_data(600, 600));
+        let jfif_data = reread.first_block_of_kind(0xE0).unwrap().data();
+        assert_eq!(jfif_data[7], DensityUnit::DotsPerInch.to_base_type());
+        assert_eq!(jfif_data[8..12], [0x02, 0x58, 0x02, 0x58]);
+        assert_eq!(reread.image_data, image.image_data);
+        assert_eq!(write_image(&reread), written);
+    }
+
+    #[test]
+    fn inserts_jfif_block_when_setting_density() {
+        let jpeg_data = encode_jpeg(|_encoder| {});
+        let mut image = Image::try_read(jpeg_data.as_slice()).unwrap();
+        image.leading_blocks.retain(|block| block.kind() != 0xE0);
+        image.set_density(DensityUnit::DotsPerCentimeter, 120, 100);
+
+        let reread = Image::try_read(write_image(&image).as_slice()).unwrap();
+        assert_eq!(reread.leading_blocks[1].kind(), 0xE0);
+        assert_eq!(reread.density_unit, DensityUnit::DotsPerCentimeter);
+        assert_eq!((reread.density_x, reread.density_y), (120, 100));
+    }
+
+    /// Asserts that writing the image fails with the given error without writing anything.
+    fn assert_write_fails(image: &Image, expected: fn(&Error) -> bool) {
+        let mut written = Vec::new();
+        let error = image.write(&mut written).unwrap_err();
+        assert!(expected(&error), "unexpected error {:?}", error);
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn validates_blocks_before_writing() {
+        let image = Image::try_read(encode_jpeg(|_encoder| {}).as_slice()).unwrap();
+        image.validate().unwrap();
+
+        let mut missing_soi = image.clone();
+        missing_soi.leading_blocks.remove(0);
+        assert_write_fails(&missing_soi, |e| matches!(e, Error::MissingStartOfImage));
+
+        let mut missing_sos = image.clone();
+        missing_sos.leading_blocks.pop();
+        assert_write_fails(&missing_sos, |e| matches!(e, Error::StartOfScanCount { obtained: 0 }));
+
+        let mut double_sos = image.clone();
+        let sos = double_sos.leading_blocks.last().unwrap().clone();
+        double_sos.leading_blocks.insert(1, sos);
+        assert_write_fails(&double_sos, |e| matches!(e, Error::StartOfScanCount { obtained: 2 }));
+
+        let mut sos_not_last = image.clone();
+        let sos = sos_not_last.leading_blocks.pop().unwrap();
+        let sos_position = sos_not_last.leading_blocks.len() - 1;
+        sos_not_last.leading_blocks.insert(sos_position, sos);
+        assert_write_fails(&sos_not_last, |e| matches!(e, Error::StartOfScanNotLast));
+
+        let mut missing_eoi = image.clone();
+        missing_eoi.trailing_blocks.clear();
+        assert_write_fails(&missing_eoi, |e| matches!(e, Error::MissingEndOfImage));
+    }
+}
@@ -0,0 +1,127 @@
+//! Syntactic validation of BCP 47 language tags.
+//!
+//! Only the syntax of a tag is checked; whether its subtags are actually registered is not.
+
+
+/// Whether the given string is a syntactically valid BCP 47 language tag, e.g. `de-CH` or
+/// `zh-Hant-TW`.
+///
+/// The tag consists of a language, which may be followed by up to three extended language subtags,
+/// then optionally a script, a region, any number of variants, any number of extensions and a
+/// private-use part. Tags entirely for private use (`x-...`) are accepted as well. Comparisons are
+/// case-insensitive.
+///
+/// Although RFC 5646 syntactically permits language subtags of four to eight letters, none are
+/// registered and they are mostly the result of mistakes such as `english`; they are therefore
+/// refused. The irregular grandfathered tags (e.g. `i-klingon`) are refused as well.
+pub fn is_valid_language_tag(tag: &str) -> bool {
+    let subtags: Vec<&str> = tag.split('-').collect();
+    if subtags.iter().any(|subtag| subtag.is_empty() || subtag.len() > 8 || !subtag.bytes().all(|b| b.is_ascii_alphanumeric())) {
+        return false;
+    }
+
+    let mut index = 0;
+    if subtags[0].eq_ignore_ascii_case("x") {
+        return is_valid_private_use(&subtags[1..]);
+    }
+
+    // language
+    if !is_alpha(subtags[index], 2..=3) {
+        return false;
+    }
+    index += 1;
+
+    // extended language subtags
+    let mut extlang_count = 0;
+    while extlang_count < 3 && subtags.get(index).is_some_and(|s| is_alpha(s, 3..=3)) {
+        index += 1;
+        extlang_count += 1;
+    }
+
+    // script
+    if subtags.get(index).is_some_and(|s| is_alpha(s, 4..=4)) {
+        index += 1;
+    }
+
+    // region
+    if subtags.get(index).is_some_and(|s| is_alpha(s, 2..=2) || is_digit(s, 3..=3)) {
+        index += 1;
+    }
+
+    // variants
+    while subtags.get(index).is_some_and(|s| is_variant(s)) {
+        index += 1;
+    }
+
+    // extensions
+    while let Some(singleton) = subtags.get(index) {
+        if singleton.len() != 1 || singleton.eq_ignore_ascii_case("x") {
+            break;
+        }
+        index += 1;
+        let extension_start = index;
+        while subtags.get(index).is_some_and(|s| s.len() >= 2) {
+            index += 1;
+        }
+        if index == extension_start {
+            // an extension needs at least one subtag
+            return false;
+        }
+    }
+
+    // private use
+    if let Some(x) = subtags.get(index) {
+        if !x.eq_ignore_ascii_case("x") {
+            return false;
+        }
+        return is_valid_private_use(&subtags[index+1..]);
+    }
+
+    true
+}
+
+/// Whether the subtags following an `x` singleton form a valid private-use part.
+fn is_valid_private_use(subtags: &[&str]) -> bool {
+    // the length and the characters have already been checked
+    !subtags.is_empty()
+}
+
+fn is_alpha(subtag: &str, lengths: std::ops::RangeInclusive<usize>) -> bool {
+    lengths.contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphabetic())
+}
+
+fn is_digit(subtag: &str, lengths: std::ops::RangeInclusive<usize>) -> bool {
+    lengths.contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_variant(subtag: &str) -> bool {
+    // 5 to 8 alphanumerics, or a digit followed by 3 alphanumerics
+    subtag.len() >= 5
+        || (subtag.len() == 4 && subtag.as_bytes()[0].is_ascii_digit())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_language_tag;
+
+    #[test]
+    fn accepts_valid_tags() {
+        assert!(is_valid_language_tag("de"));
+        assert!(is_valid_language_tag("de-CH"));
+        assert!(is_valid_language_tag("zh-Hant-TW"));
+        assert!(is_valid_language_tag("de-CH-1996"));
+        assert!(is_valid_language_tag("en-US-u-ca-gregory"));
+        assert!(is_valid_language_tag("x-whatever"));
+    }
+
+    #[test]
+    fn refuses_invalid_tags() {
+        assert!(!is_valid_language_tag(""));
+        assert!(!is_valid_language_tag("english"));
+        assert!(!is_valid_language_tag("de-"));
+        assert!(!is_valid_language_tag("de_CH"));
+        assert!(!is_valid_language_tag("en-u"));
+        assert!(!is_valid_language_tag("en-x"));
+    }
+}
@@ -0,0 +1,19 @@
+//! Portable Document Format Manual Character Recognition.
+//!
+//! Assembles PDF files from JPEG scans and manually transcribed text that makes them searchable.
+
+
+pub mod builder;
+pub mod file_to_pdf;
+pub mod icc;
+pub mod image_path;
+pub mod import;
+pub mod jpeg;
+pub mod language;
+pub mod model;
+pub mod ocr;
+pub mod overlay;
+pub mod pdf;
+pub mod recover;
+pub mod tiff;
+pub mod truetype;
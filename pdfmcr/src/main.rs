@@ -1,39 +1,56 @@
+mod admin;
+mod api_error;
+mod compression;
 mod config;
-mod file_to_pdf;
+mod events;
 mod filters;
-mod image_path;
-mod jpeg;
-mod model;
-mod pdf;
+mod image_response;
+mod shutdown;
+mod state_format;
+mod tenant;
+#[cfg(test)]
+mod test_support;
 
 
 use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
-use std::io;
-use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
 
 use askama::Template;
 use clap::Parser;
-use rocket::{FromForm, Responder, uri};
+use pdfmcr::file_to_pdf::{ConversionError, ConversionOptions, DEFAULT_PRODUCER, file_to_pdf};
+use pdfmcr::image_path::{ContentHashAlgorithm, ContentName, ImagePath};
+use pdfmcr::model::{
+    Annotation, Artifact, ColorSpace, DensityUnit, EMBEDDABLE_BIT_DEPTHS, FontFamily, JpegImage,
+    JpegImageInfo, Page,
+};
+use rocket::{Build, FromForm, Responder, Rocket, uri};
 use rocket::form::Form;
 use rocket::fs::{FileServer, TempFile};
-use rocket::http::{ContentType, Status};
+use rocket::http::{ContentType, Header};
 use rocket::response::Redirect;
+use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
 use serde::{Deserialize, Serialize};
-use sha3::Sha3_512;
-use sha3::digest::{Digest, DynDigest};
+use sha3::digest::DynDigest;
 use tokio::io::AsyncReadExt;
 use tokio::sync::RwLock;
-use tracing::error;
+use tracing::{error, warn};
 
-use crate::config::{CONFIG, CONFIG_PATH, load_config};
-use crate::image_path::ImagePath;
-use crate::model::{Annotation, Artifact, JpegImage, JpegImageInfo, Page};
+use crate::admin::Admin;
+use crate::api_error::ApiError;
+use crate::config::{CONFIG, CONFIG_PATH, Config, load_config};
+use crate::events::DocumentEvent;
+use crate::image_response::{ImageRequestHeaders, ImageResponse};
+use crate::state_format::StateFormat;
+use crate::tenant::Tenant;
 
 
-static WEB_FILE: OnceLock<RwLock<crate::model::File>> = OnceLock::new();
+static WEB_FILES: OnceLock<RwLock<BTreeMap<Tenant, Arc<RwLock<pdfmcr::model::File>>>>> = OnceLock::new();
 
 
 macro_rules! path_from_components {
@@ -68,6 +85,32 @@ enum HtmlOrRedirect {
     Redirect(rocket::response::Redirect),
 }
 
+#[derive(Debug, Responder)]
+enum HtmlOrNotFound {
+    Html(Html),
+    #[response(status = 404)]
+    NotFound(Html),
+}
+
+/// An exported PDF document.
+///
+/// The document is assembled in memory, so its length is delivered up front as `Content-Length`;
+/// the number of its pages is delivered in the `X-Pdfmcr-Page-Count` header.
+#[derive(Debug, Responder)]
+#[response(content_type = "pdf")]
+struct PdfExport {
+    data: Vec<u8>,
+    page_count: Header<'static>,
+}
+impl PdfExport {
+    pub fn new(data: Vec<u8>, page_count: usize) -> Self {
+        Self {
+            data,
+            page_count: Header::new("X-Pdfmcr-Page-Count", page_count.to_string()),
+        }
+    }
+}
+
 
 #[derive(Template)]
 #[template(path = "page.html")]
@@ -81,40 +124,252 @@ struct PageTemplate<'a> {
 #[template(path = "start.html")]
 struct StartTemplate;
 
+#[derive(Template)]
+#[template(path = "not_found.html")]
+struct NotFoundTemplate {
+    page_number: usize,
+    page_count: usize,
+}
+
+
+/// Loads a state file, falling back to an empty file if it does not exist.
+///
+/// The state file may be stored either as CBOR or as JSON; the format is detected from its
+/// contents.
+///
+/// If the state file cannot be parsed, it is renamed aside (appending `.corrupt.` and the current
+/// Unix timestamp to its name) and an empty file is returned, allowing the user to start anew
+/// without losing the corrupt data.
+fn load_state_file(state_file_path: &Path) -> Result<pdfmcr::model::File, String> {
+    let state_bytes = match std::fs::metadata(state_file_path) {
+        Ok(m) => {
+            if !m.is_file() {
+                return Err(format!("state file {:?} exists and is not a file", state_file_path));
+            }
+
+            match std::fs::read(state_file_path) {
+                Ok(sb) => sb,
+                Err(e) => return Err(format!("failed to read state file {:?}: {}", state_file_path, e)),
+            }
+        },
+        Err(e) => {
+            if e.kind() == io::ErrorKind::NotFound {
+                return Ok(pdfmcr::model::File::default());
+            } else {
+                return Err(format!("could not open state file {:?}: {}", state_file_path, e));
+            }
+        },
+    };
+
+    let state_format = StateFormat::detect(&state_bytes);
+    let parse_error = match state_format.decode(&state_bytes) {
+        Ok(s) => return Ok(s),
+        Err(e) => e,
+    };
+
+    let mut corrupt_file_name = state_file_path.file_name()
+        .unwrap_or_default()
+        .to_owned();
+    corrupt_file_name.push(format!(".corrupt.{}", time::OffsetDateTime::now_utc().unix_timestamp()));
+    let corrupt_path = state_file_path.with_file_name(corrupt_file_name);
+    if let Err(e) = std::fs::rename(state_file_path, &corrupt_path) {
+        return Err(format!(
+            "failed to parse state file {:?} as {} ({}) and failed to move it aside to {:?}: {}",
+            state_file_path, state_format, parse_error, corrupt_path, e,
+        ));
+    }
+    error!(
+        "failed to parse state file {:?} as {}: {}; moved it aside to {:?} and starting with an empty file",
+        state_file_path, state_format, parse_error, corrupt_path,
+    );
+    Ok(pdfmcr::model::File::default())
+}
+
+/// Logs a warning for each page of the given file whose image does not exist in the image
+/// directory.
+///
+/// Returns the number of missing images.
+fn warn_about_missing_images(file: &pdfmcr::model::File, image_dir: &Path) -> usize {
+    let mut missing_count = 0;
+    for (page, file_page) in file.pages.iter().enumerate() {
+        let Some(scanned_image) = file_page.scanned_image.as_ref() else {
+            continue;
+        };
+        let os_path = scanned_image.file_path.to_os_path(image_dir);
+        if !os_path.is_file() {
+            warn!("image {} of page {} not found at {}", scanned_image.file_path, page, os_path.display());
+            missing_count += 1;
+        }
+    }
+    missing_count
+}
+
+/// Obtains the state of the given tenant, loading it from its state file if necessary.
+async fn tenant_file(tenant: &Tenant) -> Result<Arc<RwLock<pdfmcr::model::File>>, ApiError> {
+    {
+        let files_guard = WEB_FILES
+            .get().expect("WEB_FILES not set?!")
+            .read().await;
+        if let Some(file_lock) = files_guard.get(tenant) {
+            return Ok(Arc::clone(file_lock));
+        }
+    }
+
+    let (state_file_path, image_dir_to_check) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        let image_dir_to_check = config_guard.check_images_on_load
+            .then(|| tenant.image_dir(Path::new(&config_guard.image_dir)));
+        (tenant.state_file_path(Path::new(&config_guard.state_file_path)), image_dir_to_check)
+    };
+    let file = match load_state_file(&state_file_path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ApiError::Internal { message: "failed to load state file" });
+        },
+    };
+    if let Some(image_dir) = image_dir_to_check {
+        warn_about_missing_images(&file, &image_dir);
+    }
+
+    let mut files_guard = WEB_FILES
+        .get().expect("WEB_FILES not set?!")
+        .write().await;
+    // another request might have loaded the state in the meantime; keep that one
+    let file_lock = files_guard
+        .entry(tenant.clone())
+        .or_insert_with(|| Arc::new(RwLock::new(file)));
+    Ok(Arc::clone(file_lock))
+}
+
 
 #[rocket::get("/")]
-async fn index() -> HtmlOrRedirect {
+async fn index(tenant: Tenant) -> Result<HtmlOrRedirect, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
     let page_count = {
-        let file_guard = WEB_FILE
-            .get().expect("WEB_FILE not set?!")
-            .read().await;
+        let file_guard = file_lock.read().await;
         file_guard.pages.len()
     };
     if page_count == 0 {
-        HtmlOrRedirect::Html(StartTemplate.render().unwrap().into())
+        Ok(HtmlOrRedirect::Html(StartTemplate.render().unwrap().into()))
     } else {
-        HtmlOrRedirect::Redirect(Redirect::to("/page/0"))
+        Ok(HtmlOrRedirect::Redirect(Redirect::to("/page/0")))
     }
 }
 
 #[rocket::get("/page/<page_number>")]
-async fn page_page(page_number: usize) -> Result<Html, (Status, Cow<'static, str>)> {
-    let (page_count, page) = {
-        let file_guard = WEB_FILE
-            .get().expect("WEB_FILE not set?!")
-            .read().await;
-        let page_count = file_guard.pages.len();
-        if page_number >= page_count {
-            return Err((Status::NotFound, Cow::Owned(format!("page {page_number} does not exist"))));
-        }
-        (page_count, file_guard.pages[page_number].clone())
+async fn page_page(tenant: Tenant, page_number: usize) -> Result<HtmlOrNotFound, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+
+    // render while holding the lock instead of cloning the page
+    // (rendering does not await, so this does not block other tasks for long)
+    let file_guard = file_lock.read().await;
+    let page_count = file_guard.pages.len();
+    let Some(page) = file_guard.pages.get(page_number) else {
+        // this is shown in the browser; answer with a page instead of a JSON error
+        let not_found_template = NotFoundTemplate {
+            page_number,
+            page_count,
+        };
+        return Ok(HtmlOrNotFound::NotFound(not_found_template.render().unwrap().into()));
     };
     let page_template = PageTemplate {
         page_number,
         page_count,
-        page: &page,
+        page,
     };
-    Ok(page_template.render().unwrap().into())
+    Ok(HtmlOrNotFound::Html(page_template.render().unwrap().into()))
+}
+
+/// The number of pages listed by [`list_pages`] if no limit is given.
+const DEFAULT_PAGE_LISTING_LIMIT: usize = 50;
+
+/// The maximum number of pages listed by [`list_pages`] at once.
+const MAX_PAGE_LISTING_LIMIT: usize = 500;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+struct PageSummary {
+    pub index: usize,
+    pub width_pt: Option<f64>,
+    pub height_pt: Option<f64>,
+    pub has_image: bool,
+    pub annotation_count: usize,
+    pub artifact_count: usize,
+    pub reviewed: bool,
+    pub source_filename: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+struct PageListing {
+    pub total_count: usize,
+    pub offset: usize,
+    pub pages: Vec<PageSummary>,
+}
+
+#[rocket::get("/pages?<offset>&<limit>&<reviewed>")]
+async fn list_pages(tenant: Tenant, offset: Option<usize>, limit: Option<usize>, reviewed: Option<bool>) -> Result<Json<PageListing>, ApiError> {
+    let offset = offset.unwrap_or(0);
+    let limit = limit
+        .unwrap_or(DEFAULT_PAGE_LISTING_LIMIT)
+        .min(MAX_PAGE_LISTING_LIMIT);
+
+    let file_lock = tenant_file(&tenant).await?;
+    let file_guard = file_lock.read().await;
+
+    // offset, limit and total count refer to the filtered pages; the index remains that within the
+    // whole document
+    let matching_pages = || file_guard.pages.iter()
+        .enumerate()
+        .filter(|(_index, page)| reviewed.is_none_or(|r| page.reviewed == r));
+    let pages = matching_pages()
+        .skip(offset)
+        .take(limit)
+        .map(|(index, page)| PageSummary {
+            index,
+            width_pt: page.width_pt(),
+            height_pt: page.height_pt(),
+            has_image: page.scanned_image.is_some(),
+            annotation_count: page.annotations.len(),
+            artifact_count: page.artifacts.len(),
+            reviewed: page.reviewed,
+            source_filename: page.source_filename.clone(),
+        })
+        .collect();
+    Ok(Json(PageListing {
+        total_count: matching_pages().count(),
+        offset,
+        pages,
+    }))
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+struct Capabilities {
+    pub color_spaces: Vec<ColorSpace>,
+    pub bit_depths: Vec<u8>,
+    pub density_units: Vec<DensityUnit>,
+    pub font_families: Vec<FontFamily>,
+    pub max_upload_bytes: Option<u64>,
+}
+
+/// Describes what the server accepts, so that clients can adapt their user interfaces.
+///
+/// The maximum upload size is the smaller of Rocket's `file` and `data-form` limits; it is `null`
+/// if neither limit is configured.
+#[rocket::get("/capabilities")]
+fn capabilities(rocket_config: &rocket::Config) -> Json<Capabilities> {
+    let max_upload_bytes = ["file", "data-form"].into_iter()
+        .filter_map(|name| rocket_config.limits.get(name))
+        .map(|limit| limit.as_u64())
+        .min();
+    Json(Capabilities {
+        color_spaces: ColorSpace::ALL.to_vec(),
+        bit_depths: EMBEDDABLE_BIT_DEPTHS.to_vec(),
+        density_units: DensityUnit::ABSOLUTE.to_vec(),
+        font_families: FontFamily::ALL.to_vec(),
+        max_upload_bytes,
+    })
 }
 
 #[derive(FromForm)]
@@ -123,27 +378,28 @@ struct MakePageForm<'r> {
     pub background_image: TempFile<'r>,
 }
 
-async fn persist_state_file() -> Result<(), (Status, Cow<'static, str>)> {
+async fn persist_state_file(tenant: &Tenant) -> Result<(), ApiError> {
     let file_path = {
         let config_guard = CONFIG
             .get().expect("CONFIG not set?!")
             .read().await;
-        config_guard.state_file_path.clone()
+        tenant.state_file_path(Path::new(&config_guard.state_file_path))
     };
+    let state_format = StateFormat::for_path(&file_path);
+    let file_lock = tenant_file(tenant).await?;
     let file_data = {
-        let file_guard = WEB_FILE
-            .get().expect("WEB_FILE not set?!")
-            .read().await;
-        let mut buf = Vec::new();
-        if let Err(e) = ciborium::into_writer(&*file_guard, &mut buf) {
-            error!("failed to encode state as CBOR: {}", e);
-            return Err((Status::InternalServerError, Cow::Borrowed("failed to encode state as CBOR")));
+        let file_guard = file_lock.read().await;
+        match state_format.encode(&file_guard) {
+            Ok(fd) => fd,
+            Err(e) => {
+                error!("failed to encode state as {}: {}", state_format, e);
+                return Err(ApiError::Internal { message: "failed to encode state" });
+            },
         }
-        buf
     };
     if let Err(e) = std::fs::write(&file_path, &file_data) {
-        error!("failed to write state CBOR file {:?}: {}", file_path, e);
-        return Err((Status::InternalServerError, Cow::Borrowed("failed to write state CBOR file")));
+        error!("failed to write state {} file {:?}: {}", state_format, file_path, e);
+        return Err(ApiError::Internal { message: "failed to write state file" });
     }
     Ok(())
 }
@@ -162,197 +418,1045 @@ impl SetAnnotationsData {
 
 
 #[rocket::post("/page/<page>/annotations", data = "<set_annotations>")]
-async fn set_page_annotations(page: usize, set_annotations: Json<SetAnnotationsData>) -> Result<Cow<'static, str>, (Status, Cow<'static, str>)> {
+async fn set_page_annotations(tenant: Tenant, page: usize, set_annotations: Result<Json<SetAnnotationsData>, rocket::serde::json::Error<'_>>) -> Result<Cow<'static, str>, ApiError> {
+    let set_annotations = set_annotations?;
+    let (strict_bounds, max_annotations_per_page) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        (config_guard.strict_annotation_bounds, config_guard.max_annotations_per_page)
+    };
+
+    let file_lock = tenant_file(&tenant).await?;
     {
-        let mut file_guard = WEB_FILE
-            .get().expect("WEB_FILE not set?!")
-            .write().await;
+        let mut file_guard = file_lock.write().await;
         if page >= file_guard.pages.len() {
-            return Err((Status::NotFound, Cow::Borrowed("no such page")));
+            return Err(ApiError::NoSuchPage { page });
         }
         let (annotations, artifacts) = set_annotations.into_inner().into_inner();
+
+        let annotation_count = annotations.len() + artifacts.len();
+        if let Some(limit) = max_annotations_per_page.filter(|&limit| annotation_count > limit) {
+            return Err(ApiError::TooManyAnnotations { page, limit });
+        }
+
+        let invalid_language = annotations.iter()
+            .chain(artifacts.iter().map(|artifact| &artifact.annotation))
+            .flat_map(|annotation| annotation.elements.iter())
+            .filter_map(|chunk| chunk.language.as_deref())
+            .find(|language| !pdfmcr::language::is_valid_language_tag(language));
+        if let Some(language) = invalid_language {
+            return Err(ApiError::InvalidLanguage { language: language.to_owned() });
+        }
+
+        check_page_typography(page, &annotations, &artifacts)?;
+
+        if strict_bounds {
+            let out_of_bounds: Vec<usize> = annotations.iter()
+                .enumerate()
+                .filter(|(_, annotation)| {
+                    // pages of unknown size cannot be checked
+                    file_guard.pages[page].contains_point_pt(annotation.left, annotation.bottom) == Some(false)
+                })
+                .map(|(index, _)| index)
+                .collect();
+            if !out_of_bounds.is_empty() {
+                return Err(ApiError::AnnotationsOutOfBounds { page, indices: out_of_bounds });
+            }
+        }
+
         file_guard.pages[page].annotations = annotations;
         file_guard.pages[page].artifacts = artifacts;
     }
+    crate::events::publish(&tenant, DocumentEvent::AnnotationsChanged { page });
 
     Ok(Cow::Borrowed("OK"))
 }
 
-#[rocket::post("/page", data = "<form>")]
-async fn make_page(mut form: Form<MakePageForm<'_>>) -> Result<Redirect, (Status, Cow<'static, str>)> {
-    use std::fmt::Write;
+#[rocket::post("/page/<page>/annotations/order", data = "<indices>")]
+async fn set_page_reading_order(tenant: Tenant, page: usize, indices: Json<Vec<usize>>) -> Result<Cow<'static, str>, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    {
+        let mut file_guard = file_lock.write().await;
+        let Some(file_page) = file_guard.pages.get_mut(page) else {
+            return Err(ApiError::NoSuchPage { page });
+        };
+        if !file_page.set_reading_order(&indices) {
+            return Err(ApiError::InvalidReadingOrder { page });
+        }
+    }
 
-    // generate a name for the JPEG file out of its size and checksum
-    let jpeg_size = form.background_image.len();
+    persist_state_file(&tenant).await?;
+    crate::events::publish(&tenant, DocumentEvent::AnnotationsChanged { page });
 
-    let filename = {
-        let mut file = match form.background_image.open().await {
-            Ok(f) => f,
-            Err(e) => {
-                error!("error opening uploaded file {:?}: {}", form.background_image, e);
-                return Err((Status::InternalServerError, Cow::Borrowed("failed to open uploaded file")));
-            },
+    Ok(Cow::Borrowed("OK"))
+}
+
+#[rocket::post("/page/<page>/reviewed", data = "<flag>")]
+async fn set_page_reviewed(tenant: Tenant, page: usize, flag: Json<bool>) -> Result<Cow<'static, str>, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    {
+        let mut file_guard = file_lock.write().await;
+        let Some(file_page) = file_guard.pages.get_mut(page) else {
+            return Err(ApiError::NoSuchPage { page });
         };
+        file_page.reviewed = flag.into_inner();
+    }
 
-        let mut sha = Sha3_512::new();
-        let mut buf = vec![0u8; 4*1024*1024];
-        loop {
-            let bytes_read = match file.read(&mut buf).await {
-                Ok(br) => br,
-                Err(e) => {
-                    error!("failed to read from uploaded file {:?}: {}", form.background_image, e);
-                    return Err((Status::InternalServerError, Cow::Borrowed("failed to read from uploaded file")));
-                },
-            };
-            if bytes_read == 0 {
-                break;
-            }
-            Digest::update(&mut sha, &buf[..bytes_read]);
+    persist_state_file(&tenant).await?;
+    crate::events::publish(&tenant, DocumentEvent::ReviewedChanged { page });
+
+    Ok(Cow::Borrowed("OK"))
+}
+
+/// Moves a page by the given number of positions, towards the end of the document if positive and
+/// towards its beginning if negative.
+///
+/// The page does not move past the first or last position. The pages in between shift by one
+/// position to make room. Returns the new index of the page.
+#[rocket::post("/page/<page>/move", data = "<delta>")]
+async fn move_page(tenant: Tenant, page: usize, delta: Json<isize>) -> Result<Json<usize>, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    let new_page = {
+        let mut file_guard = file_lock.write().await;
+        let page_count = file_guard.pages.len();
+        if page >= page_count {
+            return Err(ApiError::NoSuchPage { page });
         }
+        let new_page = page
+            .saturating_add_signed(delta.into_inner())
+            .min(page_count - 1);
+        if new_page < page {
+            file_guard.pages[new_page..=page].rotate_right(1);
+        } else {
+            file_guard.pages[page..=new_page].rotate_left(1);
+        }
+        new_page
+    };
 
-        let mut digest = [0u8; 64];
-        DynDigest::finalize_into(sha, &mut digest)
-            .expect("failed to finalize SHA3-512");
-        let mut filename = String::with_capacity(digest.len() * 2);
-        for &b in &digest {
-            write!(filename, "{:02x}", b).unwrap();
+    if new_page != page {
+        persist_state_file(&tenant).await?;
+        crate::events::publish(&tenant, DocumentEvent::PageMoved { from: page, to: new_page });
+    }
+
+    Ok(Json(new_page))
+}
+
+#[derive(FromForm)]
+struct ImportOcrForm<'r> {
+    pub source: TempFile<'r>,
+}
+
+/// Appends the words recognized by an OCR engine, as an hOCR or ALTO document, to the annotations
+/// of a page.
+#[rocket::post("/page/<page>/ocr", data = "<form>")]
+async fn import_page_ocr(tenant: Tenant, page: usize, form: Form<ImportOcrForm<'_>>) -> Result<Redirect, ApiError> {
+    let source_data = read_uploaded_file(&form.source).await?;
+    let source_text = String::from_utf8_lossy(&source_data);
+    let ocr_page = pdfmcr::ocr::parse_ocr(&source_text)
+        .map_err(|e| ApiError::InvalidOcr { page, reason: e.to_string() })?;
+
+    let max_annotations_per_page = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        config_guard.max_annotations_per_page
+    };
+
+    let file_lock = tenant_file(&tenant).await?;
+    {
+        let mut file_guard = file_lock.write().await;
+        let Some(file_page) = file_guard.pages.get_mut(page) else {
+            return Err(ApiError::NoSuchPage { page });
+        };
+        let annotations = ocr_page.to_annotations(file_page)
+            .map_err(|e| ApiError::InvalidOcr { page, reason: e.to_string() })?;
+
+        let annotation_count = file_page.annotations.len() + file_page.artifacts.len() + annotations.len();
+        if let Some(limit) = max_annotations_per_page.filter(|&limit| annotation_count > limit) {
+            return Err(ApiError::TooManyAnnotations { page, limit });
         }
+        check_page_typography(page, &annotations, &[])?;
+
+        file_page.annotations.extend(annotations);
+    }
+
+    persist_state_file(&tenant).await?;
+    crate::events::publish(&tenant, DocumentEvent::AnnotationsChanged { page });
+
+    Ok(Redirect::to(uri!(page_page(page))))
+}
+
+/// The maximum length, in characters, of a stored source file name.
+const MAX_SOURCE_FILENAME_LENGTH: usize = 255;
+
+/// Obtains the name of an uploaded file in a form suitable for storing and displaying it.
+///
+/// Only the last component of the name is kept, control characters are removed and overly long
+/// names are truncated. Returns `None` if the client did not provide a usable name.
+fn sanitized_source_filename(uploaded_file: &TempFile<'_>) -> Option<String> {
+    let raw_name = uploaded_file.raw_name()?
+        .dangerous_unsafe_unsanitized_raw()
+        .as_str();
+    // browsers on Windows might send the full path
+    let last_component = raw_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(raw_name);
+    let sanitized: String = last_component.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_SOURCE_FILENAME_LENGTH)
+        .collect();
+    let trimmed = sanitized.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_owned())
+    }
+}
 
-        // append hyphen, length and extension
-        write!(filename, "-{}.jpeg", jpeg_size).unwrap();
+/// Stores an uploaded image in the image directory and collects its metadata.
+async fn store_uploaded_image(tenant: &Tenant, uploaded_image: &mut TempFile<'_>) -> Result<JpegImage, ApiError> {
+    // generate a name for the JPEG file out of its size and checksum
+    let jpeg_size = uploaded_image.len();
+    if jpeg_size < pdfmcr::jpeg::MIN_IMAGE_SIZE {
+        // don't bother hashing and storing something that cannot possibly be a JPEG image
+        return Err(ApiError::UploadTooSmall);
+    }
 
-        filename
+    let import_jpeg_quality = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        config_guard.import_jpeg_quality
     };
 
-    // join the file to the expected base path
-    let base_path_string = {
+    // the first few bytes tell TIFF images, JPEG images and everything else apart
+    let signature = read_upload_signature(uploaded_image).await?;
+    if pdfmcr::tiff::is_tiff(&signature) {
+        let quality = import_jpeg_quality.unwrap_or(pdfmcr::tiff::DEFAULT_TRANSCODE_QUALITY);
+        return store_uploaded_tiff(tenant, uploaded_image, quality).await;
+    }
+    if !pdfmcr::jpeg::is_jpeg(&signature) {
+        return Err(ApiError::InvalidImage { reason: "neither a JPEG nor a TIFF image".to_owned() });
+    }
+    if let Some(quality) = import_jpeg_quality {
+        return store_reencoded_jpeg(tenant, uploaded_image, quality).await;
+    }
+
+    // hash and parse the upload in one go, so it only has to be persisted once
+    let (content_name, jpeg_image) = scan_uploaded_jpeg(uploaded_image).await?;
+
+    let (image_path, os_image_path) = prepare_image_path(tenant, &content_name).await?;
+
+    // persist the image there
+    if let Err(e) = uploaded_image.persist_to(&os_image_path).await {
+        error!("failed to persist uploaded file {:?} to {}: {}", uploaded_image, os_image_path.display(), e);
+        return Err(ApiError::Internal { message: "failed to persist uploaded file" });
+    }
+
+    jpeg_image_to_stored(image_path, &jpeg_image)
+}
+
+/// A reader that hashes all the data read through it.
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Box<dyn DynDigest + Send>,
+}
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R, algorithm: ContentHashAlgorithm) -> Self {
+        Self {
+            inner,
+            hasher: algorithm.new_hasher(),
+        }
+    }
+
+    fn finalize(mut self) -> [u8; 64] {
+        let mut digest = [0u8; 64];
+        self.hasher.finalize_into_reset(&mut digest)
+            .expect("failed to finalize digest");
+        digest
+    }
+}
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+/// Reads a JPEG image while computing the digest of its data using the given algorithm.
+///
+/// The image data is not retained.
+fn hash_and_read_jpeg<R: Read>(reader: R, algorithm: ContentHashAlgorithm, default_density_dpi: u16) -> Result<([u8; 64], pdfmcr::jpeg::Image), pdfmcr::jpeg::Error> {
+    let mut hashing_reader = HashingReader::new(reader, algorithm);
+    let mut jpeg_image = pdfmcr::jpeg::Image::try_read_with_default_density(&mut hashing_reader, default_density_dpi)?;
+    // the image has been read to the end, but make sure the digest covers everything
+    io::copy(&mut hashing_reader, &mut io::sink())?;
+    jpeg_image.image_data.clear();
+    Ok((hashing_reader.finalize(), jpeg_image))
+}
+
+/// Computes the content name of an uploaded JPEG image and reads its metadata in a single pass
+/// over its data.
+async fn scan_uploaded_jpeg(uploaded_image: &TempFile<'_>) -> Result<(ContentName, pdfmcr::jpeg::Image), ApiError> {
+    let (hash_algorithm, default_density_dpi) = {
         let config_guard = CONFIG
             .get().expect("CONFIG not set?!")
             .read().await;
-        config_guard.image_dir.clone()
+        (config_guard.content_hash_algorithm, config_guard.default_density_dpi)
     };
-    let image_path: ImagePath = match filename.parse() {
-        Ok(ip) => ip,
-        Err(e) => {
-            error!("generated image path {:?} is invalid: {}", filename, e);
-            return Err((Status::InternalServerError, Cow::Borrowed("generated invalid image name")));
-        },
+
+    let scan_result = if let TempFile::Buffered { content } = uploaded_image {
+        hash_and_read_jpeg(*content, hash_algorithm, default_density_dpi)
+    } else {
+        let Some(path) = uploaded_image.path() else {
+            error!("uploaded file {:?} has neither content nor path", uploaded_image);
+            return Err(ApiError::Internal { message: "failed to open uploaded file" });
+        };
+        let path = path.to_owned();
+        let scan_task_result = rocket::tokio::task::spawn_blocking(move || {
+            let file = File::open(&path)?;
+            hash_and_read_jpeg(io::BufReader::new(file), hash_algorithm, default_density_dpi)
+        }).await;
+        match scan_task_result {
+            Ok(sr) => sr,
+            Err(e) => {
+                error!("JPEG scanning task failed: {}", e);
+                return Err(ApiError::Internal { message: "failed to read from uploaded file" });
+            },
+        }
     };
-    let base_path = PathBuf::from(base_path_string);
-    let os_image_path = base_path.join(filename);
 
-    // persist the image there
-    if let Err(e) = form.background_image.persist_to(&os_image_path).await {
-        error!("failed to persist uploaded file {:?} to {}: {}", form.background_image, os_image_path.display(), e);
-        return Err((Status::InternalServerError, Cow::Borrowed("failed to persist uploaded file")));
+    match scan_result {
+        Ok((digest, jpeg_image)) => {
+            let content_name = ContentName { digest, size: uploaded_image.len() };
+            Ok((content_name, jpeg_image))
+        },
+        Err(pdfmcr::jpeg::Error::Io(e)) => {
+            error!("failed to read from uploaded file {:?}: {}", uploaded_image, e);
+            Err(ApiError::Internal { message: "failed to read from uploaded file" })
+        },
+        Err(e) => {
+            warn!("error reading uploaded file {:?} as JPEG: {}", uploaded_image, e);
+            Err(ApiError::InvalidImage { reason: e.to_string() })
+        },
     }
+}
 
-    // read the image
-    let mut image_file = match File::open(&os_image_path) {
-        Ok(i) => i,
+/// Reads the first four bytes of an uploaded file, which identify its format.
+async fn read_upload_signature(uploaded_image: &TempFile<'_>) -> Result<[u8; 4], ApiError> {
+    let mut file = match uploaded_image.open().await {
+        Ok(f) => f,
         Err(e) => {
-            error!("error opening persisted uploaded file {:?}: {}", os_image_path.display(), e);
-            return Err((Status::InternalServerError, Cow::Borrowed("failed to open persisted uploaded file")));
+            error!("error opening uploaded file {:?}: {}", uploaded_image, e);
+            return Err(ApiError::Internal { message: "failed to open uploaded file" });
         },
     };
-    let mut jpeg_image = match crate::jpeg::Image::try_read(&mut image_file) {
-        Ok(ji) => ji,
+    let mut signature = [0u8; 4];
+    match file.read_exact(&mut signature).await {
+        Ok(_) => Ok(signature),
+        // too short to be recognized as anything
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok([0u8; 4]),
+        Err(e) => {
+            error!("failed to read from uploaded file {:?}: {}", uploaded_image, e);
+            Err(ApiError::Internal { message: "failed to read from uploaded file" })
+        },
+    }
+}
+
+/// Reads the whole contents of an uploaded file into memory.
+async fn read_uploaded_file(uploaded_file: &TempFile<'_>) -> Result<Vec<u8>, ApiError> {
+    let mut file = match uploaded_file.open().await {
+        Ok(f) => f,
         Err(e) => {
-            error!("error reading uploaded file {:?} as JPEG: {}", os_image_path.display(), e);
-            return Err((Status::InternalServerError, Cow::Borrowed("failed to read persisted uploaded file as JPEG")));
+            error!("error opening uploaded file {:?}: {}", uploaded_file, e);
+            return Err(ApiError::Internal { message: "failed to open uploaded file" });
         },
     };
-    jpeg_image.image_data.clear();
+    let mut data = Vec::new();
+    if let Err(e) = file.read_to_end(&mut data).await {
+        error!("failed to read from uploaded file {:?}: {}", uploaded_file, e);
+        return Err(ApiError::Internal { message: "failed to read from uploaded file" });
+    }
+    Ok(data)
+}
 
-    // assemble the initial page structure
-    let color_space = match jpeg_image.color_space {
-        crate::jpeg::ColorSpace::Grayscale => crate::model::ColorSpace::Grayscale,
-        crate::jpeg::ColorSpace::Rgb => crate::model::ColorSpace::Rgb,
-        crate::jpeg::ColorSpace::Cmyk => crate::model::ColorSpace::Cmyk,
-        crate::jpeg::ColorSpace::Other(o) => {
-            return Err((Status::BadRequest, Cow::Owned(format!("JPEG has unknown color space {}", o))));
+/// Transcodes an uploaded TIFF image into a JPEG image with the given quality and stores it.
+async fn store_uploaded_tiff(tenant: &Tenant, uploaded_image: &mut TempFile<'_>, quality: u8) -> Result<JpegImage, ApiError> {
+    let tiff_data = read_uploaded_file(uploaded_image).await?;
+
+    let transcode_result = rocket::tokio::task::spawn_blocking(move || {
+        let tiff_image = pdfmcr::tiff::Image::try_read(io::Cursor::new(tiff_data))?;
+        tiff_image.to_jpeg_with_quality(quality)
+    }).await;
+    let jpeg_data = match transcode_result {
+        Ok(Ok(jd)) => jd,
+        Ok(Err(e)) => {
+            warn!("error transcoding uploaded file {:?} from TIFF: {}", uploaded_image, e);
+            return Err(ApiError::InvalidImage { reason: e.to_string() });
+        },
+        Err(e) => {
+            error!("TIFF transcoding task failed: {}", e);
+            return Err(ApiError::Internal { message: "failed to transcode uploaded file" });
         },
     };
-    let density_unit = match jpeg_image.density_unit {
-        crate::jpeg::DensityUnit::NoUnit => {
-            return Err((Status::BadRequest, Cow::Borrowed("JPEG images without a density unit are not supported")));
+
+    store_image_data(tenant, &jpeg_data).await
+}
+
+/// Re-encodes an uploaded JPEG image with the given quality and stores it.
+///
+/// The original image is stored instead if it is smaller than the re-encoded one or if it cannot be
+/// decoded.
+async fn store_reencoded_jpeg(tenant: &Tenant, uploaded_image: &mut TempFile<'_>, quality: u8) -> Result<JpegImage, ApiError> {
+    let original_data = read_uploaded_file(uploaded_image).await?;
+
+    let reencode_result = rocket::tokio::task::spawn_blocking(move || {
+        let reencoded = pdfmcr::jpeg::Image::try_read(io::Cursor::new(&original_data))
+            .map(|jpeg_image| pdfmcr::jpeg::decode::reencode(&jpeg_image, quality));
+        (original_data, reencoded)
+    }).await;
+    let jpeg_data = match reencode_result {
+        Ok((original_data, Ok(Ok(reencoded_data)))) => {
+            if reencoded_data.len() < original_data.len() {
+                reencoded_data
+            } else {
+                original_data
+            }
+        },
+        Ok((original_data, Ok(Err(e)))) => {
+            warn!("cannot re-encode uploaded file {:?}; storing it unchanged: {}", uploaded_image, e);
+            original_data
         },
-        crate::jpeg::DensityUnit::DotsPerInch => crate::model::DensityUnit::DotsPerInch,
-        crate::jpeg::DensityUnit::DotsPerCentimeter => crate::model::DensityUnit::DotsPerCentimeter,
-        crate::jpeg::DensityUnit::Other(o) => {
-            return Err((Status::BadRequest, Cow::Owned(format!("JPEG has unknown density unit {}", o))));
+        Ok((_original_data, Err(e))) => {
+            warn!("error reading uploaded file {:?} as JPEG: {}", uploaded_image, e);
+            return Err(ApiError::InvalidImage { reason: e.to_string() });
         },
+        Err(e) => {
+            error!("JPEG re-encoding task failed: {}", e);
+            return Err(ApiError::Internal { message: "failed to re-encode uploaded file" });
+        },
+    };
+
+    store_image_data(tenant, &jpeg_data).await
+}
+
+/// Stores JPEG image data held in memory in the image directory and collects its metadata.
+async fn store_image_data(tenant: &Tenant, jpeg_data: &[u8]) -> Result<JpegImage, ApiError> {
+    let (hash_algorithm, default_density_dpi) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        (config_guard.content_hash_algorithm, config_guard.default_density_dpi)
+    };
+
+    // the data is already in memory; parse it from there instead of reading the file back
+    let (digest, jpeg_image) = match hash_and_read_jpeg(jpeg_data, hash_algorithm, default_density_dpi) {
+        Ok(dji) => dji,
+        Err(e) => {
+            warn!("error reading image data as JPEG: {}", e);
+            return Err(ApiError::InvalidImage { reason: e.to_string() });
+        },
+    };
+    let content_name = ContentName { digest, size: jpeg_data.len().try_into().unwrap() };
+
+    let (image_path, os_image_path) = prepare_image_path(tenant, &content_name).await?;
+    if let Err(e) = rocket::tokio::fs::write(&os_image_path, jpeg_data).await {
+        error!("failed to write image to {}: {}", os_image_path.display(), e);
+        return Err(ApiError::Internal { message: "failed to write image" });
+    }
+
+    jpeg_image_to_stored(image_path, &jpeg_image)
+}
+
+/// Determines where an image with the given content name is stored, creating any necessary shard
+/// directories.
+async fn prepare_image_path(tenant: &Tenant, content_name: &ContentName) -> Result<(ImagePath, PathBuf), ApiError> {
+    // join the file to the expected base path
+    let (base_path_string, shard_levels) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        (config_guard.image_dir.clone(), config_guard.image_shard_levels)
     };
-    if jpeg_image.bit_depth == 0 {
-        return Err((Status::BadRequest, Cow::Borrowed("JPEG image cannot have a bit depth of 0")));
+    let image_path = content_name.to_sharded_path(shard_levels.into());
+    let base_path = tenant.image_dir(Path::new(&base_path_string));
+    let os_image_path = image_path.to_os_path(&base_path);
+
+    // the rules of ImagePath should rule this out; fail loudly if they ever stop doing so
+    if !os_image_path.starts_with(&base_path) {
+        error!("image path {:?} escapes image directory {}", image_path.as_str(), base_path.display());
+        return Err(ApiError::Internal { message: "image path escapes image directory" });
     }
-    if jpeg_image.width == 0 || jpeg_image.height == 0 {
-        return Err((Status::BadRequest, Cow::Borrowed("JPEG image cannot have a width or height of 0")));
+
+    // ensure the shard directories exist
+    let parent_path = os_image_path.parent().unwrap_or(&base_path);
+    if let Err(e) = rocket::tokio::fs::create_dir_all(parent_path).await {
+        error!("failed to create image directory {}: {}", parent_path.display(), e);
+        return Err(ApiError::Internal { message: "failed to create image directory" });
     }
-    if jpeg_image.density_x == 0 || jpeg_image.density_y == 0 {
-        return Err((Status::BadRequest, Cow::Borrowed("JPEG image cannot have a horizontal or vertical pixel density of 0")));
+
+    Ok((image_path, os_image_path))
+}
+
+/// Assembles the structure describing a stored JPEG image from its metadata.
+fn jpeg_image_to_stored(image_path: ImagePath, jpeg_image: &pdfmcr::jpeg::Image) -> Result<JpegImage, ApiError> {
+    if jpeg_image.lacks_huffman_tables() {
+        warn!("JPEG image {:?} does not define any Huffman tables; it might not display correctly", image_path.as_str());
     }
-    let page = Page::new(JpegImage {
-        info: JpegImageInfo {
-            bit_depth: jpeg_image.bit_depth,
-            width: jpeg_image.width,
-            height: jpeg_image.height,
-            color_space,
-            density_unit,
-            density_x: jpeg_image.density_x,
-            density_y: jpeg_image.density_y,
-        },
+
+    let info = match JpegImageInfo::try_from(jpeg_image) {
+        Ok(i) => i,
+        Err(e) => return Err(ApiError::InvalidImage { reason: e.to_string() }),
+    };
+    Ok(JpegImage {
+        info,
         file_path: image_path,
-    });
+        data_filters: None,
+    })
+}
+
+#[rocket::post("/page", data = "<form>")]
+async fn make_page(tenant: Tenant, mut form: Form<MakePageForm<'_>>) -> Result<Redirect, ApiError> {
+    let scanned_image = store_uploaded_image(&tenant, &mut form.background_image).await?;
+    let mut page = Page::new(scanned_image);
+    page.source_filename = sanitized_source_filename(&form.background_image);
 
     // append it
+    let file_lock = tenant_file(&tenant).await?;
     let new_page_index = {
-        let mut file_guard = WEB_FILE
-            .get().expect("WEB_FILE not set?!")
-            .write().await;
+        let mut file_guard = file_lock.write().await;
         let new_page_index = file_guard.pages.len();
         file_guard.pages.push(page);
         new_page_index
     };
 
     // persist the state
-    persist_state_file().await?;
+    persist_state_file(&tenant).await?;
+    crate::events::publish(&tenant, DocumentEvent::PageAdded { page: new_page_index });
 
     // redirect to the new page
     Ok(Redirect::to(uri!(page_page(new_page_index))))
 }
 
-#[rocket::get("/page/<page>/image")]
-async fn page_image(page: usize) -> Result<(ContentType, File), (Status, Cow<'static, str>)> {
-    let page_path = {
-        let file_guard = WEB_FILE
-            .get().expect("WEB_FILE not set?!")
-            .read().await;
+#[derive(FromForm)]
+struct ImportPagesForm<'r> {
+    pub source: TempFile<'r>,
+}
 
-        if page > file_guard.pages.len() {
-            return Err((Status::NotFound, Cow::Borrowed("no such page")));
-        }
-        file_guard.pages[page].scanned_image.file_path.clone()
+#[rocket::post("/import", data = "<form>")]
+async fn import_pages(tenant: Tenant, form: Form<ImportPagesForm<'_>>) -> Result<Redirect, ApiError> {
+    let source_data = read_uploaded_file(&form.source).await?;
+    let split_result = rocket::tokio::task::spawn_blocking(move || {
+        pdfmcr::import::split_pages(&source_data)
+    }).await;
+    let imported_pages = match split_result {
+        Ok(Ok(ip)) => ip,
+        Ok(Err(e)) => {
+            warn!("error splitting uploaded file {:?} into pages: {}", form.source, e);
+            return Err(ApiError::InvalidImage { reason: e.to_string() });
+        },
+        Err(e) => {
+            error!("page splitting task failed: {}", e);
+            return Err(ApiError::Internal { message: "failed to split uploaded file into pages" });
+        },
     };
+    if imported_pages.is_empty() {
+        return Err(ApiError::InvalidImage { reason: "file does not contain any pages".to_owned() });
+    }
 
-    let base_path = {
-        let config_guard = CONFIG
-            .get().expect("CONFIG not set?!")
+    // store all images before adding any pages
+    let source_filename = sanitized_source_filename(&form.source);
+    let mut pages = Vec::with_capacity(imported_pages.len());
+    for imported_page in &imported_pages {
+        let scanned_image = store_image_data(&tenant, &imported_page.jpeg_data).await?;
+        let mut page = Page::new(scanned_image);
+        page.media_box_pt = imported_page.size_pt
+            .map(|(width_pt, height_pt)| (width_pt as f64, height_pt as f64));
+        page.source_filename = source_filename.clone();
+        pages.push(page);
+    }
+
+    // append them
+    let file_lock = tenant_file(&tenant).await?;
+    let new_page_indexes = {
+        let mut file_guard = file_lock.write().await;
+        let first_new_page_index = file_guard.pages.len();
+        file_guard.pages.extend(pages);
+        first_new_page_index..file_guard.pages.len()
+    };
+
+    // persist the state
+    persist_state_file(&tenant).await?;
+    for new_page_index in new_page_indexes.clone() {
+        crate::events::publish(&tenant, DocumentEvent::PageAdded { page: new_page_index });
+    }
+
+    // redirect to the first new page
+    Ok(Redirect::to(uri!(page_page(new_page_indexes.start))))
+}
+
+#[rocket::post("/page/<page>/image", data = "<form>")]
+async fn replace_page_image(tenant: Tenant, page: usize, mut form: Form<MakePageForm<'_>>) -> Result<Redirect, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    {
+        let file_guard = file_lock.read().await;
+        if page >= file_guard.pages.len() {
+            return Err(ApiError::NoSuchPage { page });
+        }
+    }
+
+    let scanned_image = store_uploaded_image(&tenant, &mut form.background_image).await?;
+
+    {
+        let mut file_guard = file_lock.write().await;
+        // the page might have disappeared in the meantime
+        let Some(file_page) = file_guard.pages.get_mut(page) else {
+            return Err(ApiError::NoSuchPage { page });
+        };
+
+        let old_size_pt = (file_page.width_pt(), file_page.height_pt());
+        let new_size_pt = (
+            scanned_image.info.width_pt().map(|w| w as f64),
+            scanned_image.info.height_pt().map(|h| h as f64),
+        );
+        if file_page.media_box_pt.is_none() && old_size_pt != new_size_pt {
+            warn!(
+                "replacement image for page {} changes its size from {:?} to {:?} pt; annotations might no longer line up",
+                page, old_size_pt, new_size_pt,
+            );
+        }
+
+        // keep the annotations and artifacts
+        file_page.scanned_image = Some(scanned_image);
+        file_page.source_filename = sanitized_source_filename(&form.background_image);
+    }
+
+    // persist the state
+    persist_state_file(&tenant).await?;
+    crate::events::publish(&tenant, DocumentEvent::PageImageReplaced { page });
+
+    Ok(Redirect::to(uri!(page_page(page))))
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+struct PageSize {
+    pub width_pt: Option<f64>,
+    pub height_pt: Option<f64>,
+    pub width_px: Option<u16>,
+    pub height_px: Option<u16>,
+    pub density_x: Option<u16>,
+    pub density_y: Option<u16>,
+    pub density_unit: Option<DensityUnit>,
+}
+
+/// Returns the size of a page in points (1/72 in), along with the dimensions and pixel density of
+/// its scanned image from which the size is derived.
+///
+/// The image fields are `null` for text-only pages. The size in points takes a media box set on
+/// the page into account and therefore need not match the image.
+#[rocket::get("/page/<page>/size")]
+async fn page_size(tenant: Tenant, page: usize) -> Result<Json<PageSize>, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    let file_guard = file_lock.read().await;
+    let Some(file_page) = file_guard.pages.get(page) else {
+        return Err(ApiError::NoSuchPage { page });
+    };
+    let info = file_page.scanned_image.as_ref().map(|scanned_image| &scanned_image.info);
+    Ok(Json(PageSize {
+        width_pt: file_page.width_pt(),
+        height_pt: file_page.height_pt(),
+        width_px: info.map(|i| i.width),
+        height_px: info.map(|i| i.height),
+        density_x: info.map(|i| i.density_x),
+        density_y: info.map(|i| i.density_y),
+        density_unit: info.map(|i| i.density_unit),
+    }))
+}
+
+#[rocket::get("/page/<page>/image")]
+async fn page_image(tenant: Tenant, page: usize, headers: ImageRequestHeaders) -> Result<ImageResponse, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    let page_path = {
+        let file_guard = file_lock.read().await;
+
+        if page >= file_guard.pages.len() {
+            return Err(ApiError::NoSuchPage { page });
+        }
+        match file_guard.pages[page].scanned_image.as_ref() {
+            Some(scanned_image) => scanned_image.file_path.clone(),
+            None => return Err(ApiError::PageHasNoImage { page }),
+        }
+    };
+
+    let base_path = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
             .read().await;
 
-        PathBuf::from(config_guard.image_dir.as_str())
+        tenant.image_dir(Path::new(&config_guard.image_dir))
     };
 
     let page_os_path = page_path.to_os_path(&base_path);
-    let page_os_file = match File::open(&page_os_path) {
-        Ok(pof) => pof,
+    // images are named after their checksums; those that follow the scheme have a natural entity tag
+    let entity_tag = page_path.content_name().ok()
+        .map(|content_name| content_name.to_string());
+    match ImageResponse::open(&page_os_path, ContentType::JPEG, entity_tag.as_deref(), &headers).await {
+        Ok(response) => Ok(response),
         Err(e) => {
             error!("page file {:?} not found on system: {}", page_os_path.display(), e);
-            return Err((Status::InternalServerError, Cow::Borrowed("file should exist but not found on server")));
+            Err(ApiError::Internal { message: "file should exist but not found on server" })
         },
+    }
+}
+
+
+/// Streams the changes to the tenant's document as server-sent events.
+#[rocket::get("/events")]
+fn event_stream(tenant: Tenant, mut shutdown: rocket::Shutdown) -> EventStream![] {
+    use rocket::tokio::sync::broadcast::error::RecvError;
+
+    let mut receiver = crate::events::subscribe();
+    EventStream! {
+        loop {
+            let (event_tenant, event) = rocket::tokio::select! {
+                received = receiver.recv() => match received {
+                    Ok(te) => te,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(missed)) => {
+                        // tell the client to reload everything
+                        warn!("event subscriber missed {} events", missed);
+                        yield Event::data("").event("lagged");
+                        continue;
+                    },
+                },
+                _ = &mut shutdown => break,
+            };
+            if event_tenant == tenant {
+                yield Event::json(&event);
+            }
+        }
+    }
+}
+
+/// Returns the whole document of the tenant as JSON, e.g. for backups or external tools.
+#[rocket::get("/document.json")]
+async fn export_document(tenant: Tenant) -> Result<Json<pdfmcr::model::File>, ApiError> {
+    let file_lock = tenant_file(&tenant).await?;
+    let file_guard = file_lock.read().await;
+    Ok(Json(file_guard.clone()))
+}
+
+/// Checks whether a document submitted as a whole is acceptable.
+///
+/// The same rules apply as when setting the annotations of a single page; additionally, the size
+/// of each page must be known and the images must exist in the image directory.
+async fn validate_document(file: &pdfmcr::model::File, image_dir: &Path) -> Result<(), ApiError> {
+    let (strict_bounds, max_annotations_per_page) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        (config_guard.strict_annotation_bounds, config_guard.max_annotations_per_page)
     };
 
-    Ok((ContentType::JPEG, page_os_file))
+    if let Some(default_language) = file.default_language.as_deref()
+            && !pdfmcr::language::is_valid_language_tag(default_language) {
+        return Err(ApiError::InvalidLanguage { language: default_language.to_owned() });
+    }
+    let invalid_language = file.text_chunks()
+        .filter_map(|chunk| chunk.language.as_deref())
+        .find(|language| !pdfmcr::language::is_valid_language_tag(language));
+    if let Some(language) = invalid_language {
+        return Err(ApiError::InvalidLanguage { language: language.to_owned() });
+    }
+
+    for (page, file_page) in file.pages.iter().enumerate() {
+        let annotation_count = file_page.annotations.len() + file_page.artifacts.len();
+        if let Some(limit) = max_annotations_per_page.filter(|&limit| annotation_count > limit) {
+            return Err(ApiError::TooManyAnnotations { page, limit });
+        }
+
+        check_page_typography(page, &file_page.annotations, &file_page.artifacts)?;
+
+        let size_known = matches!(
+            (file_page.width_pt(), file_page.height_pt()),
+            (Some(width_pt), Some(height_pt)) if width_pt > 0.0 && height_pt > 0.0
+        );
+        if !size_known {
+            return Err(ApiError::InvalidDocument { reason: format!("page {} has no usable size", page) });
+        }
+
+        if strict_bounds {
+            let out_of_bounds: Vec<usize> = file_page.annotations.iter()
+                .enumerate()
+                .filter(|(_, annotation)| file_page.contains_point_pt(annotation.left, annotation.bottom) == Some(false))
+                .map(|(index, _)| index)
+                .collect();
+            if !out_of_bounds.is_empty() {
+                return Err(ApiError::AnnotationsOutOfBounds { page, indices: out_of_bounds });
+            }
+        }
+
+        if let Some(scanned_image) = file_page.scanned_image.as_ref() {
+            let os_path = scanned_image.file_path.to_os_path(image_dir);
+            if !rocket::tokio::fs::try_exists(&os_path).await.unwrap_or(false) {
+                return Err(ApiError::InvalidDocument {
+                    reason: format!("image {} of page {} does not exist", scanned_image.file_path, page),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the whole document of the tenant, e.g. when restoring a backup.
+///
+/// The images referenced by the document must already be present in the image directory.
+#[rocket::put("/document.json", data = "<file>")]
+async fn import_document(tenant: Tenant, file: Result<Json<pdfmcr::model::File>, rocket::serde::json::Error<'_>>) -> Result<Cow<'static, str>, ApiError> {
+    let file = file?.into_inner();
+    let image_dir = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        tenant.image_dir(Path::new(&config_guard.image_dir))
+    };
+    validate_document(&file, &image_dir).await?;
+
+    let file_lock = tenant_file(&tenant).await?;
+    {
+        let mut file_guard = file_lock.write().await;
+        *file_guard = file;
+    }
+
+    persist_state_file(&tenant).await?;
+    crate::events::publish(&tenant, DocumentEvent::DocumentReplaced);
+
+    Ok(Cow::Borrowed("OK"))
+}
+
+/// Checks the font sizes and spacings of the annotations and artifacts of a page.
+fn check_page_typography(page: usize, annotations: &[Annotation], artifacts: &[Artifact]) -> Result<(), ApiError> {
+    let annotation_errors = annotations.iter()
+        .enumerate()
+        .map(|(index, annotation)| ("annotation", index, annotation));
+    let artifact_errors = artifacts.iter()
+        .enumerate()
+        .map(|(index, artifact)| ("artifact", index, &artifact.annotation));
+    for (kind, index, annotation) in annotation_errors.chain(artifact_errors) {
+        if let Err(e) = annotation.check_typography() {
+            return Err(ApiError::InvalidTypography { page, reason: format!("{} {}: {}", kind, index, e) });
+        }
+    }
+    Ok(())
+}
+
+/// Exports the document of the tenant as PDF.
+///
+/// If `debug_text` is set (to `1`, `true`, `yes` or `on`), the text of the annotations is drawn
+/// visibly, allowing its placement to be checked.
+#[rocket::get("/document.pdf?<debug_text>")]
+async fn export_pdf(tenant: Tenant, debug_text: Option<&str>) -> Result<PdfExport, ApiError> {
+    let debug_text = debug_text
+        .is_some_and(|value| matches!(value.to_ascii_lowercase().as_str(), "1"|"true"|"yes"|"on"));
+    let (image_dir, mut options, fallback_font_path) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        let image_dir = tenant.image_dir(Path::new(&config_guard.image_dir));
+        let options = ConversionOptions {
+            interpolate_images: config_guard.interpolate_images,
+            srgb_images: config_guard.srgb_images,
+            debug_text,
+            max_annotations_per_page: config_guard.max_annotations_per_page,
+            header_comment: config_guard.pdf_header_comment.as_ref()
+                .map(|comment| comment.as_bytes().to_vec()),
+            producer: config_guard.pdf_producer.clone()
+                .or_else(|| Some(DEFAULT_PRODUCER.to_owned())),
+            pdf_version: config_guard.pdf_version,
+            ..ConversionOptions::default()
+        };
+        let fallback_font_path = config_guard.fallback_font_path.clone();
+        (image_dir, options, fallback_font_path)
+    };
+    let file = {
+        let file_lock = tenant_file(&tenant).await?;
+        let file_guard = file_lock.read().await;
+        file_guard.clone()
+    };
+    let page_count = file.pages.len();
+
+    // conversion reads the image files; don't block the async runtime with that
+    let pdf_result = rocket::tokio::task::spawn_blocking(move || {
+        if let Some(fallback_font_path) = fallback_font_path {
+            let fallback_font = std::fs::read(&fallback_font_path)
+                .map_err(|e| {
+                    error!("failed to read fallback font {:?}: {}", fallback_font_path, e);
+                    ApiError::Internal { message: "failed to read fallback font" }
+                })?;
+            options.fallback_font = Some(fallback_font);
+        }
+        let document = file_to_pdf(&file, &image_dir, &options)
+            .map_err(|e| match e {
+                ConversionError::TooManyAnnotations { page_index, limit, .. } => {
+                    // can happen if the limit has been lowered since the annotations were set
+                    warn!("refusing to convert file to PDF: {}", e);
+                    ApiError::TooManyAnnotations { page: page_index, limit }
+                },
+                ConversionError::UnknownPageSize { page_index, dimension } => {
+                    warn!("refusing to convert file to PDF: {}", e);
+                    ApiError::UnknownPageSize { page: page_index, dimension }
+                },
+                e => {
+                    error!("failed to convert file to PDF: {}", e);
+                    ApiError::Internal { message: "failed to convert file to PDF" }
+                },
+            })?;
+        let mut pdf_bytes = Vec::new();
+        document.write_pdf_to_stream(&mut pdf_bytes)
+            .map_err(|e| {
+                error!("failed to write PDF: {}", e);
+                ApiError::Internal { message: "failed to write PDF" }
+            })?;
+        Ok::<_, ApiError>(pdf_bytes)
+    }).await;
+    let pdf_bytes = match pdf_result {
+        Ok(pb) => pb?,
+        Err(e) => {
+            error!("PDF export task failed: {}", e);
+            return Err(ApiError::Internal { message: "PDF export task failed" });
+        },
+    };
+
+    Ok(PdfExport::new(pdf_bytes, page_count))
+}
+
+
+/// How long an unreferenced image must have existed before it is considered orphaned.
+///
+/// Uploaded images are stored before the page referencing them is added to the document; this
+/// keeps the garbage collector from deleting them in between.
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+struct GcImagesReport {
+    /// The paths of the orphaned images, relative to the image directory.
+    pub orphaned: Vec<String>,
+
+    /// Whether the orphaned images have been deleted.
+    pub deleted: bool,
+}
+
+/// Collects the images below the given directory that are not referenced.
+///
+/// Only files named after their content (see [`ContentName`]) are considered, and only if they are
+/// found in the shard directories matching their names; directories are only descended into if they
+/// are named like shard directories. Files modified within the [`ORPHAN_GRACE_PERIOD`] are skipped.
+fn collect_orphaned_images(
+    dir: &Path,
+    relative_dir: &str,
+    referenced: &BTreeSet<String>,
+    orphaned: &mut Vec<(String, PathBuf)>,
+) -> Result<(), io::Error> {
+    let now = SystemTime::now();
+    for entry_res in std::fs::read_dir(dir)? {
+        let entry = entry_res?;
+        let file_type = entry.file_type()?;
+        let Some(name) = entry.file_name().to_str().map(|n| n.to_owned()) else {
+            continue;
+        };
+        let relative_path = format!("{}{}", relative_dir, name);
+
+        if file_type.is_dir() {
+            let is_shard_dir = name.len() == 2
+                && name.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+            if is_shard_dir {
+                collect_orphaned_images(&entry.path(), &format!("{}/", relative_path), referenced, orphaned)?;
+            }
+            continue;
+        }
+        if !file_type.is_file() {
+            continue;
+        }
+
+        let Ok(content_name) = name.parse::<ContentName>() else {
+            continue;
+        };
+        let shard_levels = relative_dir.matches('/').count();
+        if content_name.to_sharded_path(shard_levels).as_str() != relative_path {
+            // not where we would have put it
+            continue;
+        }
+        if referenced.contains(&relative_path) {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        if now.duration_since(modified).unwrap_or(Duration::ZERO) < ORPHAN_GRACE_PERIOD {
+            continue;
+        }
+        orphaned.push((relative_path, entry.path()));
+    }
+    Ok(())
+}
+
+/// Lists the images of the tenant that are not referenced by any page and deletes them unless a dry
+/// run is requested.
+#[rocket::post("/admin/gc-images?<dry_run>")]
+async fn gc_images(_admin: Admin, tenant: Tenant, dry_run: Option<bool>) -> Result<Json<GcImagesReport>, ApiError> {
+    let dry_run = dry_run.unwrap_or(false);
+    let image_dir = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        tenant.image_dir(Path::new(&config_guard.image_dir))
+    };
+
+    // hold the lock throughout, so that no page starts referencing an image while we delete it
+    let file_lock = tenant_file(&tenant).await?;
+    let file_guard = file_lock.read().await;
+    let referenced: BTreeSet<String> = file_guard.pages.iter()
+        .filter_map(|page| page.scanned_image.as_ref())
+        .map(|scanned_image| scanned_image.file_path.as_str().to_owned())
+        .collect();
+
+    let gc_result = rocket::tokio::task::spawn_blocking(move || {
+        let mut orphaned = Vec::new();
+        match collect_orphaned_images(&image_dir, "", &referenced, &mut orphaned) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // no images stored yet
+            },
+            Err(e) => {
+                error!("failed to list images in {}: {}", image_dir.display(), e);
+                return Err(ApiError::Internal { message: "failed to list images" });
+            },
+        }
+        if !dry_run {
+            for (relative_path, os_path) in &orphaned {
+                if let Err(e) = std::fs::remove_file(os_path) {
+                    error!("failed to delete orphaned image {:?}: {}", relative_path, e);
+                    return Err(ApiError::Internal { message: "failed to delete orphaned image" });
+                }
+            }
+        }
+        Ok(orphaned.into_iter().map(|(relative_path, _)| relative_path).collect())
+    }).await;
+    drop(file_guard);
+
+    let orphaned = match gc_result {
+        Ok(o) => o?,
+        Err(e) => {
+            error!("image garbage collection task failed: {}", e);
+            return Err(ApiError::Internal { message: "image garbage collection task failed" });
+        },
+    };
+    Ok(Json(GcImagesReport {
+        orphaned,
+        deleted: !dry_run,
+    }))
 }
 
 
@@ -375,46 +1479,898 @@ fn launch_rocket() -> _ {
     CONFIG.set(RwLock::new(config.clone()))
         .expect("CONFIG already set?!");
 
-    // read the initial file if it exists
-    let initial_file = match std::fs::metadata(&config.state_file_path) {
-        Ok(m) => {
-            if !m.is_file() {
-                panic!("state file {:?} exists and is not a file", config.state_file_path);
-            }
-
-            let initial_state_file = match File::open(&config.state_file_path) {
-                Ok(ifc) => ifc,
-                Err(e) => panic!("failed to open state file {:?}: {}", config.state_file_path, e),
-            };
-            match ciborium::from_reader(&initial_state_file) {
-                Ok(is) => is,
-                Err(e) => panic!("failed to parse state file {:?} as CBOR: {}", config.state_file_path, e),
-            }
-        },
-        Err(e) => {
-            if e.kind() == io::ErrorKind::NotFound {
-                crate::model::File::default()
-            } else {
-                panic!("could not open state file {:?}: {}", config.state_file_path, e);
-            }
-        },
+    // read the initial file of the default tenant if it exists
+    // (the files of the other tenants are loaded on demand)
+    let initial_file = match load_state_file(Path::new(&config.state_file_path)) {
+        Ok(f) => f,
+        Err(e) => panic!("{}", e),
     };
-    WEB_FILE
-        .set(RwLock::new(initial_file))
-        .expect("WEB_FILE already set?!");
+    if config.check_images_on_load {
+        let image_dir = Tenant::default().image_dir(Path::new(&config.image_dir));
+        let missing_count = warn_about_missing_images(&initial_file, &image_dir);
+        if missing_count > 0 {
+            warn!("{} of {} pages refer to missing images", missing_count, initial_file.pages.len());
+        }
+    }
+    let mut initial_files = BTreeMap::new();
+    initial_files.insert(Tenant::default(), Arc::new(RwLock::new(initial_file)));
+    WEB_FILES
+        .set(RwLock::new(initial_files))
+        .expect("WEB_FILES already set?!");
 
     // now, let's get down to brass tacks
-    let static_path = path_from_components!("static");
-    let ts_dist_path = path_from_components!("ts", "dist");
+    let static_path = match config.static_dir.as_ref() {
+        Some(static_dir) => PathBuf::from(static_dir),
+        None => PathBuf::from("static"),
+    };
+    let ts_dist_path = match config.js_dir.as_ref() {
+        Some(js_dir) => PathBuf::from(js_dir),
+        None => path_from_components!("ts", "dist"),
+    };
+
+    build_rocket(&config)
+        .mount("/static", FileServer::from(&static_path).rank(2))
+        .mount("/static/js", FileServer::from(&ts_dist_path).rank(1))
+}
 
-    rocket::build()
+/// Assembles the server with all its routes except for the static files.
+fn build_rocket(config: &Config) -> Rocket<Build> {
+    let mut rocket = rocket::build();
+    if config.compress_responses {
+        rocket = rocket.attach(crate::compression::GzipCompression);
+    }
+    rocket
+        .attach(crate::shutdown::PersistOnShutdown)
         .mount("/", rocket::routes![
             index,
             page_image,
+            page_size,
             page_page,
+            list_pages,
+            capabilities,
             make_page,
+            import_pages,
+            replace_page_image,
             set_page_annotations,
+            set_page_reading_order,
+            set_page_reviewed,
+            move_page,
+            import_page_ocr,
+            export_pdf,
+            export_document,
+            import_document,
+            event_stream,
+            gc_images,
         ])
-        .mount("/static", FileServer::from(&static_path).rank(2))
-        .mount("/static/js", FileServer::from(&ts_dist_path).rank(1))
+        .register("/", rocket::catchers![
+            crate::api_error::payload_too_large,
+        ])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::FileTimes;
+
+    use rocket::http::Status;
+
+    use super::*;
+    use crate::admin::ADMIN_HEADER;
+    use crate::test_support::{
+        ADMIN_SECRET, client, jpeg_data, stored_copies, tenant_header, test_dir, upload_file, upload_image,
+        upload_page,
+    };
+
+    #[rocket::async_test]
+    async fn replacing_image_keeps_annotations() {
+        let client = client().await;
+        let tenant = "replacing_image_keeps_annotations";
+        let page = upload_page(&client, tenant).await;
+
+        let response = client.post(format!("/page/{}/annotations", page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(r#"{
+                "annotations": [{
+                    "left": 2, "bottom": 3, "font_size": 12.0, "leading": 0.0,
+                    "elements": [{
+                        "text": "Hello", "font_variant": "Regular",
+                        "character_spacing": 0.0, "word_spacing": 0.0,
+                        "language": null, "alternate_text": null, "actual_text": null, "expansion": null
+                    }]
+                }],
+                "artifacts": []
+            }"#)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = upload_image(&client, tenant, &format!("/page/{}/image", page), &jpeg_data(32, 24)).await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+        let file_guard = file_lock.read().await;
+        let file_page = &file_guard.pages[page];
+        let scanned_image = file_page.scanned_image.as_ref().unwrap();
+        assert_eq!((scanned_image.info.width, scanned_image.info.height), (32, 24));
+        assert_eq!(file_page.annotations.len(), 1);
+        assert_eq!(file_page.annotations[0].elements[0].text, "Hello");
+    }
+
+    #[rocket::async_test]
+    async fn refuses_replacing_image_of_missing_page() {
+        let client = client().await;
+        let tenant = "refuses_replacing_image_of_missing_page";
+        let response = upload_image(&client, tenant, "/page/999999/image", &jpeg_data(16, 16)).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_tiny_uploads() {
+        let client = client().await;
+        let tenant = "refuses_tiny_uploads";
+        for image_data in [&b""[..], b"\xFF\xD8\xFF"] {
+            let response = upload_image(&client, tenant, "/page", image_data).await;
+            assert_eq!(response.status(), Status::BadRequest);
+        }
+    }
+
+    async fn error_code(response: rocket::local::asynchronous::LocalResponse<'_>) -> String {
+        let body: rocket::serde::json::Value = response.into_json().await.unwrap();
+        body["code"].as_str().unwrap().to_owned()
+    }
+
+    #[rocket::async_test]
+    async fn reports_missing_page_as_error_code() {
+        let client = client().await;
+        let tenant = "reports_missing_page_as_error_code";
+        let response = client.get("/page/999999/image").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(error_code(response).await, "no_such_page");
+    }
+
+    #[rocket::async_test]
+    async fn reports_invalid_jpeg_as_error_code() {
+        let client = client().await;
+        let tenant = "reports_invalid_jpeg_as_error_code";
+        let response = upload_image(&client, tenant, "/page", &[0x42; 1024]).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "invalid_image");
+    }
+
+    #[rocket::async_test]
+    async fn reports_oversized_upload_as_error_code() {
+        let client = client().await;
+        let tenant = "reports_oversized_upload_as_error_code";
+        // larger than Rocket's default limit for files, but within the one for forms
+        let response = upload_image(&client, tenant, "/page", &vec![0x42; 3 * 1024 * 1024 / 2]).await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(error_code(response).await, "upload_too_large");
+    }
+
+    #[rocket::async_test]
+    async fn lists_window_of_pages() {
+        let client = client().await;
+        let tenant = "lists_window_of_pages";
+        let first_page = upload_page(&client, tenant).await;
+        let second_page = upload_page(&client, tenant).await;
+
+        let response = client.get(format!("/pages?offset={}&limit=1", first_page))
+            .header(tenant_header(tenant))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let listing: PageListing = response.into_json().await.unwrap();
+        assert!(listing.total_count > second_page);
+        assert_eq!(listing.offset, first_page);
+        assert_eq!(listing.pages.len(), 1);
+        assert_eq!(listing.pages[0].index, first_page);
+        assert!(listing.pages[0].has_image);
+        assert_eq!(listing.pages[0].annotation_count, 0);
+
+        let response = client.get(format!("/pages?offset={}", second_page))
+            .header(tenant_header(tenant))
+            .dispatch().await;
+        let listing: PageListing = response.into_json().await.unwrap();
+        assert_eq!(listing.pages[0].index, second_page);
+        assert_eq!(listing.pages.len(), listing.total_count - second_page);
+
+        let response = client.get("/pages?offset=999999").header(tenant_header(tenant)).dispatch().await;
+        let listing: PageListing = response.into_json().await.unwrap();
+        assert!(listing.pages.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn describes_capabilities() {
+        let client = client().await;
+        let response = client.get("/capabilities").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let capabilities: Capabilities = response.into_json().await.unwrap();
+        assert_eq!(capabilities.color_spaces, ColorSpace::ALL);
+        assert_eq!(capabilities.bit_depths, EMBEDDABLE_BIT_DEPTHS);
+        assert_eq!(capabilities.density_units, DensityUnit::ABSOLUTE);
+        assert_eq!(capabilities.font_families, FontFamily::ALL);
+
+        // the file limit is the smaller one by default
+        let file_limit = client.rocket().config().limits.get("file").unwrap();
+        assert_eq!(capabilities.max_upload_bytes, Some(file_limit.as_u64()));
+    }
+
+    #[test]
+    fn moves_corrupt_state_file_aside() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-corrupt-state-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state_file_path = dir.join("state.cbor");
+        std::fs::write(&state_file_path, b"\xFF\xFFthis is not CBOR").unwrap();
+
+        let file = load_state_file(&state_file_path).unwrap();
+        let remaining_files: Vec<String> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(file.pages.len(), 0);
+        assert_eq!(remaining_files.len(), 1);
+        assert!(remaining_files[0].starts_with("state.cbor.corrupt."));
+    }
+
+    #[rocket::async_test]
+    async fn announces_annotation_changes() {
+        use rocket::tokio::io::AsyncReadExt;
+
+        let client = client().await;
+        let tenant = "announces_annotation_changes";
+        let page = upload_page(&client, tenant).await;
+        let mut events = client.get("/events").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(events.status(), Status::Ok);
+
+        let response = client.post(format!("/page/{}/annotations", page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(r#"{"annotations": [], "artifacts": []}"#)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // other tests might cause events too; look for ours
+        let expected_event = format!(r#"data:{{"type":"annotations_changed","page":{}}}"#, page);
+        let mut received = String::new();
+        let mut buf = [0u8; 256];
+        let wait = async {
+            while !received.contains(&expected_event) {
+                let read_count = events.read(&mut buf).await.unwrap();
+                assert_ne!(read_count, 0);
+                received.push_str(&String::from_utf8_lossy(&buf[..read_count]));
+            }
+        };
+        rocket::tokio::time::timeout(std::time::Duration::from_secs(10), wait).await
+            .expect("event not received");
+    }
+
+    #[rocket::async_test]
+    async fn rejects_off_page_annotations_in_strict_mode() {
+        async fn set_strict_bounds(strict: bool) {
+            let mut config_guard = CONFIG
+                .get().expect("CONFIG not set?!")
+                .write().await;
+            config_guard.strict_annotation_bounds = strict;
+        }
+
+        let client = client().await;
+        let tenant = "rejects_off_page_annotations_in_strict_mode";
+        // 16 x 16 pixels at 72 dpi
+        let page = upload_page(&client, tenant).await;
+        let annotations = r#"{
+            "annotations": [
+                {"left": 2, "bottom": 3, "font_size": 12.0, "leading": 0.0, "elements": []},
+                {"left": 200, "bottom": 3, "font_size": 12.0, "leading": 0.0, "elements": []}
+            ],
+            "artifacts": []
+        }"#;
+
+        set_strict_bounds(true).await;
+        let response = client.post(format!("/page/{}/annotations", page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(annotations)
+            .dispatch().await;
+        set_strict_bounds(false).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "annotations_out_of_bounds");
+
+        let response = client.post(format!("/page/{}/annotations", page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(annotations)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn renders_html_for_missing_page() {
+        let client = client().await;
+        let tenant = "renders_html_for_missing_page";
+        let response = client.get("/page/999999").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let body = response.into_string().await.unwrap();
+        assert!(body.contains(r#"<a href="/">"#));
+    }
+
+    #[rocket::async_test]
+    async fn filters_listing_by_review_state() {
+        async fn listed_indices(client: &rocket::local::asynchronous::Client, tenant: &str, reviewed: bool) -> Vec<usize> {
+            let response = client.get(format!("/pages?limit={}&reviewed={}", MAX_PAGE_LISTING_LIMIT, reviewed))
+                .header(tenant_header(tenant))
+                .dispatch().await;
+            let listing: PageListing = response.into_json().await.unwrap();
+            assert!(listing.pages.iter().all(|p| p.reviewed == reviewed));
+            listing.pages.iter().map(|p| p.index).collect()
+        }
+
+        let client = client().await;
+        let tenant = "filters_listing_by_review_state";
+        let reviewed_page = upload_page(&client, tenant).await;
+        let unreviewed_page = upload_page(&client, tenant).await;
+
+        let response = client.post(format!("/page/{}/reviewed", reviewed_page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body("true")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let reviewed = listed_indices(&client, tenant, true).await;
+        assert!(reviewed.contains(&reviewed_page));
+        assert!(!reviewed.contains(&unreviewed_page));
+        let unreviewed = listed_indices(&client, tenant, false).await;
+        assert!(!unreviewed.contains(&reviewed_page));
+        assert!(unreviewed.contains(&unreviewed_page));
+
+        // and back
+        let response = client.post(format!("/page/{}/reviewed", reviewed_page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body("false")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(listed_indices(&client, tenant, false).await.contains(&reviewed_page));
+    }
+
+    #[rocket::async_test]
+    async fn imports_pages_of_pdf() {
+        // assemble a two-page PDF using our own export
+        let dir = std::env::temp_dir().join(format!("pdfmcr-import-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut source_file = pdfmcr::model::File::default();
+        for (name, width) in [("first.jpeg", 16), ("second.jpeg", 24)] {
+            let os_path = dir.join(name);
+            let data = jpeg_data(width, 16);
+            std::fs::write(&os_path, &data).unwrap();
+            let jpeg_image = pdfmcr::jpeg::Image::try_read(data.as_slice()).unwrap();
+            let scanned_image = jpeg_image_to_stored(name.parse().unwrap(), &jpeg_image).unwrap();
+            source_file.pages.push(Page::new(scanned_image));
+        }
+        let document = file_to_pdf(&source_file, &dir, &ConversionOptions::default()).unwrap();
+        let mut pdf_data = Vec::new();
+        document.write_pdf_to_stream(&mut pdf_data).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let client = client().await;
+        let tenant = "imports_pages_of_pdf";
+        let response = upload_file(&client, tenant, "/import", "source", "scan.pdf", "application/pdf", &pdf_data).await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        let first_page: usize = location.strip_prefix("/page/").unwrap().parse().unwrap();
+
+        let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+        let file_guard = file_lock.read().await;
+        let widths: Vec<u16> = file_guard.pages[first_page..first_page+2].iter()
+            .map(|page| page.scanned_image.as_ref().unwrap().info.width)
+            .collect();
+        assert_eq!(widths, [16, 24]);
+    }
+
+    #[rocket::async_test]
+    async fn remembers_source_filename() {
+        let client = client().await;
+        let tenant = "remembers_source_filename";
+        let response = upload_file(
+            &client, tenant, "/page", "background-image", "scans/2024/page 1.jpeg", "image/jpeg", &jpeg_data(16, 16),
+        ).await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        let page: usize = location.strip_prefix("/page/").unwrap().parse().unwrap();
+
+        let response = client.get(format!("/pages?offset={}&limit=1", page))
+            .header(tenant_header(tenant))
+            .dispatch().await;
+        let listing: PageListing = response.into_json().await.unwrap();
+        assert_eq!(listing.pages[0].source_filename.as_deref(), Some("page 1.jpeg"));
+
+        let response = client.get(format!("/page/{}", page)).header(tenant_header(tenant)).dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("page 1.jpeg"));
+    }
+
+    #[rocket::async_test]
+    async fn compresses_json_for_clients_accepting_gzip() {
+        use std::io::Read as _;
+
+        let client = client().await;
+        let tenant = "compresses_json_for_clients_accepting_gzip";
+        // make sure the listing is long enough to be worth compressing
+        for _ in 0..4 {
+            upload_page(&client, tenant).await;
+        }
+
+        let response = client.get("/pages")
+            .header(tenant_header(tenant))
+            .header(rocket::http::Header::new("Accept-Encoding", "gzip, deflate"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+        let compressed = response.into_bytes().await.unwrap();
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed).unwrap();
+        let listing: PageListing = rocket::serde::json::from_str(&decompressed).unwrap();
+        assert!(listing.pages.len() >= 4);
+
+        // not without asking
+        let response = client.get("/pages").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+    }
+
+    #[rocket::async_test]
+    async fn stores_images_at_validated_path() {
+        let client = client().await;
+        let tenant = "stores_images_at_validated_path";
+        let page = upload_page(&client, tenant).await;
+        let image_dir = Tenant::named(tenant).image_dir(&crate::test_support::test_dir().join("images"));
+
+        let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+        let file_path = {
+            let file_guard = file_lock.read().await;
+            file_guard.pages[page].scanned_image.as_ref().unwrap().file_path.clone()
+        };
+        let content_name = file_path.content_name().unwrap();
+        let (image_path, os_image_path) = prepare_image_path(&Tenant::named(tenant), &content_name).await.unwrap();
+        assert_eq!(image_path, file_path);
+        assert_eq!(os_image_path, file_path.to_os_path(&image_dir));
+        assert!(os_image_path.starts_with(&image_dir));
+        assert_eq!(std::fs::read(&os_image_path).unwrap(), jpeg_data(16, 16));
+    }
+
+    #[rocket::async_test]
+    async fn hashes_and_reads_upload_in_single_pass() {
+        let client = client().await;
+        let tenant = "hashes_and_reads_upload_in_single_pass";
+        let data = jpeg_data(40, 24);
+
+        // single pass
+        let (digest, jpeg_image) = hash_and_read_jpeg(data.as_slice(), ContentHashAlgorithm::Sha3_512, 300).unwrap();
+
+        // two passes
+        let mut hasher = ContentHashAlgorithm::Sha3_512.new_hasher();
+        hasher.update(&data);
+        let mut expected_digest = [0u8; 64];
+        hasher.finalize_into_reset(&mut expected_digest).unwrap();
+        let expected_image = pdfmcr::jpeg::Image::try_read_with_default_density(data.as_slice(), 300).unwrap();
+        assert_eq!(digest, expected_digest);
+        assert_eq!(
+            JpegImageInfo::try_from(&jpeg_image).unwrap(),
+            JpegImageInfo::try_from(&expected_image).unwrap(),
+        );
+
+        // the upload is written exactly once, to its final location
+        let response = upload_image(&client, tenant, "/page", &data).await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let content_name = ContentName { digest, size: data.len().try_into().unwrap() };
+        let (_image_path, os_image_path) = prepare_image_path(&Tenant::named(tenant), &content_name).await.unwrap();
+        assert_eq!(std::fs::read(&os_image_path).unwrap(), data);
+        assert_eq!(stored_copies(&data), 1);
+    }
+
+    #[rocket::async_test]
+    async fn delivers_requested_range_of_image() {
+        let client = client().await;
+        let tenant = "delivers_requested_range_of_image";
+        let mut data = jpeg_data(32, 16);
+        // pad the scan data so the image is longer than the range
+        let end_of_image = data.len() - 2;
+        data.splice(end_of_image..end_of_image, [0x55; 200]);
+        let response = upload_image(&client, tenant, "/page", &data).await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap().to_owned();
+
+        let response = client.get(format!("{}/image", location))
+            .header(tenant_header(tenant))
+            .header(rocket::http::Header::new("Range", "bytes=0-99"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(
+            response.headers().get_one("Content-Range"),
+            Some(format!("bytes 0-99/{}", data.len()).as_str()),
+        );
+        assert_eq!(response.into_bytes().await.unwrap(), &data[0..100]);
+    }
+
+    #[rocket::async_test]
+    async fn answers_matching_if_none_match_with_not_modified() {
+        let client = client().await;
+        let tenant = "answers_matching_if_none_match_with_not_modified";
+        let page = upload_page(&client, tenant).await;
+
+        let response = client.get(format!("/page/{}/image", page))
+            .header(tenant_header(tenant))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let entity_tag = response.headers().get_one("ETag").unwrap().to_owned();
+
+        let response = client.get(format!("/page/{}/image", page))
+            .header(tenant_header(tenant))
+            .header(rocket::http::Header::new("If-None-Match", entity_tag.clone()))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(entity_tag.as_str()));
+
+        let response = client.get(format!("/page/{}/image", page))
+            .header(tenant_header(tenant))
+            .header(rocket::http::Header::new("If-None-Match", "\"something-else\""))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_non_jpeg_uploads_without_storing_them() {
+        let client = client().await;
+        let tenant = "rejects_non_jpeg_uploads_without_storing_them";
+        let mut png_data = b"\x89PNG\r\n\x1A\n\x00\x00\x00\x0DIHDR".to_vec();
+        png_data.extend_from_slice(&[0x42; 64]);
+
+        let response = upload_image(&client, tenant, "/page", &png_data).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "invalid_image");
+        assert_eq!(stored_copies(&png_data), 0);
+    }
+
+    #[rocket::async_test]
+    async fn round_trips_document_as_json() {
+        let client = client().await;
+        let tenant = "round_trips_document_as_json";
+        upload_page(&client, tenant).await;
+        upload_page(&client, tenant).await;
+
+        let response = client.get("/document.json").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let exported = response.into_string().await.unwrap();
+
+        let response = client.put("/document.json")
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(&exported)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get("/document.json").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), exported);
+
+        // a document referencing missing images is refused and changes nothing
+        let mut document: rocket::serde::json::Value = rocket::serde::json::from_str(&exported).unwrap();
+        document["pages"][1]["scanned_image"]["file_path"] = "does/not/exist.jpeg".into();
+        let response = client.put("/document.json")
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(document.to_string())
+            .dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "invalid_document");
+
+        let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+        assert_eq!(file_lock.read().await.pages.len(), 2);
+        let response = client.get("/document.json").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), exported);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_absurd_typography() {
+        let client = client().await;
+        let tenant = "refuses_absurd_typography";
+        let page = upload_page(&client, tenant).await;
+
+        let annotations_body = |font_size: &str, character_spacing: &str| format!(r#"{{
+            "annotations": [{{
+                "left": 2, "bottom": 3, "font_size": {}, "leading": 0.0,
+                "elements": [{{
+                    "text": "Hello", "font_variant": "Regular",
+                    "character_spacing": {}, "word_spacing": 0.0,
+                    "language": null, "alternate_text": null, "actual_text": null, "expansion": null
+                }}]
+            }}],
+            "artifacts": []
+        }}"#, font_size, character_spacing);
+
+        let cases = [
+            ("12.0", "0.0", Status::Ok, None),
+            ("5000.0", "0.0", Status::BadRequest, Some("invalid_typography")),
+            ("12.0", "2000.0", Status::BadRequest, Some("invalid_typography")),
+            ("0.0", "0.0", Status::BadRequest, Some("malformed_body")),
+        ];
+        for (font_size, character_spacing, status, code) in cases {
+            let response = client.post(format!("/page/{}/annotations", page))
+                .header(tenant_header(tenant))
+                .header(ContentType::JSON)
+                .body(annotations_body(font_size, character_spacing))
+                .dispatch().await;
+            assert_eq!(response.status(), status, "font size {}, character spacing {}", font_size, character_spacing);
+            if let Some(code) = code {
+                assert_eq!(error_code(response).await, code);
+            }
+        }
+
+        let response = client.post(format!("/page/{}/annotations", page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(r#"{"annotations": ["#)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "malformed_body");
+    }
+
+    #[test]
+    fn counts_missing_images() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-missing-images-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = jpeg_data(16, 16);
+        let jpeg_image = pdfmcr::jpeg::Image::try_read(data.as_slice()).unwrap();
+        let mut file = pdfmcr::model::File::default();
+        for name in ["present.jpeg", "missing.jpeg"] {
+            let scanned_image = jpeg_image_to_stored(name.parse().unwrap(), &jpeg_image).unwrap();
+            file.pages.push(Page::new(scanned_image));
+        }
+        file.pages.push(Page::new_text_only(612.0, 792.0));
+        std::fs::write(dir.join("present.jpeg"), &data).unwrap();
+
+        let missing_count = warn_about_missing_images(&file, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(missing_count, 1);
+    }
+
+    #[rocket::async_test]
+    async fn reports_page_size() {
+        let client = client().await;
+        let tenant = "reports_page_size";
+        let image_page = upload_page(&client, tenant).await;
+
+        let response = client.get(format!("/page/{}/size", image_page)).header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let size: PageSize = response.into_json().await.unwrap();
+        assert_eq!(size, PageSize {
+            width_pt: Some(16.0),
+            height_pt: Some(16.0),
+            width_px: Some(16),
+            height_px: Some(16),
+            density_x: Some(72),
+            density_y: Some(72),
+            density_unit: Some(DensityUnit::DotsPerInch),
+        });
+
+        // the media box takes precedence; text-only pages have no image
+        let text_page = {
+            let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+            let mut file_guard = file_lock.write().await;
+            file_guard.pages[image_page].media_box_pt = Some((595.28, 841.89));
+            file_guard.pages.push(Page::new_text_only(612.0, 792.0));
+            file_guard.pages.len() - 1
+        };
+        let response = client.get(format!("/page/{}/size", image_page)).header(tenant_header(tenant)).dispatch().await;
+        let size: PageSize = response.into_json().await.unwrap();
+        assert_eq!((size.width_pt, size.height_pt), (Some(595.28), Some(841.89)));
+        assert_eq!((size.width_px, size.height_px), (Some(16), Some(16)));
+
+        let response = client.get(format!("/page/{}/size", text_page)).header(tenant_header(tenant)).dispatch().await;
+        let size: PageSize = response.into_json().await.unwrap();
+        assert_eq!(size, PageSize {
+            width_pt: Some(612.0),
+            height_pt: Some(792.0),
+            width_px: None,
+            height_px: None,
+            density_x: None,
+            density_y: None,
+            density_unit: None,
+        });
+
+        let response = client.get(format!("/page/{}/size", text_page + 1)).header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(error_code(response).await, "no_such_page");
+    }
+
+    #[rocket::async_test]
+    async fn refuses_to_export_pages_of_unknown_size() {
+        let client = client().await;
+        let tenant = "refuses_to_export_pages_of_unknown_size";
+        upload_page(&client, tenant).await;
+        let page = upload_page(&client, tenant).await;
+        {
+            let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+            let mut file_guard = file_lock.write().await;
+            file_guard.pages[page].scanned_image.as_mut().unwrap().info.density_unit = DensityUnit::NoUnit;
+        }
+
+        let response = client.get("/document.pdf").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let error: rocket::serde::json::Value = response.into_json().await.unwrap();
+        assert_eq!(error["code"], "unknown_page_size");
+        assert!(error["message"].as_str().unwrap().contains(&format!("page {}", page)));
+    }
+
+    #[rocket::async_test]
+    async fn moves_page_by_positions() {
+        let client = client().await;
+        let tenant = "moves_page_by_positions";
+        let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+        // tell the pages apart by their width
+        file_lock.write().await.pages = (0..4)
+            .map(|i| Page::new_text_only(100.0 + f64::from(i), 100.0))
+            .collect();
+
+        async fn move_page(client: &rocket::local::asynchronous::Client, tenant: &str, page: usize, delta: isize) -> usize {
+            let response = client.post(format!("/page/{}/move", page))
+                .header(tenant_header(tenant))
+                .header(ContentType::JSON)
+                .body(delta.to_string())
+                .dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json().await.unwrap()
+        }
+        async fn page_order(file_lock: &RwLock<pdfmcr::model::File>) -> Vec<u64> {
+            file_lock.read().await.pages.iter()
+                .map(|page| (page.width_pt().unwrap() - 100.0) as u64)
+                .collect()
+        }
+
+        // down
+        assert_eq!(move_page(&client, tenant, 0, 2).await, 2);
+        assert_eq!(page_order(&file_lock).await, [1, 2, 0, 3]);
+        // up
+        assert_eq!(move_page(&client, tenant, 3, -2).await, 1);
+        assert_eq!(page_order(&file_lock).await, [1, 3, 2, 0]);
+        // past the end
+        assert_eq!(move_page(&client, tenant, 1, 10).await, 3);
+        assert_eq!(page_order(&file_lock).await, [1, 2, 0, 3]);
+        // past the beginning
+        assert_eq!(move_page(&client, tenant, 2, -10).await, 0);
+        assert_eq!(page_order(&file_lock).await, [0, 1, 2, 3]);
+        // nowhere
+        assert_eq!(move_page(&client, tenant, 1, 0).await, 1);
+        assert_eq!(page_order(&file_lock).await, [0, 1, 2, 3]);
+
+        let response = client.post("/page/4/move")
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body("1")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(error_code(response).await, "no_such_page");
+    }
+
+    #[rocket::async_test]
+    async fn announces_page_count_of_exported_pdf() {
+        let client = client().await;
+        let tenant = "announces_page_count_of_exported_pdf";
+        for _ in 0..3 {
+            upload_page(&client, tenant).await;
+        }
+
+        let response = client.get("/document.pdf").header(tenant_header(tenant)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PDF));
+        assert_eq!(response.headers().get_one("X-Pdfmcr-Page-Count"), Some("3"));
+        let pdf_data = response.into_bytes().await.unwrap();
+        assert_eq!(lopdf::Document::load_mem(&pdf_data).unwrap().get_pages().len(), 3);
+    }
+
+    #[rocket::async_test]
+    async fn imports_words_from_hocr() {
+        let client = client().await;
+        let tenant = "imports_words_from_hocr";
+        // 16 x 16 pixels at 72 dpi
+        let page = upload_page(&client, tenant).await;
+        let hocr = concat!(
+            "<div class='ocr_page'>",
+            "<span class='ocrx_word' title='bbox 2 3 10 11'>Hello</span>",
+            "<span class='ocrx_word' title='bbox 11 3 15 11'>OCR</span>",
+            "</div>",
+        );
+
+        let response = upload_file(
+            &client, tenant, &format!("/page/{}/ocr", page), "source", "page.hocr", "text/html", hocr.as_bytes(),
+        ).await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let file_lock = tenant_file(&Tenant::named(tenant)).await.unwrap();
+        let file_guard = file_lock.read().await;
+        let annotations = &file_guard.pages[page].annotations;
+        let placed: Vec<(u64, u64, String)> = annotations.iter()
+            .map(|a| (a.left, a.bottom, a.elements[0].text.clone()))
+            .collect();
+        assert_eq!(placed, [(2, 5, "Hello".to_owned()), (11, 5, "OCR".to_owned())]);
+        drop(file_guard);
+
+        let response = upload_file(
+            &client, tenant, &format!("/page/{}/ocr", page), "source", "page.txt", "text/plain", b"no OCR here",
+        ).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "invalid_ocr");
+    }
+
+    /// Stores an image that is not referenced by any page, pretending it was stored at the given time.
+    fn store_orphan(image_dir: &Path, name: &str, modified: SystemTime) {
+        let path = image_dir.join(name);
+        std::fs::write(&path, b"orphan").unwrap();
+        File::options().write(true).open(&path).unwrap()
+            .set_times(FileTimes::new().set_modified(modified))
+            .unwrap();
+    }
+
+    async fn gc_report(client: &rocket::local::asynchronous::Client, tenant: &str, dry_run: bool) -> GcImagesReport {
+        let response = client.post(format!("/admin/gc-images?dry_run={}", dry_run))
+            .header(tenant_header(tenant))
+            .header(Header::new(ADMIN_HEADER, ADMIN_SECRET))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn collects_orphaned_images() {
+        let client = client().await;
+        let tenant = "collects_orphaned_images";
+        let page = upload_page(&client, tenant).await;
+        let image_dir = Tenant::named(tenant).image_dir(&test_dir().join("images"));
+
+        let long_ago = SystemTime::now() - 2 * ORPHAN_GRACE_PERIOD;
+        let orphan_name = ContentName { digest: [0x11; 64], size: 6 }.to_string();
+        let recent_name = ContentName { digest: [0x22; 64], size: 6 }.to_string();
+        store_orphan(&image_dir, &orphan_name, long_ago);
+        store_orphan(&image_dir, &recent_name, SystemTime::now());
+        // not named like a stored image
+        store_orphan(&image_dir, "notes.txt", long_ago);
+
+        let report = gc_report(&client, tenant, true).await;
+        assert_eq!(report.orphaned, vec![orphan_name.clone()]);
+        assert!(!report.deleted);
+        assert!(image_dir.join(&orphan_name).exists());
+
+        let report = gc_report(&client, tenant, false).await;
+        assert_eq!(report.orphaned, vec![orphan_name.clone()]);
+        assert!(report.deleted);
+        assert!(!image_dir.join(&orphan_name).exists());
+        assert!(image_dir.join(&recent_name).exists());
+        assert!(image_dir.join("notes.txt").exists());
+
+        // the referenced image remains
+        let response = client.get(format!("/page/{}/image", page))
+            .header(tenant_header(tenant))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(gc_report(&client, tenant, true).await.orphaned.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn refuses_garbage_collection_without_admin_secret() {
+        let client = client().await;
+        let tenant = "refuses_garbage_collection_without_admin_secret";
+        let response = client.post("/admin/gc-images?dry_run=true")
+            .header(tenant_header(tenant))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.post("/admin/gc-images?dry_run=true")
+            .header(tenant_header(tenant))
+            .header(Header::new(ADMIN_HEADER, "wrong secret"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+    }
 }
@@ -1,34 +1,108 @@
 //! Structures representing data within pdfmcr.
 
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::{self, Write};
 
 use from_to_repr::FromToRepr;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use strict_num::{FiniteF64, NonZeroPositiveF64};
 
 use crate::image_path::ImagePath;
-use crate::pdf::write_pdf_string;
+use crate::pdf::{encode_win_ansi, write_pdf_byte_string, write_pdf_hex_codes, write_pdf_string};
 
 
 /// A pdfmcr file: a list of pages with annotations.
-#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, PartialOrd, Serialize)]
 pub struct File {
     /// The pages and their annotations.
     pub pages: Vec<Page>,
 
     /// The default language for this document, as a BCP 47 language tag.
     pub default_language: Option<String>,
+
+    /// The title of the document, which viewers show instead of the file name.
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// Whether the text of the annotations is drawn visibly instead of invisibly over the scans.
+    ///
+    /// Useful for documents whose text is meant to be read directly, e.g. documents consisting of
+    /// text-only pages. The text remains selectable and searchable either way.
+    #[serde(default)]
+    pub visible_text: bool,
+}
+impl File {
+    /// Returns the text chunks of all annotations and artifacts on all pages.
+    pub fn text_chunks(&self) -> impl Iterator<Item = &TextChunk> {
+        self.pages.iter()
+            .flat_map(|page| page.text_chunks())
+    }
+
+    /// Infers a default language for this document from the languages of its text chunks.
+    ///
+    /// The most common language is chosen, but only if every chunk specifies a language; otherwise,
+    /// chunks without a language would implicitly be declared to be in the inferred language.
+    pub fn infer_default_language(&self) -> Option<&str> {
+        let mut language_to_count: BTreeMap<&str, usize> = BTreeMap::new();
+        for chunk in self.text_chunks() {
+            let language = chunk.language.as_deref()?;
+            *language_to_count.entry(language).or_insert(0) += 1;
+        }
+        language_to_count.into_iter()
+            .max_by_key(|(_language, count)| *count)
+            .map(|(language, _count)| language)
+    }
+
+    /// Returns the default language of this document, inferring it if it has not been set.
+    pub fn effective_default_language(&self) -> Option<&str> {
+        self.default_language.as_deref()
+            .or_else(|| self.infer_default_language())
+    }
+}
+
+
+/// Deserializes the media box of a page, whose dimensions are stored as integers in state files
+/// written before fractional page sizes were supported.
+fn deserialize_media_box_pt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<(f64, f64)>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Dimension {
+        Integer(u64),
+        Float(f64),
+    }
+    impl From<Dimension> for f64 {
+        fn from(value: Dimension) -> Self {
+            match value {
+                Dimension::Integer(i) => i as f64,
+                Dimension::Float(f) => f,
+            }
+        }
+    }
+
+    let media_box: Option<(Dimension, Dimension)> = Option::deserialize(deserializer)?;
+    Ok(media_box.map(|(width, height)| (width.into(), height.into())))
 }
 
 
 /// A single page with annotations.
-#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
 pub struct Page {
     /// The scanned image of the page, in JPEG format.
-    pub scanned_image: JpegImage,
+    ///
+    /// Pages without a scanned image (e.g. inserted title pages) only contain text.
+    pub scanned_image: Option<JpegImage>,
+
+    /// The size of the page, in points (1/72 in), as `(width, height)`.
+    ///
+    /// Required for pages without a scanned image. Pages with a scanned image derive their size
+    /// from the dimensions and pixel density of the image unless this is set; setting it overrides
+    /// a wrong density recorded in the image, which is then scaled to fill the page.
+    #[serde(default, deserialize_with = "deserialize_media_box_pt")]
+    pub media_box_pt: Option<(f64, f64)>,
 
-    /// The annotations on the page, in reading order.
+    /// The annotations on the page, in reading order unless reordered using [`Annotation::order`].
     ///
     /// Annotations represent the actual content.
     pub annotations: Vec<Annotation>,
@@ -38,19 +112,152 @@ pub struct Page {
     /// Artifacts represent elements that are printed on the page but which are not the actual page
     /// content, e.g. page numbers.
     pub artifacts: Vec<Artifact>,
+
+    /// Whether the transcription of the page has been reviewed.
+    #[serde(default)]
+    pub reviewed: bool,
+
+    /// The name of the file from which the scanned image of the page was taken, as uploaded.
+    #[serde(default)]
+    pub source_filename: Option<String>,
+
+    /// The margins around the page content, in points (1/72 in).
+    ///
+    /// The margins enlarge the media box of the page; the scanned image and the annotations keep
+    /// their size and are moved inward accordingly. Annotation coordinates remain relative to the
+    /// page content.
+    #[serde(default)]
+    pub margins_pt: Option<PageMargins>,
 }
 impl Page {
     pub fn new(scanned_image: JpegImage) -> Self {
         Self {
-            scanned_image,
+            scanned_image: Some(scanned_image),
+            media_box_pt: None,
+            annotations: Vec::new(),
+            artifacts: Vec::new(),
+            reviewed: false,
+            source_filename: None,
+            margins_pt: None,
+        }
+    }
+
+    /// Creates a page without a scanned image with the given size in points (1/72 in).
+    pub fn new_text_only(width_pt: f64, height_pt: f64) -> Self {
+        Self {
+            scanned_image: None,
+            media_box_pt: Some((width_pt, height_pt)),
             annotations: Vec::new(),
             artifacts: Vec::new(),
+            reviewed: false,
+            source_filename: None,
+            margins_pt: None,
+        }
+    }
+
+    /// The width of the page, in points (1/72 in).
+    pub fn width_pt(&self) -> Option<f64> {
+        match (self.media_box_pt, self.scanned_image.as_ref()) {
+            (Some((width_pt, _)), _) => Some(width_pt),
+            (None, Some(scanned_image)) => scanned_image.info.width_pt().map(|w| w as f64),
+            (None, None) => None,
+        }
+    }
+
+    /// The height of the page, in points (1/72 in).
+    pub fn height_pt(&self) -> Option<f64> {
+        match (self.media_box_pt, self.scanned_image.as_ref()) {
+            (Some((_, height_pt)), _) => Some(height_pt),
+            (None, Some(scanned_image)) => scanned_image.info.height_pt().map(|h| h as f64),
+            (None, None) => None,
+        }
+    }
+
+    /// The size of the media box of the page, including the margins, in points (1/72 in), as
+    /// `(width, height)`.
+    pub fn media_box_size_pt(&self) -> Option<(f64, f64)> {
+        let margins = self.margins_pt.unwrap_or_default();
+        Some((
+            self.width_pt()? + (margins.left + margins.right) as f64,
+            self.height_pt()? + (margins.bottom + margins.top) as f64,
+        ))
+    }
+
+    /// Returns the text chunks of all annotations and artifacts on this page.
+    pub fn text_chunks(&self) -> impl Iterator<Item = &TextChunk> {
+        self.annotations.iter()
+            .chain(self.artifacts.iter().map(|artifact| &artifact.annotation))
+            .flat_map(|annotation| annotation.elements.iter())
+    }
+
+    /// Whether the given point, in points (1/72 in) from the bottom left corner, lies on the page.
+    ///
+    /// Returns `None` if the size of the page is unknown.
+    pub fn contains_point_pt(&self, x: u64, y: u64) -> Option<bool> {
+        Some(x as f64 <= self.width_pt()? && y as f64 <= self.height_pt()?)
+    }
+
+    /// Returns the annotations of the page in reading order.
+    ///
+    /// Annotations with an explicit [`order`](Annotation::order) are sorted by it and come first;
+    /// annotations without one follow. Annotations with the same order keep their order within
+    /// [`annotations`](Page::annotations).
+    pub fn annotations_in_reading_order(&self) -> Vec<&Annotation> {
+        let mut annotations: Vec<&Annotation> = self.annotations.iter().collect();
+        annotations.sort_by_key(|annotation| (annotation.order.is_none(), annotation.order));
+        annotations
+    }
+
+    /// Sets the reading order of the annotations of the page.
+    ///
+    /// `indices` contains the index of each annotation within [`annotations`](Page::annotations),
+    /// in the desired reading order. Returns `false` and leaves the page unchanged if `indices` is
+    /// not a permutation of the indices of the annotations.
+    pub fn set_reading_order(&mut self, indices: &[usize]) -> bool {
+        if indices.len() != self.annotations.len() {
+            return false;
+        }
+        let mut seen = vec![false; indices.len()];
+        for &index in indices {
+            if index >= seen.len() || seen[index] {
+                return false;
+            }
+            seen[index] = true;
+        }
+
+        for (position, &index) in indices.iter().enumerate() {
+            self.annotations[index].order = Some(position.try_into().unwrap());
+        }
+        true
+    }
+}
+
+
+/// The margins around the content of a page, in points (1/72 in).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct PageMargins {
+    pub left: u64,
+    pub bottom: u64,
+    pub right: u64,
+    pub top: u64,
+}
+impl PageMargins {
+    /// Creates margins of the same size on all sides.
+    pub const fn uniform(margin_pt: u64) -> Self {
+        Self {
+            left: margin_pt,
+            bottom: margin_pt,
+            right: margin_pt,
+            top: margin_pt,
         }
     }
 }
 
 
-/// Information about a JPEG image.
+/// Information about a scanned image.
+///
+/// Most scanned images are JPEG images; bilevel scans may also be stored using CCITT Group 4
+/// compression, as specified by `encoding`.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct JpegImageInfo {
     /// The bit depth of the image.
@@ -73,6 +280,16 @@ pub struct JpegImageInfo {
 
     /// The pixel density in the vertical direction (across the height).
     pub density_y: u16,
+
+    /// Whether the color components are stored inverted.
+    ///
+    /// This is the case for CMYK images written by Adobe applications.
+    #[serde(default)]
+    pub inverted: bool,
+
+    /// The compression with which the image data is stored.
+    #[serde(default)]
+    pub encoding: ImageEncoding,
 }
 impl JpegImageInfo {
     pub fn width_pt(&self) -> Option<u64> {
@@ -83,6 +300,152 @@ impl JpegImageInfo {
         self.density_unit.try_to_points(self.height, self.density_y)
     }
 }
+impl TryFrom<&crate::jpeg::Image> for JpegImageInfo {
+    type Error = ImageInfoError;
+
+    fn try_from(value: &crate::jpeg::Image) -> Result<Self, Self::Error> {
+        let color_space = ColorSpace::try_from(value.color_space)?;
+        let density_unit = DensityUnit::try_from(value.density_unit)?;
+        if value.bit_depth == 0 {
+            return Err(ImageInfoError::ZeroBitDepth);
+        }
+        if value.width == 0 || value.height == 0 {
+            return Err(ImageInfoError::ZeroSize);
+        }
+        if value.density_x == 0 || value.density_y == 0 {
+            return Err(ImageInfoError::ZeroDensity);
+        }
+        let inverted = color_space == ColorSpace::Cmyk && value.adobe_transform.is_some();
+        Ok(Self {
+            bit_depth: value.bit_depth,
+            width: value.width,
+            height: value.height,
+            color_space,
+            density_unit,
+            density_x: value.density_x,
+            density_y: value.density_y,
+            inverted,
+            encoding: ImageEncoding::Jpeg,
+        })
+    }
+}
+impl TryFrom<&crate::tiff::Image> for JpegImageInfo {
+    type Error = ImageInfoError;
+
+    /// Derives information about a bilevel TIFF image compressed using CCITT Group 4.
+    ///
+    /// The data of such images can be embedded into a PDF file without transcoding.
+    fn try_from(value: &crate::tiff::Image) -> Result<Self, Self::Error> {
+        if value.compression != crate::tiff::Compression::CcittGroup4 || value.bits_per_sample != [1] {
+            return Err(ImageInfoError::UnsupportedEncoding);
+        }
+        let density_unit = DensityUnit::try_from(value.density_unit)?;
+        let (Ok(width), Ok(height)) = (u16::try_from(value.width), u16::try_from(value.height)) else {
+            return Err(ImageInfoError::TooLarge);
+        };
+        let (Ok(density_x), Ok(density_y)) = (u16::try_from(value.density_x), u16::try_from(value.density_y)) else {
+            return Err(ImageInfoError::TooLarge);
+        };
+        if width == 0 || height == 0 {
+            return Err(ImageInfoError::ZeroSize);
+        }
+        if density_x == 0 || density_y == 0 {
+            return Err(ImageInfoError::ZeroDensity);
+        }
+        Ok(Self {
+            bit_depth: 1,
+            width,
+            height,
+            color_space: ColorSpace::Grayscale,
+            density_unit,
+            density_x,
+            density_y,
+            inverted: false,
+            encoding: ImageEncoding::CcittGroup4 {
+                black_is_1: value.photometric_interpretation == crate::tiff::PhotometricInterpretation::BlackIsZero,
+            },
+        })
+    }
+}
+
+/// The bit depths of scanned images that can be embedded into a PDF file: 1 for bilevel images
+/// compressed using CCITT Group 4 and 8 for JPEG images.
+pub const EMBEDDABLE_BIT_DEPTHS: [u8; 2] = [1, 8];
+
+/// The compression with which the data of a scanned image is stored.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum ImageEncoding {
+    /// JPEG (DCT) compression, stored in a JFIF or Exif file.
+    #[default]
+    Jpeg,
+
+    /// CCITT Group 4 compression of a bilevel image, stored as the raw encoded data.
+    CcittGroup4 {
+        /// Whether black pixels are encoded as 1 bits instead of 0 bits.
+        black_is_1: bool,
+    },
+}
+impl ImageEncoding {
+    /// The PDF name of the filter decoding data in this encoding.
+    pub fn pdf_filter_name(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "DCTDecode",
+            Self::CcittGroup4 { .. } => "CCITTFaxDecode",
+        }
+    }
+}
+
+/// An error that can occur when deriving [`JpegImageInfo`] from a parsed JPEG image.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ImageInfoError {
+    /// The image is stored in a color space not supported by pdfmcr.
+    UnknownColorSpace(u8),
+
+    /// The image does not specify the unit of its pixel density.
+    NoDensityUnit,
+
+    /// The image specifies its pixel density in an unknown unit.
+    UnknownDensityUnit(u8),
+
+    /// The image has a bit depth of 0.
+    ZeroBitDepth,
+
+    /// The image has a width or height of 0.
+    ZeroSize,
+
+    /// The image has a horizontal or vertical pixel density of 0.
+    ZeroDensity,
+
+    /// The image has a width, height or pixel density that does not fit into 16 bits.
+    TooLarge,
+
+    /// The image is stored in an encoding that cannot be embedded into a PDF file as-is.
+    UnsupportedEncoding,
+}
+impl fmt::Display for ImageInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownColorSpace(o)
+                => write!(f, "JPEG has unknown color space {}", o),
+            Self::NoDensityUnit
+                => write!(f, "JPEG images without a density unit are not supported"),
+            Self::UnknownDensityUnit(o)
+                => write!(f, "JPEG has unknown density unit {}", o),
+            Self::ZeroBitDepth
+                => write!(f, "JPEG image cannot have a bit depth of 0"),
+            Self::ZeroSize
+                => write!(f, "JPEG image cannot have a width or height of 0"),
+            Self::ZeroDensity
+                => write!(f, "JPEG image cannot have a horizontal or vertical pixel density of 0"),
+            Self::TooLarge
+                => write!(f, "image dimensions or pixel density exceed 16 bits"),
+            Self::UnsupportedEncoding
+                => write!(f, "image encoding cannot be embedded as-is"),
+        }
+    }
+}
+impl std::error::Error for ImageInfoError {
+}
 
 
 /// A JPEG image.
@@ -93,8 +456,25 @@ pub struct JpegImage {
 
     /// The path to the file containing the actual full data of the image, in JFIF or Exif formats.
     ///
-    /// JFIF and Exif are the most common representations of JPEG files.
+    /// JFIF and Exif are the most common representations of JPEG files. Images compressed using
+    /// CCITT Group 4 are stored as the raw encoded data instead.
     pub file_path: ImagePath,
+
+    /// The PDF names of the filters with which the stored data of the image is decoded, in order.
+    ///
+    /// If `None`, the single filter matching the encoding of the image is used. This allows e.g.
+    /// additionally Flate-compressed images or JPEG 2000 images (`JPXDecode`).
+    #[serde(default)]
+    pub data_filters: Option<Vec<String>>,
+}
+impl JpegImage {
+    /// The PDF names of the filters with which the stored data of the image is decoded, in order.
+    pub fn effective_data_filters(&self) -> Vec<String> {
+        match self.data_filters.as_ref() {
+            Some(data_filters) => data_filters.clone(),
+            None => vec![self.info.encoding.pdf_filter_name().to_owned()],
+        }
+    }
 }
 
 /// The color space of an image or graphics system.
@@ -106,6 +486,9 @@ pub enum ColorSpace {
     Cmyk = 4,
 }
 impl ColorSpace {
+    /// All color spaces, in the order of their component counts.
+    pub const ALL: [ColorSpace; 3] = [Self::Grayscale, Self::Rgb, Self::Cmyk];
+
     pub fn as_pdf_name(&self) -> &'static str {
         match self {
             Self::Grayscale => "/DeviceGray",
@@ -113,6 +496,27 @@ impl ColorSpace {
             Self::Cmyk => "/DeviceCMYK",
         }
     }
+
+    /// The number of color components of a pixel in this color space.
+    pub fn component_count(&self) -> usize {
+        match self {
+            Self::Grayscale => 1,
+            Self::Rgb => 3,
+            Self::Cmyk => 4,
+        }
+    }
+}
+impl TryFrom<crate::jpeg::ColorSpace> for ColorSpace {
+    type Error = ImageInfoError;
+
+    fn try_from(value: crate::jpeg::ColorSpace) -> Result<Self, Self::Error> {
+        match value {
+            crate::jpeg::ColorSpace::Grayscale => Ok(Self::Grayscale),
+            crate::jpeg::ColorSpace::Rgb => Ok(Self::Rgb),
+            crate::jpeg::ColorSpace::Cmyk => Ok(Self::Cmyk),
+            crate::jpeg::ColorSpace::Other(o) => Err(ImageInfoError::UnknownColorSpace(o)),
+        }
+    }
 }
 
 /// The unit in which pixel (dot) density is specified.
@@ -124,10 +528,17 @@ pub enum DensityUnit {
     DotsPerCentimeter = 2,
 }
 impl DensityUnit {
+    /// The units specifying an absolute density, which are the only ones accepted for scanned
+    /// images.
+    pub const ABSOLUTE: [DensityUnit; 2] = [Self::DotsPerInch, Self::DotsPerCentimeter];
+
     /// Uses the density unit to convert the pixel count and density value into points (1/72 in).
     ///
-    /// Returns `None` for [`DensityUnit::NoUnit`].
+    /// Returns `None` for [`DensityUnit::NoUnit`] and for a density of 0.
     pub fn try_to_points(&self, pixel_count: u16, density: u16) -> Option<u64> {
+        if density == 0 {
+            return None;
+        }
         match self {
             Self::NoUnit => None,
             Self::DotsPerInch => Some(u64::from(pixel_count) * 72 / u64::from(density)),
@@ -135,6 +546,115 @@ impl DensityUnit {
         }
     }
 }
+impl TryFrom<crate::jpeg::DensityUnit> for DensityUnit {
+    type Error = ImageInfoError;
+
+    /// Converts an absolute density unit read from an image.
+    ///
+    /// Images without a density unit only specify their pixel aspect ratio, which is insufficient
+    /// to place them on a page; they are refused.
+    fn try_from(value: crate::jpeg::DensityUnit) -> Result<Self, Self::Error> {
+        match value {
+            crate::jpeg::DensityUnit::NoUnit => Err(ImageInfoError::NoDensityUnit),
+            crate::jpeg::DensityUnit::DotsPerInch => Ok(Self::DotsPerInch),
+            crate::jpeg::DensityUnit::DotsPerCentimeter => Ok(Self::DotsPerCentimeter),
+            crate::jpeg::DensityUnit::Other(o) => Err(ImageInfoError::UnknownDensityUnit(o)),
+        }
+    }
+}
+
+
+/// Document-wide settings that influence how annotations are drawn.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DrawingContext<'a> {
+    /// The default language of the document.
+    ///
+    /// Text chunks in this language do not declare their language separately.
+    pub document_language: Option<&'a str>,
+
+    /// Whether text is drawn visibly, for proofing the placement of annotations.
+    ///
+    /// Visible text is filled using the color and graphics state named by [`DEBUG_TEXT_COLOR`] and
+    /// [`DEBUG_TEXT_GRAPHICS_STATE`]; the graphics state must be available in the resources of the
+    /// page.
+    pub debug_text: bool,
+
+    /// Whether text is drawn visibly (filled in the current color) as the regular output, instead
+    /// of invisibly.
+    pub visible_text: bool,
+
+    /// The codes of the characters in the fallback fonts.
+    ///
+    /// Text chunks that cannot be encoded for the standard fonts (resource names `F0` to `F3`) are
+    /// drawn using the fallback font of the same variant (resource names `FU0` to `FU3`) instead;
+    /// all their characters must be mapped.
+    pub fallback_codes: Option<&'a BTreeMap<char, u16>>,
+}
+
+/// The fill color, as the operands of the `rg` operator, with which text is drawn when it is drawn
+/// visibly for debugging.
+pub const DEBUG_TEXT_COLOR: &str = "1 0 0";
+
+/// The resource name of the graphics state applied when text is drawn visibly for debugging.
+pub const DEBUG_TEXT_GRAPHICS_STATE: &str = "GSDebug";
+
+/// The largest font size of an annotation accepted by [`Annotation::check_typography`], in points.
+pub const MAX_FONT_SIZE_PT: f64 = 1000.0;
+
+/// The largest magnitude of the leading, character spacing and word spacing accepted by
+/// [`Annotation::check_typography`], in points.
+pub const MAX_SPACING_PT: f64 = 1000.0;
+
+/// The largest horizontal scaling of a text chunk accepted by [`Annotation::check_typography`], in
+/// percent.
+pub const MAX_HORIZONTAL_SCALE_PERCENT: u32 = 1000;
+
+
+/// A typographic value of an annotation that lies outside of the accepted range.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum TypographyError {
+    /// The font size exceeds [`MAX_FONT_SIZE_PT`].
+    FontSizeTooLarge(NonZeroPositiveF64),
+
+    /// The magnitude of the leading exceeds [`MAX_SPACING_PT`].
+    LeadingOutOfRange(FiniteF64),
+
+    /// The magnitude of the character spacing of the chunk with the given index exceeds
+    /// [`MAX_SPACING_PT`].
+    CharacterSpacingOutOfRange { chunk: usize, value: FiniteF64 },
+
+    /// The magnitude of the word spacing of the chunk with the given index exceeds
+    /// [`MAX_SPACING_PT`].
+    WordSpacingOutOfRange { chunk: usize, value: FiniteF64 },
+
+    /// The horizontal scaling of the chunk with the given index is 0 or exceeds
+    /// [`MAX_HORIZONTAL_SCALE_PERCENT`].
+    HorizontalScaleOutOfRange { chunk: usize, value: u32 },
+
+    /// The magnitude of the text rise of the chunk with the given index exceeds
+    /// [`MAX_SPACING_PT`].
+    TextRiseOutOfRange { chunk: usize, value: i64 },
+}
+impl fmt::Display for TypographyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FontSizeTooLarge(font_size)
+                => write!(f, "font size {} exceeds the maximum of {} pt", font_size.get(), MAX_FONT_SIZE_PT),
+            Self::LeadingOutOfRange(leading)
+                => write!(f, "leading {} exceeds the range of ±{} pt", leading.get(), MAX_SPACING_PT),
+            Self::CharacterSpacingOutOfRange { chunk, value }
+                => write!(f, "character spacing {} of chunk {} exceeds the range of ±{} pt", value.get(), chunk, MAX_SPACING_PT),
+            Self::WordSpacingOutOfRange { chunk, value }
+                => write!(f, "word spacing {} of chunk {} exceeds the range of ±{} pt", value.get(), chunk, MAX_SPACING_PT),
+            Self::HorizontalScaleOutOfRange { chunk, value }
+                => write!(f, "horizontal scaling {}% of chunk {} is outside of the range of 1% to {}%", value, chunk, MAX_HORIZONTAL_SCALE_PERCENT),
+            Self::TextRiseOutOfRange { chunk, value }
+                => write!(f, "text rise {} of chunk {} exceeds the range of ±{} pt", value, chunk, MAX_SPACING_PT),
+        }
+    }
+}
+impl std::error::Error for TypographyError {
+}
 
 
 /// A single cohesive annotation on the page that represents actual content.
@@ -157,13 +677,80 @@ pub struct Annotation {
 
     /// The elements of the annotation.
     pub elements: Vec<TextChunk>,
+
+    /// The position of the annotation in the reading order of its page.
+    ///
+    /// If not set, the position of the annotation within [`Page::annotations`] is used, after all
+    /// annotations for which the order is set.
+    #[serde(default)]
+    pub order: Option<u32>,
+
+    /// The role of the annotation within the logical structure of the document.
+    ///
+    /// If not set, the annotation is a paragraph.
+    #[serde(default)]
+    pub block_role: Option<BlockRole>,
 }
 impl Annotation {
-    pub fn write_drawing_commands<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+    /// Returns the text of this annotation as it would be read, without any formatting.
+    ///
+    /// The actual text of a chunk is preferred over its displayed text.
+    pub fn plain_text(&self) -> String {
+        let mut text = String::new();
+        for element in &self.elements {
+            text.push_str(element.actual_text.as_deref().unwrap_or(&element.text));
+        }
+        text
+    }
+
+    /// Returns the language shared by all the chunks of this annotation, if any.
+    ///
+    /// If any chunk does not specify its language (and therefore uses the default language of the
+    /// document) or the chunks differ in their languages, there is no common language.
+    pub fn common_language(&self) -> Option<&str> {
+        let (first, rest) = self.elements.split_first()?;
+        let language = first.language.as_deref()?;
+        rest.iter()
+            .all(|chunk| chunk.language.as_deref().is_some_and(|l| l.eq_ignore_ascii_case(language)))
+            .then_some(language)
+    }
+
+    /// Checks whether the font size and spacings of this annotation lie within sensible ranges.
+    ///
+    /// Non-positive font sizes and non-finite values are already excluded by the types of the
+    /// fields; this additionally refuses absurdly large values, which are most likely typos.
+    pub fn check_typography(&self) -> Result<(), TypographyError> {
+        if self.font_size.get() > MAX_FONT_SIZE_PT {
+            return Err(TypographyError::FontSizeTooLarge(self.font_size));
+        }
+        if self.leading.get().abs() > MAX_SPACING_PT {
+            return Err(TypographyError::LeadingOutOfRange(self.leading));
+        }
+        for (chunk, element) in self.elements.iter().enumerate() {
+            if element.character_spacing.get().abs() > MAX_SPACING_PT {
+                return Err(TypographyError::CharacterSpacingOutOfRange { chunk, value: element.character_spacing });
+            }
+            if element.word_spacing.get().abs() > MAX_SPACING_PT {
+                return Err(TypographyError::WordSpacingOutOfRange { chunk, value: element.word_spacing });
+            }
+            if let Some(value) = element.horizontal_scale.filter(|s| *s == 0 || *s > MAX_HORIZONTAL_SCALE_PERCENT) {
+                return Err(TypographyError::HorizontalScaleOutOfRange { chunk, value });
+            }
+            if let Some(value) = element.text_rise.filter(|r| r.unsigned_abs() as f64 > MAX_SPACING_PT) {
+                return Err(TypographyError::TextRiseOutOfRange { chunk, value });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_drawing_commands<W: Write>(&self, mut writer: W, context: &DrawingContext) -> Result<(), io::Error> {
         writer.write_all(b" BT")?;
+        if context.debug_text {
+            write!(writer, "/{} gs {} rg", DEBUG_TEXT_GRAPHICS_STATE, DEBUG_TEXT_COLOR)?;
+        }
         write!(writer, " 1 0 0 1 {} {} Tm", self.left, self.bottom)?;
         for element in &self.elements {
-            element.write_drawing_commands(&mut writer, self.font_size, self.leading)?;
+            element.write_drawing_commands(&mut writer, self.font_size, self.leading, context)?;
         }
         writer.write_all(b" ET")?;
         Ok(())
@@ -181,10 +768,10 @@ pub struct Artifact {
     pub annotation: Annotation,
 }
 impl Artifact {
-    pub fn write_drawing_commands<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+    pub fn write_drawing_commands<W: Write>(&self, mut writer: W, context: &DrawingContext) -> Result<(), io::Error> {
         write!(writer, "/Artifact<</Type{}>>BDC", self.kind.as_pdf_name())?;
-        self.annotation.write_drawing_commands(&mut writer)?;
-        writer.write_all(b" EDC")?;
+        self.annotation.write_drawing_commands(&mut writer, context)?;
+        writer.write_all(b" EMC")?;
         Ok(())
     }
 }
@@ -224,6 +811,87 @@ impl ArtifactKind {
     }
 }
 
+/// The role of an [`Annotation`] within the logical structure of the document.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum BlockRole {
+    /// A paragraph of running text.
+    Paragraph,
+
+    /// A heading of the first (topmost) level.
+    Heading1,
+
+    /// A heading of the second level.
+    Heading2,
+
+    /// A heading of the third level.
+    Heading3,
+
+    /// A heading of the fourth level.
+    Heading4,
+
+    /// A heading of the fifth level.
+    Heading5,
+
+    /// A heading of the sixth (lowest) level.
+    Heading6,
+
+    /// An item of a list. Consecutive list items on a page form a list.
+    ListItem,
+
+    /// A quotation set apart from the running text.
+    BlockQuote,
+
+    /// The caption of a figure or table.
+    Caption,
+}
+impl BlockRole {
+    /// The type of the structure element representing an annotation with this role, without the
+    /// leading slash.
+    pub const fn as_structure_type(&self) -> &'static str {
+        match self {
+            Self::Paragraph => "P",
+            Self::Heading1 => "H1",
+            Self::Heading2 => "H2",
+            Self::Heading3 => "H3",
+            Self::Heading4 => "H4",
+            Self::Heading5 => "H5",
+            Self::Heading6 => "H6",
+            Self::ListItem => "LI",
+            Self::BlockQuote => "BlockQuote",
+            Self::Caption => "Caption",
+        }
+    }
+
+    /// The tag of the marked-content sequence containing the text of an annotation with this role,
+    /// without the leading slash.
+    ///
+    /// This is the type of the structure element directly owning the text; for list items, this is
+    /// the body of the item (`LBody`) within the item itself (`LI`).
+    pub const fn as_marked_content_tag(&self) -> &'static str {
+        match self {
+            Self::ListItem => "LBody",
+            other => other.as_structure_type(),
+        }
+    }
+
+    /// Obtains the role whose annotations are tagged with the given marked-content tag.
+    pub fn from_marked_content_tag(tag: &[u8]) -> Option<Self> {
+        match tag {
+            b"P" => Some(Self::Paragraph),
+            b"H1" => Some(Self::Heading1),
+            b"H2" => Some(Self::Heading2),
+            b"H3" => Some(Self::Heading3),
+            b"H4" => Some(Self::Heading4),
+            b"H5" => Some(Self::Heading5),
+            b"H6" => Some(Self::Heading6),
+            b"LBody" => Some(Self::ListItem),
+            b"BlockQuote" => Some(Self::BlockQuote),
+            b"Caption" => Some(Self::Caption),
+            _ => None,
+        }
+    }
+}
+
 /// A chunk of text.
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct TextChunk {
@@ -239,6 +907,20 @@ pub struct TextChunk {
     /// Word spacing.
     pub word_spacing: FiniteF64,
 
+    /// Horizontal scaling of the glyphs, in percent of their regular width.
+    ///
+    /// Useful to match condensed or expanded type in the scan. If not set, the glyphs are not
+    /// scaled, which is equivalent to 100.
+    #[serde(default)]
+    pub horizontal_scale: Option<u32>,
+
+    /// Text rise, the distance in points by which the baseline is raised (or lowered, if negative),
+    /// e.g. for superscripts and subscripts.
+    ///
+    /// If not set, the baseline is not moved, which is equivalent to 0.
+    #[serde(default)]
+    pub text_rise: Option<i64>,
+
     /// The language of this chunk, as a BCP 47 language tag, if it differs from the default
     /// document language.
     pub language: Option<String>,
@@ -265,15 +947,32 @@ pub struct TextChunk {
     pub expansion: Option<String>,
 }
 impl TextChunk {
-    pub fn write_drawing_commands<W: Write>(&self, mut writer: W, font_size: NonZeroPositiveF64, leading: FiniteF64) -> Result<(), io::Error> {
-        let need_span =
-            self.language.is_some()
+    /// The language declared for this chunk in a document of the given language, i.e. its language
+    /// if it differs from the document's.
+    fn declared_language<'a>(&'a self, document_language: Option<&str>) -> Option<&'a str> {
+        self.language.as_deref()
+            .filter(|l| Some(*l) != document_language)
+    }
+
+    /// Whether the chunk is drawn within a `Span` marked-content sequence carrying its language,
+    /// alternate text, actual text or expansion, given the language of the document.
+    ///
+    /// Marked-content sequences with these properties require PDF 1.5.
+    pub fn needs_span(&self, document_language: Option<&str>) -> bool {
+        self.declared_language(document_language).is_some()
             || self.alternate_text.is_some()
             || self.actual_text.is_some()
-            || self.expansion.is_some();
+            || self.expansion.is_some()
+    }
 
-        // pick the correct font
-        write!(writer, "/F{} {} Tf", self.font_variant.as_index(), font_size.get())?;
+    pub fn write_drawing_commands<W: Write>(&self, mut writer: W, font_size: NonZeroPositiveF64, leading: FiniteF64, context: &DrawingContext) -> Result<(), io::Error> {
+        let language = self.declared_language(context.document_language);
+        let need_span = self.needs_span(context.document_language);
+
+        // pick the correct font; text which the standard fonts cannot encode goes to the fallback font
+        let win_ansi_text = encode_win_ansi(&self.text);
+        let font_prefix = if win_ansi_text.is_some() { "F" } else { "FU" };
+        write!(writer, "/{}{} {} Tf", font_prefix, self.font_variant.as_index(), font_size.get())?;
 
         // set some spacing settings
         if self.character_spacing.get() != 0.0 {
@@ -285,14 +984,25 @@ impl TextChunk {
         if leading.get() != 0.0 {
             write!(writer, " {} TL", leading.get())?;
         }
+        if let Some(horizontal_scale) = self.horizontal_scale {
+            write!(writer, " {} Tz", horizontal_scale)?;
+        }
+        if let Some(text_rise) = self.text_rise {
+            write!(writer, " {} Ts", text_rise)?;
+        }
 
-        // do not actually output the characters
-        // (neither fill nor stroke nor influence the clipping path)
-        write!(writer, " 3 Tr")?;
+        if context.debug_text || context.visible_text {
+            // fill the characters (when debugging, so that their placement can be checked)
+            write!(writer, " 0 Tr")?;
+        } else {
+            // do not actually output the characters
+            // (neither fill nor stroke nor influence the clipping path)
+            write!(writer, " 3 Tr")?;
+        }
 
         if need_span {
             writer.write_all(b"/Span<<")?;
-            if let Some(language) = self.language.as_ref() {
+            if let Some(language) = language {
                 writer.write_all(b"/Lang")?;
                 write_pdf_string(language, &mut writer)?;
             }
@@ -311,16 +1021,73 @@ impl TextChunk {
             writer.write_all(b">>BDC")?;
         }
 
-        write_pdf_string(&self.text, &mut writer)?;
+        match win_ansi_text {
+            Some(bytes) => write_pdf_byte_string(&bytes, &mut writer)?,
+            None => {
+                let codes = self.text.chars()
+                    .map(|c| context.fallback_codes
+                        .and_then(|codes| codes.get(&c))
+                        .copied()
+                        .ok_or_else(|| io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("character {:?} has no code in the fallback font", c),
+                        )))
+                    .collect::<Result<Vec<u16>, io::Error>>()?;
+                write_pdf_hex_codes(&codes, &mut writer)?;
+            },
+        }
         writer.write_all(b"Tj")?;
 
         if need_span {
             writer.write_all(b" EMC")?;
         }
+
+        // unlike the other settings, scaling and rise are not set by every chunk; reset them so
+        // they don't leak into the following ones
+        if self.horizontal_scale.is_some() {
+            writer.write_all(b" 100 Tz")?;
+        }
+        if self.text_rise.is_some() {
+            writer.write_all(b" 0 Ts")?;
+        }
         Ok(())
     }
 }
 
+/// The family of a font.
+///
+/// Only the families among the standard 14 fonts of PDF are supported, as these need not be
+/// embedded.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum FontFamily {
+    #[default]
+    Times,
+    Helvetica,
+    Courier,
+}
+impl FontFamily {
+    /// All font families.
+    pub const ALL: [FontFamily; 3] = [Self::Times, Self::Helvetica, Self::Courier];
+
+    /// The PDF name of the standard font of this family in the given variant.
+    pub const fn standard_font_name(&self, variant: FontVariant) -> &'static str {
+        match (self, variant) {
+            (Self::Times, FontVariant::Regular) => "Times-Roman",
+            (Self::Times, FontVariant::Italic) => "Times-Italic",
+            (Self::Times, FontVariant::Bold) => "Times-Bold",
+            (Self::Times, FontVariant::BoldItalic) => "Times-BoldItalic",
+            (Self::Helvetica, FontVariant::Regular) => "Helvetica",
+            (Self::Helvetica, FontVariant::Italic) => "Helvetica-Oblique",
+            (Self::Helvetica, FontVariant::Bold) => "Helvetica-Bold",
+            (Self::Helvetica, FontVariant::BoldItalic) => "Helvetica-BoldOblique",
+            (Self::Courier, FontVariant::Regular) => "Courier",
+            (Self::Courier, FontVariant::Italic) => "Courier-Oblique",
+            (Self::Courier, FontVariant::Bold) => "Courier-Bold",
+            (Self::Courier, FontVariant::BoldItalic) => "Courier-BoldOblique",
+        }
+    }
+}
+
 /// The variant of a font.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 #[repr(u8)]
@@ -331,6 +1098,9 @@ pub enum FontVariant {
     BoldItalic,
 }
 impl FontVariant {
+    /// All font variants, in the order of their indices.
+    pub const ALL: [FontVariant; 4] = [Self::Regular, Self::Italic, Self::Bold, Self::BoldItalic];
+
     pub const fn as_index(&self) -> u8 {
         match self {
             Self::Regular => 0b00,
@@ -349,3 +1119,148 @@ impl FontVariant {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_jpeg_color_spaces() {
+        assert_eq!(ColorSpace::try_from(crate::jpeg::ColorSpace::Grayscale), Ok(ColorSpace::Grayscale));
+        assert_eq!(ColorSpace::try_from(crate::jpeg::ColorSpace::Rgb), Ok(ColorSpace::Rgb));
+        assert_eq!(ColorSpace::try_from(crate::jpeg::ColorSpace::Cmyk), Ok(ColorSpace::Cmyk));
+        assert_eq!(
+            ColorSpace::try_from(crate::jpeg::ColorSpace::Other(2)),
+            Err(ImageInfoError::UnknownColorSpace(2)),
+        );
+    }
+
+    #[test]
+    fn converts_jpeg_density_units() {
+        assert_eq!(DensityUnit::try_from(crate::jpeg::DensityUnit::DotsPerInch), Ok(DensityUnit::DotsPerInch));
+        assert_eq!(DensityUnit::try_from(crate::jpeg::DensityUnit::DotsPerCentimeter), Ok(DensityUnit::DotsPerCentimeter));
+        assert_eq!(
+            DensityUnit::try_from(crate::jpeg::DensityUnit::NoUnit),
+            Err(ImageInfoError::NoDensityUnit),
+        );
+        assert_eq!(
+            DensityUnit::try_from(crate::jpeg::DensityUnit::Other(7)),
+            Err(ImageInfoError::UnknownDensityUnit(7)),
+        );
+    }
+
+    fn annotation_with_typography(font_size: f64, leading: f64, character_spacing: f64) -> Annotation {
+        Annotation {
+            left: 10,
+            bottom: 20,
+            font_size: NonZeroPositiveF64::new(font_size).unwrap(),
+            leading: FiniteF64::new(leading).unwrap(),
+            elements: vec![
+                TextChunk {
+                    text: "Hello".to_owned(),
+                    font_variant: FontVariant::Regular,
+                    character_spacing: FiniteF64::new(character_spacing).unwrap(),
+                    word_spacing: FiniteF64::new(0.0).unwrap(),
+                    horizontal_scale: None,
+                    text_rise: None,
+                    language: None,
+                    alternate_text: None,
+                    actual_text: None,
+                    expansion: None,
+                },
+            ],
+            order: None,
+            block_role: None,
+        }
+    }
+
+    #[test]
+    fn checks_typography() {
+        assert_eq!(annotation_with_typography(12.0, -14.0, 0.5).check_typography(), Ok(()));
+        assert_eq!(annotation_with_typography(MAX_FONT_SIZE_PT, MAX_SPACING_PT, -MAX_SPACING_PT).check_typography(), Ok(()));
+
+        assert_eq!(
+            annotation_with_typography(5000.0, 0.0, 0.0).check_typography(),
+            Err(TypographyError::FontSizeTooLarge(NonZeroPositiveF64::new(5000.0).unwrap())),
+        );
+        assert_eq!(
+            annotation_with_typography(12.0, -2000.0, 0.0).check_typography(),
+            Err(TypographyError::LeadingOutOfRange(FiniteF64::new(-2000.0).unwrap())),
+        );
+        assert_eq!(
+            annotation_with_typography(12.0, 0.0, 2000.0).check_typography(),
+            Err(TypographyError::CharacterSpacingOutOfRange { chunk: 0, value: FiniteF64::new(2000.0).unwrap() }),
+        );
+    }
+
+    #[test]
+    fn finds_common_language() {
+        fn in_languages(languages: &[Option<&str>]) -> Annotation {
+            let mut annotation = annotation_with_typography(12.0, 0.0, 0.0);
+            let chunk = annotation.elements.pop().unwrap();
+            for language in languages {
+                annotation.elements.push(TextChunk {
+                    language: language.map(|l| l.to_owned()),
+                    ..chunk.clone()
+                });
+            }
+            annotation
+        }
+
+        assert_eq!(in_languages(&[Some("de")]).common_language(), Some("de"));
+        assert_eq!(in_languages(&[Some("de-AT"), Some("DE-at")]).common_language(), Some("de-AT"));
+        assert_eq!(in_languages(&[Some("en"), Some("de")]).common_language(), None);
+        assert_eq!(in_languages(&[Some("de"), None]).common_language(), None);
+        assert_eq!(in_languages(&[None]).common_language(), None);
+        assert_eq!(in_languages(&[]).common_language(), None);
+    }
+
+    #[test]
+    fn resets_scaling_and_rise_after_chunk() {
+        let mut annotation = annotation_with_typography(12.0, 0.0, 0.0);
+        let mut raised = annotation.elements[0].clone();
+        raised.horizontal_scale = Some(85);
+        raised.text_rise = Some(-3);
+        annotation.elements.insert(0, raised);
+
+        let mut commands = Vec::new();
+        annotation.write_drawing_commands(&mut commands, &DrawingContext::default()).unwrap();
+        let commands = String::from_utf8_lossy(&commands);
+        let scale_at = commands.find(" 85 Tz").unwrap();
+        let rise_at = commands.find(" -3 Ts").unwrap();
+        let scale_reset_at = commands.find(" 100 Tz").unwrap();
+        let rise_reset_at = commands.find(" 0 Ts").unwrap();
+        let first_text_at = commands.find("Tj").unwrap();
+        assert!(scale_at < first_text_at && rise_at < first_text_at, "{}", commands);
+        assert!(first_text_at < scale_reset_at && first_text_at < rise_reset_at, "{}", commands);
+        // only the chunk setting them resets them
+        assert_eq!(commands.matches(" 100 Tz").count(), 1, "{}", commands);
+        assert_eq!(commands.matches(" 0 Ts").count(), 1, "{}", commands);
+    }
+
+    #[test]
+    fn checks_scaling_and_rise() {
+        let with_scaling_and_rise = |horizontal_scale, text_rise| {
+            let mut annotation = annotation_with_typography(12.0, 0.0, 0.0);
+            annotation.elements[0].horizontal_scale = horizontal_scale;
+            annotation.elements[0].text_rise = text_rise;
+            annotation.check_typography()
+        };
+
+        assert_eq!(with_scaling_and_rise(Some(85), Some(-3)), Ok(()));
+        assert_eq!(with_scaling_and_rise(None, None), Ok(()));
+        assert_eq!(
+            with_scaling_and_rise(Some(0), None),
+            Err(TypographyError::HorizontalScaleOutOfRange { chunk: 0, value: 0 }),
+        );
+        assert_eq!(
+            with_scaling_and_rise(Some(1001), None),
+            Err(TypographyError::HorizontalScaleOutOfRange { chunk: 0, value: 1001 }),
+        );
+        assert_eq!(
+            with_scaling_and_rise(None, Some(-2000)),
+            Err(TypographyError::TextRiseOutOfRange { chunk: 0, value: -2000 }),
+        );
+    }
+}
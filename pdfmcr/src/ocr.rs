@@ -0,0 +1,594 @@
+//! Importing the word boxes recognized by OCR engines as annotations.
+//!
+//! Two formats are supported: hOCR, an HTML document in which each recognized word is an element
+//! of class `ocrx_word` whose `title` contains its bounding box (`bbox x0 y0 x1 y1`), and ALTO, an
+//! XML document in which each recognized word is a `String` element with the attributes `CONTENT`,
+//! `HPOS`, `VPOS`, `WIDTH` and `HEIGHT`.
+//!
+//! Both formats measure from the top left corner of the image, with the vertical axis pointing
+//! downward; PDF measures from the bottom left corner of the page, with the vertical axis pointing
+//! upward. Each word becomes an annotation with a single text chunk placed at the bottom left
+//! corner of the word's box, with the height of the box as the font size.
+//!
+//! Only the little of HTML and XML that is required to find the words is understood; in
+//! particular, the documents are not validated.
+
+
+use std::fmt;
+
+use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+use crate::model::{Annotation, FontVariant, Page, TextChunk};
+
+
+/// An error that can occur while importing OCR output.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Error {
+    /// The document is neither hOCR nor ALTO.
+    UnknownFormat,
+
+    /// The document ends within a tag.
+    UnterminatedTag,
+
+    /// The box of a word cannot be parsed.
+    InvalidBox { value: String },
+
+    /// The ALTO document measures in an unknown unit.
+    UnknownUnit { unit: String },
+
+    /// The size of the page onto which the words are to be placed is unknown.
+    UnknownPageSize,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat
+                => write!(f, "document is neither hOCR nor ALTO"),
+            Self::UnterminatedTag
+                => write!(f, "document ends within a tag"),
+            Self::InvalidBox { value }
+                => write!(f, "invalid word box {:?}", value),
+            Self::UnknownUnit { unit }
+                => write!(f, "unknown measurement unit {:?}", unit),
+            Self::UnknownPageSize
+                => write!(f, "size of the page is unknown"),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+/// The unit in which the coordinates of OCR output are given.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum OcrUnit {
+    /// Pixels of the scanned image. Always the case for hOCR and the default for ALTO.
+    #[default]
+    Pixel,
+
+    /// Tenths of a millimeter (ALTO `mm10`).
+    TenthMillimeter,
+
+    /// 1/1200 in (ALTO `inch1200`).
+    Inch1200,
+}
+impl OcrUnit {
+    /// The number of points (1/72 in) per unit, if the unit has a fixed physical size.
+    pub fn points_per_unit(&self) -> Option<f64> {
+        match self {
+            Self::Pixel => None,
+            Self::TenthMillimeter => Some(72.0 / 254.0),
+            Self::Inch1200 => Some(72.0 / 1200.0),
+        }
+    }
+}
+
+
+/// A word recognized by an OCR engine.
+///
+/// Coordinates are given in the [`OcrUnit`] of the page, measured from the top left corner.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct OcrWord {
+    pub text: String,
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+
+/// A page of OCR output.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct OcrPage {
+    /// The unit in which all coordinates are given.
+    pub unit: OcrUnit,
+
+    /// The size of the page as `(width, height)`, if the document specifies it.
+    pub size: Option<(f64, f64)>,
+
+    /// The recognized words, in reading order.
+    pub words: Vec<OcrWord>,
+}
+impl OcrPage {
+    /// Converts the recognized words into annotations on the given page.
+    ///
+    /// The scale is derived from the size of the page in points and, in decreasing order of
+    /// preference, the size of the page given by the OCR output, the physical size of the unit, or
+    /// the pixel dimensions of the scanned image of the page. Words consisting only of whitespace
+    /// are skipped.
+    pub fn to_annotations(&self, page: &Page) -> Result<Vec<Annotation>, Error> {
+        let (Some(width_pt), Some(height_pt)) = (page.width_pt(), page.height_pt()) else {
+            return Err(Error::UnknownPageSize);
+        };
+
+        let (scale_x, scale_y) = if let Some((width, height)) = self.size.filter(|(w, h)| *w > 0.0 && *h > 0.0) {
+            (width_pt / width, height_pt / height)
+        } else if let Some(points_per_unit) = self.unit.points_per_unit() {
+            (points_per_unit, points_per_unit)
+        } else if let Some(scanned_image) = page.scanned_image.as_ref() {
+            (width_pt / f64::from(scanned_image.info.width), height_pt / f64::from(scanned_image.info.height))
+        } else {
+            return Err(Error::UnknownPageSize);
+        };
+
+        let mut annotations = Vec::with_capacity(self.words.len());
+        for word in &self.words {
+            let text = word.text.trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            // flip the vertical axis
+            let left = (word.left * scale_x).round().max(0.0);
+            let bottom = (height_pt - word.bottom * scale_y).round().max(0.0);
+            let font_size = NonZeroPositiveF64::new(((word.bottom - word.top) * scale_y).max(1.0))
+                .unwrap_or_else(|| NonZeroPositiveF64::new(1.0).unwrap());
+
+            annotations.push(Annotation {
+                left: left as u64,
+                bottom: bottom as u64,
+                font_size,
+                leading: FiniteF64::new(0.0).unwrap(),
+                elements: vec![TextChunk {
+                    text: text.to_owned(),
+                    font_variant: FontVariant::Regular,
+                    character_spacing: FiniteF64::new(0.0).unwrap(),
+                    word_spacing: FiniteF64::new(0.0).unwrap(),
+                    horizontal_scale: None,
+                    text_rise: None,
+                    language: None,
+                    alternate_text: None,
+                    actual_text: None,
+                    expansion: None,
+                }],
+                order: None,
+                block_role: None,
+            });
+        }
+        Ok(annotations)
+    }
+}
+
+
+/// Reads the first page of an hOCR or ALTO document, detecting the format from its contents.
+pub fn parse_ocr(text: &str) -> Result<OcrPage, Error> {
+    if text.contains("ocrx_word") || text.contains("ocr_page") {
+        parse_hocr(text)
+    } else if text.contains("<alto") {
+        parse_alto(text)
+    } else {
+        Err(Error::UnknownFormat)
+    }
+}
+
+
+/// Reads the words of the first page of an hOCR document.
+pub fn parse_hocr(text: &str) -> Result<OcrPage, Error> {
+    let mut page = OcrPage::default();
+    let mut pages_seen = 0;
+
+    // the word currently being read, and how many elements are open within it
+    let mut current_word: Option<(OcrWord, usize)> = None;
+
+    for token in Tokenizer::new(text) {
+        match token? {
+            Token::Text(t) => {
+                if let Some((word, _)) = current_word.as_mut() {
+                    word.text.push_str(&decode_entities(t));
+                }
+            },
+            Token::Tag(tag) => {
+                if let Some((_, depth)) = current_word.as_mut() {
+                    if tag.closing {
+                        if *depth == 0 {
+                            page.words.push(current_word.take().unwrap().0);
+                        } else {
+                            *depth -= 1;
+                        }
+                    } else if !tag.self_closing && !is_html_void_element(tag.name) {
+                        *depth += 1;
+                    }
+                    continue;
+                }
+                if tag.closing {
+                    continue;
+                }
+
+                let classes = tag.attribute("class").unwrap_or_default();
+                let title = tag.attribute("title").unwrap_or_default();
+                if has_class(&classes, "ocr_page") {
+                    pages_seen += 1;
+                    if pages_seen > 1 {
+                        break;
+                    }
+                    if let Some((left, top, right, bottom)) = parse_hocr_bbox(&title)? {
+                        page.size = Some((right - left, bottom - top));
+                    }
+                } else if has_class(&classes, "ocrx_word") {
+                    let Some((left, top, right, bottom)) = parse_hocr_bbox(&title)? else {
+                        return Err(Error::InvalidBox { value: title });
+                    };
+                    let word = OcrWord { text: String::new(), left, top, right, bottom };
+                    if tag.self_closing || is_html_void_element(tag.name) {
+                        page.words.push(word);
+                    } else {
+                        current_word = Some((word, 0));
+                    }
+                }
+            },
+        }
+    }
+    if let Some((word, _)) = current_word {
+        page.words.push(word);
+    }
+    Ok(page)
+}
+
+/// Obtains the `bbox` property from the `title` attribute of an hOCR element.
+fn parse_hocr_bbox(title: &str) -> Result<Option<(f64, f64, f64, f64)>, Error> {
+    for property in title.split(';') {
+        let mut pieces = property.split_whitespace();
+        if pieces.next() != Some("bbox") {
+            continue;
+        }
+        let coordinates: Vec<f64> = pieces
+            .map(|piece| piece.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| Error::InvalidBox { value: property.trim().to_owned() })?;
+        if coordinates.len() != 4 || coordinates[2] < coordinates[0] || coordinates[3] < coordinates[1] {
+            return Err(Error::InvalidBox { value: property.trim().to_owned() });
+        }
+        return Ok(Some((coordinates[0], coordinates[1], coordinates[2], coordinates[3])));
+    }
+    Ok(None)
+}
+
+fn has_class(classes: &str, class: &str) -> bool {
+    classes.split_ascii_whitespace().any(|c| c == class)
+}
+
+fn is_html_void_element(name: &str) -> bool {
+    const VOID_ELEMENTS: [&str; 14] = [
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+        "source", "track", "wbr",
+    ];
+    VOID_ELEMENTS.iter().any(|v| v.eq_ignore_ascii_case(name))
+}
+
+
+/// Reads the words of the first page of an ALTO document.
+pub fn parse_alto(text: &str) -> Result<OcrPage, Error> {
+    let mut page = OcrPage::default();
+    let mut pages_seen = 0;
+    let mut in_unit = false;
+
+    for token in Tokenizer::new(text) {
+        match token? {
+            Token::Text(t) => {
+                if in_unit {
+                    let unit = decode_entities(t);
+                    page.unit = match unit.trim() {
+                        "pixel" => OcrUnit::Pixel,
+                        "mm10" => OcrUnit::TenthMillimeter,
+                        "inch1200" => OcrUnit::Inch1200,
+                        other => return Err(Error::UnknownUnit { unit: other.to_owned() }),
+                    };
+                }
+            },
+            Token::Tag(tag) => {
+                // elements may be namespace-prefixed
+                let local_name = tag.name.rsplit(':').next().unwrap();
+                if tag.closing {
+                    if local_name == "MeasurementUnit" {
+                        in_unit = false;
+                    }
+                    continue;
+                }
+                match local_name {
+                    "MeasurementUnit" => {
+                        in_unit = !tag.self_closing;
+                    },
+                    "Page" => {
+                        pages_seen += 1;
+                        if pages_seen > 1 {
+                            break;
+                        }
+                        if let (Some(width), Some(height)) = (tag.attribute("WIDTH"), tag.attribute("HEIGHT")) {
+                            page.size = Some((parse_alto_length(&width)?, parse_alto_length(&height)?));
+                        }
+                    },
+                    "String" => {
+                        let left = parse_alto_length(&tag.attribute("HPOS").unwrap_or_default())?;
+                        let top = parse_alto_length(&tag.attribute("VPOS").unwrap_or_default())?;
+                        let width = parse_alto_length(&tag.attribute("WIDTH").unwrap_or_default())?;
+                        let height = parse_alto_length(&tag.attribute("HEIGHT").unwrap_or_default())?;
+                        page.words.push(OcrWord {
+                            text: tag.attribute("CONTENT").unwrap_or_default(),
+                            left,
+                            top,
+                            right: left + width,
+                            bottom: top + height,
+                        });
+                    },
+                    _ => {},
+                }
+            },
+        }
+    }
+    Ok(page)
+}
+
+fn parse_alto_length(value: &str) -> Result<f64, Error> {
+    value.trim().parse::<f64>().ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .ok_or_else(|| Error::InvalidBox { value: value.to_owned() })
+}
+
+
+/// A tag of an HTML or XML document.
+struct Tag<'a> {
+    name: &'a str,
+    attributes: Vec<(&'a str, &'a str)>,
+    closing: bool,
+    self_closing: bool,
+}
+impl<'a> Tag<'a> {
+    /// Returns the value of the attribute with the given name, with entities decoded.
+    ///
+    /// Attribute names are compared case-insensitively, as in HTML.
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.attributes.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| decode_entities(v))
+    }
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Tag(Tag<'a>),
+}
+
+/// Splits an HTML or XML document into text and tags, skipping comments, declarations and
+/// processing instructions.
+struct Tokenizer<'a> {
+    rest: &'a str,
+}
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { rest: text }
+    }
+
+    fn parse_tag(inner: &'a str) -> Tag<'a> {
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(i) => (true, i),
+            None => (false, inner),
+        };
+        let (self_closing, mut inner) = match inner.strip_suffix('/') {
+            Some(i) => (true, i),
+            None => (false, inner),
+        };
+
+        let name_end = inner.find(|c: char| c.is_whitespace()).unwrap_or(inner.len());
+        let name = &inner[..name_end];
+        inner = &inner[name_end..];
+
+        let mut attributes = Vec::new();
+        loop {
+            inner = inner.trim_start();
+            if inner.is_empty() {
+                break;
+            }
+            let name_end = inner.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(inner.len());
+            let attribute_name = &inner[..name_end];
+            inner = inner[name_end..].trim_start();
+            let Some(after_equals) = inner.strip_prefix('=') else {
+                // attribute without a value
+                attributes.push((attribute_name, ""));
+                continue;
+            };
+            inner = after_equals.trim_start();
+            let value = if let Some(quote) = inner.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                let value_end = inner[1..].find(quote).map(|i| i + 1).unwrap_or(inner.len());
+                let value = &inner[1..value_end];
+                inner = &inner[(value_end + 1).min(inner.len())..];
+                value
+            } else {
+                let value_end = inner.find(|c: char| c.is_whitespace()).unwrap_or(inner.len());
+                let value = &inner[..value_end];
+                inner = &inner[value_end..];
+                value
+            };
+            attributes.push((attribute_name, value));
+        }
+
+        Tag { name, attributes, closing, self_closing }
+    }
+}
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            if !self.rest.starts_with('<') {
+                let text_end = self.rest.find('<').unwrap_or(self.rest.len());
+                let text = &self.rest[..text_end];
+                self.rest = &self.rest[text_end..];
+                return Some(Ok(Token::Text(text)));
+            }
+
+            let terminator = if self.rest.starts_with("<!--") {
+                "-->"
+            } else if self.rest.starts_with("<![CDATA[") {
+                "]]>"
+            } else if self.rest.starts_with("<?") {
+                "?>"
+            } else {
+                ">"
+            };
+            let Some(end) = self.rest[1..].find(terminator).map(|i| i + 1) else {
+                self.rest = "";
+                return Some(Err(Error::UnterminatedTag));
+            };
+            let inner = &self.rest[1..end];
+            self.rest = &self.rest[end + terminator.len()..];
+            if inner.starts_with('!') || inner.starts_with('?') {
+                continue;
+            }
+            return Some(Ok(Token::Tag(Self::parse_tag(inner.trim()))));
+        }
+    }
+}
+
+
+/// Decodes the predefined and numeric character entities of HTML and XML.
+///
+/// Unknown entities are kept as they are.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(ampersand) = rest.find('&') {
+        decoded.push_str(&rest[..ampersand]);
+        rest = &rest[ampersand..];
+        let Some(semicolon) = rest.find(';').filter(|i| *i <= 10) else {
+            decoded.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..semicolon];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{A0}'),
+            _ => {
+                if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse().ok().and_then(char::from_u32)
+                } else {
+                    None
+                }
+            },
+        };
+        match character {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[semicolon + 1..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOCR: &str = concat!(
+        "<!DOCTYPE html>\n<html><body>\n",
+        "<div class='ocr_page' title='image \"scan.png\"; bbox 0 0 1224 1584'>\n",
+        "<span class='ocrx_word' title='bbox 100 200 300 240; x_wconf 95'>Tom &amp; <strong>Jerry</strong></span>\n",
+        "<span class='ocrx_word' title='bbox 320 200 400 240'>&#x263A;</span>\n",
+        "<span class='ocrx_word' title='bbox 420 200 500 240'> </span>\n",
+        "</div>\n",
+        "<div class='ocr_page' title='bbox 0 0 1224 1584'>\n",
+        "<span class='ocrx_word' title='bbox 1 2 3 4'>second page</span>\n",
+        "</div>\n",
+        "</body></html>\n",
+    );
+
+    const ALTO: &str = concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<alto:alto xmlns:alto=\"http://www.loc.gov/standards/alto/ns-v4#\">\n",
+        "<alto:Description><alto:MeasurementUnit>mm10</alto:MeasurementUnit></alto:Description>\n",
+        "<alto:Layout><alto:Page ID=\"p1\"><alto:PrintSpace>\n",
+        "<alto:String CONTENT=\"Grüß&quot;e\" HPOS=\"254\" VPOS=\"508\" WIDTH=\"127\" HEIGHT=\"50.8\"/>\n",
+        "</alto:PrintSpace></alto:Page></alto:Layout>\n",
+        "</alto:alto>\n",
+    );
+
+    #[test]
+    fn parses_first_page_of_hocr() {
+        let page = parse_ocr(HOCR).unwrap();
+        assert_eq!(page.unit, OcrUnit::Pixel);
+        assert_eq!(page.size, Some((1224.0, 1584.0)));
+        let texts: Vec<&str> = page.words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(texts, ["Tom & Jerry", "\u{263A}", " "]);
+        assert_eq!(
+            (page.words[0].left, page.words[0].top, page.words[0].right, page.words[0].bottom),
+            (100.0, 200.0, 300.0, 240.0),
+        );
+    }
+
+    #[test]
+    fn parses_alto() {
+        let page = parse_ocr(ALTO).unwrap();
+        assert_eq!(page.unit, OcrUnit::TenthMillimeter);
+        assert_eq!(page.size, None);
+        assert_eq!(
+            page.words,
+            [OcrWord { text: "Grüß\"e".to_owned(), left: 254.0, top: 508.0, right: 381.0, bottom: 558.8 }],
+        );
+    }
+
+    #[test]
+    fn places_words_on_page() {
+        // the hOCR page is twice the size of the PDF page
+        let page = Page::new_text_only(612.0, 792.0);
+        let annotations = parse_ocr(HOCR).unwrap().to_annotations(&page).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!((annotations[0].left, annotations[0].bottom), (50, 672));
+        assert_eq!(annotations[0].font_size.get(), 20.0);
+        assert_eq!(annotations[0].elements[0].text, "Tom & Jerry");
+
+        // 1 in from the left, 2 in from the top, 0.2 in high
+        let annotations = parse_ocr(ALTO).unwrap().to_annotations(&page).unwrap();
+        assert_eq!((annotations[0].left, annotations[0].bottom), (72, 792 - 158));
+        assert!((annotations[0].font_size.get() - 14.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn refuses_unusable_documents() {
+        assert_eq!(parse_ocr("just some text"), Err(Error::UnknownFormat));
+        assert_eq!(parse_ocr("<div class='ocr_page'"), Err(Error::UnterminatedTag));
+        assert_eq!(
+            parse_ocr("<span class='ocrx_word' title='bbox 3 4 1 2'>x</span>"),
+            Err(Error::InvalidBox { value: "bbox 3 4 1 2".to_owned() }),
+        );
+        assert_eq!(
+            parse_ocr("<alto><MeasurementUnit>furlong</MeasurementUnit></alto>"),
+            Err(Error::UnknownUnit { unit: "furlong".to_owned() }),
+        );
+    }
+}
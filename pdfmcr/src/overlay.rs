@@ -0,0 +1,386 @@
+//! A line-based text format for the annotations of a page.
+//!
+//! The format is meant to be written and compared by humans. Each line is a record consisting of
+//! fields separated by tab characters (shown as two spaces below); the first field names the kind
+//! of record:
+//!
+//! ```text
+//! annotation  72  700  12  0  role=Heading1
+//! chunk  Bold  Chapter One
+//! annotation  72  680  10.5  1.5
+//! chunk  Regular  It was a dark and stormy night;  lang=en
+//! chunk  Italic  la nuit  lang=fr  alt=the night
+//! ```
+//!
+//! An `annotation` record has the fields left, bottom, font size and leading, in points (1/72 in),
+//! followed by optional `order=` and `role=` attributes. It is followed by one `chunk` record per
+//! text chunk, with the fields font variant and text, followed by optional `cs=` (character
+//! spacing), `ws=` (word spacing), `hs=` (horizontal scaling, in percent), `rise=` (text rise),
+//! `lang=`, `alt=` (alternate text), `actual=` (actual text) and `exp=` (expansion) attributes.
+//!
+//! Within each field, a backslash, a tab, a line feed and a carriage return are written as `\\`,
+//! `\t`, `\n` and `\r`, respectively; no other escapes exist. Empty lines and lines starting with
+//! `#` are ignored.
+
+
+use std::fmt::{self, Write};
+
+use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+use crate::model::{Annotation, BlockRole, FontVariant, TextChunk};
+
+
+/// An error that can occur while reading annotations in the text overlay format.
+///
+/// Line numbers start at 1.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Error {
+    /// The line starts with an unknown kind of record.
+    UnknownRecord { line: usize, kind: String },
+
+    /// A `chunk` record appears before the first `annotation` record.
+    ChunkWithoutAnnotation { line: usize },
+
+    /// A required field is missing.
+    MissingField { line: usize, field: &'static str },
+
+    /// The value of a field cannot be parsed.
+    InvalidValue { line: usize, field: &'static str, value: String },
+
+    /// An optional field is not of the form `key=value` or its key is unknown.
+    UnknownAttribute { line: usize, attribute: String },
+
+    /// A field contains a backslash that does not start a valid escape sequence.
+    InvalidEscape { line: usize },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownRecord { line, kind }
+                => write!(f, "line {}: unknown record kind {:?}", line, kind),
+            Self::ChunkWithoutAnnotation { line }
+                => write!(f, "line {}: chunk outside of an annotation", line),
+            Self::MissingField { line, field }
+                => write!(f, "line {}: missing {}", line, field),
+            Self::InvalidValue { line, field, value }
+                => write!(f, "line {}: invalid {} {:?}", line, field, value),
+            Self::UnknownAttribute { line, attribute }
+                => write!(f, "line {}: unknown attribute {:?}", line, attribute),
+            Self::InvalidEscape { line }
+                => write!(f, "line {}: invalid escape sequence", line),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+fn font_variant_name(font_variant: FontVariant) -> &'static str {
+    match font_variant {
+        FontVariant::Regular => "Regular",
+        FontVariant::Italic => "Italic",
+        FontVariant::Bold => "Bold",
+        FontVariant::BoldItalic => "BoldItalic",
+    }
+}
+
+fn block_role_name(block_role: BlockRole) -> &'static str {
+    match block_role {
+        BlockRole::Paragraph => "Paragraph",
+        BlockRole::Heading1 => "Heading1",
+        BlockRole::Heading2 => "Heading2",
+        BlockRole::Heading3 => "Heading3",
+        BlockRole::Heading4 => "Heading4",
+        BlockRole::Heading5 => "Heading5",
+        BlockRole::Heading6 => "Heading6",
+        BlockRole::ListItem => "ListItem",
+        BlockRole::BlockQuote => "BlockQuote",
+        BlockRole::Caption => "Caption",
+    }
+}
+
+const ALL_BLOCK_ROLES: [BlockRole; 10] = [
+    BlockRole::Paragraph,
+    BlockRole::Heading1, BlockRole::Heading2, BlockRole::Heading3,
+    BlockRole::Heading4, BlockRole::Heading5, BlockRole::Heading6,
+    BlockRole::ListItem, BlockRole::BlockQuote, BlockRole::Caption,
+];
+
+
+fn escape_into(value: &str, output: &mut String) {
+    for c in value.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            other => output.push(other),
+        }
+    }
+}
+
+fn unescape(field: &str, line: usize) -> Result<String, Error> {
+    let mut value = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => value.push('\\'),
+            Some('t') => value.push('\t'),
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            _ => return Err(Error::InvalidEscape { line }),
+        }
+    }
+    Ok(value)
+}
+
+fn push_attribute(key: &str, value: &str, output: &mut String) {
+    output.push('\t');
+    output.push_str(key);
+    output.push('=');
+    escape_into(value, output);
+}
+
+
+/// Writes annotations in the text overlay format.
+pub fn annotations_to_overlay(annotations: &[Annotation]) -> String {
+    let mut output = String::new();
+    for annotation in annotations {
+        write!(
+            output, "annotation\t{}\t{}\t{}\t{}",
+            annotation.left, annotation.bottom, annotation.font_size.get(), annotation.leading.get(),
+        ).unwrap();
+        if let Some(order) = annotation.order {
+            push_attribute("order", &order.to_string(), &mut output);
+        }
+        if let Some(block_role) = annotation.block_role {
+            push_attribute("role", block_role_name(block_role), &mut output);
+        }
+        output.push('\n');
+
+        for chunk in &annotation.elements {
+            output.push_str("chunk\t");
+            output.push_str(font_variant_name(chunk.font_variant));
+            output.push('\t');
+            escape_into(&chunk.text, &mut output);
+            if chunk.character_spacing.get() != 0.0 {
+                push_attribute("cs", &chunk.character_spacing.get().to_string(), &mut output);
+            }
+            if chunk.word_spacing.get() != 0.0 {
+                push_attribute("ws", &chunk.word_spacing.get().to_string(), &mut output);
+            }
+            if let Some(horizontal_scale) = chunk.horizontal_scale {
+                push_attribute("hs", &horizontal_scale.to_string(), &mut output);
+            }
+            if let Some(text_rise) = chunk.text_rise {
+                push_attribute("rise", &text_rise.to_string(), &mut output);
+            }
+            let text_attributes = [
+                ("lang", &chunk.language),
+                ("alt", &chunk.alternate_text),
+                ("actual", &chunk.actual_text),
+                ("exp", &chunk.expansion),
+            ];
+            for (key, value) in text_attributes {
+                if let Some(value) = value {
+                    push_attribute(key, value, &mut output);
+                }
+            }
+            output.push('\n');
+        }
+    }
+    output
+}
+
+
+fn required_field<'a, I: Iterator<Item = &'a str>>(fields: &mut I, line: usize, field: &'static str) -> Result<&'a str, Error> {
+    fields.next()
+        .ok_or(Error::MissingField { line, field })
+}
+
+fn parse_finite(value: &str, line: usize, field: &'static str) -> Result<FiniteF64, Error> {
+    value.parse().ok()
+        .and_then(FiniteF64::new)
+        .ok_or_else(|| Error::InvalidValue { line, field, value: value.to_owned() })
+}
+
+fn parse_attribute(field: &str, line: usize) -> Result<(&str, String), Error> {
+    let Some((key, raw_value)) = field.split_once('=') else {
+        return Err(Error::UnknownAttribute { line, attribute: field.to_owned() });
+    };
+    Ok((key, unescape(raw_value, line)?))
+}
+
+fn parse_annotation<'a, I: Iterator<Item = &'a str>>(mut fields: I, line: usize) -> Result<Annotation, Error> {
+    let left_str = required_field(&mut fields, line, "left")?;
+    let left = left_str.parse()
+        .map_err(|_| Error::InvalidValue { line, field: "left", value: left_str.to_owned() })?;
+    let bottom_str = required_field(&mut fields, line, "bottom")?;
+    let bottom = bottom_str.parse()
+        .map_err(|_| Error::InvalidValue { line, field: "bottom", value: bottom_str.to_owned() })?;
+    let font_size_str = required_field(&mut fields, line, "font size")?;
+    let font_size = font_size_str.parse().ok()
+        .and_then(NonZeroPositiveF64::new)
+        .ok_or_else(|| Error::InvalidValue { line, field: "font size", value: font_size_str.to_owned() })?;
+    let leading = parse_finite(required_field(&mut fields, line, "leading")?, line, "leading")?;
+
+    let mut annotation = Annotation {
+        left,
+        bottom,
+        font_size,
+        leading,
+        elements: Vec::new(),
+        order: None,
+        block_role: None,
+    };
+    for field in fields {
+        let (key, value) = parse_attribute(field, line)?;
+        match key {
+            "order" => {
+                let order = value.parse()
+                    .map_err(|_| Error::InvalidValue { line, field: "order", value: value.clone() })?;
+                annotation.order = Some(order);
+            },
+            "role" => {
+                let block_role = ALL_BLOCK_ROLES.into_iter()
+                    .find(|role| block_role_name(*role) == value)
+                    .ok_or_else(|| Error::InvalidValue { line, field: "role", value: value.clone() })?;
+                annotation.block_role = Some(block_role);
+            },
+            _ => return Err(Error::UnknownAttribute { line, attribute: field.to_owned() }),
+        }
+    }
+    Ok(annotation)
+}
+
+fn parse_chunk<'a, I: Iterator<Item = &'a str>>(mut fields: I, line: usize) -> Result<TextChunk, Error> {
+    let font_variant_str = required_field(&mut fields, line, "font variant")?;
+    let font_variant = FontVariant::ALL.into_iter()
+        .find(|variant| font_variant_name(*variant) == font_variant_str)
+        .ok_or_else(|| Error::InvalidValue { line, field: "font variant", value: font_variant_str.to_owned() })?;
+    let text = unescape(required_field(&mut fields, line, "text")?, line)?;
+
+    let mut chunk = TextChunk {
+        text,
+        font_variant,
+        character_spacing: FiniteF64::new(0.0).unwrap(),
+        word_spacing: FiniteF64::new(0.0).unwrap(),
+        horizontal_scale: None,
+        text_rise: None,
+        language: None,
+        alternate_text: None,
+        actual_text: None,
+        expansion: None,
+    };
+    for field in fields {
+        let (key, value) = parse_attribute(field, line)?;
+        match key {
+            "cs" => chunk.character_spacing = parse_finite(&value, line, "character spacing")?,
+            "ws" => chunk.word_spacing = parse_finite(&value, line, "word spacing")?,
+            "hs" => {
+                let horizontal_scale = value.parse()
+                    .map_err(|_| Error::InvalidValue { line, field: "horizontal scaling", value: value.clone() })?;
+                chunk.horizontal_scale = Some(horizontal_scale);
+            },
+            "rise" => {
+                let text_rise = value.parse()
+                    .map_err(|_| Error::InvalidValue { line, field: "text rise", value: value.clone() })?;
+                chunk.text_rise = Some(text_rise);
+            },
+            "lang" => chunk.language = Some(value),
+            "alt" => chunk.alternate_text = Some(value),
+            "actual" => chunk.actual_text = Some(value),
+            "exp" => chunk.expansion = Some(value),
+            _ => return Err(Error::UnknownAttribute { line, attribute: field.to_owned() }),
+        }
+    }
+    Ok(chunk)
+}
+
+/// Reads annotations in the text overlay format.
+///
+/// Languages are not validated; callers accepting annotations from outside should check them as
+/// they would check annotations from any other source.
+pub fn annotations_from_overlay(text: &str) -> Result<Vec<Annotation>, Error> {
+    let mut annotations: Vec<Annotation> = Vec::new();
+    for (line_index, line_text) in text.lines().enumerate() {
+        let line = line_index + 1;
+        if line_text.is_empty() || line_text.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line_text.split('\t');
+        let kind = fields.next().unwrap();
+        match kind {
+            "annotation" => {
+                annotations.push(parse_annotation(fields, line)?);
+            },
+            "chunk" => {
+                let Some(annotation) = annotations.last_mut() else {
+                    return Err(Error::ChunkWithoutAnnotation { line });
+                };
+                annotation.elements.push(parse_chunk(fields, line)?);
+            },
+            other => return Err(Error::UnknownRecord { line, kind: other.to_owned() }),
+        }
+    }
+    Ok(annotations)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = concat!(
+        "annotation\t72\t700\t12\t0\torder=1\trole=Heading1\n",
+        "chunk\tBold\tChapter One\ths=85\trise=-3\n",
+        "annotation\t72\t680\t10.5\t1.5\n",
+        "chunk\tRegular\tIt was a dark\\tand \\\\stormy\\n night;\tws=0.25\tlang=en\n",
+        "chunk\tItalic\tla nuit\tcs=-0.5\tlang=fr\talt=the night\tactual=la nuit\texp=la nuit\n",
+    );
+
+    #[test]
+    fn round_trips_overlay_text() {
+        let annotations = annotations_from_overlay(SAMPLE).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].order, Some(1));
+        assert_eq!(annotations[0].block_role, Some(BlockRole::Heading1));
+        assert_eq!(annotations[0].elements[0].horizontal_scale, Some(85));
+        assert_eq!(annotations[0].elements[0].text_rise, Some(-3));
+        assert_eq!(annotations[1].elements[0].text, "It was a dark\tand \\stormy\n night;");
+        assert_eq!(annotations[1].elements[1].language.as_deref(), Some("fr"));
+        assert_eq!(annotations_to_overlay(&annotations), SAMPLE);
+    }
+
+    #[test]
+    fn skips_comments_and_empty_lines() {
+        let annotations = annotations_from_overlay("# a comment\n\nannotation\t1\t2\t3\t0\n").unwrap();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].elements, []);
+    }
+
+    #[test]
+    fn refuses_malformed_input() {
+        assert_eq!(
+            annotations_from_overlay("chunk\tRegular\tloose\n"),
+            Err(Error::ChunkWithoutAnnotation { line: 1 }),
+        );
+        assert_eq!(
+            annotations_from_overlay("annotation\t1\t2\t0\t0\n"),
+            Err(Error::InvalidValue { line: 1, field: "font size", value: "0".to_owned() }),
+        );
+        assert_eq!(
+            annotations_from_overlay("annotation\t1\t2\t3\t0\tcolor=red\n"),
+            Err(Error::UnknownAttribute { line: 1, attribute: "color=red".to_owned() }),
+        );
+        assert_eq!(
+            annotations_from_overlay("annotation\t1\t2\t3\t0\nchunk\tRegular\tbad \\x escape\n"),
+            Err(Error::InvalidEscape { line: 2 }),
+        );
+    }
+}
@@ -6,32 +6,137 @@ use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+use strict_num::FiniteF64;
+use time::OffsetDateTime;
+
 
 /// The ID of a PDF object.
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct PdfId(pub u64);
 
 
+/// A version of the Portable Document Format.
+///
+/// Versions older than 1.4 are not supported, as they lack features (e.g. the document language)
+/// that pdfmcr relies upon.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum PdfVersion {
+    #[serde(rename = "1.4")] V1_4,
+    #[default]
+    #[serde(rename = "1.5")] V1_5,
+    #[serde(rename = "1.6")] V1_6,
+    #[serde(rename = "1.7")] V1_7,
+    #[serde(rename = "2.0")] V2_0,
+}
+impl PdfVersion {
+    /// All supported versions, from oldest to newest.
+    pub const ALL: [PdfVersion; 5] = [Self::V1_4, Self::V1_5, Self::V1_6, Self::V1_7, Self::V2_0];
+
+    /// The version number as it appears in the file header.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::V1_4 => "1.4",
+            Self::V1_5 => "1.5",
+            Self::V1_6 => "1.6",
+            Self::V1_7 => "1.7",
+            Self::V2_0 => "2.0",
+        }
+    }
+}
+
+
+/// The comment conventionally written into the second line of a PDF file, without the leading `%`.
+///
+/// Its bytes have the high bit set, which makes tools treat the file as binary data.
+pub const DEFAULT_BINARY_COMMENT: &[u8] = b"\xE2\xE3\xCF\xD3";
+
+/// The number of bytes with the high bit set that a header comment must contain to mark the file as
+/// binary data.
+const MIN_BINARY_COMMENT_HIGH_BYTES: usize = 4;
+
+
 /// A document in Portable Document Format.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Document {
+    /// The version of PDF to which the document conforms.
+    pub version: PdfVersion,
+
+    /// The comment written into the header following the version, without the leading `%`.
+    ///
+    /// If not set, [`DEFAULT_BINARY_COMMENT`] is written. If the comment contains fewer than four
+    /// bytes with the high bit set, [`DEFAULT_BINARY_COMMENT`] is written on a line of its own
+    /// before it, so that the file is still recognized as binary data. The comment must not
+    /// contain line breaks.
+    pub header_comment: Option<Vec<u8>>,
+
     /// A mapping from IDs to objects.
     ///
     /// Generation numbers are always 0 in this simplified implementation.
     pub objects: BTreeMap<PdfId, Content>,
 }
 impl Document {
+    /// Writes the document to a seekable writer, starting at its current position.
+    ///
+    /// The output is identical to that of [`Document::write_pdf_to_stream`]. In particular, if the
+    /// writer is not positioned at its start, all the offsets (including `startxref`) are relative
+    /// to the position at which the document begins, so the document can be extracted from the
+    /// surrounding data as-is.
     pub fn write_pdf<W: Write + Seek>(&self, writer: &mut W) -> Result<(), io::Error> {
-        let pdf_start_pos = writer.stream_position()?;
+        // all offsets within the file are relative to its start, so we don't actually need to seek
+        self.write_pdf_to_stream(writer)
+    }
+
+    /// Writes the document to any writer, e.g. a network socket.
+    ///
+    /// The byte offsets required for the cross-reference table are obtained by counting the bytes
+    /// written.
+    pub fn write_pdf_to_stream<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let mut writer = CountingWriter::new(writer);
+        let writer = &mut writer;
+
+        // ensure we are not using features unavailable in the chosen PDF version
+        for (&id, data) in &self.objects {
+            let minimum_version = data.minimum_version();
+            if minimum_version > self.version {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "object {} requires PDF {} but the document is PDF {}",
+                        id.0, minimum_version.as_str(), self.version.as_str(),
+                    ),
+                ));
+            }
+        }
 
         // header (magic and binary detection comment line)
-        writer.write_all(b"%PDF-1.5\n%\xE2\xE3\xCF\xD3\n\n")?;
+        writeln!(writer, "%PDF-{}", self.version.as_str())?;
+        match self.header_comment.as_deref() {
+            None => {
+                write_pdf_comment(DEFAULT_BINARY_COMMENT, writer)?;
+            },
+            Some(comment) => {
+                if comment.contains(&b'\r') || comment.contains(&b'\n') {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "header comment contains a line break",
+                    ));
+                }
+                let high_byte_count = comment.iter()
+                    .filter(|b| **b >= 0x80)
+                    .count();
+                if high_byte_count < MIN_BINARY_COMMENT_HIGH_BYTES {
+                    write_pdf_comment(DEFAULT_BINARY_COMMENT, writer)?;
+                }
+                write_pdf_comment(comment, writer)?;
+            },
+        }
+        writer.write_all(b"\n")?;
 
         let mut xref_offsets = BTreeMap::new();
         for (&id, data) in &self.objects {
-            let object_start_abs = writer.stream_position()?;
-            xref_offsets.insert(id, object_start_abs - pdf_start_pos);
-            write!(writer, "{} 0 obj\n", id.0)?;
+            xref_offsets.insert(id, writer.count());
+            writeln!(writer, "{} 0 obj", id.0)?;
             data.write_content(writer)?;
             writer.write_all(b"\nendobj\n")?;
         }
@@ -41,17 +146,25 @@ impl Document {
             .max()
             .expect("no objects");
 
-        let xref_abs = writer.stream_position()?;
+        let xref_start = writer.count();
         writer.write_all(b"xref\n")?;
-        write!(writer, "0 {}\n", max_obj_id + 1)?;
-        let mut cur_obj_id = 0;
-        for (&id, &xref_offset) in &xref_offsets {
-            while cur_obj_id < id.0 {
-                write!(writer, "{:010} 65535 f\r\n", xref_offset)?;
-                cur_obj_id += 1;
+        writeln!(writer, "0 {}", max_obj_id + 1)?;
+        // free entries form a linked list starting at object 0; each one points to the next free
+        // object number (or back to 0 at the end of the list)
+        let free_obj_ids: Vec<u64> = (1..max_obj_id)
+            .filter(|&obj_id| !xref_offsets.contains_key(&PdfId(obj_id)))
+            .collect();
+        let next_free_obj_id = |obj_id: u64| -> u64 {
+            free_obj_ids.iter()
+                .copied()
+                .find(|&free_obj_id| free_obj_id > obj_id)
+                .unwrap_or(0)
+        };
+        for obj_id in 0..=max_obj_id {
+            match xref_offsets.get(&PdfId(obj_id)) {
+                Some(xref_offset) => write!(writer, "{:010} 00000 n\r\n", xref_offset)?,
+                None => write!(writer, "{:010} 65535 f\r\n", next_free_obj_id(obj_id))?,
             }
-            write!(writer, "{:010} 00000 n\r\n", xref_offset)?;
-            cur_obj_id += 1;
         }
 
         let root_obj_id = self.objects.iter()
@@ -60,16 +173,61 @@ impl Document {
             .nth(0)
             .expect("no catalog object found");
 
+        let info_obj_id = self.objects.iter()
+            .filter(|(_id, data)| matches!(data, Content::Info(_)))
+            .map(|(id, _data)| *id)
+            .nth(0);
+
         writer.write_all(b"trailer\n")?;
-        write!(writer, "<</Size {}/Root {} 0 R>>\n", max_obj_id + 1, root_obj_id.0)?;
-        write!(writer, "startxref\n{}\n%%EOF\n", xref_abs - pdf_start_pos)?;
+        write!(writer, "<</Size {}/Root {} 0 R", max_obj_id + 1, root_obj_id.0)?;
+        if let Some(info_obj_id) = info_obj_id {
+            write!(writer, "/Info {} 0 R", info_obj_id.0)?;
+        }
+        writer.write_all(b">>\n")?;
+        write!(writer, "startxref\n{}\n%%EOF\n", xref_start)?;
         Ok(())
     }
 }
 
+/// A writer that counts the bytes written through it.
+#[derive(Debug)]
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes written so far.
+    pub fn count(&self) -> u64 { self.count }
+
+    pub fn into_inner(self) -> W { self.inner }
+}
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += u64::try_from(written).unwrap();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 /// A PDF object whose content can be written to a byte stream.
 pub trait Object {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error>;
+
+    /// The oldest PDF version supporting all the features used by this object.
+    fn minimum_version(&self) -> PdfVersion {
+        PdfVersion::V1_4
+    }
 }
 
 /// The contents of a PDF object.
@@ -81,6 +239,27 @@ pub enum Content {
     PageContents(PageContents),
     ImageXObject(ImageXObject),
     StandardFont(StandardFont),
+    CompositeFont(CompositeFont),
+    CidFont(CidFont),
+    FontDescriptor(FontDescriptor),
+    FontFile2(FontFile2),
+    CidToGidMap(CidToGidMap),
+    ToUnicodeCMap(ToUnicodeCMap),
+    TextAnnotation(TextAnnotation),
+    IccProfile(IccProfile),
+    OutputIntent(OutputIntent),
+    Info(Info),
+    XmpMetadata(XmpMetadata),
+    StructTreeRoot(StructTreeRoot),
+    StructElem(StructElem),
+    ParentTree(ParentTree),
+    ExtGState(ExtGState),
+
+    /// An object not modeled by this crate, given as the bytes of its serialized representation.
+    ///
+    /// The bytes are written verbatim between `obj` and `endobj`; they must form exactly one valid
+    /// PDF object (e.g. a dictionary or a stream).
+    Raw(Vec<u8>),
 }
 impl Object for Content {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
@@ -91,6 +270,49 @@ impl Object for Content {
             Self::PageContents(page_contents) => page_contents.write_content(writer),
             Self::ImageXObject(image_xobject) => image_xobject.write_content(writer),
             Self::StandardFont(font) => font.write_content(writer),
+            Self::CompositeFont(font) => font.write_content(writer),
+            Self::CidFont(font) => font.write_content(writer),
+            Self::FontDescriptor(descriptor) => descriptor.write_content(writer),
+            Self::FontFile2(font_file) => font_file.write_content(writer),
+            Self::CidToGidMap(map) => map.write_content(writer),
+            Self::ToUnicodeCMap(cmap) => cmap.write_content(writer),
+            Self::TextAnnotation(annotation) => annotation.write_content(writer),
+            Self::IccProfile(profile) => profile.write_content(writer),
+            Self::OutputIntent(intent) => intent.write_content(writer),
+            Self::Info(info) => info.write_content(writer),
+            Self::XmpMetadata(metadata) => metadata.write_content(writer),
+            Self::StructTreeRoot(root) => root.write_content(writer),
+            Self::StructElem(element) => element.write_content(writer),
+            Self::ParentTree(tree) => tree.write_content(writer),
+            Self::ExtGState(state) => state.write_content(writer),
+            Self::Raw(bytes) => writer.write_all(bytes),
+        }
+    }
+
+    fn minimum_version(&self) -> PdfVersion {
+        match self {
+            Self::Catalog(catalog) => catalog.minimum_version(),
+            Self::Pages(pages) => pages.minimum_version(),
+            Self::Page(page) => page.minimum_version(),
+            Self::PageContents(page_contents) => page_contents.minimum_version(),
+            Self::ImageXObject(image_xobject) => image_xobject.minimum_version(),
+            Self::StandardFont(font) => font.minimum_version(),
+            Self::CompositeFont(font) => font.minimum_version(),
+            Self::CidFont(font) => font.minimum_version(),
+            Self::FontDescriptor(descriptor) => descriptor.minimum_version(),
+            Self::FontFile2(font_file) => font_file.minimum_version(),
+            Self::CidToGidMap(map) => map.minimum_version(),
+            Self::ToUnicodeCMap(cmap) => cmap.minimum_version(),
+            Self::TextAnnotation(annotation) => annotation.minimum_version(),
+            Self::IccProfile(profile) => profile.minimum_version(),
+            Self::OutputIntent(intent) => intent.minimum_version(),
+            Self::Info(info) => info.minimum_version(),
+            Self::XmpMetadata(metadata) => metadata.minimum_version(),
+            Self::StructTreeRoot(root) => root.minimum_version(),
+            Self::StructElem(element) => element.minimum_version(),
+            Self::ParentTree(tree) => tree.minimum_version(),
+            Self::ExtGState(state) => state.minimum_version(),
+            Self::Raw(_) => PdfVersion::V1_4,
         }
     }
 }
@@ -102,6 +324,19 @@ impl Object for Content {
 pub struct Catalog {
     pub root_pages_id: PdfId,
     pub lang: Option<String>,
+
+    /// The output intents of the document, describing the color characteristics of the devices on
+    /// which the document is intended to be reproduced.
+    pub output_intents: Vec<PdfId>,
+
+    /// The root of the structure tree; if set, the document is declared as a tagged PDF.
+    pub structure_tree_root: Option<PdfId>,
+
+    /// The XMP metadata stream of the document.
+    pub metadata: Option<PdfId>,
+
+    /// Whether viewers show the title of the document in their title bar instead of the file name.
+    pub display_doc_title: bool,
 }
 impl Object for Catalog {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
@@ -111,9 +346,35 @@ impl Object for Catalog {
             writer.write_all(b"/Lang")?;
             write_pdf_string(&lang, writer)?;
         }
+        if !self.output_intents.is_empty() {
+            writer.write_all(b"/OutputIntents[")?;
+            for (i, output_intent) in self.output_intents.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b" ")?;
+                }
+                write!(writer, "{} 0 R", output_intent.0)?;
+            }
+            writer.write_all(b"]")?;
+        }
+        if let Some(structure_tree_root) = self.structure_tree_root {
+            writer.write_all(b"/MarkInfo<</Marked true>>")?;
+            write!(writer, "/StructTreeRoot {} 0 R", structure_tree_root.0)?;
+        }
+        if let Some(metadata) = self.metadata {
+            write!(writer, "/Metadata {} 0 R", metadata.0)?;
+        }
+        if self.display_doc_title {
+            writer.write_all(b"/ViewerPreferences<</DisplayDocTitle true>>")?;
+        }
         writer.write_all(b">>")?;
         Ok(())
     }
+
+    fn minimum_version(&self) -> PdfVersion {
+        // the entries for tagged documents (MarkInfo, StructTreeRoot), output intents, metadata
+        // streams, the document language and DisplayDocTitle were all added in PDF 1.4
+        PdfVersion::V1_4
+    }
 }
 
 /// A Pages PDF object, a branch in the page tree.
@@ -124,6 +385,12 @@ impl Object for Catalog {
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Pages {
     pub children: Vec<PdfId>,
+
+    /// The number of leaf pages below this node.
+    ///
+    /// This equals the number of children only if all children are [`Page`] objects; if some of them
+    /// are [`Pages`] objects themselves, their pages are counted instead.
+    pub page_count: u64,
 }
 impl Object for Pages {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
@@ -141,7 +408,7 @@ impl Object for Pages {
         }
         writer.write_all(b"]")?;
 
-        write!(writer, "/Count {}", self.children.len())?;
+        write!(writer, "/Count {}", self.page_count)?;
 
         writer.write_all(b">>")?;
         Ok(())
@@ -159,19 +426,32 @@ pub struct Page {
     pub parent: PdfId,
 
     /// The width of the page, in points (1/72 in).
-    pub width_pt: u64,
+    pub width_pt: FiniteF64,
 
     /// The height of the page, in points (1/72 in).
-    pub height_pt: u64,
+    pub height_pt: FiniteF64,
 
-    /// The ID of the content stream describing the contents of this page.
-    pub contents: Option<PdfId>,
+    /// The IDs of the content streams describing the contents of this page.
+    ///
+    /// Multiple streams are concatenated in order, as if they were a single stream; a single stream
+    /// is referenced directly instead of through an array.
+    pub contents: Vec<PdfId>,
 
     /// Mapping of names to XObjects referenced by this page.
     pub xobject_refs: BTreeMap<String, PdfId>,
 
     /// Mapping of names to fonts referenced by this page.
     pub font_refs: BTreeMap<String, PdfId>,
+
+    /// Mapping of names to graphics state parameter dictionaries referenced by this page.
+    pub ext_g_state_refs: BTreeMap<String, PdfId>,
+
+    /// The IDs of the interactive annotations placed on this page.
+    pub annotations: Vec<PdfId>,
+
+    /// The key of this page in the [`ParentTree`], if its contents contain marked-content
+    /// sequences belonging to structure elements.
+    pub struct_parents: Option<u64>,
 }
 impl Object for Page {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
@@ -189,7 +469,15 @@ impl Object for Page {
         }
         if self.font_refs.len() > 0 {
             writer.write_all(b"/Font<<")?;
-            for (name, id) in &self.xobject_refs {
+            for (name, id) in &self.font_refs {
+                write_pdf_name(name, writer)?;
+                write!(writer, " {} 0 R", id.0)?;
+            }
+            writer.write_all(b">>")?;
+        }
+        if !self.ext_g_state_refs.is_empty() {
+            writer.write_all(b"/ExtGState<<")?;
+            for (name, id) in &self.ext_g_state_refs {
                 write_pdf_name(name, writer)?;
                 write!(writer, " {} 0 R", id.0)?;
             }
@@ -197,9 +485,39 @@ impl Object for Page {
         }
         writer.write_all(b">>")?;
 
-        write!(writer, "/MediaBox[0 0 {} {}]", self.width_pt, self.height_pt)?;
-        if let Some(contents) = self.contents {
-            write!(writer, "/Contents {} 0 R", contents.0)?;
+        write!(writer, "/MediaBox[0 0 {} {}]", self.width_pt.get(), self.height_pt.get())?;
+        match self.contents.as_slice() {
+            [] => {},
+            [contents] => write!(writer, "/Contents {} 0 R", contents.0)?,
+            contents => {
+                writer.write_all(b"/Contents[")?;
+                let mut first_contents = true;
+                for contents_id in contents {
+                    if first_contents {
+                        first_contents = false;
+                    } else {
+                        writer.write_all(b" ")?;
+                    }
+                    write!(writer, "{} 0 R", contents_id.0)?;
+                }
+                writer.write_all(b"]")?;
+            },
+        }
+        if !self.annotations.is_empty() {
+            writer.write_all(b"/Annots[")?;
+            let mut first_annotation = true;
+            for annotation_id in &self.annotations {
+                if first_annotation {
+                    first_annotation = false;
+                } else {
+                    writer.write_all(b" ")?;
+                }
+                write!(writer, "{} 0 R", annotation_id.0)?;
+            }
+            writer.write_all(b"]")?;
+        }
+        if let Some(struct_parents) = self.struct_parents {
+            write!(writer, "/StructParents {}", struct_parents)?;
         }
 
         writer.write_all(b">>")?;
@@ -212,8 +530,97 @@ impl Object for Page {
 pub struct PageContents {
     /// The drawing commands, in PDF's postfix operator notation.
     ///
-    /// Since we are using inline UTF-16 strings, it's better to consider this a binary string.
+    /// Since the strings shown are encoded for their fonts, it's better to consider this a binary
+    /// string.
     pub commands: Vec<u8>,
+
+    /// The oldest PDF version supporting all the operators and marked-content properties used by
+    /// the commands.
+    ///
+    /// The commands are opaque to the writer, so their producer has to declare this.
+    pub minimum_version: PdfVersion,
+}
+impl PageContents {
+    /// Renders the drawing commands in a human-readable form for debugging.
+    ///
+    /// Each operator is placed on its own line after its operands, with text objects, saved
+    /// graphics states and marked-content sequences indented. String literals encoded as UTF-16BE
+    /// are decoded. The stored commands are not modified and the result is not necessarily a valid
+    /// content stream.
+    pub fn to_debug_string(&self) -> String {
+        const OPENING_OPERATORS: [&str; 4] = ["BT", "q", "BDC", "BMC"];
+        const CLOSING_OPERATORS: [&str; 3] = ["ET", "Q", "EMC"];
+
+        let mut ret = String::new();
+        let mut depth: usize = 0;
+        let mut operands: Vec<String> = Vec::new();
+        let bytes = self.commands.as_slice();
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            if is_pdf_whitespace(b) {
+                i += 1;
+                continue;
+            }
+
+            let (token, next_i) = match b {
+                b'(' => {
+                    let (string_bytes, next_i) = read_pdf_literal_string(bytes, i);
+                    (debug_pdf_string(&string_bytes), next_i)
+                },
+                b'<' if bytes.get(i + 1) == Some(&b'<') => ("<<".to_owned(), i + 2),
+                b'>' if bytes.get(i + 1) == Some(&b'>') => (">>".to_owned(), i + 2),
+                b'<' => {
+                    let end = bytes[i..].iter()
+                        .position(|&c| c == b'>')
+                        .map(|p| i + p + 1)
+                        .unwrap_or(bytes.len());
+                    (String::from_utf8_lossy(&bytes[i..end]).into_owned(), end)
+                },
+                b'[' | b']' | b'{' | b'}' | b')' | b'>' => ((b as char).to_string(), i + 1),
+                _ => {
+                    // a name, a number or an operator; these end at white-space or a delimiter
+                    let start = if b == b'/' { i + 1 } else { i };
+                    let end = bytes[start..].iter()
+                        .position(|&c| is_pdf_whitespace(c) || is_pdf_delimiter(c))
+                        .map(|p| start + p)
+                        .unwrap_or(bytes.len());
+                    (String::from_utf8_lossy(&bytes[i..end]).into_owned(), end)
+                },
+            };
+            i = next_i;
+
+            let is_operator = token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '\'' || c == '"')
+                && token != "true" && token != "false" && token != "null";
+            if !is_operator {
+                operands.push(token);
+                continue;
+            }
+
+            if CLOSING_OPERATORS.contains(&token.as_str()) {
+                depth = depth.saturating_sub(1);
+            }
+            for _ in 0..depth {
+                ret.push_str("  ");
+            }
+            for operand in operands.drain(..) {
+                ret.push_str(&operand);
+                ret.push(' ');
+            }
+            ret.push_str(&token);
+            ret.push('\n');
+            if OPENING_OPERATORS.contains(&token.as_str()) {
+                depth += 1;
+            }
+        }
+
+        if !operands.is_empty() {
+            // dangling operands without an operator
+            ret.push_str(&operands.join(" "));
+            ret.push('\n');
+        }
+        ret
+    }
 }
 impl Object for PageContents {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
@@ -224,6 +631,43 @@ impl Object for PageContents {
         write_pdf_stream(&self.commands, writer)?;
         Ok(())
     }
+
+    fn minimum_version(&self) -> PdfVersion {
+        self.minimum_version
+    }
+}
+
+/// Parameters controlling how a filter decodes the data of a stream.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum FilterParameters {
+    /// Parameters of the `/CCITTFaxDecode` filter.
+    CcittFax {
+        /// The encoding scheme: negative for pure two-dimensional encoding (Group 4), zero for pure
+        /// one-dimensional encoding (Group 3, 1-D) and positive for mixed encoding (Group 3, 2-D).
+        k: i64,
+
+        /// The width of the image, in pixels.
+        columns: u64,
+
+        /// The height of the image, in pixels.
+        rows: u64,
+
+        /// Whether black pixels are decoded as 1 bits instead of 0 bits.
+        black_is_1: bool,
+    },
+}
+impl FilterParameters {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        match self {
+            Self::CcittFax { k, columns, rows, black_is_1 } => {
+                write!(writer, "<</K {}", k)?;
+                write!(writer, "/Columns {}", columns)?;
+                write!(writer, "/Rows {}", rows)?;
+                write!(writer, "/BlackIs1 {}", if *black_is_1 { "true" } else { "false" })?;
+                writer.write_all(b">>")
+            },
+        }
+    }
 }
 
 /// An external object (XObject) which is an image.
@@ -235,12 +679,34 @@ pub struct ImageXObject {
     /// The height of the image, in pixels.
     pub height: u64,
 
-    /// The PDF name of the color space of the image.
+    /// The PDF name of the color space of the image, including the leading slash.
+    ///
+    /// Ignored if `image_mask` is set, since image masks have no color space.
     pub color_space: &'static str,
 
+    /// The ID of the [`IccProfile`] with which the colors of the image are interpreted, if any.
+    ///
+    /// If set, the image has an ICC-based color space instead of `color_space`; they must have the
+    /// same number of components.
+    pub icc_profile: Option<PdfId>,
+
+    /// Whether the image is a stencil mask.
+    ///
+    /// An image mask has one bit per pixel and no color space; it is painted in the current fill
+    /// color wherever its samples are 0 (or 1, if `decode` is `[1, 0]`). This is useful for
+    /// stamping, e.g. watermarks.
+    pub image_mask: bool,
+
     /// The number of bits used to encode one color component of one pixel.
     pub bits_per_component: u8,
 
+    /// The decode array, mapping each color component's sample values to the range of values of the
+    /// color space, as pairs of minimum and maximum.
+    ///
+    /// An empty decode array retains the default mapping; e.g. `[1, 0, 1, 0, 1, 0, 1, 0]` inverts
+    /// all components of a CMYK image.
+    pub decode: Vec<i64>,
+
     /// Recommend that the image be interpolated when scaled.
     ///
     /// PDF viewers are free to ignore the value of this attribute.
@@ -249,24 +715,61 @@ pub struct ImageXObject {
     /// A list of PDF names of the filters applied to the image, in order.
     pub data_filters: Vec<String>,
 
+    /// The parameters of the filters in `data_filters`, in the same order.
+    ///
+    /// `None` (as well as a missing entry) retains the default parameters of the filter.
+    pub filter_parameters: Vec<Option<FilterParameters>>,
+
     /// The operating system path to the data of the image.
+    ///
+    /// The file is copied into the PDF verbatim when the object is written; it is not parsed, so
+    /// the remaining fields must already describe the image correctly.
     pub os_path: PathBuf,
 }
 impl Object for ImageXObject {
     fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
         // find the image file's size
         let mut image_file = File::open(&self.os_path)?;
-        let mut file_size = image_file.seek(SeekFrom::End(0))?;
+        let file_size = image_file.seek(SeekFrom::End(0))?;
         image_file.seek(SeekFrom::Start(0))?;
 
         writer.write_all(b"<</Type/XObject/Subtype/Image")?;
         write!(writer, "/Width {}", self.width)?;
         write!(writer, "/Height {}", self.height)?;
 
-        writer.write_all(b"/ColorSpace")?;
-        write_pdf_name(self.color_space, writer)?;
+        if self.image_mask {
+            if self.bits_per_component != 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("image masks must have 1 bit per component, not {}", self.bits_per_component),
+                ));
+            }
+            if !self.decode.is_empty() && self.decode != [0, 1] && self.decode != [1, 0] {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid decode array for image mask: {:?}", self.decode),
+                ));
+            }
+            writer.write_all(b"/ImageMask true")?;
+        } else if let Some(icc_profile) = self.icc_profile {
+            write!(writer, "/ColorSpace[/ICCBased {} 0 R]", icc_profile.0)?;
+        } else {
+            writer.write_all(b"/ColorSpace")?;
+            writer.write_all(self.color_space.as_bytes())?;
+        }
 
         write!(writer, "/BitsPerComponent {}", self.bits_per_component)?;
+
+        if !self.decode.is_empty() {
+            writer.write_all(b"/Decode[")?;
+            for (i, value) in self.decode.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b" ")?;
+                }
+                write!(writer, "{}", value)?;
+            }
+            writer.write_all(b"]")?;
+        }
         write!(writer, "/Interpolate {}", if self.interpolate { "true" } else { "false" })?;
 
         if self.data_filters.len() > 0 {
@@ -276,34 +779,38 @@ impl Object for ImageXObject {
             }
             writer.write_all(b"]")?;
         }
+        if self.filter_parameters.iter().any(|fp| fp.is_some()) {
+            writer.write_all(b"/DecodeParms[")?;
+            for i in 0..self.data_filters.len() {
+                if i > 0 {
+                    writer.write_all(b" ")?;
+                }
+                match self.filter_parameters.get(i) {
+                    Some(Some(parameters)) => parameters.write(writer)?,
+                    _ => writer.write_all(b"null")?,
+                }
+            }
+            writer.write_all(b"]")?;
+        }
 
         write!(writer, "/Length {}", file_size)?;
 
         writer.write_all(b">>")?;
-        writer.write_all(b"\nstream\n")?;
-
-        let mut buf = vec![0u8; 4*1024*1024];
-        let buf_size_u64: u64 = buf.len().try_into().unwrap();
-        while file_size > 0 {
-            let read_at_most_u64 = file_size.min(buf_size_u64);
-            let read_at_most: usize = read_at_most_u64.try_into().unwrap();
-
-            let actually_read = image_file.read(&mut buf[..read_at_most])?;
-            if actually_read == 0 {
-                // short read
-                return Err(io::ErrorKind::UnexpectedEof.into());
-            }
-
-            let actually_read_u64: u64 = actually_read.try_into().unwrap();
-            file_size -= actually_read_u64;
-
-            writer.write_all(&buf[..actually_read])?;
-        }
 
-        writer.write_all(b"\nendstream")?;
+        // stream the file contents straight into the output
+        write_pdf_stream_from_reader(&mut image_file, file_size, writer)?;
 
         Ok(())
     }
+
+    fn minimum_version(&self) -> PdfVersion {
+        if self.data_filters.iter().any(|f| f == "JPXDecode") {
+            // JPEG 2000 support was added in PDF 1.5
+            PdfVersion::V1_5
+        } else {
+            PdfVersion::V1_4
+        }
+    }
 }
 
 /// One of the standard 14 fonts.
@@ -317,11 +824,579 @@ impl Object for StandardFont {
         writer.write_all(b"<</Type/Font/Subtype/Type1")?;
         writer.write_all(b"/BaseFont")?;
         write_pdf_name(&self.name, writer)?;
+        writer.write_all(b"/Encoding/WinAnsiEncoding>>")?;
+        Ok(())
+    }
+}
+
+/// A composite font whose glyphs are selected by two-byte codes, used for text that cannot be
+/// encoded in WinAnsiEncoding.
+///
+/// The codes are mapped back to Unicode by a [`ToUnicodeCMap`], which keeps the text searchable.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CompositeFont {
+    /// The PDF name of the font.
+    pub name: String,
+
+    /// The CID font providing the glyphs.
+    pub descendant: PdfId,
+
+    /// The map from the codes to Unicode text.
+    pub to_unicode: PdfId,
+}
+impl Object for CompositeFont {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/Font/Subtype/Type0")?;
+        writer.write_all(b"/BaseFont")?;
+        write_pdf_name(&self.name, writer)?;
+        writer.write_all(b"/Encoding/Identity-H")?;
+        write!(writer, "/DescendantFonts[{} 0 R]", self.descendant.0)?;
+        write!(writer, "/ToUnicode {} 0 R", self.to_unicode.0)?;
+        writer.write_all(b">>")?;
+        Ok(())
+    }
+}
+
+/// The CID font below a [`CompositeFont`], whose glyphs are taken from an embedded TrueType font.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CidFont {
+    /// The PDF name of the font.
+    pub name: String,
+
+    /// The descriptor of the font, which references the embedded font program.
+    pub descriptor: PdfId,
+
+    /// The map from CIDs to the glyph IDs of the font program; see [`CidToGidMap`].
+    pub cid_to_gid_map: PdfId,
+
+    /// The width of glyphs not listed in `widths`, in thousandths of the font size.
+    pub default_width: u32,
+
+    /// The width of each glyph, starting at CID 1, in thousandths of the font size.
+    pub widths: Vec<u32>,
+}
+impl Object for CidFont {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/Font/Subtype/CIDFontType2")?;
+        writer.write_all(b"/BaseFont")?;
+        write_pdf_name(&self.name, writer)?;
+        writer.write_all(b"/CIDSystemInfo<</Registry(Adobe)/Ordering(Identity)/Supplement 0>>")?;
+        write!(writer, "/FontDescriptor {} 0 R", self.descriptor.0)?;
+        write!(writer, "/DW {}", self.default_width)?;
+        if !self.widths.is_empty() {
+            writer.write_all(b"/W[1[")?;
+            for (i, width) in self.widths.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b" ")?;
+                }
+                write!(writer, "{}", width)?;
+            }
+            writer.write_all(b"]]")?;
+        }
+        write!(writer, "/CIDToGIDMap {} 0 R", self.cid_to_gid_map.0)?;
+        writer.write_all(b">>")?;
+        Ok(())
+    }
+}
+
+/// The descriptor of an embedded TrueType font.
+///
+/// All dimensions are in thousandths of the font size.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FontDescriptor {
+    /// The PDF name of the font.
+    pub name: String,
+
+    /// The characteristics of the font, as a combination of the flags defined by the PDF
+    /// specification (e.g. 1 for fixed pitch, 4 for symbolic, 64 for italic).
+    pub flags: u32,
+
+    /// The bounding box of all glyphs, as `[left, bottom, right, top]`.
+    pub bounding_box: [i32; 4],
+
+    /// The angle of the vertical strokes, in degrees counterclockwise from the vertical.
+    pub italic_angle: i32,
+
+    /// The distance from the baseline to the top of the highest glyphs.
+    pub ascent: i32,
+
+    /// The distance from the baseline to the bottom of the lowest glyphs; usually negative.
+    pub descent: i32,
+
+    /// The height of capital letters.
+    pub cap_height: i32,
+
+    /// The thickness of the vertical stems of the glyphs.
+    pub stem_v: i32,
+
+    /// The embedded font program; see [`FontFile2`].
+    pub font_file: PdfId,
+}
+impl Object for FontDescriptor {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/FontDescriptor/FontName")?;
+        write_pdf_name(&self.name, writer)?;
+        write!(writer, "/Flags {}", self.flags)?;
+        write!(
+            writer, "/FontBBox[{} {} {} {}]",
+            self.bounding_box[0], self.bounding_box[1], self.bounding_box[2], self.bounding_box[3],
+        )?;
+        write!(writer, "/ItalicAngle {}", self.italic_angle)?;
+        write!(writer, "/Ascent {}", self.ascent)?;
+        write!(writer, "/Descent {}", self.descent)?;
+        write!(writer, "/CapHeight {}", self.cap_height)?;
+        write!(writer, "/StemV {}", self.stem_v)?;
+        write!(writer, "/FontFile2 {} 0 R", self.font_file.0)?;
         writer.write_all(b">>")?;
         Ok(())
     }
 }
 
+/// An embedded TrueType font program.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FontFile2 {
+    /// The data of the font program, as created by [`crate::truetype::Font::subset`].
+    pub data: Vec<u8>,
+}
+impl Object for FontFile2 {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        write!(writer, "<</Length {}/Length1 {}>>", self.data.len(), self.data.len())?;
+        write_pdf_stream(&self.data, writer)?;
+        Ok(())
+    }
+}
+
+/// The map from the CIDs of a [`CidFont`] to the glyph IDs of its embedded font program.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CidToGidMap {
+    /// The glyph ID of each CID, starting at CID 0.
+    pub glyph_ids: Vec<u16>,
+}
+impl Object for CidToGidMap {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let data: Vec<u8> = self.glyph_ids.iter()
+            .flat_map(|glyph_id| glyph_id.to_be_bytes())
+            .collect();
+        write!(writer, "<</Length {}>>", data.len())?;
+        write_pdf_stream(&data, writer)?;
+        Ok(())
+    }
+}
+
+/// A CMap mapping the two-byte codes of a [`CompositeFont`] to Unicode characters.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ToUnicodeCMap {
+    /// The character represented by each code.
+    pub mappings: BTreeMap<u16, char>,
+}
+impl ToUnicodeCMap {
+    /// The largest number of mappings in one `beginbfchar` section.
+    const MAX_SECTION_LENGTH: usize = 100;
+
+    fn cmap_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"/CIDInit /ProcSet findresource begin\n");
+        data.extend_from_slice(b"12 dict begin\n");
+        data.extend_from_slice(b"begincmap\n");
+        data.extend_from_slice(b"/CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n");
+        data.extend_from_slice(b"/CMapName /Adobe-Identity-UCS def\n");
+        data.extend_from_slice(b"/CMapType 2 def\n");
+        data.extend_from_slice(b"1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n");
+
+        let mappings: Vec<(&u16, &char)> = self.mappings.iter().collect();
+        for section in mappings.chunks(Self::MAX_SECTION_LENGTH) {
+            data.extend_from_slice(format!("{} beginbfchar\n", section.len()).as_bytes());
+            for (code, c) in section {
+                data.extend_from_slice(format!("<{:04X}> <", code).as_bytes());
+                let mut buf = [0u16; 2];
+                for word in c.encode_utf16(&mut buf) {
+                    data.extend_from_slice(format!("{:04X}", word).as_bytes());
+                }
+                data.extend_from_slice(b">\n");
+            }
+            data.extend_from_slice(b"endbfchar\n");
+        }
+
+        data.extend_from_slice(b"endcmap\n");
+        data.extend_from_slice(b"CMapName currentdict /CMap defineresource pop\n");
+        data.extend_from_slice(b"end\n");
+        data.extend_from_slice(b"end");
+        data
+    }
+}
+impl Object for ToUnicodeCMap {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let data = self.cmap_data();
+        write!(writer, "<</Length {}>>", data.len())?;
+        write_pdf_stream(&data, writer)?;
+        Ok(())
+    }
+}
+
+/// An interactive text annotation ("sticky note") on a page.
+///
+/// Not to be confused with [`crate::model::Annotation`], which is rendered into the content stream
+/// of the page.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TextAnnotation {
+    /// The rectangle on the page to which the annotation is attached, in points (1/72 in), as
+    /// `[left, bottom, right, top]`.
+    pub rect: [u64; 4],
+
+    /// The text displayed by the annotation.
+    pub contents: String,
+}
+impl Object for TextAnnotation {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/Annot/Subtype/Text")?;
+        write!(writer, "/Rect[{} {} {} {}]", self.rect[0], self.rect[1], self.rect[2], self.rect[3])?;
+        writer.write_all(b"/Contents")?;
+        write_pdf_string(&self.contents, writer)?;
+        writer.write_all(b"/Open false>>")?;
+        Ok(())
+    }
+}
+
+/// An embedded ICC color profile.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct IccProfile {
+    /// The number of color components described by the profile (1, 3 or 4).
+    pub components: u8,
+
+    /// The data of the profile, as defined by the ICC specification.
+    pub data: Vec<u8>,
+}
+impl Object for IccProfile {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<<")?;
+        write!(writer, "/N {}", self.components)?;
+        write!(writer, "/Length {}", self.data.len())?;
+        writer.write_all(b">>")?;
+
+        write_pdf_stream(&self.data, writer)?;
+        Ok(())
+    }
+}
+
+/// An output intent, describing the color characteristics of the device on which the document is
+/// intended to be reproduced.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct OutputIntent {
+    /// The subtype of the output intent, e.g. `GTS_PDFA1` for PDF/A.
+    pub subtype: String,
+
+    /// The identifier of the intended output condition, e.g. `sRGB IEC61966-2.1`.
+    pub output_condition_identifier: String,
+
+    /// The ICC profile describing the intended output condition.
+    pub dest_output_profile: PdfId,
+}
+impl Object for OutputIntent {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/OutputIntent/S")?;
+        write_pdf_name(&self.subtype, writer)?;
+        writer.write_all(b"/OutputConditionIdentifier")?;
+        write_pdf_string(&self.output_condition_identifier, writer)?;
+        write!(writer, "/DestOutputProfile {} 0 R", self.dest_output_profile.0)?;
+        writer.write_all(b">>")?;
+        Ok(())
+    }
+}
+
+/// A graphics state parameter dictionary, which can be applied using the `gs` operator.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ExtGState {
+    /// The opacity with which shapes and text are filled, in percent.
+    pub fill_opacity_percent: Option<u8>,
+}
+impl Object for ExtGState {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/ExtGState")?;
+        if let Some(fill_opacity_percent) = self.fill_opacity_percent {
+            write!(writer, "/ca {}", f64::from(fill_opacity_percent.min(100)) / 100.0)?;
+        }
+        writer.write_all(b">>")?;
+        Ok(())
+    }
+}
+
+/// The document information dictionary, containing metadata about the document.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Info {
+    /// The title of the document.
+    pub title: Option<String>,
+
+    /// The date and time at which the document was created.
+    pub creation_date: Option<OffsetDateTime>,
+
+    /// The date and time at which the document was most recently modified.
+    pub mod_date: Option<OffsetDateTime>,
+
+    /// The name of the software that produced the document.
+    pub producer: Option<String>,
+}
+impl Object for Info {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<<")?;
+        if let Some(title) = self.title.as_ref() {
+            writer.write_all(b"/Title")?;
+            write_pdf_string(title, writer)?;
+        }
+        if let Some(creation_date) = self.creation_date.as_ref() {
+            writer.write_all(b"/CreationDate")?;
+            write_pdf_date(creation_date, writer)?;
+        }
+        if let Some(mod_date) = self.mod_date.as_ref() {
+            writer.write_all(b"/ModDate")?;
+            write_pdf_date(mod_date, writer)?;
+        }
+        if let Some(producer) = self.producer.as_ref() {
+            writer.write_all(b"/Producer")?;
+            write_pdf_string(producer, writer)?;
+        }
+        writer.write_all(b">>")?;
+        Ok(())
+    }
+}
+
+/// The XMP metadata stream of a document, carrying the same information as its [`Info`]
+/// dictionary.
+///
+/// PDF/A requires both to agree; PDF 2.0 deprecates the information dictionary in favor of the
+/// metadata stream.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct XmpMetadata {
+    /// The information to store.
+    pub info: Info,
+}
+impl XmpMetadata {
+    fn xmp_data(&self) -> Vec<u8> {
+        let mut xml = String::new();
+        xml.push_str("<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+        xml.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+        xml.push_str("<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+        xml.push_str("<rdf:Description rdf:about=\"\"");
+        xml.push_str(" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"");
+        xml.push_str(" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"");
+        xml.push_str(" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\n");
+        if let Some(title) = self.info.title.as_ref() {
+            xml.push_str("<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">");
+            push_xml_escaped(&mut xml, title);
+            xml.push_str("</rdf:li></rdf:Alt></dc:title>\n");
+        }
+        if let Some(creation_date) = self.info.creation_date.as_ref() {
+            xml.push_str("<xmp:CreateDate>");
+            push_xmp_date(&mut xml, creation_date);
+            xml.push_str("</xmp:CreateDate>\n");
+        }
+        if let Some(mod_date) = self.info.mod_date.as_ref() {
+            xml.push_str("<xmp:ModifyDate>");
+            push_xmp_date(&mut xml, mod_date);
+            xml.push_str("</xmp:ModifyDate>\n");
+        }
+        if let Some(producer) = self.info.producer.as_ref() {
+            xml.push_str("<pdf:Producer>");
+            push_xml_escaped(&mut xml, producer);
+            xml.push_str("</pdf:Producer>\n");
+        }
+        xml.push_str("</rdf:Description>\n");
+        xml.push_str("</rdf:RDF>\n");
+        xml.push_str("</x:xmpmeta>\n");
+        xml.push_str("<?xpacket end=\"w\"?>");
+        xml.into_bytes()
+    }
+}
+impl Object for XmpMetadata {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let data = self.xmp_data();
+        write!(writer, "<</Type/Metadata/Subtype/XML/Length {}>>", data.len())?;
+        write_pdf_stream(&data, writer)?;
+        Ok(())
+    }
+}
+
+/// Appends the text to the XML string, escaping the characters with special meaning.
+fn push_xml_escaped(xml: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            other => xml.push(other),
+        }
+    }
+}
+
+/// Appends the date in the ISO 8601 format used by XMP to the XML string.
+fn push_xmp_date(xml: &mut String, date: &OffsetDateTime) {
+    xml.push_str(&format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        date.year(), u8::from(date.month()), date.day(),
+        date.hour(), date.minute(), date.second(),
+    ));
+    let offset = date.offset();
+    if offset.is_utc() {
+        xml.push('Z');
+    } else {
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        xml.push_str(&format!(
+            "{}{:02}:{:02}",
+            sign, offset.whole_hours().unsigned_abs(), offset.minutes_past_hour().unsigned_abs(),
+        ));
+    }
+}
+
+/// The root of the structure tree, which describes the logical structure of a tagged document.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StructTreeRoot {
+    /// The top-level structure elements.
+    pub kids: Vec<PdfId>,
+
+    /// The ID of the [`ParentTree`] of the document.
+    pub parent_tree: PdfId,
+
+    /// A key greater than all keys in the parent tree.
+    pub parent_tree_next_key: u64,
+}
+impl Object for StructTreeRoot {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/StructTreeRoot/K[")?;
+        for (i, kid) in self.kids.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b" ")?;
+            }
+            write!(writer, "{} 0 R", kid.0)?;
+        }
+        writer.write_all(b"]")?;
+        write!(writer, "/ParentTree {} 0 R", self.parent_tree.0)?;
+        write!(writer, "/ParentTreeNextKey {}", self.parent_tree_next_key)?;
+        writer.write_all(b">>")?;
+        Ok(())
+    }
+}
+
+/// A child of a structure element.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum StructKid {
+    /// Another structure element.
+    Element(PdfId),
+
+    /// A marked-content sequence, given by its marked-content identifier (MCID), on the page of the
+    /// parent structure element.
+    MarkedContent(u64),
+}
+
+/// A structure element, a node in the structure tree.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StructElem {
+    /// The structure type, e.g. `Document` or `P`, without the leading slash.
+    pub structure_type: String,
+
+    /// The ID of the parent structure element or of the [`StructTreeRoot`].
+    pub parent: PdfId,
+
+    /// The ID of the page on which the marked-content sequences among the kids are found.
+    pub page: Option<PdfId>,
+
+    /// The children of this element, in reading order.
+    pub kids: Vec<StructKid>,
+
+    /// The language of the content of this element, as a BCP 47 language tag.
+    ///
+    /// If not set, the language is inherited from the parent element and ultimately the catalog.
+    pub lang: Option<String>,
+}
+impl Object for StructElem {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Type/StructElem/S")?;
+        write_pdf_name(&self.structure_type, writer)?;
+        write!(writer, "/P {} 0 R", self.parent.0)?;
+        if let Some(page) = self.page {
+            write!(writer, "/Pg {} 0 R", page.0)?;
+        }
+        if let Some(lang) = self.lang.as_ref() {
+            writer.write_all(b"/Lang")?;
+            write_pdf_string(lang, writer)?;
+        }
+        writer.write_all(b"/K[")?;
+        for (i, kid) in self.kids.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b" ")?;
+            }
+            match kid {
+                StructKid::Element(id) => write!(writer, "{} 0 R", id.0)?,
+                StructKid::MarkedContent(mcid) => write!(writer, "{}", mcid)?,
+            }
+        }
+        writer.write_all(b"]>>")?;
+        Ok(())
+    }
+}
+
+/// The parent tree of a tagged document, mapping the marked-content sequences of each page to the
+/// structure elements they belong to.
+///
+/// This is a number tree consisting of a single node.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ParentTree {
+    /// For each `/StructParents` key of a page, the IDs of the structure elements owning the
+    /// marked-content sequences on the page, indexed by marked-content identifier (MCID).
+    pub entries: BTreeMap<u64, Vec<PdfId>>,
+}
+impl Object for ParentTree {
+    fn write_content<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(b"<</Nums[")?;
+        for (i, (key, elements)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b" ")?;
+            }
+            write!(writer, "{}[", key)?;
+            for (j, element) in elements.iter().enumerate() {
+                if j > 0 {
+                    writer.write_all(b" ")?;
+                }
+                write!(writer, "{} 0 R", element.0)?;
+            }
+            writer.write_all(b"]")?;
+        }
+        writer.write_all(b"]>>")?;
+        Ok(())
+    }
+}
+
+/// Writes out a date in PDF format.
+///
+/// The date is written as a string of the form `(D:YYYYMMDDHHmmSSOHH'mm')`, where `O` is the sign
+/// of the offset from UTC; dates in UTC are written as `(D:YYYYMMDDHHmmSSZ)`.
+pub fn write_pdf_date<W: Write>(date: &OffsetDateTime, writer: &mut W) -> Result<(), io::Error> {
+    write!(
+        writer,
+        "(D:{:04}{:02}{:02}{:02}{:02}{:02}",
+        date.year(), u8::from(date.month()), date.day(),
+        date.hour(), date.minute(), date.second(),
+    )?;
+    let offset = date.offset();
+    if offset.is_utc() {
+        writer.write_all(b"Z)")?;
+    } else {
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        write!(
+            writer,
+            "{}{:02}'{:02}')",
+            sign, offset.whole_hours().unsigned_abs(), offset.minutes_past_hour().unsigned_abs(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes out a comment line, prepending the `%`.
+fn write_pdf_comment<W: Write>(comment: &[u8], writer: &mut W) -> Result<(), io::Error> {
+    writer.write_all(b"%")?;
+    writer.write_all(comment)?;
+    writer.write_all(b"\n")
+}
+
 /// Writes out a textual string in PDF format.
 ///
 /// The string is wrapped in parentheses (`(` and `)`), encoded in UTF-16BE with BOM, and all
@@ -346,6 +1421,60 @@ pub fn write_pdf_string<W: Write>(string: &str, writer: &mut W) -> Result<(), io
     Ok(())
 }
 
+/// Encodes the text in WinAnsiEncoding, the encoding of the standard fonts.
+///
+/// Returns `None` if the text contains a character that cannot be encoded, including control
+/// characters.
+pub fn encode_win_ansi(text: &str) -> Option<Vec<u8>> {
+    // the characters of 0x80 to 0x9F; the other printable ones match Unicode
+    const HIGH_CONTROL_CHARS: [Option<char>; 32] = [
+        Some('\u{20AC}'), None, Some('\u{201A}'), Some('\u{0192}'),
+        Some('\u{201E}'), Some('\u{2026}'), Some('\u{2020}'), Some('\u{2021}'),
+        Some('\u{02C6}'), Some('\u{2030}'), Some('\u{0160}'), Some('\u{2039}'),
+        Some('\u{0152}'), None, Some('\u{017D}'), None,
+        None, Some('\u{2018}'), Some('\u{2019}'), Some('\u{201C}'),
+        Some('\u{201D}'), Some('\u{2022}'), Some('\u{2013}'), Some('\u{2014}'),
+        Some('\u{02DC}'), Some('\u{2122}'), Some('\u{0161}'), Some('\u{203A}'),
+        Some('\u{0153}'), None, Some('\u{017E}'), Some('\u{0178}'),
+    ];
+
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' | '\u{A0}'..='\u{FF}' => Some(c as u8),
+            _ => HIGH_CONTROL_CHARS.iter()
+                .position(|&hc| hc == Some(c))
+                .map(|index| 0x80 + index as u8),
+        })
+        .collect()
+}
+
+/// Writes out a byte string in PDF format.
+///
+/// The string is wrapped in parentheses (`(` and `)`); backslashes and parentheses are escaped
+/// with a preceding backslash and carriage returns (which would be read as line feeds) as `\r`.
+pub fn write_pdf_byte_string<W: Write>(bytes: &[u8], writer: &mut W) -> Result<(), io::Error> {
+    writer.write_all(b"(")?;
+    for &b in bytes {
+        match b {
+            b'(' | b')' | b'\\' => writer.write_all(&[b'\\', b])?,
+            b'\r' => writer.write_all(b"\\r")?,
+            _ => writer.write_all(&[b])?,
+        }
+    }
+    writer.write_all(b")")?;
+    Ok(())
+}
+
+/// Writes out two-byte codes as a hexadecimal PDF string.
+pub fn write_pdf_hex_codes<W: Write>(codes: &[u16], writer: &mut W) -> Result<(), io::Error> {
+    writer.write_all(b"<")?;
+    for code in codes {
+        write!(writer, "{:04X}", code)?;
+    }
+    writer.write_all(b">")?;
+    Ok(())
+}
+
 /// Writes out a PDF name.
 ///
 /// The string starts with a slash (`/`). The number sign (`#`) as well as regular characters
@@ -383,3 +1512,512 @@ pub fn write_pdf_stream<W: Write>(data: &[u8], writer: &mut W) -> Result<(), io:
     writer.write_all(b"\nendstream")?;
     Ok(())
 }
+
+/// Writes out a delimited PDF stream whose data is read from a reader.
+///
+/// Exactly `length` bytes are copied from `reader` to `writer` in chunks, so the data never has to
+/// be held in memory in its entirety. A reader that ends prematurely causes an
+/// [`io::ErrorKind::UnexpectedEof`] error.
+pub fn write_pdf_stream_from_reader<R: Read, W: Write>(reader: &mut R, mut length: u64, writer: &mut W) -> Result<(), io::Error> {
+    writer.write_all(b"\nstream\n")?;
+
+    let mut buf = vec![0u8; 4*1024*1024];
+    let buf_size_u64: u64 = buf.len().try_into().unwrap();
+    while length > 0 {
+        let read_at_most_u64 = length.min(buf_size_u64);
+        let read_at_most: usize = read_at_most_u64.try_into().unwrap();
+
+        let actually_read = reader.read(&mut buf[..read_at_most])?;
+        if actually_read == 0 {
+            // short read
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let actually_read_u64: u64 = actually_read.try_into().unwrap();
+        length -= actually_read_u64;
+
+        writer.write_all(&buf[..actually_read])?;
+    }
+
+    writer.write_all(b"\nendstream")?;
+    Ok(())
+}
+
+/// Whether the byte is a PDF white-space character.
+fn is_pdf_whitespace(b: u8) -> bool {
+    matches!(b, 0x00 | 0x09 | 0x0A | 0x0C | 0x0D | 0x20)
+}
+
+/// Whether the byte is a PDF delimiter character.
+fn is_pdf_delimiter(b: u8) -> bool {
+    matches!(b, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+/// Reads a PDF literal string starting at the opening parenthesis at `start`.
+///
+/// Returns the unescaped bytes of the string and the index just past its closing parenthesis.
+fn read_pdf_literal_string(bytes: &[u8], start: usize) -> (Vec<u8>, usize) {
+    let mut ret = Vec::new();
+    let mut nesting: usize = 0;
+    let mut i = start + 1;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        match b {
+            b'(' => {
+                nesting += 1;
+                ret.push(b);
+            },
+            b')' => {
+                if nesting == 0 {
+                    break;
+                }
+                nesting -= 1;
+                ret.push(b);
+            },
+            b'\\' => {
+                let Some(&escaped) = bytes.get(i) else { break };
+                i += 1;
+                match escaped {
+                    b'n' => ret.push(b'\n'),
+                    b'r' => ret.push(b'\r'),
+                    b't' => ret.push(b'\t'),
+                    b'b' => ret.push(0x08),
+                    b'f' => ret.push(0x0C),
+                    b'0'..=b'7' => {
+                        // up to three octal digits
+                        let mut value = u32::from(escaped - b'0');
+                        for _ in 0..2 {
+                            match bytes.get(i) {
+                                Some(&d @ b'0'..=b'7') => {
+                                    value = value * 8 + u32::from(d - b'0');
+                                    i += 1;
+                                },
+                                _ => break,
+                            }
+                        }
+                        ret.push((value & 0xFF) as u8);
+                    },
+                    b'\r' => {
+                        // line continuation
+                        if bytes.get(i) == Some(&b'\n') {
+                            i += 1;
+                        }
+                    },
+                    b'\n' => {
+                        // line continuation
+                    },
+                    other => ret.push(other),
+                }
+            },
+            other => ret.push(other),
+        }
+    }
+    (ret, i)
+}
+
+/// Renders the bytes of a PDF string literal in a human-readable form.
+///
+/// Strings starting with the UTF-16BE byte order mark are decoded as UTF-16; other strings are
+/// rendered byte by byte, with non-printable bytes as octal escapes.
+fn debug_pdf_string(string_bytes: &[u8]) -> String {
+    fn push_escaped(string: &mut String, c: char) {
+        if c == '(' || c == ')' || c == '\\' {
+            string.push('\\');
+        }
+        string.push(c);
+    }
+
+    let mut ret = String::from("(");
+    if let Some(utf16_bytes) = string_bytes.strip_prefix(b"\xFE\xFF") {
+        let words = utf16_bytes.chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]));
+        for c in char::decode_utf16(words) {
+            push_escaped(&mut ret, c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+    } else {
+        for &b in string_bytes {
+            if b.is_ascii_graphic() || b == b' ' {
+                push_escaped(&mut ret, char::from(b));
+            } else {
+                ret.push_str(&format!("\\{:03o}", b));
+            }
+        }
+    }
+    ret.push(')');
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Assembles a document with a catalog (1), an empty page tree (2) and the given objects.
+    fn minimal_document(objects: impl IntoIterator<Item = (PdfId, Content)>) -> Document {
+        let mut document = Document {
+            version: PdfVersion::default(),
+            header_comment: None,
+            objects: BTreeMap::new(),
+        };
+        document.objects.insert(PdfId(1), Content::Catalog(Catalog {
+            root_pages_id: PdfId(2),
+            lang: None,
+            output_intents: Vec::new(),
+            structure_tree_root: None,
+            metadata: None,
+            display_doc_title: false,
+        }));
+        document.objects.insert(PdfId(2), Content::Pages(Pages {
+            children: Vec::new(),
+            page_count: 0,
+        }));
+        document.objects.extend(objects);
+        document
+    }
+
+    fn write_to_lopdf(document: &Document) -> lopdf::Document {
+        let mut pdf_bytes = Cursor::new(Vec::new());
+        document.write_pdf(&mut pdf_bytes).unwrap();
+        lopdf::Document::load_mem(pdf_bytes.get_ref()).unwrap()
+    }
+
+    #[test]
+    fn writes_raw_objects_verbatim() {
+        let document = minimal_document([
+            (PdfId(3), Content::Raw(b"<</Type/Example/Answer 42/Names[/A/B]>>".to_vec())),
+        ]);
+        let pdf = write_to_lopdf(&document);
+
+        let raw = pdf.get_dictionary((3, 0)).unwrap();
+        assert_eq!(raw.get(b"Type").unwrap().as_name().unwrap(), b"Example");
+        assert_eq!(raw.get(b"Answer").unwrap().as_i64().unwrap(), 42);
+        assert_eq!(raw.get(b"Names").unwrap().as_array().unwrap().len(), 2);
+    }
+
+    fn pdf_date(offset_hours: i8, offset_minutes: i8) -> String {
+        let date = time::Date::from_calendar_date(2024, time::Month::March, 5).unwrap()
+            .with_hms(7, 8, 9).unwrap()
+            .assume_offset(time::UtcOffset::from_hms(offset_hours, offset_minutes, 0).unwrap());
+        let mut buf = Vec::new();
+        write_pdf_date(&date, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn formats_dates_with_offset() {
+        assert_eq!(pdf_date(0, 0), "(D:20240305070809Z)");
+        assert_eq!(pdf_date(2, 0), "(D:20240305070809+02'00')");
+        assert_eq!(pdf_date(-5, -30), "(D:20240305070809-05'30')");
+    }
+
+    #[test]
+    fn references_info_from_trailer() {
+        let date = time::Date::from_calendar_date(2024, time::Month::March, 5).unwrap()
+            .with_hms(7, 8, 9).unwrap()
+            .assume_utc();
+        let document = minimal_document([
+            (PdfId(3), Content::Info(Info { title: None, creation_date: Some(date), mod_date: None, producer: None })),
+        ]);
+        let pdf = write_to_lopdf(&document);
+
+        let info_id = pdf.trailer.get(b"Info").unwrap().as_reference().unwrap();
+        let info = pdf.get_dictionary(info_id).unwrap();
+        assert_eq!(info.get(b"CreationDate").unwrap().as_str().unwrap(), b"D:20240305070809Z");
+        assert!(info.get(b"ModDate").is_err());
+    }
+
+    #[test]
+    fn writes_image_masks_without_color_space() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-image-mask-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let os_path = dir.join("stamp.bin");
+        // 8 x 2 pixels, one bit each
+        std::fs::write(&os_path, [0b1010_1010, 0b0101_0101]).unwrap();
+
+        let mut image_mask = ImageXObject {
+            width: 8,
+            height: 2,
+            color_space: "/DeviceGray",
+            icc_profile: None,
+            image_mask: true,
+            bits_per_component: 1,
+            decode: vec![1, 0],
+            interpolate: false,
+            data_filters: Vec::new(),
+            filter_parameters: Vec::new(),
+            os_path,
+        };
+        let mut buf = Vec::new();
+        image_mask.write_content(&mut buf).unwrap();
+
+        // image masks can only have one bit per pixel
+        image_mask.bits_per_component = 8;
+        let error = image_mask.write_content(&mut Vec::new()).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let written = String::from_utf8_lossy(&buf);
+        assert!(written.contains("/ImageMask true"));
+        assert!(written.contains("/Decode[1 0]"));
+        assert!(!written.contains("/ColorSpace"));
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// Returns the comment lines following the version line of the written document.
+    fn written_header_comments(document: &Document) -> Vec<u8> {
+        let mut pdf_bytes = Cursor::new(Vec::new());
+        document.write_pdf(&mut pdf_bytes).unwrap();
+        let data = pdf_bytes.into_inner();
+        let version_end = data.iter().position(|b| *b == b'\n').unwrap();
+        let header_end = data.windows(2).position(|w| w == b"\n\n").unwrap();
+        data[version_end+1..header_end+1].to_vec()
+    }
+
+    #[test]
+    fn writes_default_header_comment() {
+        let document = minimal_document([]);
+        assert_eq!(written_header_comments(&document), b"%\xE2\xE3\xCF\xD3\n");
+    }
+
+    #[test]
+    fn writes_custom_header_comment() {
+        // an ASCII comment is preceded by the binary detection comment
+        let mut document = minimal_document([]);
+        document.header_comment = Some(b"made by pdfmcr".to_vec());
+        assert_eq!(written_header_comments(&document), b"%\xE2\xE3\xCF\xD3\n%made by pdfmcr\n");
+        write_to_lopdf(&document);
+
+        // a comment with enough high bytes replaces it
+        document.header_comment = Some(b"\xC4\xD6\xDC\xDF pdfmcr".to_vec());
+        assert_eq!(written_header_comments(&document), b"%\xC4\xD6\xDC\xDF pdfmcr\n");
+        write_to_lopdf(&document);
+    }
+
+    #[test]
+    fn refuses_header_comment_with_line_break() {
+        let mut document = minimal_document([]);
+        document.header_comment = Some(b"two\nlines".to_vec());
+        let mut pdf_bytes = Cursor::new(Vec::new());
+        let error = document.write_pdf(&mut pdf_bytes).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    fn write_document(document: &Document) -> Result<Vec<u8>, io::Error> {
+        let mut pdf_bytes = Vec::new();
+        document.write_pdf_to_stream(&mut pdf_bytes)?;
+        Ok(pdf_bytes)
+    }
+
+    #[test]
+    fn header_reflects_version() {
+        for (version, header) in PdfVersion::ALL.into_iter().zip([&b"1.4"[..], b"1.5", b"1.6", b"1.7", b"2.0"]) {
+            let mut document = minimal_document([]);
+            document.version = version;
+            let pdf_bytes = write_document(&document).unwrap();
+            let expected = [&b"%PDF-"[..], header, b"\n%", DEFAULT_BINARY_COMMENT, b"\n"].concat();
+            assert!(pdf_bytes.starts_with(&expected), "wrong header for {:?}", version);
+        }
+    }
+
+    #[test]
+    fn refuses_features_of_newer_versions() {
+        let mut document = minimal_document([
+            (PdfId(3), Content::PageContents(PageContents {
+                commands: b"/Span<</ActualText(x)>>BDC EMC".to_vec(),
+                minimum_version: PdfVersion::V1_5,
+            })),
+        ]);
+        document.version = PdfVersion::V1_4;
+        let error = write_document(&document).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        document.version = PdfVersion::V1_5;
+        write_document(&document).unwrap();
+    }
+
+    /// A writer that is not seekable and accepts at most three bytes per call.
+    struct TrickleWriter(Vec<u8>);
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let length = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..length]);
+            Ok(length)
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn stream_output_matches_seekable_output() {
+        let mut document = minimal_document([]);
+        document.version = PdfVersion::V1_7;
+        let mut cursor = io::Cursor::new(Vec::new());
+        document.write_pdf(&mut cursor).unwrap();
+        let seekable_bytes = cursor.into_inner();
+
+        assert_eq!(write_document(&document).unwrap(), seekable_bytes);
+        let mut trickle_writer = TrickleWriter(Vec::new());
+        document.write_pdf_to_stream(&mut trickle_writer).unwrap();
+        assert_eq!(trickle_writer.0, seekable_bytes);
+
+        // the comparison is not vacuous
+        let mut other_document = minimal_document([]);
+        other_document.version = PdfVersion::V2_0;
+        assert_ne!(write_document(&other_document).unwrap(), seekable_bytes);
+    }
+
+    #[test]
+    fn counting_writer_counts_bytes_accepted() {
+        let mut counting_writer = CountingWriter::new(TrickleWriter(Vec::new()));
+        assert_eq!(counting_writer.count(), 0);
+        counting_writer.write_all(b"").unwrap();
+        assert_eq!(counting_writer.count(), 0);
+
+        // the inner writer only accepts part of the buffer
+        assert_eq!(counting_writer.write(b"abcdef").unwrap(), 3);
+        assert_eq!(counting_writer.count(), 3);
+        counting_writer.write_all(b"ghij").unwrap();
+        assert_eq!(counting_writer.count(), 7);
+        assert_eq!(counting_writer.into_inner().0, b"abcghij");
+    }
+
+    /// Collects the IDs of all objects referenced by the given object.
+    fn collect_references(object: &lopdf::Object, references: &mut Vec<lopdf::ObjectId>) {
+        match object {
+            lopdf::Object::Reference(id) => references.push(*id),
+            lopdf::Object::Array(items) => {
+                for item in items {
+                    collect_references(item, references);
+                }
+            },
+            lopdf::Object::Dictionary(dict) => {
+                for (_key, value) in dict.iter() {
+                    collect_references(value, references);
+                }
+            },
+            lopdf::Object::Stream(stream) => {
+                for (_key, value) in stream.dict.iter() {
+                    collect_references(value, references);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    #[test]
+    fn writes_document_at_nonzero_offset() {
+        let mut document = minimal_document([]);
+        document.version = PdfVersion::V1_4;
+        // leave object 3 free
+        document.objects.insert(PdfId(4), Content::PageContents(PageContents {
+            commands: b"q Q".to_vec(),
+            minimum_version: PdfVersion::V1_4,
+        }));
+
+        let prefix = [b'x'; 100];
+        let mut cursor = io::Cursor::new(prefix.to_vec());
+        cursor.seek(SeekFrom::End(0)).unwrap();
+        document.write_pdf(&mut cursor).unwrap();
+        let container = cursor.into_inner();
+        assert_eq!(container[..100], prefix);
+        let pdf_bytes = &container[100..];
+        assert_eq!(pdf_bytes, write_document(&document).unwrap());
+
+        // startxref is relative to the start of the document
+        let startxref_pos = pdf_bytes.windows(10).rposition(|window| window == b"startxref\n").unwrap();
+        let xref_offset: usize = std::str::from_utf8(&pdf_bytes[startxref_pos + 10..])
+            .unwrap()
+            .lines().next().unwrap()
+            .parse().unwrap();
+        assert!(pdf_bytes[xref_offset..].starts_with(b"xref\n0 5\n0000000003 65535 f\r\n"));
+
+        let pdf = lopdf::Document::load_mem(pdf_bytes).unwrap();
+        assert_eq!(pdf.objects.len(), 3);
+        for obj_id in [1, 2, 4] {
+            let object = pdf.get_object((obj_id, 0)).unwrap();
+            let mut references = Vec::new();
+            collect_references(object, &mut references);
+            for reference in references {
+                assert!(pdf.get_object(reference).is_ok(), "object {} references missing {:?}", obj_id, reference);
+            }
+        }
+        assert!(pdf.get_object((3, 0)).is_err());
+        assert_eq!(pdf.catalog().unwrap().get(b"Pages").unwrap().as_reference().unwrap(), (2, 0));
+    }
+
+    /// A reader that returns at most three bytes per call.
+    struct TrickleReader<'a>(&'a [u8]);
+    impl Read for TrickleReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let length = buf.len().min(3).min(self.0.len());
+            buf[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+
+    fn sample_data() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn streamed_data_matches_buffered_data() {
+        let data = sample_data();
+        let length = u64::try_from(data.len()).unwrap();
+        let mut buffered = Vec::new();
+        write_pdf_stream(&data, &mut buffered).unwrap();
+
+        let mut streamed = Vec::new();
+        write_pdf_stream_from_reader(&mut data.as_slice(), length, &mut streamed).unwrap();
+        assert_eq!(streamed, buffered);
+
+        let mut trickled = Vec::new();
+        write_pdf_stream_from_reader(&mut TrickleReader(&data), length, &mut trickled).unwrap();
+        assert_eq!(trickled, buffered);
+
+        // only the given number of bytes is copied
+        let mut truncated = Vec::new();
+        write_pdf_stream_from_reader(&mut data.as_slice(), 100, &mut truncated).unwrap();
+        let mut expected = Vec::new();
+        write_pdf_stream(&data[..100], &mut expected).unwrap();
+        assert_eq!(truncated, expected);
+    }
+
+    #[test]
+    fn refuses_short_stream_data() {
+        let data = sample_data();
+        let length = u64::try_from(data.len()).unwrap() + 1;
+        let error = write_pdf_stream_from_reader(&mut data.as_slice(), length, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn image_data_is_streamed_from_file() {
+        let data = sample_data();
+        let os_path = std::env::temp_dir().join(format!("pdfmcr-stream-test-{}.jpeg", std::process::id()));
+        std::fs::write(&os_path, &data).unwrap();
+        let image = ImageXObject {
+            width: 100,
+            height: 100,
+            color_space: "/DeviceGray",
+            icc_profile: None,
+            image_mask: false,
+            bits_per_component: 8,
+            decode: Vec::new(),
+            interpolate: false,
+            data_filters: vec!["DCTDecode".to_owned()],
+            filter_parameters: Vec::new(),
+            os_path: os_path.clone(),
+        };
+        let mut written = Vec::new();
+        let result = image.write_content(&mut written);
+        std::fs::remove_file(&os_path).unwrap();
+        result.unwrap();
+
+        let mut expected = b"<</Type/XObject/Subtype/Image/Width 100/Height 100/ColorSpace/DeviceGray/BitsPerComponent 8/Interpolate false/Filter[/DCTDecode]/Length 10000>>".to_vec();
+        write_pdf_stream(&data, &mut expected).unwrap();
+        assert_eq!(written, expected);
+    }
+}
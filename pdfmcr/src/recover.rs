@@ -0,0 +1,493 @@
+//! Recovering the pages and annotations of a file from a PDF generated from it.
+//!
+//! This is useful if the state has been lost but the exported PDF is still available. Only what
+//! the PDF actually contains can be recovered; in particular, the names of the image files and the
+//! review status of the pages are lost.
+
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use lopdf::{Encoding, Object};
+use lopdf::content::Operation;
+use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+use crate::import::{pdf_page_jpeg_data, pdf_page_size_pt};
+use crate::model::{Annotation, Artifact, ArtifactKind, BlockRole, FontVariant, TextChunk};
+
+
+/// An error that can occur while recovering pages from a PDF file.
+#[derive(Debug)]
+pub enum Error {
+    /// The PDF file cannot be read.
+    Pdf(lopdf::Error),
+
+    /// The image on the page with the given index cannot be extracted.
+    Image(crate::import::Error),
+
+    /// The page with the given index has neither an image nor a media box.
+    UnknownPageSize { page_index: usize },
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pdf(e)
+                => write!(f, "PDF error: {}", e),
+            Self::Image(e)
+                => write!(f, "image error: {}", e),
+            Self::UnknownPageSize { page_index }
+                => write!(f, "page {} has neither an image nor a media box", page_index),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Pdf(e) => Some(e),
+            Self::Image(e) => Some(e),
+            Self::UnknownPageSize { .. } => None,
+        }
+    }
+}
+impl From<lopdf::Error> for Error {
+    fn from(value: lopdf::Error) -> Self { Self::Pdf(value) }
+}
+impl From<crate::import::Error> for Error {
+    fn from(value: crate::import::Error) -> Self { Self::Image(value) }
+}
+
+
+/// A page recovered from a PDF file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecoveredPage {
+    /// The scanned image of the page, as a JPEG file, if the page has one.
+    pub jpeg_data: Option<Vec<u8>>,
+
+    /// The size of the page in points (1/72 in), as `(width, height)`, if the page has a media box.
+    pub size_pt: Option<(u64, u64)>,
+
+    /// The annotations on the page, in reading order.
+    pub annotations: Vec<Annotation>,
+
+    /// The artifacts on the page.
+    pub artifacts: Vec<Artifact>,
+}
+
+/// The contents of a PDF file recovered by [`recover_pages`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecoveredDocument {
+    /// The pages of the document.
+    pub pages: Vec<RecoveredPage>,
+
+    /// The language of the document, as a BCP 47 language tag, if it declares one.
+    pub default_language: Option<String>,
+
+    /// The title of the document, if its information dictionary declares one.
+    pub title: Option<String>,
+}
+
+
+/// Recovers the pages of a PDF file generated by pdfmcr.
+///
+/// The scanned image of each page is the largest image on it. Annotations and artifacts are
+/// recovered from the text objects of the content stream of the page; text objects within a
+/// marked-content sequence tagged `Artifact` become artifacts, all others become annotations.
+pub fn recover_pages(data: &[u8]) -> Result<RecoveredDocument, Error> {
+    let document = lopdf::Document::load_mem(data)?;
+
+    let default_language = document.catalog().ok()
+        .and_then(|catalog| catalog.get(b"Lang").ok())
+        .and_then(|lang| lopdf::decode_text_string(lang).ok());
+
+    let title = document.trailer.get_deref(b"Info", &document).ok()
+        .and_then(|info| info.as_dict().ok())
+        .and_then(|info| info.get(b"Title").ok())
+        .and_then(|title| lopdf::decode_text_string(title).ok());
+
+    let mut pages = Vec::new();
+    for (page_index, page_id) in document.page_iter().enumerate() {
+        let jpeg_data = pdf_page_jpeg_data(&document, page_index, page_id)?;
+        let size_pt = pdf_page_size_pt(&document, page_id);
+        if jpeg_data.is_none() && size_pt.is_none() {
+            return Err(Error::UnknownPageSize { page_index });
+        }
+
+        // fonts whose encoding is unknown to lopdf can only show UTF-16 text strings
+        let font_encodings: BTreeMap<Vec<u8>, Encoding> = document.get_page_fonts(page_id)?
+            .into_iter()
+            .filter_map(|(name, font)| font.get_font_encoding(&document).ok().map(|encoding| (name, encoding)))
+            .collect();
+
+        let content = document.get_and_decode_page_content(page_id)?;
+        let (annotations, artifacts) = recover_annotations(&content.operations, &font_encodings);
+        pages.push(RecoveredPage {
+            jpeg_data,
+            size_pt,
+            annotations,
+            artifacts,
+        });
+    }
+    Ok(RecoveredDocument {
+        pages,
+        default_language,
+        title,
+    })
+}
+
+
+/// A marked-content sequence that is currently open.
+enum MarkedContent {
+    Artifact(Option<ArtifactKind>),
+    Span(SpanProperties),
+    Block(BlockRole),
+    Other,
+}
+
+/// The properties of a span of text, as attached to its marked-content sequence.
+#[derive(Default)]
+struct SpanProperties {
+    language: Option<String>,
+    alternate_text: Option<String>,
+    actual_text: Option<String>,
+    expansion: Option<String>,
+}
+
+/// The text state while walking through a text object.
+struct TextState {
+    font_name: Vec<u8>,
+    font_variant: FontVariant,
+    font_size: f64,
+    character_spacing: f64,
+    word_spacing: f64,
+    leading: f64,
+    horizontal_scale: f64,
+    text_rise: f64,
+}
+impl Default for TextState {
+    fn default() -> Self {
+        Self {
+            font_name: Vec::new(),
+            font_variant: FontVariant::Regular,
+            font_size: 1.0,
+            character_spacing: 0.0,
+            word_spacing: 0.0,
+            leading: 0.0,
+            horizontal_scale: 100.0,
+            text_rise: 0.0,
+        }
+    }
+}
+
+/// Obtains a number from a content stream operand.
+///
+/// lopdf only stores real numbers with single precision; the values are rounded to the thousandths
+/// to avoid artifacts such as 10.300000190734863.
+fn operand_number(operand: Option<&Object>) -> Option<f64> {
+    let value = match operand? {
+        Object::Integer(i) => *i as f64,
+        Object::Real(r) => f64::from(*r),
+        _ => return None,
+    };
+    Some((value * 1000.0).round() / 1000.0)
+}
+
+fn finite(value: f64) -> FiniteF64 {
+    FiniteF64::new(value).unwrap_or_else(|| FiniteF64::new(0.0).unwrap())
+}
+
+fn artifact_kind_from_name(name: &[u8]) -> Option<ArtifactKind> {
+    match name {
+        b"Pagination" => Some(ArtifactKind::Pagination),
+        b"Layout" => Some(ArtifactKind::Layout),
+        b"Page" => Some(ArtifactKind::Page),
+        b"Background" => Some(ArtifactKind::Background),
+        _ => None,
+    }
+}
+
+/// Obtains the font variant from the resource name of a standard (`F0` to `F3`) or fallback
+/// (`FU0` to `FU3`) font.
+fn font_variant_from_resource_name(name: &[u8]) -> Option<FontVariant> {
+    let index = name.strip_prefix(b"FU")
+        .or_else(|| name.strip_prefix(b"F"))?;
+    FontVariant::ALL.into_iter()
+        .find(|variant| index == variant.as_index().to_string().as_bytes())
+}
+
+/// Decodes the string shown by a text-showing operator using the encoding of the current font.
+///
+/// Older versions of pdfmcr wrote UTF-16BE text strings regardless of the font; these are
+/// recognized by their byte order mark.
+fn decode_shown_text(text: &Object, font_encoding: Option<&Encoding>) -> Option<String> {
+    let bytes = text.as_str().ok()?;
+    if bytes.starts_with(b"\xFE\xFF") {
+        return lopdf::decode_text_string(text).ok();
+    }
+    match font_encoding {
+        Some(encoding) => encoding.bytes_to_string(bytes).ok(),
+        None => lopdf::decode_text_string(text).ok(),
+    }
+}
+
+/// Recovers the annotations and artifacts drawn by the given content stream operations.
+///
+/// The encodings of the fonts available to the content stream are given by resource name.
+fn recover_annotations(operations: &[Operation], font_encodings: &BTreeMap<Vec<u8>, Encoding>) -> (Vec<Annotation>, Vec<Artifact>) {
+    let mut annotations = Vec::new();
+    let mut artifacts = Vec::new();
+
+    let mut marked_content: Vec<MarkedContent> = Vec::new();
+    let mut text_state = TextState::default();
+    let mut current: Option<(u64, u64, Vec<TextChunk>)> = None;
+
+    for operation in operations {
+        let operands = &operation.operands;
+        match operation.operator.as_str() {
+            "BMC" => {
+                let is_artifact = operands.first()
+                    .and_then(|tag| tag.as_name().ok())
+                    .is_some_and(|tag| tag == b"Artifact");
+                marked_content.push(if is_artifact { MarkedContent::Artifact(None) } else { MarkedContent::Other });
+            },
+            "BDC" => {
+                let tag = operands.first().and_then(|tag| tag.as_name().ok());
+                let properties = operands.get(1).and_then(|properties| properties.as_dict().ok());
+                let text_property = |key: &[u8]| properties
+                    .and_then(|p| p.get(key).ok())
+                    .and_then(|value| lopdf::decode_text_string(value).ok());
+                let entry = match tag {
+                    Some(b"Artifact") => {
+                        let kind = properties
+                            .and_then(|p| p.get(b"Type").ok())
+                            .and_then(|kind| kind.as_name().ok())
+                            .and_then(artifact_kind_from_name);
+                        MarkedContent::Artifact(kind)
+                    },
+                    Some(b"Span") => MarkedContent::Span(SpanProperties {
+                        language: text_property(b"Lang"),
+                        alternate_text: text_property(b"Alt"),
+                        actual_text: text_property(b"ActualText"),
+                        expansion: text_property(b"E"),
+                    }),
+                    Some(other) => match BlockRole::from_marked_content_tag(other) {
+                        Some(block_role) => MarkedContent::Block(block_role),
+                        None => MarkedContent::Other,
+                    },
+                    None => MarkedContent::Other,
+                };
+                marked_content.push(entry);
+            },
+            "EMC" => {
+                marked_content.pop();
+            },
+            "BT" => {
+                text_state = TextState::default();
+                current = Some((0, 0, Vec::new()));
+            },
+            "Tm" => {
+                if let Some((left, bottom, _chunks)) = current.as_mut() {
+                    // annotations are only ever translated
+                    let x = operand_number(operands.get(4)).unwrap_or(0.0);
+                    let y = operand_number(operands.get(5)).unwrap_or(0.0);
+                    *left = x.max(0.0).round() as u64;
+                    *bottom = y.max(0.0).round() as u64;
+                }
+            },
+            "Tf" => {
+                if let Some(name) = operands.first().and_then(|name| name.as_name().ok()) {
+                    text_state.font_name = name.to_vec();
+                    if let Some(variant) = font_variant_from_resource_name(name) {
+                        text_state.font_variant = variant;
+                    }
+                }
+                if let Some(size) = operand_number(operands.get(1)) {
+                    text_state.font_size = size;
+                }
+            },
+            "Tc" => {
+                text_state.character_spacing = operand_number(operands.first()).unwrap_or(0.0);
+            },
+            "Tw" => {
+                text_state.word_spacing = operand_number(operands.first()).unwrap_or(0.0);
+            },
+            "TL" => {
+                text_state.leading = operand_number(operands.first()).unwrap_or(0.0);
+            },
+            "Tz" => {
+                text_state.horizontal_scale = operand_number(operands.first()).unwrap_or(100.0);
+            },
+            "Ts" => {
+                text_state.text_rise = operand_number(operands.first()).unwrap_or(0.0);
+            },
+            "Tj" => {
+                let Some((_left, _bottom, chunks)) = current.as_mut() else {
+                    continue;
+                };
+                let font_encoding = font_encodings.get(&text_state.font_name);
+                let Some(text) = operands.first().and_then(|text| decode_shown_text(text, font_encoding)) else {
+                    continue;
+                };
+                let span = marked_content.iter().rev().find_map(|mc| match mc {
+                    MarkedContent::Span(properties) => Some(properties),
+                    _ => None,
+                });
+                chunks.push(TextChunk {
+                    text,
+                    font_variant: text_state.font_variant,
+                    character_spacing: finite(text_state.character_spacing),
+                    word_spacing: finite(text_state.word_spacing),
+                    // only stored if they differ from the defaults
+                    horizontal_scale: Some(text_state.horizontal_scale.round())
+                        .filter(|s| *s != 100.0 && *s > 0.0 && *s <= f64::from(u32::MAX))
+                        .map(|s| s as u32),
+                    text_rise: Some(text_state.text_rise.round())
+                        .filter(|r| *r != 0.0 && r.is_finite())
+                        .map(|r| r as i64),
+                    language: span.and_then(|s| s.language.clone()),
+                    alternate_text: span.and_then(|s| s.alternate_text.clone()),
+                    actual_text: span.and_then(|s| s.actual_text.clone()),
+                    expansion: span.and_then(|s| s.expansion.clone()),
+                });
+            },
+            "ET" => {
+                let Some((left, bottom, elements)) = current.take() else {
+                    continue;
+                };
+                let font_size = NonZeroPositiveF64::new(text_state.font_size)
+                    .unwrap_or_else(|| NonZeroPositiveF64::new(1.0).unwrap());
+                // paragraphs are the default; don't distinguish them from annotations without a role
+                let block_role = marked_content.iter().rev().find_map(|mc| match mc {
+                    MarkedContent::Block(block_role) => Some(*block_role),
+                    _ => None,
+                }).filter(|block_role| *block_role != BlockRole::Paragraph);
+                let annotation = Annotation {
+                    left,
+                    bottom,
+                    font_size,
+                    leading: finite(text_state.leading),
+                    elements,
+                    order: None,
+                    block_role,
+                };
+                let artifact_kind = marked_content.iter().rev().find_map(|mc| match mc {
+                    MarkedContent::Artifact(kind) => Some(*kind),
+                    _ => None,
+                });
+                match artifact_kind {
+                    Some(kind) => artifacts.push(Artifact {
+                        kind: kind.unwrap_or(ArtifactKind::Layout),
+                        annotation,
+                    }),
+                    None => annotations.push(annotation),
+                }
+            },
+            _ => {},
+        }
+    }
+
+    (annotations, artifacts)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+    use super::*;
+    use crate::file_to_pdf::{ConversionOptions, file_to_pdf};
+    use crate::model::{
+        BlockRole, ColorSpace, DensityUnit, File, ImageEncoding, JpegImage, JpegImageInfo, Page,
+    };
+
+    fn sample_annotation(text: &str, language: Option<&str>) -> Annotation {
+        Annotation {
+            left: 36,
+            bottom: 100,
+            font_size: NonZeroPositiveF64::new(11.0).unwrap(),
+            leading: FiniteF64::new(13.0).unwrap(),
+            elements: vec![
+                TextChunk {
+                    text: text.to_owned(),
+                    font_variant: FontVariant::Bold,
+                    character_spacing: FiniteF64::new(0.0).unwrap(),
+                    word_spacing: FiniteF64::new(0.5).unwrap(),
+                    horizontal_scale: None,
+                    text_rise: None,
+                    language: language.map(|l| l.to_owned()),
+                    alternate_text: None,
+                    actual_text: None,
+                    expansion: None,
+                },
+            ],
+            order: None,
+            block_role: None,
+        }
+    }
+
+    #[test]
+    fn recovers_pages_of_generated_pdf() {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-recover-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let jpeg_data = b"\xFF\xD8pretend this is a JPEG image\xFF\xD9".to_vec();
+        std::fs::write(dir.join("page.jpeg"), &jpeg_data).unwrap();
+
+        let mut image_page = Page::new(JpegImage {
+            info: JpegImageInfo {
+                bit_depth: 8,
+                width: 600,
+                height: 900,
+                color_space: ColorSpace::Grayscale,
+                density_unit: DensityUnit::DotsPerInch,
+                density_x: 300,
+                density_y: 300,
+                inverted: false,
+                encoding: ImageEncoding::Jpeg,
+            },
+            file_path: "page.jpeg".parse().unwrap(),
+            data_filters: None,
+        });
+        let mut heading = sample_annotation("gescannt", Some("de"));
+        heading.block_role = Some(BlockRole::Heading1);
+        heading.elements[0].horizontal_scale = Some(85);
+        heading.elements[0].text_rise = Some(-3);
+        image_page.annotations.push(heading);
+        let mut text_page = Page::new_text_only(612.0, 792.0);
+        text_page.artifacts.push(Artifact {
+            kind: ArtifactKind::Pagination,
+            annotation: sample_annotation("2", None),
+        });
+        let file = File {
+            pages: vec![image_page, text_page],
+            default_language: Some("en".to_owned()),
+            title: Some("Sample & Co.".to_owned()),
+            visible_text: false,
+        };
+
+        let document = file_to_pdf(&file, &dir, &ConversionOptions::default()).unwrap();
+        let mut pdf_bytes = std::io::Cursor::new(Vec::new());
+        document.write_pdf(&mut pdf_bytes).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let recovered = recover_pages(pdf_bytes.get_ref()).unwrap();
+        assert_eq!(recovered.default_language.as_deref(), Some("en"));
+        assert_eq!(recovered.title.as_deref(), Some("Sample & Co."));
+        assert_eq!(recovered.pages.len(), 2);
+
+        let recovered_image_page = &recovered.pages[0];
+        assert_eq!(recovered_image_page.jpeg_data.as_ref(), Some(&jpeg_data));
+        assert_eq!(recovered_image_page.size_pt, Some((144, 216)));
+        assert_eq!(recovered_image_page.annotations, file.pages[0].annotations);
+        assert_eq!(recovered_image_page.artifacts, []);
+
+        let recovered_text_page = &recovered.pages[1];
+        assert_eq!(recovered_text_page.jpeg_data, None);
+        assert_eq!(recovered_text_page.size_pt, Some((612, 792)));
+        assert_eq!(recovered_text_page.annotations, []);
+        assert_eq!(recovered_text_page.artifacts, file.pages[1].artifacts);
+    }
+
+    #[test]
+    fn refuses_garbage() {
+        assert!(matches!(recover_pages(b"not a PDF file"), Err(Error::Pdf(_))));
+    }
+}
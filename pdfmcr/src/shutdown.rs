@@ -0,0 +1,85 @@
+//! Persisting the state when the server shuts down.
+
+
+use rocket::{Orbit, Rocket};
+use rocket::fairing::{Fairing, Info, Kind};
+use tracing::{error, info};
+
+use crate::WEB_FILES;
+use crate::tenant::Tenant;
+
+
+/// Writes the state of each tenant whose state has been loaded into memory to its state file when
+/// the server shuts down.
+///
+/// Not every change is persisted immediately (e.g. setting the annotations of a page); this ensures
+/// that such changes survive stopping the server, e.g. using Ctrl+C.
+pub(crate) struct PersistOnShutdown;
+
+#[rocket::async_trait]
+impl Fairing for PersistOnShutdown {
+    fn info(&self) -> Info {
+        Info {
+            name: "persist state on shutdown",
+            kind: Kind::Shutdown,
+        }
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        let tenants: Vec<Tenant> = {
+            let files_guard = WEB_FILES
+                .get().expect("WEB_FILES not set?!")
+                .read().await;
+            files_guard.keys().cloned().collect()
+        };
+
+        let mut persisted_count = 0;
+        for tenant in &tenants {
+            // persist_state_file logs the details
+            match crate::persist_state_file(tenant).await {
+                Ok(()) => persisted_count += 1,
+                Err(e) => error!("failed to persist state on shutdown: {}", e),
+            }
+        }
+        info!("persisted the state of {} of {} tenants on shutdown", persisted_count, tenants.len());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rocket::http::{ContentType, Status};
+
+    use super::*;
+    use crate::test_support::{client, tenant_header, test_dir, upload_page};
+
+    #[rocket::async_test]
+    async fn persists_pending_edits_on_shutdown() {
+        let client = client().await;
+        let tenant = "persists_pending_edits_on_shutdown";
+        let page = upload_page(&client, tenant).await;
+        let state_file_path = Tenant::named(tenant).state_file_path(&test_dir().join("state.cbor"));
+        let persisted_annotation_count = |state_file_path: &Path| {
+            let file = crate::load_state_file(state_file_path).unwrap();
+            file.pages[page].annotations.len()
+        };
+
+        let response = client.post(format!("/page/{}/annotations", page))
+            .header(tenant_header(tenant))
+            .header(ContentType::JSON)
+            .body(r#"{
+                "annotations": [{"left": 2, "bottom": 3, "font_size": 12.0, "leading": 0.0, "elements": []}],
+                "artifacts": []
+            }"#)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        drop(response);
+        // the edit is only held in memory
+        assert_eq!(persisted_annotation_count(&state_file_path), 0);
+
+        client.terminate().await;
+        assert_eq!(persisted_annotation_count(&state_file_path), 1);
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::path::Path;
+
+
+/// The format in which a state file is stored.
+///
+/// CBOR is compact and the default; JSON is easier to inspect and edit by hand.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum StateFormat {
+    Cbor,
+    Json,
+}
+impl StateFormat {
+    /// The format in which the state file at the given path is written.
+    ///
+    /// State files whose names end in `.json` are written as JSON, all others as CBOR.
+    pub fn for_path(path: &Path) -> Self {
+        let is_json = path.extension()
+            .map(|extension| extension.eq_ignore_ascii_case("json"))
+            .unwrap_or(false);
+        if is_json {
+            Self::Json
+        } else {
+            Self::Cbor
+        }
+    }
+
+    /// Detects the format of the given state file contents.
+    ///
+    /// The state is a map, so JSON state starts with an opening brace (possibly preceded by
+    /// whitespace). In CBOR, the brace would be the start of a text string, which a state file never
+    /// is.
+    pub fn detect(data: &[u8]) -> Self {
+        let first_byte = data.iter()
+            .copied()
+            .find(|b| !b.is_ascii_whitespace());
+        if first_byte == Some(b'{') {
+            Self::Json
+        } else {
+            Self::Cbor
+        }
+    }
+
+    /// Decodes the state from data in this format.
+    pub fn decode(&self, data: &[u8]) -> Result<pdfmcr::model::File, String> {
+        match self {
+            Self::Cbor => ciborium::from_reader(data)
+                .map_err(|e| e.to_string()),
+            Self::Json => serde_json::from_slice(data)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Encodes the state in this format.
+    pub fn encode(&self, file: &pdfmcr::model::File) -> Result<Vec<u8>, String> {
+        let mut buf = Vec::new();
+        match self {
+            Self::Cbor => ciborium::into_writer(file, &mut buf)
+                .map_err(|e| e.to_string())?,
+            Self::Json => serde_json::to_writer_pretty(&mut buf, file)
+                .map_err(|e| e.to_string())?,
+        }
+        Ok(buf)
+    }
+}
+impl fmt::Display for StateFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cbor => write!(f, "CBOR"),
+            Self::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use pdfmcr::model::{Annotation, BlockRole, File, FontVariant, Page, TextChunk};
+    use strict_num::{FiniteF64, NonZeroPositiveF64};
+
+    use super::StateFormat;
+
+    fn sample_file() -> File {
+        let mut page = Page::new_text_only(595.28, 841.89);
+        page.reviewed = true;
+        page.source_filename = Some("scan.jpeg".to_owned());
+        page.annotations.push(Annotation {
+            left: 72,
+            bottom: 700,
+            font_size: NonZeroPositiveF64::new(12.0).unwrap(),
+            leading: FiniteF64::new(14.5).unwrap(),
+            elements: vec![
+                TextChunk {
+                    text: "Grüß Gott".to_owned(),
+                    font_variant: FontVariant::Italic,
+                    character_spacing: FiniteF64::new(0.0).unwrap(),
+                    word_spacing: FiniteF64::new(1.25).unwrap(),
+                    horizontal_scale: Some(85),
+                    text_rise: Some(-3),
+                    language: Some("de-AT".to_owned()),
+                    alternate_text: None,
+                    actual_text: None,
+                    expansion: None,
+                },
+            ],
+            order: Some(1),
+            block_role: Some(BlockRole::Heading2),
+        });
+        File {
+            pages: vec![page],
+            default_language: Some("de".to_owned()),
+            title: Some("Testdokument".to_owned()),
+            visible_text: true,
+        }
+    }
+
+    #[test]
+    fn chooses_format_by_extension() {
+        assert_eq!(StateFormat::for_path("state.cbor".as_ref()), StateFormat::Cbor);
+        assert_eq!(StateFormat::for_path("state".as_ref()), StateFormat::Cbor);
+        assert_eq!(StateFormat::for_path("state.json".as_ref()), StateFormat::Json);
+        assert_eq!(StateFormat::for_path("STATE.JSON".as_ref()), StateFormat::Json);
+    }
+
+    #[test]
+    fn round_trips_through_both_formats() {
+        let file = sample_file();
+        let mut decoded_files = Vec::new();
+        for format in [StateFormat::Cbor, StateFormat::Json] {
+            let data = format.encode(&file).unwrap();
+            assert_eq!(StateFormat::detect(&data), format);
+            decoded_files.push(format.decode(&data).unwrap());
+        }
+        assert_eq!(decoded_files[0], file);
+        assert_eq!(decoded_files[1], file);
+    }
+
+    #[test]
+    fn reads_state_without_visible_text() {
+        let file = StateFormat::Json.decode(br#"{"pages": [], "default_language": null}"#).unwrap();
+        assert!(!file.visible_text);
+        assert_eq!(file.title, None);
+    }
+
+    #[test]
+    fn reads_pages_without_fractional_media_box() {
+        // older state files lack the media box or store it as integers
+        let state = serde_json::json!({
+            "pages": [
+                {"scanned_image": null, "annotations": [], "artifacts": []},
+                {"scanned_image": null, "media_box_pt": [595, 842], "annotations": [], "artifacts": []},
+            ],
+            "default_language": null,
+        });
+        let json_data = serde_json::to_vec(&state).unwrap();
+        let mut cbor_data = Vec::new();
+        ciborium::into_writer(&state, &mut cbor_data).unwrap();
+
+        for (format, data) in [(StateFormat::Json, json_data), (StateFormat::Cbor, cbor_data)] {
+            let file = format.decode(&data).unwrap();
+            assert_eq!(file.pages[0].media_box_pt, None, "{}", format);
+            assert_eq!(file.pages[1].media_box_pt, Some((595.0, 842.0)), "{}", format);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha3::Sha3_512;
+use tracing::warn;
+
+use crate::config::CONFIG;
+
+
+/// The name of the header identifying the tenant on whose behalf a request is made.
+///
+/// The value of the header has the form `<tenant>.<signature>`, where the signature is the
+/// HMAC-SHA3-512 of the tenant identifier keyed with the configured tenant secret, encoded as
+/// hexadecimal digits.
+pub(crate) const TENANT_HEADER: &str = "X-Pdfmcr-Tenant";
+
+/// The maximum length of a tenant identifier.
+const MAX_TENANT_LENGTH: usize = 64;
+
+
+/// The tenant on whose behalf a request is made.
+///
+/// Each tenant has its own state file and its own subdirectory of the image directory. If no tenant
+/// secret is configured, all requests are made on behalf of the default tenant, which uses the
+/// state file and image directory from the configuration directly.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Tenant(Option<String>);
+impl Tenant {
+    /// Whether the given string is acceptable as a tenant identifier.
+    ///
+    /// Tenant identifiers are used as file and directory names; they are therefore restricted to
+    /// ASCII letters, digits, hyphens and underscores.
+    fn is_valid_identifier(identifier: &str) -> bool {
+        !identifier.is_empty()
+            && identifier.len() <= MAX_TENANT_LENGTH
+            && identifier.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    }
+
+    /// Verifies the value of a tenant header, returning the tenant if the signature is valid.
+    fn from_signed_value(value: &str, secret: &str) -> Option<Self> {
+        let (identifier, hex_signature) = value.rsplit_once('.')?;
+        if !Self::is_valid_identifier(identifier) {
+            return None;
+        }
+        if hex_signature.len() % 2 != 0 || !hex_signature.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let signature: Vec<u8> = (0..hex_signature.len()/2)
+            .map(|i| u8::from_str_radix(&hex_signature[2*i..2*i+2], 16).unwrap())
+            .collect();
+
+        let mut mac = Hmac::<Sha3_512>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(identifier.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        Some(Self(Some(identifier.to_owned())))
+    }
+
+    /// Returns the directory in which the images of this tenant are stored.
+    ///
+    /// Since tenant identifiers cannot contain path separators or `..`, and image paths cannot
+    /// escape the directory they are resolved against, a tenant cannot access another tenant's
+    /// images.
+    pub fn image_dir(&self, base_image_dir: &Path) -> PathBuf {
+        match self.0.as_ref() {
+            Some(identifier) => base_image_dir.join(identifier),
+            None => base_image_dir.to_owned(),
+        }
+    }
+
+    /// Returns the path of the state file of this tenant.
+    ///
+    /// The state file of a tenant is stored next to the configured state file, with the tenant
+    /// identifier inserted before the extension, e.g. `state.cbor` becomes `state.tenant.cbor`.
+    pub fn state_file_path(&self, base_state_file_path: &Path) -> PathBuf {
+        let Some(identifier) = self.0.as_ref() else {
+            return base_state_file_path.to_owned();
+        };
+        let mut file_name = base_state_file_path.file_stem()
+            .unwrap_or_default()
+            .to_owned();
+        file_name.push(".");
+        file_name.push(identifier);
+        if let Some(extension) = base_state_file_path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        base_state_file_path.with_file_name(file_name)
+    }
+
+    /// Returns the tenant with the given identifier.
+    #[cfg(test)]
+    pub(crate) fn named(identifier: &str) -> Self {
+        Self(Some(identifier.to_owned()))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Tenant {
+    type Error = Cow<'static, str>;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let tenant_secret = {
+            let config_guard = CONFIG
+                .get().expect("CONFIG not set?!")
+                .read().await;
+            config_guard.tenant_secret.clone()
+        };
+        let Some(tenant_secret) = tenant_secret else {
+            // multi-tenancy is not enabled
+            return Outcome::Success(Self::default());
+        };
+
+        let Some(header_value) = request.headers().get_one(TENANT_HEADER) else {
+            return Outcome::Error((Status::Unauthorized, Cow::Borrowed("tenant header missing")));
+        };
+        match Self::from_signed_value(header_value, &tenant_secret) {
+            Some(tenant) => Outcome::Success(tenant),
+            None => {
+                warn!("rejecting request with invalid tenant header {:?}", header_value);
+                Outcome::Error((Status::Forbidden, Cow::Borrowed("invalid tenant header")))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    use super::*;
+    use crate::test_support::{TENANT_SECRET, client, tenant_header, tenant_header_value, test_dir, upload_page};
+
+    async fn page_count(client: &Client, identifier: &str) -> u64 {
+        let response = client.get("/pages")
+            .header(tenant_header(identifier))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let listing: serde_json::Value = response.into_json().await.unwrap();
+        listing["total_count"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn verifies_signed_values() {
+        let value = tenant_header_value("alice");
+        assert_eq!(Tenant::from_signed_value(&value, TENANT_SECRET), Some(Tenant::named("alice")));
+        assert_eq!(Tenant::from_signed_value(&value, "another secret"), None);
+
+        // the signature does not carry over to another tenant
+        let (_identifier, signature) = value.rsplit_once('.').unwrap();
+        assert_eq!(Tenant::from_signed_value(&format!("bob.{}", signature), TENANT_SECRET), None);
+
+        // identifiers that could escape the image directory are refused even if signed
+        for identifier in ["..", "a/b", ""] {
+            assert_eq!(Tenant::from_signed_value(&tenant_header_value(identifier), TENANT_SECRET), None);
+        }
+    }
+
+    #[test]
+    fn separates_paths_of_tenants() {
+        let alice = Tenant::named("alice");
+        let bob = Tenant::named("bob");
+        let image_dir = Path::new("images");
+        let state_file_path = Path::new("state").join("state.cbor");
+
+        assert_eq!(Tenant::default().image_dir(image_dir), image_dir);
+        assert_eq!(alice.image_dir(image_dir), image_dir.join("alice"));
+        assert_ne!(alice.image_dir(image_dir), bob.image_dir(image_dir));
+
+        assert_eq!(Tenant::default().state_file_path(&state_file_path), state_file_path);
+        assert_eq!(alice.state_file_path(&state_file_path), Path::new("state").join("state.alice.cbor"));
+        assert_eq!(bob.state_file_path(&state_file_path), Path::new("state").join("state.bob.cbor"));
+    }
+
+    #[rocket::async_test]
+    async fn tenants_do_not_see_each_others_pages() {
+        let client = client().await;
+        upload_page(&client, "tenant-alice").await;
+        assert_eq!(page_count(&client, "tenant-alice").await, 1);
+        assert_eq!(page_count(&client, "tenant-bob").await, 0);
+
+        let response = client.get("/page/0/image")
+            .header(tenant_header("tenant-alice"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/page/0/image")
+            .header(tenant_header("tenant-bob"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        // each tenant has its own state file and image directory
+        assert!(test_dir().join("state.tenant-alice.cbor").is_file());
+        assert!(!test_dir().join("state.tenant-bob.cbor").exists());
+        assert!(test_dir().join("images").join("tenant-alice").is_dir());
+        assert!(!test_dir().join("images").join("tenant-bob").exists());
+    }
+
+    #[rocket::async_test]
+    async fn refuses_missing_or_forged_tenant_header() {
+        let client = client().await;
+        let response = client.get("/pages").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.get("/pages")
+            .header(Header::new(TENANT_HEADER, "tenant-mallory.00"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+}
@@ -0,0 +1,165 @@
+//! The environment shared by the tests of the server.
+//!
+//! The configuration and the states are global; they are set up once and shared by all tests, which
+//! keep out of each other's way by acting on behalf of different tenants.
+
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use hmac::{Hmac, Mac};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use sha3::Sha3_512;
+use tokio::sync::RwLock;
+
+use crate::WEB_FILES;
+use crate::config::{CONFIG, Config};
+use crate::tenant::TENANT_HEADER;
+
+
+/// The secret with which the tests sign tenant headers.
+pub(crate) const TENANT_SECRET: &str = "tenant secret for tests";
+
+/// The secret with which the tests authorize administrative requests.
+pub(crate) const ADMIN_SECRET: &str = "admin secret for tests";
+
+static TEST_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+
+/// Sets up the shared configuration and states if necessary, returning the directory containing
+/// the state files.
+///
+/// The images are stored in the `images` subdirectory.
+pub(crate) fn test_dir() -> &'static Path {
+    TEST_DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("pdfmcr-test-{}", std::process::id()));
+        let image_dir = dir.join("images");
+        std::fs::create_dir_all(&image_dir).unwrap();
+
+        let config: Config = toml::from_str(&format!(
+            "state_file_path = {:?}\nimage_dir = {:?}\ntenant_secret = {:?}\nadmin_secret = {:?}\n",
+            dir.join("state.cbor").to_str().unwrap(),
+            image_dir.to_str().unwrap(),
+            TENANT_SECRET,
+            ADMIN_SECRET,
+        )).unwrap();
+        CONFIG.set(RwLock::new(config)).expect("CONFIG already set?!");
+        WEB_FILES.set(RwLock::new(BTreeMap::new())).expect("WEB_FILES already set?!");
+        dir
+    })
+}
+
+/// Creates a client for the server using the shared configuration.
+pub(crate) async fn client() -> Client {
+    test_dir();
+    let config = CONFIG
+        .get().expect("CONFIG not set?!")
+        .read().await
+        .clone();
+    Client::untracked(crate::build_rocket(&config)).await.unwrap()
+}
+
+/// Returns the value of the tenant header identifying the given tenant.
+pub(crate) fn tenant_header_value(identifier: &str) -> String {
+    let mut mac = Hmac::<Sha3_512>::new_from_slice(TENANT_SECRET.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(identifier.as_bytes());
+    let mut value = format!("{}.", identifier);
+    for b in mac.finalize().into_bytes() {
+        value.push_str(&format!("{:02x}", b));
+    }
+    value
+}
+
+/// Returns the tenant header identifying the given tenant.
+pub(crate) fn tenant_header(identifier: &str) -> Header<'static> {
+    Header::new(TENANT_HEADER, tenant_header_value(identifier))
+}
+
+/// Assembles a grayscale JPEG file of the given size at 72 dpi.
+///
+/// Only the headers are meaningful; the image data is not actually decodable.
+pub(crate) fn jpeg_data(width: u16, height: u16) -> Vec<u8> {
+    let mut data = vec![0xFF, 0xD8];
+    // JFIF 1.01, 72 dpi, no thumbnail
+    data.extend_from_slice(b"\xFF\xE0\x00\x10JFIF\x00\x01\x01\x01\x00\x48\x00\x48\x00\x00");
+    // 8 bits, one component
+    data.extend_from_slice(b"\xFF\xC0\x00\x0B\x08");
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(b"\x01\x01\x11\x00");
+    data.extend_from_slice(b"\xFF\xDA\x00\x08\x01\x01\x00\x00\x3F\x00");
+    data.extend_from_slice(&[0x12, 0x34, 0x56]);
+    data.extend_from_slice(&[0xFF, 0xD9]);
+    data
+}
+
+/// Uploads the given data as the image of a multipart form to the given URI on behalf of the given
+/// tenant.
+pub(crate) async fn upload_image<'c>(
+    client: &'c Client,
+    tenant: &str,
+    uri: &str,
+    image_data: &[u8],
+) -> LocalResponse<'c> {
+    upload_file(client, tenant, uri, "background-image", "scan.jpeg", "image/jpeg", image_data).await
+}
+
+/// Uploads the given data as a file in the field with the given name of a multipart form to the
+/// given URI on behalf of the given tenant.
+pub(crate) async fn upload_file<'c>(
+    client: &'c Client,
+    tenant: &str,
+    uri: &str,
+    field_name: &str,
+    file_name: &str,
+    content_type: &str,
+    data: &[u8],
+) -> LocalResponse<'c> {
+    let boundary = "pdfmcr-test-boundary";
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+        boundary, field_name, file_name, content_type,
+    ).into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    client.post(uri.to_owned())
+        .header(tenant_header(tenant))
+        .header(ContentType::new("multipart", "form-data").with_params(("boundary", boundary)))
+        .body(body)
+        .dispatch().await
+}
+
+/// Uploads a page with a small grayscale image on behalf of the given tenant, returning the index
+/// of the new page.
+pub(crate) async fn upload_page(client: &Client, tenant: &str) -> usize {
+    let response = upload_image(client, tenant, "/page", &jpeg_data(16, 16)).await;
+    // redirects to the new page
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("Location").unwrap();
+    location.strip_prefix("/page/").unwrap().parse().unwrap()
+}
+
+
+/// Counts the files in the image directory containing exactly the given data.
+pub(crate) fn stored_copies(data: &[u8]) -> usize {
+    let mut copies = 0;
+    let mut pending_dirs = vec![test_dir().join("images")];
+    while let Some(dir) = pending_dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending_dirs.push(path);
+            } else if std::fs::read(&path).unwrap() == data {
+                copies += 1;
+            }
+        }
+    }
+    copies
+}
@@ -0,0 +1,1294 @@
+//! Parsing the Tagged Image File Format (TIFF).
+//!
+//! TIFF structures are used both as standalone image files (as output by many archival scanners)
+//! and to store Exif metadata within JPEG files.
+
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use from_to_repr::from_to_other;
+
+use crate::jpeg::DensityUnit;
+
+
+/// The quality with which TIFF images are transcoded into JPEG images unless specified otherwise.
+pub const DEFAULT_TRANSCODE_QUALITY: u8 = 90;
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const TAG_IMAGE_WIDTH: u16 = 0x0100;
+const TAG_IMAGE_LENGTH: u16 = 0x0101;
+const TAG_BITS_PER_SAMPLE: u16 = 0x0102;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 0x0106;
+const TAG_FILL_ORDER: u16 = 0x010A;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_SAMPLES_PER_PIXEL: u16 = 0x0115;
+const TAG_ROWS_PER_STRIP: u16 = 0x0116;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_X_RESOLUTION: u16 = 0x011A;
+const TAG_Y_RESOLUTION: u16 = 0x011B;
+const TAG_PLANAR_CONFIGURATION: u16 = 0x011C;
+const TAG_RESOLUTION_UNIT: u16 = 0x0128;
+const TAG_TILE_OFFSETS: u16 = 0x0144;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    ByteOrderIndicator { bytes: [u8; 2] },
+    Version { version: u16 },
+    BigPointerSize { size: u16 },
+    BigReserved { value: u16 },
+    UnknownType { data_type: ValueType },
+    IfdLoop { offset: u64 },
+    ValueCount { tag: u16, count: u64 },
+    MultipleImages { count: usize },
+    MissingTag { tag: u16 },
+    InvalidTagValue { tag: u16 },
+    Tiled,
+    StripCount { offsets: usize, byte_counts: usize },
+    UnsupportedCompression { compression: Compression },
+    UnsupportedPixelFormat {
+        photometric_interpretation: PhotometricInterpretation,
+        bits_per_sample: Vec<u16>,
+    },
+    UnsupportedPlanarConfiguration { planar_configuration: u16 },
+    TooLarge { width: u32, height: u32 },
+    DensityTooLarge { density_x: u32, density_y: u32 },
+    DataLength { expected: usize, obtained: usize },
+    MultipleStrips { count: usize },
+    Encoding(jpeg_encoder::EncodingError),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+            Self::ByteOrderIndicator { bytes }
+                => write!(f, "unrecognized byte order indicator 0x{:02X} 0x{:02X}", bytes[0], bytes[1]),
+            Self::Version { version }
+                => write!(f, "unknown TIFF version {}", version),
+            Self::BigPointerSize { size }
+                => write!(f, "unexpected BigTIFF pointer size {}", size),
+            Self::BigReserved { value }
+                => write!(f, "unexpected BigTIFF reserved value {}", value),
+            Self::UnknownType { data_type }
+                => write!(f, "unknown data type {:?}", data_type),
+            Self::IfdLoop { offset }
+                => write!(f, "image file directory at offset {} is referenced multiple times", offset),
+            Self::ValueCount { tag, count }
+                => write!(f, "tag 0x{:04X} has too many values ({})", tag, count),
+            Self::MultipleImages { count }
+                => write!(f, "expected a single image, obtained {}", count),
+            Self::MissingTag { tag }
+                => write!(f, "required tag 0x{:04X} is missing", tag),
+            Self::InvalidTagValue { tag }
+                => write!(f, "tag 0x{:04X} has an invalid value", tag),
+            Self::Tiled
+                => write!(f, "tiled images are not supported"),
+            Self::StripCount { offsets, byte_counts }
+                => write!(f, "image has {} strip offsets but {} strip byte counts", offsets, byte_counts),
+            Self::UnsupportedCompression { compression }
+                => write!(f, "unsupported compression {:?}", compression),
+            Self::UnsupportedPixelFormat { photometric_interpretation, bits_per_sample }
+                => write!(f, "unsupported pixel format {:?} with bits per sample {:?}", photometric_interpretation, bits_per_sample),
+            Self::UnsupportedPlanarConfiguration { planar_configuration }
+                => write!(f, "unsupported planar configuration {}", planar_configuration),
+            Self::TooLarge { width, height }
+                => write!(f, "image of {}x{} pixels is too large to be transcoded", width, height),
+            Self::DensityTooLarge { density_x, density_y }
+                => write!(f, "pixel density {}x{} is too large to be transcoded", density_x, density_y),
+            Self::DataLength { expected, obtained }
+                => write!(f, "expected {} bytes of image data, obtained {}", expected, obtained),
+            Self::MultipleStrips { count }
+                => write!(f, "expected the image data in a single strip, obtained {} strips", count),
+            Self::Encoding(e)
+                => write!(f, "failed to encode JPEG: {}", e),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::ByteOrderIndicator { .. } => None,
+            Self::Version { .. } => None,
+            Self::BigPointerSize { .. } => None,
+            Self::BigReserved { .. } => None,
+            Self::UnknownType { .. } => None,
+            Self::IfdLoop { .. } => None,
+            Self::ValueCount { .. } => None,
+            Self::MultipleImages { .. } => None,
+            Self::MissingTag { .. } => None,
+            Self::InvalidTagValue { .. } => None,
+            Self::Tiled => None,
+            Self::StripCount { .. } => None,
+            Self::UnsupportedCompression { .. } => None,
+            Self::UnsupportedPixelFormat { .. } => None,
+            Self::UnsupportedPlanarConfiguration { .. } => None,
+            Self::TooLarge { .. } => None,
+            Self::DensityTooLarge { .. } => None,
+            Self::DataLength { .. } => None,
+            Self::MultipleStrips { .. } => None,
+            Self::Encoding(e) => Some(e),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self { Self::Io(value) }
+}
+impl From<jpeg_encoder::EncodingError> for Error {
+    fn from(value: jpeg_encoder::EncodingError) -> Self { Self::Encoding(value) }
+}
+
+
+macro_rules! impl_read {
+    ($name:ident, $type:ty, $buf_size:expr) => {
+        fn $name(&mut self) -> Result<$type, Error> {
+            let mut buf = [0u8; $buf_size];
+            self.reader.read_exact(&mut buf)?;
+            Ok(if self.big_endian {
+                <$type>::from_be_bytes(buf)
+            } else {
+                <$type>::from_le_bytes(buf)
+            })
+        }
+    };
+}
+
+
+struct Reader<R: Read + Seek> {
+    reader: R,
+    big_endian: bool,
+    ptr64: bool,
+}
+impl<R: Read + Seek> Reader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut byte_order_buf = [0u8; 2];
+        reader.read_exact(&mut byte_order_buf)?;
+        let big_endian = match (byte_order_buf[0], byte_order_buf[1]) {
+            (0x4D, 0x4D) => true, // MM (Motorola Motorola)
+            (0x49, 0x49) => false, // II (Intel Intel)
+            _ => return Err(Error::ByteOrderIndicator { bytes: byte_order_buf }),
+        };
+        let mut tiff_reader = Self {
+            reader,
+            big_endian,
+            ptr64: false,
+        };
+
+        // read 16 bytes
+        let version = tiff_reader.read_u16()?;
+        match version {
+            42 => {
+                // standard TIFF
+            },
+            43 => {
+                // BigTIFF
+                tiff_reader.ptr64 = true;
+            },
+            other => return Err(Error::Version { version: other }),
+        }
+
+        if tiff_reader.ptr64 {
+            // we have a few more fields in the header
+            let pointer_size = tiff_reader.read_u16()?;
+            if pointer_size != 8 {
+                return Err(Error::BigPointerSize { size: pointer_size });
+            }
+
+            let reserved = tiff_reader.read_u16()?;
+            if reserved != 0 {
+                return Err(Error::BigReserved { value: reserved });
+            }
+        }
+
+        // where is the first directory?
+        let dir_offset = tiff_reader.read_offset()?;
+
+        // go there
+        tiff_reader.reader.seek(SeekFrom::Start(dir_offset))?;
+
+        Ok(tiff_reader)
+    }
+
+    fn read_i8(&mut self) -> Result<i8, Error> {
+        let mut buf = [0u8];
+        self.reader.read_exact(&mut buf)?;
+        Ok(i8::from_ne_bytes(buf))
+    }
+
+    impl_read!(read_u16, u16, 2);
+    impl_read!(read_u32, u32, 4);
+    impl_read!(read_u64, u64, 8);
+    impl_read!(read_i16, i16, 2);
+    impl_read!(read_i32, i32, 4);
+    impl_read!(read_i64, i64, 8);
+    impl_read!(read_f32, f32, 4);
+    impl_read!(read_f64, f64, 8);
+
+    fn read_offset(&mut self) -> Result<u64, Error> {
+        if self.ptr64 {
+            self.read_u64()
+        } else {
+            self.read_u32()
+                .map(|i| i.into())
+        }
+    }
+
+    fn read_ifd_entry_count(&mut self) -> Result<u64, Error> {
+        if self.ptr64 {
+            self.read_u64()
+        } else {
+            self.read_u16()
+                .map(|i| i.into())
+        }
+    }
+
+    fn read_value_count(&mut self, tag: u16) -> Result<u32, Error> {
+        if self.ptr64 {
+            let count = self.read_u64()?;
+            count.try_into()
+                .map_err(|_| Error::ValueCount { tag, count })
+        } else {
+            self.read_u32()
+        }
+    }
+
+    fn read_type(&mut self) -> Result<ValueType, Error> {
+        let base_value = self.read_u16()?;
+        Ok(ValueType::from_base_type(base_value))
+    }
+
+    fn read_value_or_pointer(&mut self, tag: u16, kind: ValueType, count: u32) -> Result<ValueOrPointer, Error> {
+        let mut inline_buf = [0u8; 8];
+        let inline_slice = if self.ptr64 {
+            self.reader.read_exact(&mut inline_buf)?;
+            &inline_buf[..]
+        } else {
+            self.reader.read_exact(&mut inline_buf[0..4])?;
+            &inline_buf[0..4]
+        };
+
+        // how much space does this value need?
+        let single_value_size = match kind.single_element_size() {
+            Some(svs) => svs,
+            None => {
+                // unknown value type; return the inline bytes raw
+                return Ok(ValueOrPointer::Unknown {
+                    tag,
+                    value_type: kind,
+                    count,
+                    inline_data: inline_buf,
+                });
+            },
+        };
+
+        // how much space do all the values need?
+        // (if that overflows, they certainly do not fit inline)
+        let total_size = single_value_size.checked_mul(usize::try_from(count).unwrap());
+        if total_size.is_none_or(|ts| ts > inline_slice.len()) {
+            // it's a pointer
+            let pointer_value: u64 = match (self.ptr64, self.big_endian) {
+                (false, false) => u32::from_le_bytes(inline_buf[0..4].try_into().unwrap()).into(),
+                (false, true) => u32::from_be_bytes(inline_buf[0..4].try_into().unwrap()).into(),
+                (true, false) => u64::from_le_bytes(inline_buf),
+                (true, true) => u64::from_be_bytes(inline_buf),
+            };
+            Ok(ValueOrPointer::Pointer {
+                tag,
+                value_type: kind,
+                count,
+                pointer: pointer_value,
+            })
+        } else {
+            // it fits inline
+            let inline_cursor = Cursor::new(inline_slice);
+            let mut inline_reader = Reader {
+                reader: inline_cursor,
+                big_endian: self.big_endian,
+                ptr64: self.ptr64,
+            };
+            let values = inline_reader.read_values(tag, kind, count)?;
+            Ok(ValueOrPointer::Value {
+                tag,
+                values,
+            })
+        }
+    }
+
+    fn remaining_length(&mut self) -> Result<u64, Error> {
+        let position = self.reader.stream_position()?;
+        let end = self.reader.seek(SeekFrom::End(0))?;
+        self.reader.seek(SeekFrom::Start(position))?;
+        Ok(end.saturating_sub(position))
+    }
+
+    fn read_values(&mut self, tag: u16, kind: ValueType, count: u32) -> Result<Values, Error> {
+        // the count is taken from the file; make sure the values can actually be there
+        // before reading them (and never allocate up front according to the count)
+        let count_usize: usize = count.try_into().unwrap();
+        let single_value_size = kind.single_element_size()
+            .ok_or(Error::UnknownType { data_type: kind })?;
+        let too_many = || Error::ValueCount { tag, count: count.into() };
+        let total_size = single_value_size.checked_mul(count_usize)
+            .and_then(|ts| u64::try_from(ts).ok())
+            .ok_or_else(too_many)?;
+        if total_size > self.remaining_length()? {
+            return Err(too_many());
+        }
+
+        match kind {
+            ValueType::Byte|ValueType::Ascii|ValueType::Undefined => {
+                let mut buf = Vec::new();
+                (&mut self.reader).take(total_size).read_to_end(&mut buf)?;
+                if buf.len() != count_usize {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                match kind {
+                    ValueType::Byte => Ok(Values::Byte(buf)),
+                    ValueType::Ascii => Ok(Values::Ascii(buf)),
+                    ValueType::Undefined => Ok(Values::Undefined(buf)),
+                    _ => unreachable!(),
+                }
+            },
+            ValueType::Short => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_u16()?;
+                    buf.push(value);
+                }
+                Ok(Values::Short(buf))
+            },
+            ValueType::Long => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_u32()?;
+                    buf.push(value);
+                }
+                Ok(Values::Long(buf))
+            },
+            ValueType::Rational => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let numerator = self.read_u32()?;
+                    let denominator = self.read_u32()?;
+                    buf.push((numerator, denominator));
+                }
+                Ok(Values::Rational(buf))
+            },
+            ValueType::SByte => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_i8()?;
+                    buf.push(value);
+                }
+                Ok(Values::SByte(buf))
+            },
+            ValueType::SShort => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_i16()?;
+                    buf.push(value);
+                }
+                Ok(Values::SShort(buf))
+            },
+            ValueType::SLong => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_i32()?;
+                    buf.push(value);
+                }
+                Ok(Values::SLong(buf))
+            },
+            ValueType::SRational => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let numerator = self.read_i32()?;
+                    let denominator = self.read_i32()?;
+                    buf.push((numerator, denominator));
+                }
+                Ok(Values::SRational(buf))
+            },
+            ValueType::Float => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_f32()?;
+                    buf.push(value);
+                }
+                Ok(Values::Float(buf))
+            },
+            ValueType::Double => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_f64()?;
+                    buf.push(value);
+                }
+                Ok(Values::Double(buf))
+            },
+            ValueType::Ifd => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_u32()?;
+                    buf.push(value);
+                }
+                Ok(Values::Ifd(buf))
+            },
+            ValueType::Long8 => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_u64()?;
+                    buf.push(value);
+                }
+                Ok(Values::Long8(buf))
+            },
+            ValueType::SLong8 => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_i64()?;
+                    buf.push(value);
+                }
+                Ok(Values::SLong8(buf))
+            },
+            ValueType::Ifd8 => {
+                let mut buf = Vec::new();
+                for _ in 0..count_usize {
+                    let value = self.read_u64()?;
+                    buf.push(value);
+                }
+                Ok(Values::Ifd8(buf))
+            },
+            ValueType::Other(_) => Err(Error::UnknownType { data_type: kind }),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+#[from_to_other(base_type = u16, derive_compare = "as_int")]
+pub enum ValueType {
+    Byte = 1,
+    Ascii = 2,
+    Short = 3,
+    Long = 4,
+    Rational = 5,
+    SByte = 6,
+    Undefined = 7,
+    SShort = 8,
+    SLong = 9,
+    SRational = 10,
+    Float = 11,
+    Double = 12,
+    Ifd = 13,
+    // Unicode = 14, Complex = 15, Adobe-internal assignments
+    Long8 = 16,
+    SLong8 = 17,
+    Ifd8 = 18,
+    Other(u16),
+}
+impl ValueType {
+    pub fn single_element_size(&self) -> Option<usize> {
+        match self {
+            Self::Byte|Self::Ascii|Self::SByte|Self::Undefined => Some(1),
+            Self::Short|Self::SShort => Some(2),
+            Self::Long|Self::SLong|Self::Float|Self::Ifd => Some(4),
+            Self::Rational|Self::SRational|Self::Double|Self::Long8|Self::SLong8|Self::Ifd8 => Some(8),
+            Self::Other(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum Values {
+    Byte(Vec<u8>),
+    Ascii(Vec<u8>),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    Ifd(Vec<u32>),
+    Long8(Vec<u64>),
+    SLong8(Vec<i64>),
+    Ifd8(Vec<u64>),
+}
+impl Values {
+    /// Returns the values as unsigned integers, if they are of an unsigned integer type.
+    fn to_unsigned(&self) -> Option<Vec<u64>> {
+        match self {
+            Self::Byte(vals) => Some(vals.iter().map(|v| (*v).into()).collect()),
+            Self::Short(vals) => Some(vals.iter().map(|v| (*v).into()).collect()),
+            Self::Long(vals) => Some(vals.iter().map(|v| (*v).into()).collect()),
+            Self::Long8(vals) => Some(vals.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an unsigned integer if this is exactly one value of an integer type.
+    ///
+    /// Values of signed integer types are only returned if they are not negative.
+    pub fn as_single_u64(&self) -> Option<u64> {
+        fn single<T: Copy>(vals: &[T]) -> Option<T> {
+            match vals {
+                [val] => Some(*val),
+                _ => None,
+            }
+        }
+
+        match self {
+            Self::Byte(vals) => single(vals).map(|v| v.into()),
+            Self::Short(vals) => single(vals).map(|v| v.into()),
+            Self::Long(vals) => single(vals).map(|v| v.into()),
+            Self::Long8(vals) => single(vals),
+            Self::SByte(vals) => single(vals).and_then(|v| v.try_into().ok()),
+            Self::SShort(vals) => single(vals).and_then(|v| v.try_into().ok()),
+            Self::SLong(vals) => single(vals).and_then(|v| v.try_into().ok()),
+            Self::SLong8(vals) => single(vals).and_then(|v| v.try_into().ok()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum ValueOrPointer {
+    Value { tag: u16, values: Values },
+    Pointer { tag: u16, value_type: ValueType, count: u32, pointer: u64 },
+    Unknown { tag: u16, value_type: ValueType, count: u32, inline_data: [u8; 8] },
+}
+impl ValueOrPointer {
+    pub fn tag(&self) -> u16 {
+        match self {
+            Self::Value { tag, .. } => *tag,
+            Self::Pointer { tag, .. } => *tag,
+            Self::Unknown { tag, .. } => *tag,
+        }
+    }
+
+    pub fn value(&self) -> Option<&Values> {
+        match self {
+            Self::Value { values, .. } => Some(values),
+            _ => None,
+        }
+    }
+}
+
+
+/// Returns the values of the entry with the given tag in an image file directory.
+pub fn find_values(ifd: &[ValueOrPointer], tag: u16) -> Option<&Values> {
+    ifd.iter()
+        .filter(|v| v.tag() == tag)
+        .filter_map(|v| v.value())
+        .next()
+}
+
+/// Reads all image file directories (IFDs) of a TIFF structure, resolving values stored outside of
+/// the directories themselves.
+///
+/// Returns the reader positioned at an unspecified location alongside the directories.
+fn read_ifds_with_reader<R: Read + Seek>(reader: R) -> Result<(Reader<R>, Vec<Vec<ValueOrPointer>>), Error> {
+    let mut tiff = Reader::new(reader)?;
+
+    let mut ifds_values = Vec::new();
+    let mut seen_offsets = BTreeSet::new();
+    seen_offsets.insert(tiff.reader.stream_position()?);
+
+    loop {
+        // how many entries in the IFD do we have?
+        let ifd_entry_count = tiff.read_ifd_entry_count()?;
+        let mut values = Vec::new();
+
+        // run through them, collecting the values
+        for _ in 0..ifd_entry_count {
+            let tag = tiff.read_u16()?;
+            let kind = tiff.read_type()?;
+            let count = tiff.read_value_count(tag)?;
+
+            let value_or_pointer = tiff.read_value_or_pointer(tag, kind, count)?;
+            values.push(value_or_pointer);
+        }
+
+        ifds_values.push(values);
+
+        // the next value is the pointer to the next IFD
+        let next_ifd_offset = tiff.read_offset()?;
+        if next_ifd_offset == 0 {
+            // we are done
+            break;
+        }
+        if !seen_offsets.insert(next_ifd_offset) {
+            return Err(Error::IfdLoop { offset: next_ifd_offset });
+        }
+
+        tiff.reader.seek(SeekFrom::Start(next_ifd_offset))?;
+    }
+
+    // dereference the pointers
+    for values in &mut ifds_values {
+        for value in values {
+            if let ValueOrPointer::Pointer { tag, value_type, count, pointer } = value {
+                tiff.reader.seek(SeekFrom::Start(*pointer))?;
+                let values = tiff.read_values(*tag, *value_type, *count)?;
+                *value = ValueOrPointer::Value { tag: *tag, values };
+            }
+        }
+    }
+
+    Ok((tiff, ifds_values))
+}
+
+/// Reads all image file directories (IFDs) of a TIFF structure, resolving values stored outside of
+/// the directories themselves.
+pub fn read_ifds<R: Read + Seek>(reader: R) -> Result<Vec<Vec<ValueOrPointer>>, Error> {
+    read_ifds_with_reader(reader)
+        .map(|(_reader, ifds)| ifds)
+}
+
+
+#[derive(Clone, Copy, Debug)]
+#[from_to_other(base_type = u16, derive_compare = "as_int")]
+pub enum Compression {
+    Uncompressed = 1,
+    CcittModifiedHuffman = 2,
+    CcittGroup3 = 3,
+    CcittGroup4 = 4,
+    Lzw = 5,
+    OldJpeg = 6,
+    Jpeg = 7,
+    Deflate = 8,
+    PackBits = 32773,
+    Other(u16),
+}
+
+#[derive(Clone, Copy, Debug)]
+#[from_to_other(base_type = u16, derive_compare = "as_int")]
+pub enum PhotometricInterpretation {
+    WhiteIsZero = 0,
+    BlackIsZero = 1,
+    Rgb = 2,
+    Palette = 3,
+    TransparencyMask = 4,
+    Cmyk = 5,
+    YCbCr = 6,
+    CieLab = 8,
+    Other(u16),
+}
+
+
+/// An image stored in a TIFF file.
+///
+/// Only images stored in strips with interleaved samples (planar configuration 1) are supported.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_sample: Vec<u16>,
+    pub compression: Compression,
+    pub photometric_interpretation: PhotometricInterpretation,
+    pub rows_per_strip: u32,
+
+    /// Whether the pixels of bilevel images are packed starting with the least significant bit of
+    /// each byte.
+    pub least_significant_bit_first: bool,
+
+    pub density_unit: DensityUnit,
+    pub density_x: u32,
+    pub density_y: u32,
+
+    /// The (possibly compressed) data of each strip, from top to bottom.
+    pub strips: Vec<Vec<u8>>,
+}
+impl Image {
+    /// Reads a TIFF file containing a single image.
+    pub fn try_read<R: Read + Seek>(reader: R) -> Result<Self, Error> {
+        let mut images = Self::try_read_all(reader)?;
+        if images.len() != 1 {
+            return Err(Error::MultipleImages { count: images.len() });
+        }
+        Ok(images.remove(0))
+    }
+
+    /// Reads all images (e.g. the pages of a multi-page scan) from a TIFF file, in order.
+    ///
+    /// Reduced-resolution versions of other images (thumbnails) are skipped.
+    pub fn try_read_all<R: Read + Seek>(reader: R) -> Result<Vec<Self>, Error> {
+        let (mut tiff, ifds) = read_ifds_with_reader(reader)?;
+        let mut images = Vec::with_capacity(ifds.len());
+        for ifd in &ifds {
+            let new_subfile_type = find_values(ifd, TAG_NEW_SUBFILE_TYPE)
+                .and_then(|values| values.to_unsigned())
+                .and_then(|vals| vals.first().copied())
+                .unwrap_or(0);
+            if new_subfile_type & 0x1 != 0 {
+                // reduced-resolution image
+                continue;
+            }
+            images.push(Self::from_ifd(&mut tiff.reader, ifd)?);
+        }
+        Ok(images)
+    }
+
+    fn from_ifd<R: Read + Seek>(reader: &mut R, ifd: &[ValueOrPointer]) -> Result<Self, Error> {
+        let unsigned_values = |tag: u16| -> Result<Option<Vec<u64>>, Error> {
+            match find_values(ifd, tag) {
+                Some(values) => values.to_unsigned()
+                    .map(Some)
+                    .ok_or(Error::InvalidTagValue { tag }),
+                None => Ok(None),
+            }
+        };
+        let single_value = |tag: u16| -> Result<Option<u64>, Error> {
+            find_values(ifd, tag)
+                .map(|values| values.as_single_u64().ok_or(Error::InvalidTagValue { tag }))
+                .transpose()
+        };
+        let resolution = |tag: u16| -> Result<Option<u32>, Error> {
+            match find_values(ifd, tag) {
+                Some(Values::Rational(vals)) if vals.len() == 1 && vals[0].1 != 0
+                    => Ok(Some(vals[0].0 / vals[0].1)),
+                Some(_) => Err(Error::InvalidTagValue { tag }),
+                None => Ok(None),
+            }
+        };
+
+        if find_values(ifd, TAG_TILE_OFFSETS).is_some() {
+            return Err(Error::Tiled);
+        }
+
+        let width: u32 = single_value(TAG_IMAGE_WIDTH)?
+            .ok_or(Error::MissingTag { tag: TAG_IMAGE_WIDTH })?
+            .try_into().map_err(|_| Error::InvalidTagValue { tag: TAG_IMAGE_WIDTH })?;
+        let height: u32 = single_value(TAG_IMAGE_LENGTH)?
+            .ok_or(Error::MissingTag { tag: TAG_IMAGE_LENGTH })?
+            .try_into().map_err(|_| Error::InvalidTagValue { tag: TAG_IMAGE_LENGTH })?;
+
+        // defaults according to TIFF 6.0
+        let samples_per_pixel = single_value(TAG_SAMPLES_PER_PIXEL)?.unwrap_or(1);
+        let bits_per_sample: Vec<u16> = match unsigned_values(TAG_BITS_PER_SAMPLE)? {
+            Some(vals) => vals.into_iter()
+                .map(|v| v.try_into().map_err(|_| Error::InvalidTagValue { tag: TAG_BITS_PER_SAMPLE }))
+                .collect::<Result<_, _>>()?,
+            None => vec![1],
+        };
+        if u64::try_from(bits_per_sample.len()).unwrap() != samples_per_pixel {
+            return Err(Error::InvalidTagValue { tag: TAG_BITS_PER_SAMPLE });
+        }
+        let compression = single_value(TAG_COMPRESSION)?
+            .unwrap_or(1);
+        let compression = Compression::from_base_type(
+            compression.try_into().map_err(|_| Error::InvalidTagValue { tag: TAG_COMPRESSION })?
+        );
+        let photometric_interpretation = single_value(TAG_PHOTOMETRIC_INTERPRETATION)?
+            .ok_or(Error::MissingTag { tag: TAG_PHOTOMETRIC_INTERPRETATION })?;
+        let photometric_interpretation = PhotometricInterpretation::from_base_type(
+            photometric_interpretation.try_into().map_err(|_| Error::InvalidTagValue { tag: TAG_PHOTOMETRIC_INTERPRETATION })?
+        );
+        let planar_configuration = single_value(TAG_PLANAR_CONFIGURATION)?.unwrap_or(1);
+        if planar_configuration != 1 {
+            return Err(Error::UnsupportedPlanarConfiguration {
+                planar_configuration: planar_configuration.try_into().unwrap_or(u16::MAX),
+            });
+        }
+        let rows_per_strip = single_value(TAG_ROWS_PER_STRIP)?
+            .map(|rps| u32::try_from(rps).unwrap_or(u32::MAX))
+            .unwrap_or(u32::MAX)
+            .min(height);
+        let least_significant_bit_first = match single_value(TAG_FILL_ORDER)?.unwrap_or(1) {
+            1 => false,
+            2 => true,
+            _ => return Err(Error::InvalidTagValue { tag: TAG_FILL_ORDER }),
+        };
+
+        // like Exif, fall back to 72 dpi
+        let density_x = resolution(TAG_X_RESOLUTION)?.unwrap_or(72);
+        let density_y = resolution(TAG_Y_RESOLUTION)?.unwrap_or(density_x);
+        let density_unit = match single_value(TAG_RESOLUTION_UNIT)?.unwrap_or(2) {
+            1 => DensityUnit::NoUnit,
+            2 => DensityUnit::DotsPerInch,
+            3 => DensityUnit::DotsPerCentimeter,
+            _ => return Err(Error::InvalidTagValue { tag: TAG_RESOLUTION_UNIT }),
+        };
+
+        // collect the strips
+        let strip_offsets = unsigned_values(TAG_STRIP_OFFSETS)?
+            .ok_or(Error::MissingTag { tag: TAG_STRIP_OFFSETS })?;
+        let strip_byte_counts = unsigned_values(TAG_STRIP_BYTE_COUNTS)?
+            .ok_or(Error::MissingTag { tag: TAG_STRIP_BYTE_COUNTS })?;
+        if strip_offsets.len() != strip_byte_counts.len() {
+            return Err(Error::StripCount { offsets: strip_offsets.len(), byte_counts: strip_byte_counts.len() });
+        }
+        let mut strips = Vec::with_capacity(strip_offsets.len());
+        for (&offset, &byte_count) in strip_offsets.iter().zip(strip_byte_counts.iter()) {
+            reader.seek(SeekFrom::Start(offset))?;
+
+            // do not trust the byte count enough to preallocate the memory
+            let mut strip = Vec::new();
+            reader.by_ref().take(byte_count).read_to_end(&mut strip)?;
+            if u64::try_from(strip.len()).unwrap() != byte_count {
+                return Err(Error::DataLength {
+                    expected: byte_count.try_into().unwrap_or(usize::MAX),
+                    obtained: strip.len(),
+                });
+            }
+            strips.push(strip);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            bits_per_sample,
+            compression,
+            photometric_interpretation,
+            rows_per_strip,
+            least_significant_bit_first,
+            density_unit,
+            density_x,
+            density_y,
+            strips,
+        })
+    }
+
+    /// The number of bytes occupied by a single row of uncompressed pixels.
+    fn row_byte_count(&self) -> Result<usize, Error> {
+        let bits_per_pixel: usize = self.bits_per_sample.iter()
+            .map(|b| usize::from(*b))
+            .sum();
+        usize::try_from(self.width).unwrap()
+            .checked_mul(bits_per_pixel)
+            .map(|bits| bits.div_ceil(8))
+            .ok_or(Error::TooLarge { width: self.width, height: self.height })
+    }
+
+    /// Returns the uncompressed pixel data of the image, row by row.
+    pub fn decompress(&self) -> Result<Vec<u8>, Error> {
+        // the dimensions are taken from the file; the pixel buffer grows with the data actually
+        // present in the strips instead of being allocated according to them
+        let too_large = || Error::TooLarge { width: self.width, height: self.height };
+        let row_byte_count = self.row_byte_count()?;
+        let rows_per_strip = usize::try_from(self.rows_per_strip).unwrap();
+        let mut remaining_rows = usize::try_from(self.height).unwrap();
+        let mut pixels = Vec::new();
+        for strip in &self.strips {
+            if remaining_rows == 0 {
+                break;
+            }
+            let strip_rows = rows_per_strip.min(remaining_rows);
+            let strip_byte_count = strip_rows.checked_mul(row_byte_count)
+                .ok_or_else(too_large)?;
+            match self.compression {
+                Compression::Uncompressed => {
+                    if strip.len() < strip_byte_count {
+                        return Err(Error::DataLength { expected: strip_byte_count, obtained: strip.len() });
+                    }
+                    pixels.extend_from_slice(&strip[..strip_byte_count]);
+                },
+                Compression::PackBits => {
+                    let unpacked = unpack_bits(strip, strip_byte_count);
+                    if unpacked.len() < strip_byte_count {
+                        return Err(Error::DataLength { expected: strip_byte_count, obtained: unpacked.len() });
+                    }
+                    pixels.extend_from_slice(&unpacked);
+                },
+                other => return Err(Error::UnsupportedCompression { compression: other }),
+            }
+            remaining_rows -= strip_rows;
+        }
+        if remaining_rows > 0 {
+            let expected = row_byte_count.checked_mul(usize::try_from(self.height).unwrap())
+                .ok_or_else(too_large)?;
+            return Err(Error::DataLength { expected, obtained: pixels.len() });
+        }
+        Ok(pixels)
+    }
+
+    /// Transcodes the image into a JPEG image with the same dimensions and pixel density.
+    ///
+    /// Grayscale and RGB images with 8 bits per sample as well as bilevel images are supported.
+    pub fn to_jpeg(&self) -> Result<Vec<u8>, Error> {
+        self.to_jpeg_with_quality(DEFAULT_TRANSCODE_QUALITY)
+    }
+
+    /// Transcodes the image into a JPEG image with the same dimensions and pixel density, using the
+    /// given quality (1 to 100).
+    pub fn to_jpeg_with_quality(&self, quality: u8) -> Result<Vec<u8>, Error> {
+        let (Ok(width), Ok(height)) = (u16::try_from(self.width), u16::try_from(self.height)) else {
+            return Err(Error::TooLarge { width: self.width, height: self.height });
+        };
+        let (Ok(density_x), Ok(density_y)) = (u16::try_from(self.density_x), u16::try_from(self.density_y)) else {
+            return Err(Error::DensityTooLarge { density_x: self.density_x, density_y: self.density_y });
+        };
+        let density = match self.density_unit {
+            DensityUnit::DotsPerInch => jpeg_encoder::Density::Inch { x: density_x, y: density_y },
+            DensityUnit::DotsPerCentimeter => jpeg_encoder::Density::Centimeter { x: density_x, y: density_y },
+            _ => jpeg_encoder::Density::None,
+        };
+
+        let unsupported_pixel_format = || Error::UnsupportedPixelFormat {
+            photometric_interpretation: self.photometric_interpretation,
+            bits_per_sample: self.bits_per_sample.clone(),
+        };
+        let pixels = self.decompress()?;
+        let (samples, color_type) = match (self.photometric_interpretation, self.bits_per_sample.as_slice()) {
+            (PhotometricInterpretation::BlackIsZero, [8]) => (pixels, jpeg_encoder::ColorType::Luma),
+            (PhotometricInterpretation::WhiteIsZero, [8]) => {
+                let inverted = pixels.into_iter()
+                    .map(|p| 0xFF - p)
+                    .collect();
+                (inverted, jpeg_encoder::ColorType::Luma)
+            },
+            (PhotometricInterpretation::BlackIsZero|PhotometricInterpretation::WhiteIsZero, [1]) => {
+                // expand to one byte per pixel
+                let white_bit = if self.photometric_interpretation == PhotometricInterpretation::BlackIsZero { 1 } else { 0 };
+                let row_byte_count = self.row_byte_count()?;
+                let mut expanded = Vec::new();
+                for row in pixels.chunks(row_byte_count) {
+                    for x in 0..usize::from(width) {
+                        let shift = if self.least_significant_bit_first { x % 8 } else { 7 - (x % 8) };
+                        let bit = (row[x / 8] >> shift) & 0x01;
+                        expanded.push(if bit == white_bit { 0xFF } else { 0x00 });
+                    }
+                }
+                (expanded, jpeg_encoder::ColorType::Luma)
+            },
+            (PhotometricInterpretation::Rgb, [8, 8, 8]) => (pixels, jpeg_encoder::ColorType::Rgb),
+            (PhotometricInterpretation::Rgb, [8, 8, 8, 8]) => (pixels, jpeg_encoder::ColorType::Rgba),
+            _ => return Err(unsupported_pixel_format()),
+        };
+
+        let mut jpeg_data = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg_data, quality);
+        encoder.set_density(density);
+        encoder.encode(&samples, width, height, color_type)?;
+        Ok(jpeg_data)
+    }
+
+    /// Returns the data of a bilevel image compressed using CCITT Group 4, as expected by the PDF
+    /// `/CCITTFaxDecode` filter.
+    ///
+    /// Since each strip is compressed independently, only images stored in a single strip are
+    /// supported.
+    pub fn ccitt_group4_data(&self) -> Result<Vec<u8>, Error> {
+        if self.compression != Compression::CcittGroup4 {
+            return Err(Error::UnsupportedCompression { compression: self.compression });
+        }
+        if self.bits_per_sample != [1] {
+            return Err(Error::UnsupportedPixelFormat {
+                photometric_interpretation: self.photometric_interpretation,
+                bits_per_sample: self.bits_per_sample.clone(),
+            });
+        }
+        if self.strips.len() != 1 {
+            return Err(Error::MultipleStrips { count: self.strips.len() });
+        }
+        let mut data = self.strips[0].clone();
+        if self.least_significant_bit_first {
+            // PDF expects the most significant bit first
+            for byte in &mut data {
+                *byte = byte.reverse_bits();
+            }
+        }
+        Ok(data)
+    }
+}
+
+
+/// Whether the given data starts with a TIFF header.
+pub fn is_tiff(data: &[u8]) -> bool {
+    data.starts_with(b"II\x2A\x00")
+        || data.starts_with(b"MM\x00\x2A")
+        || data.starts_with(b"II\x2B\x00")
+        || data.starts_with(b"MM\x00\x2B")
+}
+
+
+/// Decodes PackBits-compressed data, stopping once the given number of bytes has been obtained.
+fn unpack_bits(data: &[u8], max_length: usize) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < data.len() && ret.len() < max_length {
+        let header = data[i] as i8;
+        i += 1;
+        if header >= 0 {
+            // copy the next header+1 bytes literally
+            let count = usize::try_from(header).unwrap() + 1;
+            let end = (i + count).min(data.len());
+            ret.extend_from_slice(&data[i..end]);
+            i = end;
+        } else if header != -128 {
+            // repeat the next byte 1-header times
+            let Some(&value) = data.get(i) else { break };
+            i += 1;
+            let count = usize::try_from(1 - i16::from(header)).unwrap();
+            ret.extend(std::iter::repeat_n(value, count));
+        }
+        // -128 is a no-op
+    }
+    ret.truncate(max_length);
+    ret
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the values of a directory entry, returning the type, the count and the bytes.
+    fn encode_values(values: &Values, big_endian: bool) -> (u16, u64, Vec<u8>) {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        match values {
+            Values::Ascii(vals) => (2, vals.len().try_into().unwrap(), vals.clone()),
+            Values::Short(vals) => (3, vals.len().try_into().unwrap(), vals.iter().flat_map(|v| u16_bytes(*v)).collect()),
+            Values::Long(vals) => (4, vals.len().try_into().unwrap(), vals.iter().flat_map(|v| u32_bytes(*v)).collect()),
+            Values::Rational(vals) => (
+                5,
+                vals.len().try_into().unwrap(),
+                vals.iter().flat_map(|(n, d)| u32_bytes(*n).into_iter().chain(u32_bytes(*d))).collect(),
+            ),
+            other => panic!("unsupported test values {:?}", other),
+        }
+    }
+
+    /// Assembles a TIFF file with a single image file directory.
+    ///
+    /// The image data directly follows the header; the directory follows the image data and is
+    /// itself followed by the values that do not fit into their entries.
+    fn build_tiff(big_endian: bool, big_tiff: bool, image_data: &[u8], entries: &[(u16, Values)]) -> Vec<u8> {
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u64_bytes = |v: u64| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let offset_bytes = |v: u64| if big_tiff {
+            u64_bytes(v).to_vec()
+        } else {
+            u32_bytes(v.try_into().unwrap()).to_vec()
+        };
+
+        let mut tiff = Vec::new();
+        tiff.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+        let (header_length, entry_length, count_length, inline_length) = if big_tiff {
+            tiff.extend_from_slice(&u16_bytes(43));
+            tiff.extend_from_slice(&u16_bytes(8));
+            tiff.extend_from_slice(&u16_bytes(0));
+            (16, 20, 8, 8)
+        } else {
+            tiff.extend_from_slice(&u16_bytes(42));
+            (8, 12, 2, 4)
+        };
+        let ifd_offset = header_length + image_data.len();
+        tiff.extend_from_slice(&offset_bytes(ifd_offset.try_into().unwrap()));
+        tiff.extend_from_slice(image_data);
+
+        let mut external_offset = ifd_offset + count_length + entries.len() * entry_length + inline_length;
+        let mut external_data = Vec::new();
+        if big_tiff {
+            tiff.extend_from_slice(&u64_bytes(entries.len().try_into().unwrap()));
+        } else {
+            tiff.extend_from_slice(&u16_bytes(entries.len().try_into().unwrap()));
+        }
+        for (tag, values) in entries {
+            let (value_type, count, mut data) = encode_values(values, big_endian);
+            tiff.extend_from_slice(&u16_bytes(*tag));
+            tiff.extend_from_slice(&u16_bytes(value_type));
+            if big_tiff {
+                tiff.extend_from_slice(&u64_bytes(count));
+            } else {
+                tiff.extend_from_slice(&u32_bytes(count.try_into().unwrap()));
+            }
+            if data.len() <= inline_length {
+                data.resize(inline_length, 0x00);
+                tiff.extend_from_slice(&data);
+            } else {
+                tiff.extend_from_slice(&offset_bytes(external_offset.try_into().unwrap()));
+                external_offset += data.len();
+                external_data.extend_from_slice(&data);
+            }
+        }
+        // no further directories
+        tiff.extend_from_slice(&offset_bytes(0));
+        tiff.extend_from_slice(&external_data);
+        tiff
+    }
+
+    /// The directory entries of a grayscale image with 8 bits per sample stored in a single strip.
+    fn grayscale_entries(width: u16, height: u16, strip_offset: u32) -> Vec<(u16, Values)> {
+        vec![
+            (TAG_IMAGE_WIDTH, Values::Short(vec![width])),
+            (TAG_IMAGE_LENGTH, Values::Short(vec![height])),
+            (TAG_BITS_PER_SAMPLE, Values::Short(vec![8])),
+            (TAG_COMPRESSION, Values::Short(vec![1])),
+            (TAG_PHOTOMETRIC_INTERPRETATION, Values::Short(vec![1])),
+            (TAG_STRIP_OFFSETS, Values::Long(vec![strip_offset])),
+            (TAG_ROWS_PER_STRIP, Values::Short(vec![height])),
+            (TAG_STRIP_BYTE_COUNTS, Values::Long(vec![u32::from(width) * u32::from(height)])),
+            (TAG_X_RESOLUTION, Values::Rational(vec![(300, 1)])),
+            (TAG_Y_RESOLUTION, Values::Rational(vec![(600, 2)])),
+            (TAG_RESOLUTION_UNIT, Values::Short(vec![2])),
+        ]
+    }
+
+    #[test]
+    fn reads_inline_and_offset_values() {
+        let entries = vec![
+            // inline in both variants
+            (TAG_IMAGE_WIDTH, Values::Short(vec![1234])),
+            (TAG_STRIP_OFFSETS, Values::Long(vec![0x0102_0304])),
+            // at an offset in classic TIFF, inline in BigTIFF
+            (TAG_BITS_PER_SAMPLE, Values::Short(vec![8, 8, 8])),
+            (TAG_X_RESOLUTION, Values::Rational(vec![(300, 1)])),
+            // at an offset in both variants
+            (0x010E, Values::Ascii(b"scanned page\0".to_vec())),
+            (TAG_Y_RESOLUTION, Values::Rational(vec![(300, 1), (600, 2)])),
+        ];
+        for big_endian in [false, true] {
+            for big_tiff in [false, true] {
+                let tiff = build_tiff(big_endian, big_tiff, &[], &entries);
+                let ifds = read_ifds(Cursor::new(&tiff)).unwrap();
+                assert_eq!(ifds.len(), 1, "big_endian={} big_tiff={}", big_endian, big_tiff);
+                for (tag, values) in &entries {
+                    assert_eq!(
+                        find_values(&ifds[0], *tag), Some(values),
+                        "tag=0x{:04X} big_endian={} big_tiff={}", tag, big_endian, big_tiff,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_directory_loop() {
+        let mut tiff = build_tiff(false, false, &[], &[(TAG_IMAGE_WIDTH, Values::Short(vec![1]))]);
+        // point the next-directory offset back at the first directory
+        let next_offset_pos = tiff.len() - 4;
+        tiff[next_offset_pos..].copy_from_slice(&8u32.to_le_bytes());
+        assert!(matches!(read_ifds(Cursor::new(&tiff)), Err(Error::IfdLoop { offset: 8 })));
+    }
+
+    #[test]
+    fn refuses_more_values_than_the_file_contains() {
+        for big_tiff in [false, true] {
+            let mut tiff = build_tiff(false, big_tiff, &[], &[(TAG_STRIP_OFFSETS, Values::Long(vec![1, 2, 3]))]);
+            // claim that the entry has 2**32 - 1 values
+            let count_pos = if big_tiff { 16 + 8 + 4 } else { 8 + 2 + 4 };
+            tiff[count_pos..count_pos+4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(
+                matches!(
+                    read_ifds(Cursor::new(&tiff)),
+                    Err(Error::ValueCount { tag: TAG_STRIP_OFFSETS, count: 0xFFFF_FFFF }),
+                ),
+                "big_tiff={}", big_tiff,
+            );
+        }
+    }
+
+    #[test]
+    fn reads_uncompressed_grayscale_image() {
+        let pixels: Vec<u8> = (0..32).map(|i| i * 8).collect();
+        for big_endian in [false, true] {
+            for big_tiff in [false, true] {
+                let strip_offset = if big_tiff { 16 } else { 8 };
+                let tiff = build_tiff(big_endian, big_tiff, &pixels, &grayscale_entries(8, 4, strip_offset));
+                let image = Image::try_read(Cursor::new(&tiff)).unwrap();
+                assert_eq!((image.width, image.height), (8, 4));
+                assert_eq!(image.bits_per_sample, [8]);
+                assert_eq!(image.compression, Compression::Uncompressed);
+                assert_eq!(image.density_unit, DensityUnit::DotsPerInch);
+                assert_eq!((image.density_x, image.density_y), (300, 300));
+                assert_eq!(image.decompress().unwrap(), pixels);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_to_decompress_oversized_image() {
+        let image = Image {
+            width: u32::MAX,
+            height: u32::MAX,
+            bits_per_sample: vec![8, 8, 8, 8],
+            compression: Compression::PackBits,
+            photometric_interpretation: PhotometricInterpretation::Rgb,
+            rows_per_strip: u32::MAX,
+            least_significant_bit_first: false,
+            density_unit: DensityUnit::DotsPerInch,
+            density_x: 300,
+            density_y: 300,
+            strips: vec![vec![0xFF, 0x00]],
+        };
+        assert!(matches!(image.decompress(), Err(Error::TooLarge { width: u32::MAX, height: u32::MAX })));
+    }
+
+    #[test]
+    fn unpacks_bits_without_reserving_maximum_length() {
+        // literal run of 2, repeat run of 3, no-op
+        let packed = [0x01, 0x10, 0x20, 0xFE, 0x30, 0x80];
+        assert_eq!(unpack_bits(&packed, usize::MAX), [0x10, 0x20, 0x30, 0x30, 0x30]);
+        assert_eq!(unpack_bits(&packed, 3), [0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn converts_uncompressed_image_into_page() {
+        let pixels: Vec<u8> = (0..32).map(|i| i * 8).collect();
+        let tiff = build_tiff(false, false, &pixels, &grayscale_entries(8, 4, 8));
+        let pages = crate::import::split_pages(&tiff).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].size_pt, None);
+
+        let jpeg = crate::jpeg::Image::try_read(pages[0].jpeg_data.as_slice()).unwrap();
+        let info = crate::model::JpegImageInfo::try_from(&jpeg).unwrap();
+        assert_eq!((info.width, info.height), (8, 4));
+        assert_eq!(info.color_space, crate::model::ColorSpace::Grayscale);
+        assert_eq!((info.density_x, info.density_y), (300, 300));
+    }
+
+    #[test]
+    fn skips_reduced_resolution_images() {
+        let pixels = [0u8; 4];
+        let mut entries = grayscale_entries(2, 2, 8);
+        entries.insert(0, (TAG_NEW_SUBFILE_TYPE, Values::Long(vec![0])));
+        let tiff = build_tiff(false, false, &pixels, &entries);
+        assert_eq!(Image::try_read_all(Cursor::new(&tiff)).unwrap().len(), 1);
+
+        // a reduced-resolution version is skipped
+        entries[0] = (TAG_NEW_SUBFILE_TYPE, Values::Long(vec![1]));
+        let tiff = build_tiff(false, false, &pixels, &entries);
+        assert!(matches!(Image::try_read(Cursor::new(&tiff)), Err(Error::MultipleImages { count: 0 })));
+    }
+
+    #[test]
+    fn single_integer_values_as_u64() {
+        assert_eq!(Values::Byte(vec![0xFF]).as_single_u64(), Some(0xFF));
+        assert_eq!(Values::Short(vec![0xFFFF]).as_single_u64(), Some(0xFFFF));
+        assert_eq!(Values::Long(vec![0xFFFF_FFFF]).as_single_u64(), Some(0xFFFF_FFFF));
+        assert_eq!(Values::Long8(vec![u64::MAX]).as_single_u64(), Some(u64::MAX));
+        assert_eq!(Values::SByte(vec![0x7F]).as_single_u64(), Some(0x7F));
+        assert_eq!(Values::SShort(vec![0]).as_single_u64(), Some(0));
+        assert_eq!(Values::SLong(vec![i32::MAX]).as_single_u64(), Some(0x7FFF_FFFF));
+        assert_eq!(Values::SLong8(vec![i64::MAX]).as_single_u64(), Some(0x7FFF_FFFF_FFFF_FFFF));
+    }
+
+    #[test]
+    fn rejects_other_values_as_u64() {
+        // negative
+        assert_eq!(Values::SByte(vec![-1]).as_single_u64(), None);
+        assert_eq!(Values::SShort(vec![i16::MIN]).as_single_u64(), None);
+        assert_eq!(Values::SLong(vec![-1]).as_single_u64(), None);
+        assert_eq!(Values::SLong8(vec![i64::MIN]).as_single_u64(), None);
+
+        // not exactly one value
+        assert_eq!(Values::Short(vec![]).as_single_u64(), None);
+        assert_eq!(Values::Short(vec![1, 2]).as_single_u64(), None);
+        assert_eq!(Values::Long8(vec![1, 2, 3]).as_single_u64(), None);
+
+        // not integers
+        assert_eq!(Values::Rational(vec![(300, 1)]).as_single_u64(), None);
+        assert_eq!(Values::Ascii(b"1".to_vec()).as_single_u64(), None);
+        assert_eq!(Values::Undefined(vec![1]).as_single_u64(), None);
+        assert_eq!(Values::Float(vec![1.0]).as_single_u64(), None);
+        assert_eq!(Values::Ifd(vec![8]).as_single_u64(), None);
+    }
+}
@@ -0,0 +1,719 @@
+//! Reading and subsetting TrueType fonts.
+//!
+//! Text that the standard fonts cannot encode is drawn using a TrueType font embedded into the
+//! document. Only the glyphs actually used are embedded; the others are emptied out, which keeps
+//! the glyph IDs (and thereby the mapping from character codes to glyphs) unchanged.
+
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
+
+const TAG_CMAP: [u8; 4] = *b"cmap";
+const TAG_CVT: [u8; 4] = *b"cvt ";
+const TAG_FPGM: [u8; 4] = *b"fpgm";
+const TAG_GLYF: [u8; 4] = *b"glyf";
+const TAG_HEAD: [u8; 4] = *b"head";
+const TAG_HHEA: [u8; 4] = *b"hhea";
+const TAG_HMTX: [u8; 4] = *b"hmtx";
+const TAG_LOCA: [u8; 4] = *b"loca";
+const TAG_MAXP: [u8; 4] = *b"maxp";
+const TAG_NAME: [u8; 4] = *b"name";
+const TAG_OS2: [u8; 4] = *b"OS/2";
+const TAG_POST: [u8; 4] = *b"post";
+const TAG_PREP: [u8; 4] = *b"prep";
+
+/// The tables copied into a subset; PDF only requires those needed to render the glyphs.
+const SUBSET_TABLES: [[u8; 4]; 9] = [
+    TAG_CVT, TAG_FPGM, TAG_GLYF, TAG_HEAD, TAG_HHEA, TAG_HMTX, TAG_LOCA, TAG_MAXP, TAG_PREP,
+];
+
+/// The value to which the checksum of a whole font file is adjusted.
+const CHECKSUM_MAGIC: u32 = 0xB1B0AFBA;
+
+// flags of the components of composite glyphs
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+
+/// An error that can occur while reading a TrueType font.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Error {
+    /// The data is not a TrueType font; e.g. it is an OpenType font with CFF outlines.
+    NotTrueType { version: u32 },
+
+    /// A table required by pdfmcr is missing.
+    MissingTable { tag: [u8; 4] },
+
+    /// The data ends in the middle of a structure.
+    Truncated,
+
+    /// The font has no character map for Unicode.
+    NoUnicodeCmap,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotTrueType { version }
+                => write!(f, "not a TrueType font (version 0x{:08X})", version),
+            Self::MissingTable { tag }
+                => write!(f, "font has no {:?} table", String::from_utf8_lossy(tag)),
+            Self::Truncated
+                => write!(f, "font data is truncated"),
+            Self::NoUnicodeCmap
+                => write!(f, "font has no Unicode character map"),
+        }
+    }
+}
+impl std::error::Error for Error {
+}
+
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or(Error::Truncated)
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Result<i16, Error> {
+    read_u16(data, offset).map(|value| value as i16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or(Error::Truncated)
+}
+
+
+/// A TrueType font.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Font<'a> {
+    data: &'a [u8],
+    tables: BTreeMap<[u8; 4], Range<usize>>,
+    cmap_subtable: Range<usize>,
+
+    /// The number of font units per em.
+    pub units_per_em: u16,
+
+    /// The bounding box of all glyphs, in font units, as `[x_min, y_min, x_max, y_max]`.
+    pub bounding_box: [i16; 4],
+
+    /// The distance from the baseline to the top of the highest glyphs, in font units.
+    pub ascent: i16,
+
+    /// The distance from the baseline to the bottom of the lowest glyphs, in font units; usually
+    /// negative.
+    pub descent: i16,
+
+    /// The height of capital letters, in font units.
+    pub cap_height: i16,
+
+    /// The angle of the vertical strokes, in degrees counterclockwise from the vertical.
+    pub italic_angle: i32,
+
+    /// The weight of the font, from 100 (thin) to 900 (black); regular fonts have 400.
+    pub weight_class: u16,
+
+    /// Whether all glyphs have the same width.
+    pub is_fixed_pitch: bool,
+
+    /// The PostScript name of the font.
+    pub postscript_name: String,
+
+    long_loca: bool,
+    glyph_count: u16,
+    advance_widths: Vec<u16>,
+}
+impl<'a> Font<'a> {
+    /// Reads a TrueType font from its data.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let version = read_u32(data, 0)?;
+        if version != 0x0001_0000 && version != u32::from_be_bytes(*b"true") {
+            return Err(Error::NotTrueType { version });
+        }
+
+        let tables = read_table_directory(data)?;
+        let table = |tag: [u8; 4]| tables.get(&tag)
+            .map(|range| &data[range.clone()])
+            .ok_or(Error::MissingTable { tag });
+
+        let head = table(TAG_HEAD)?;
+        let units_per_em = read_u16(head, 18)?;
+        let bounding_box = [
+            read_i16(head, 36)?, read_i16(head, 38)?,
+            read_i16(head, 40)?, read_i16(head, 42)?,
+        ];
+        let long_loca = read_i16(head, 50)? != 0;
+
+        let hhea = table(TAG_HHEA)?;
+        let mut ascent = read_i16(hhea, 4)?;
+        let mut descent = read_i16(hhea, 6)?;
+        let h_metric_count = usize::from(read_u16(hhea, 34)?);
+
+        let glyph_count = read_u16(table(TAG_MAXP)?, 4)?;
+
+        // the glyphs past the last metric have the same advance width as the last one
+        let hmtx = table(TAG_HMTX)?;
+        let mut advance_widths = Vec::with_capacity(usize::from(glyph_count));
+        for glyph in 0..usize::from(glyph_count) {
+            let advance_width = if glyph < h_metric_count {
+                read_u16(hmtx, 4*glyph)?
+            } else {
+                *advance_widths.last().ok_or(Error::Truncated)?
+            };
+            advance_widths.push(advance_width);
+        }
+
+        // OS/2 and post are optional on Apple platforms
+        let mut cap_height = ascent;
+        let mut weight_class = 400;
+        if let Ok(os2) = table(TAG_OS2) {
+            weight_class = read_u16(os2, 4)?;
+            ascent = read_i16(os2, 68)?;
+            descent = read_i16(os2, 70)?;
+            if read_u16(os2, 0)? >= 2 {
+                cap_height = read_i16(os2, 88)?;
+            }
+        }
+        let mut italic_angle = 0;
+        let mut is_fixed_pitch = false;
+        if let Ok(post) = table(TAG_POST) {
+            // 16.16 fixed-point value
+            italic_angle = (read_u32(post, 4)? as i32) >> 16;
+            is_fixed_pitch = read_u32(post, 12)? != 0;
+        }
+
+        let postscript_name = table(TAG_NAME).ok()
+            .and_then(|name| read_postscript_name(name).ok().flatten())
+            .unwrap_or_else(|| "Unnamed".to_owned());
+
+        let cmap_subtable = find_unicode_cmap(data, tables.get(&TAG_CMAP).ok_or(Error::MissingTable { tag: TAG_CMAP })?.clone())?;
+
+        // ensure the glyph locations can be read
+        let loca = table(TAG_LOCA)?;
+        let loca_entry_size = if long_loca { 4 } else { 2 };
+        if loca.len() < loca_entry_size * (usize::from(glyph_count) + 1) {
+            return Err(Error::Truncated);
+        }
+        table(TAG_GLYF)?;
+
+        Ok(Self {
+            data,
+            tables,
+            cmap_subtable,
+            units_per_em,
+            bounding_box,
+            ascent,
+            descent,
+            cap_height,
+            italic_angle,
+            weight_class,
+            is_fixed_pitch,
+            postscript_name,
+            long_loca,
+            glyph_count,
+            advance_widths,
+        })
+    }
+
+    fn table(&self, tag: [u8; 4]) -> Option<&[u8]> {
+        self.tables.get(&tag)
+            .map(|range| &self.data[range.clone()])
+    }
+
+    /// Converts a value in font units into thousandths of an em, the unit of glyph space in PDF.
+    pub fn to_glyph_space(&self, value: i32) -> i32 {
+        if self.units_per_em == 0 {
+            return value;
+        }
+        ((i64::from(value) * 1000) / i64::from(self.units_per_em)) as i32
+    }
+
+    /// The ID of the glyph representing the given character, if the font contains one.
+    pub fn glyph_id(&self, c: char) -> Option<u16> {
+        let cmap = &self.data[self.cmap_subtable.clone()];
+        let glyph = match read_u16(cmap, 0).ok()? {
+            4 => lookup_cmap_format_4(cmap, c).ok()?,
+            12 => lookup_cmap_format_12(cmap, c).ok()?,
+            _ => None,
+        }?;
+        Some(glyph).filter(|g| *g != 0 && *g < self.glyph_count)
+    }
+
+    /// The advance width of the glyph with the given ID, in thousandths of an em.
+    pub fn advance_width(&self, glyph: u16) -> u32 {
+        let advance_width = self.advance_widths.get(usize::from(glyph))
+            .copied()
+            .unwrap_or(0);
+        self.to_glyph_space(advance_width.into()).try_into().unwrap_or(0)
+    }
+
+    fn glyph_data(&self, glyph: u16) -> Result<&[u8], Error> {
+        let loca = self.table(TAG_LOCA).ok_or(Error::MissingTable { tag: TAG_LOCA })?;
+        let glyf = self.table(TAG_GLYF).ok_or(Error::MissingTable { tag: TAG_GLYF })?;
+        let index = usize::from(glyph);
+        let (start, end) = if self.long_loca {
+            (read_u32(loca, 4*index)? as usize, read_u32(loca, 4*index + 4)? as usize)
+        } else {
+            (2 * usize::from(read_u16(loca, 2*index)?), 2 * usize::from(read_u16(loca, 2*index + 2)?))
+        };
+        if start > end {
+            return Err(Error::Truncated);
+        }
+        glyf.get(start..end).ok_or(Error::Truncated)
+    }
+
+    /// Creates a font containing only the outlines of the given glyphs and of the glyph `.notdef`.
+    ///
+    /// The components of composite glyphs are retained as well. All other glyphs remain in the
+    /// font but are empty, so glyph IDs do not change. Only the tables required for embedding into
+    /// PDF are retained.
+    pub fn subset(&self, glyphs: &BTreeSet<u16>) -> Result<Vec<u8>, Error> {
+        // collect the components of composite glyphs
+        let mut retained: BTreeSet<u16> = BTreeSet::new();
+        let mut pending: Vec<u16> = glyphs.iter()
+            .copied()
+            .chain(std::iter::once(0))
+            .filter(|glyph| *glyph < self.glyph_count)
+            .collect();
+        while let Some(glyph) = pending.pop() {
+            if !retained.insert(glyph) {
+                continue;
+            }
+            for component in composite_components(self.glyph_data(glyph)?)? {
+                if component < self.glyph_count {
+                    pending.push(component);
+                }
+            }
+        }
+
+        let mut glyf = Vec::new();
+        let mut loca = Vec::with_capacity(4 * (usize::from(self.glyph_count) + 1));
+        for glyph in 0..self.glyph_count {
+            loca.extend_from_slice(&u32::try_from(glyf.len()).unwrap().to_be_bytes());
+            if retained.contains(&glyph) {
+                glyf.extend_from_slice(self.glyph_data(glyph)?);
+                while glyf.len() % 4 != 0 {
+                    glyf.push(0);
+                }
+            }
+        }
+        loca.extend_from_slice(&u32::try_from(glyf.len()).unwrap().to_be_bytes());
+
+        let mut tables = BTreeMap::new();
+        for tag in SUBSET_TABLES {
+            if let Some(table) = self.table(tag) {
+                tables.insert(tag, table.to_vec());
+            }
+        }
+        tables.insert(TAG_GLYF, glyf);
+        tables.insert(TAG_LOCA, loca);
+        if let Some(head) = tables.get_mut(&TAG_HEAD) {
+            // the new location table has long entries
+            head[50..52].copy_from_slice(&1u16.to_be_bytes());
+        }
+        Ok(write_font(tables))
+    }
+}
+
+
+/// Reads the locations of the tables of a font.
+fn read_table_directory(data: &[u8]) -> Result<BTreeMap<[u8; 4], Range<usize>>, Error> {
+    let table_count = read_u16(data, 4)?;
+    let mut tables = BTreeMap::new();
+    for i in 0..usize::from(table_count) {
+        let record_offset = 12 + 16*i;
+        let tag: [u8; 4] = data.get(record_offset..record_offset + 4)
+            .ok_or(Error::Truncated)?
+            .try_into().unwrap();
+        let offset: usize = read_u32(data, record_offset + 8)?.try_into().unwrap();
+        let length: usize = read_u32(data, record_offset + 12)?.try_into().unwrap();
+        if data.len() < offset || data.len() - offset < length {
+            return Err(Error::Truncated);
+        }
+        tables.insert(tag, offset..offset + length);
+    }
+    Ok(tables)
+}
+
+/// Reads the PostScript name (name ID 6) from a `name` table.
+fn read_postscript_name(name: &[u8]) -> Result<Option<String>, Error> {
+    let count = usize::from(read_u16(name, 2)?);
+    let storage_offset = usize::from(read_u16(name, 4)?);
+    for i in 0..count {
+        let record = 6 + 12*i;
+        let platform = read_u16(name, record)?;
+        let name_id = read_u16(name, record + 6)?;
+        if name_id != 6 {
+            continue;
+        }
+        let length = usize::from(read_u16(name, record + 8)?);
+        let offset = storage_offset + usize::from(read_u16(name, record + 10)?);
+        let bytes = name.get(offset..offset + length).ok_or(Error::Truncated)?;
+        let string = match platform {
+            // UTF-16BE
+            0 | 3 => {
+                let words = bytes.chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+                char::decode_utf16(words)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            },
+            _ => String::from_utf8_lossy(bytes).into_owned(),
+        };
+        // PostScript names are restricted to printable ASCII without delimiters
+        let string: String = string.chars()
+            .filter(|c| c.is_ascii_graphic() && !"[](){}<>/%".contains(*c))
+            .collect();
+        if !string.is_empty() {
+            return Ok(Some(string));
+        }
+    }
+    Ok(None)
+}
+
+/// Finds the most comprehensive Unicode subtable of a `cmap` table, returning its location within
+/// the font data.
+fn find_unicode_cmap(data: &[u8], cmap: Range<usize>) -> Result<Range<usize>, Error> {
+    let table = &data[cmap.clone()];
+    let count = usize::from(read_u16(table, 2)?);
+    let mut best: Option<(u8, usize)> = None;
+    for i in 0..count {
+        let record = 4 + 8*i;
+        let platform = read_u16(table, record)?;
+        let encoding = read_u16(table, record + 2)?;
+        let offset: usize = read_u32(table, record + 4)?.try_into().unwrap();
+        let format = read_u16(table, offset)?;
+        // full Unicode is preferred over the Basic Multilingual Plane
+        let rank = match (platform, encoding, format) {
+            (3, 10, 12) | (0, 4, 12) | (0, 6, 12) => 2,
+            (3, 1, 4) | (0, 3, 4) => 1,
+            _ => continue,
+        };
+        if best.is_none_or(|(best_rank, _)| rank > best_rank) {
+            best = Some((rank, offset));
+        }
+    }
+    let (_, offset) = best.ok_or(Error::NoUnicodeCmap)?;
+    Ok(cmap.start + offset..cmap.end)
+}
+
+fn lookup_cmap_format_4(subtable: &[u8], c: char) -> Result<Option<u16>, Error> {
+    let Ok(code) = u16::try_from(u32::from(c)) else {
+        return Ok(None);
+    };
+    let segment_count = usize::from(read_u16(subtable, 6)? / 2);
+    let end_codes = 14;
+    let start_codes = end_codes + 2*segment_count + 2;
+    let id_deltas = start_codes + 2*segment_count;
+    let id_range_offsets = id_deltas + 2*segment_count;
+    for segment in 0..segment_count {
+        if read_u16(subtable, end_codes + 2*segment)? < code {
+            continue;
+        }
+        let start_code = read_u16(subtable, start_codes + 2*segment)?;
+        if start_code > code {
+            return Ok(None);
+        }
+        let id_delta = read_u16(subtable, id_deltas + 2*segment)?;
+        let id_range_offset_position = id_range_offsets + 2*segment;
+        let id_range_offset = usize::from(read_u16(subtable, id_range_offset_position)?);
+        if id_range_offset == 0 {
+            return Ok(Some(code.wrapping_add(id_delta)));
+        }
+        let glyph_position = id_range_offset_position + id_range_offset + 2*usize::from(code - start_code);
+        let glyph = read_u16(subtable, glyph_position)?;
+        if glyph == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(glyph.wrapping_add(id_delta)));
+    }
+    Ok(None)
+}
+
+fn lookup_cmap_format_12(subtable: &[u8], c: char) -> Result<Option<u16>, Error> {
+    let code = u32::from(c);
+    let group_count: usize = read_u32(subtable, 12)?.try_into().unwrap();
+    for group in 0..group_count {
+        let record = 16 + 12*group;
+        let start_code = read_u32(subtable, record)?;
+        let end_code = read_u32(subtable, record + 4)?;
+        if (start_code..=end_code).contains(&code) {
+            let start_glyph = read_u32(subtable, record + 8)?;
+            return Ok(u16::try_from(start_glyph + (code - start_code)).ok());
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the IDs of the glyphs of which a composite glyph consists, or none if the glyph is
+/// simple.
+fn composite_components(glyph_data: &[u8]) -> Result<Vec<u16>, Error> {
+    let mut components = Vec::new();
+    if glyph_data.is_empty() || read_i16(glyph_data, 0)? >= 0 {
+        return Ok(components);
+    }
+
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(glyph_data, offset)?;
+        components.push(read_u16(glyph_data, offset + 2)?);
+        offset += 4;
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            offset += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            offset += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            offset += 8;
+        }
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+    Ok(components)
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4)
+        .map(|chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(word)
+        })
+        .fold(0u32, |sum, word| sum.wrapping_add(word))
+}
+
+/// Assembles a font file from its tables, computing the checksums.
+fn write_font(mut tables: BTreeMap<[u8; 4], Vec<u8>>) -> Vec<u8> {
+    if let Some(head) = tables.get_mut(&TAG_HEAD) {
+        // the adjustment is computed over the whole font with the adjustment itself set to 0
+        if head.len() >= 12 {
+            head[8..12].copy_from_slice(&[0; 4]);
+        }
+    }
+
+    let table_count: u16 = tables.len().try_into().unwrap();
+    let entry_selector = if table_count == 0 { 0 } else { 15 - table_count.leading_zeros() as u16 };
+    let search_range = 16 * (1u16 << entry_selector);
+    let range_shift = 16*table_count - search_range.min(16*table_count);
+
+    let mut font = Vec::new();
+    font.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    font.extend_from_slice(&table_count.to_be_bytes());
+    font.extend_from_slice(&search_range.to_be_bytes());
+    font.extend_from_slice(&entry_selector.to_be_bytes());
+    font.extend_from_slice(&range_shift.to_be_bytes());
+
+    let mut offset = font.len() + 16*tables.len();
+    let mut head_offset = None;
+    for (tag, data) in &tables {
+        if *tag == TAG_HEAD {
+            head_offset = Some(offset);
+        }
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum(data).to_be_bytes());
+        font.extend_from_slice(&u32::try_from(offset).unwrap().to_be_bytes());
+        font.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+        offset += data.len().next_multiple_of(4);
+    }
+    for data in tables.values() {
+        font.extend_from_slice(data);
+        while font.len() % 4 != 0 {
+            font.push(0);
+        }
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = CHECKSUM_MAGIC.wrapping_sub(checksum(&font));
+        font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    font
+}
+
+
+/// Assembles a small TrueType font for tests.
+///
+/// It has four glyphs: `.notdef` (0), a triangle mapped to U+03A9 (1), a composite of the triangle
+/// mapped to U+0436 (2) and an unmapped square (3). It has 1000 units per em and all advance widths
+/// are 600 except that of the composite glyph, which is 700.
+#[cfg(test)]
+pub(crate) fn test_font() -> Vec<u8> {
+    fn be16(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    // simple glyph: one contour of three on-curve points, coordinates as 16-bit deltas
+    let triangle = [
+        be16(&[1, 0, 0, 500, 700]),
+        be16(&[2, 0]),
+        vec![0x01, 0x01, 0x01],
+        be16(&[0, 250, 250]),
+        be16(&[0, 700, 0xFD44]),
+    ].concat();
+    // composite glyph: the triangle, offset by byte arguments
+    let composite = [
+        be16(&[0xFFFF, 0, 0, 500, 700]),
+        be16(&[0x0002, 1]),
+        vec![10, 0],
+    ].concat();
+    let square = [
+        be16(&[1, 0, 0, 500, 500]),
+        be16(&[3, 0]),
+        vec![0x01, 0x01, 0x01, 0x01],
+        be16(&[0, 0, 500, 0]),
+        be16(&[0, 500, 0, 0xFE0C]),
+    ].concat();
+
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    for glyph in [&Vec::new(), &triangle, &composite, &square] {
+        loca.extend_from_slice(&be16(&[(glyf.len() / 2) as u16]));
+        glyf.extend_from_slice(glyph);
+        if glyf.len() % 2 != 0 {
+            glyf.push(0);
+        }
+    }
+    loca.extend_from_slice(&be16(&[(glyf.len() / 2) as u16]));
+
+    let mut head = vec![0u8; 54];
+    head[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    head[12..16].copy_from_slice(&0x5F0F3CF5u32.to_be_bytes());
+    head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+    head[36..44].copy_from_slice(&be16(&[0, 0xFF38, 500, 800]));
+
+    let mut hhea = vec![0u8; 36];
+    hhea[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    hhea[4..8].copy_from_slice(&be16(&[800, 0xFF38]));
+    hhea[34..36].copy_from_slice(&be16(&[3]));
+
+    let mut maxp = vec![0u8; 6];
+    maxp[0..4].copy_from_slice(&0x0000_5000u32.to_be_bytes());
+    maxp[4..6].copy_from_slice(&be16(&[4]));
+
+    // the last glyph reuses the advance width of the last metric
+    let hmtx = be16(&[600, 0, 600, 0, 700, 0, 0]);
+
+    // format 4 subtable with segments for U+03A9, U+0436 and the final 0xFFFF
+    let cmap_subtable = be16(&[
+        4, 40, 0, 6, 4, 1, 2,
+        0x03A9, 0x0436, 0xFFFF,
+        0,
+        0x03A9, 0x0436, 0xFFFF,
+        1u16.wrapping_sub(0x03A9), 2u16.wrapping_sub(0x0436), 1,
+        0, 0, 0,
+    ]);
+    let cmap = [be16(&[0, 1, 3, 1, 0, 12]), cmap_subtable].concat();
+
+    let name_string = be16(&"TestFont".encode_utf16().collect::<Vec<u16>>());
+    let name = [
+        be16(&[0, 1, 18, 3, 1, 0x0409, 6, name_string.len() as u16, 0]),
+        name_string,
+    ].concat();
+
+    let mut os2 = vec![0u8; 96];
+    os2[0..2].copy_from_slice(&be16(&[2]));
+    os2[4..6].copy_from_slice(&be16(&[400]));
+    os2[68..72].copy_from_slice(&be16(&[750, 0xFF06]));
+    os2[88..90].copy_from_slice(&be16(&[650]));
+
+    let mut post = vec![0u8; 32];
+    post[0..4].copy_from_slice(&0x0003_0000u32.to_be_bytes());
+
+    let tables = BTreeMap::from([
+        (TAG_CMAP, cmap),
+        (TAG_GLYF, glyf),
+        (TAG_HEAD, head),
+        (TAG_HHEA, hhea),
+        (TAG_HMTX, hmtx),
+        (TAG_LOCA, loca),
+        (TAG_MAXP, maxp),
+        (TAG_NAME, name),
+        (TAG_OS2, os2),
+        (TAG_POST, post),
+    ]);
+    write_font(tables)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_metrics_and_names() {
+        let data = test_font();
+        let font = Font::parse(&data).unwrap();
+        assert_eq!(font.units_per_em, 1000);
+        assert_eq!(font.bounding_box, [0, -200, 500, 800]);
+        assert_eq!(font.ascent, 750);
+        assert_eq!(font.descent, -250);
+        assert_eq!(font.cap_height, 650);
+        assert_eq!(font.weight_class, 400);
+        assert_eq!(font.postscript_name, "TestFont");
+    }
+
+    #[test]
+    fn maps_characters_to_glyphs() {
+        let data = test_font();
+        let font = Font::parse(&data).unwrap();
+        assert_eq!(font.glyph_id('\u{03A9}'), Some(1));
+        assert_eq!(font.glyph_id('\u{0436}'), Some(2));
+        assert_eq!(font.glyph_id('A'), None);
+        assert_eq!(font.advance_width(1), 600);
+        assert_eq!(font.advance_width(2), 700);
+        assert_eq!(font.advance_width(3), 700);
+    }
+
+    #[test]
+    fn subset_retains_components_and_empties_other_glyphs() {
+        let data = test_font();
+        let font = Font::parse(&data).unwrap();
+        let subset = font.subset(&BTreeSet::from([2])).unwrap();
+        let tables = read_table_directory(&subset).unwrap();
+        assert!(!tables.contains_key(&TAG_NAME));
+        assert!(!tables.contains_key(&TAG_CMAP));
+
+        let loca = &subset[tables[&TAG_LOCA].clone()];
+        let glyf = &subset[tables[&TAG_GLYF].clone()];
+        let glyph = |index: usize| {
+            let start = read_u32(loca, 4*index).unwrap() as usize;
+            let end = read_u32(loca, 4*index + 4).unwrap() as usize;
+            &glyf[start..end]
+        };
+        assert_eq!(loca.len(), 4 * 5);
+        assert!(glyph(0).is_empty());
+        assert!(glyph(1).starts_with(font.glyph_data(1).unwrap()));
+        assert!(glyph(2).starts_with(font.glyph_data(2).unwrap()));
+        assert!(glyph(3).is_empty());
+
+        let head = &subset[tables[&TAG_HEAD].clone()];
+        assert_eq!(read_u16(head, 50).unwrap(), 1);
+    }
+
+    #[test]
+    fn subset_checksum_is_adjusted() {
+        let data = test_font();
+        let font = Font::parse(&data).unwrap();
+        let subset = font.subset(&BTreeSet::from([1])).unwrap();
+        assert_eq!(checksum(&subset), CHECKSUM_MAGIC);
+    }
+
+    #[test]
+    fn rejects_cff_fonts() {
+        let mut data = test_font();
+        data[0..4].copy_from_slice(b"OTTO");
+        assert_eq!(Font::parse(&data), Err(Error::NotTrueType { version: u32::from_be_bytes(*b"OTTO") }));
+    }
+
+    #[test]
+    fn rejects_truncated_fonts() {
+        let data = test_font();
+        assert_eq!(Font::parse(&data[..100]), Err(Error::Truncated));
+    }
+}
@@ -10,8 +10,44 @@ struct Opts {
 }
 
 
-const POINTS_PER_INCH: f32 = 72.0;
-const POINTS_PER_CM: f32 = 3600.0 / 127.0;
+const POINTS_PER_INCH: f64 = 72.0;
+const POINTS_PER_CM: f64 = 3600.0 / 127.0;
+
+
+/// Obtains the dimensions of a media box, validating them.
+///
+/// On failure, returns the line describing the problem.
+fn media_box_dimensions(page_number: usize, media_box: &lopdf::Object) -> Result<[f64; 4], String> {
+    let media_box_array = match media_box.as_array() {
+        Ok(mba) => mba,
+        Err(_) => return Err(format!("page {} media box not an array", page_number)),
+    };
+    if media_box_array.len() != 4 {
+        return Err(format!("page {} media box has {} elements instead of 4", page_number, media_box_array.len()));
+    }
+
+    let mut dimensions = [0f64; 4];
+    for (elem_index, elem) in media_box_array.iter().enumerate() {
+        let value = match elem {
+            // media boxes are far too small for the precision lost beyond 2**53 to matter
+            lopdf::Object::Integer(i) => *i as f64,
+            lopdf::Object::Real(r) => f64::from(*r),
+            other => return Err(format!("page {} media box element {} is not a float but {:?}", page_number, elem_index, other)),
+        };
+        if !value.is_finite() {
+            return Err(format!("page {} media box element {} is not finite but {}", page_number, elem_index, value));
+        }
+        if value < 0.0 {
+            return Err(format!("page {} media box element {} is negative ({})", page_number, elem_index, value));
+        }
+        dimensions[elem_index] = value;
+    }
+
+    if dimensions[0] != 0.0 || dimensions[1] != 0.0 {
+        return Err(format!("page {} media box is not anchored at (0, 0) but at ({}, {})", page_number, dimensions[0], dimensions[1]));
+    }
+    Ok(dimensions)
+}
 
 
 fn main() {
@@ -40,43 +76,13 @@ fn main() {
                 continue;
             },
         };
-        let media_box_array = match media_box.as_array() {
-            Ok(mba) => mba,
-            Err(_) => {
-                eprintln!("page {} media box not an array", page_number);
+        let dimensions = match media_box_dimensions(page_number, media_box) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("{}", e);
                 continue;
             },
         };
-        if media_box_array.len() != 4 {
-            eprintln!("page {} media box has {} elements instead of 4", page_number, media_box_array.len());
-            continue;
-        }
-
-        let mut dimensions = [0f32; 4];
-        let mut dimensions_ok = true;
-        for (elem_index, elem) in media_box_array.iter().enumerate() {
-            match elem {
-                lopdf::Object::Integer(i) => {
-                    dimensions[elem_index] = *i as f32;
-                },
-                lopdf::Object::Real(r) => {
-                    dimensions[elem_index] = *r;
-                },
-                other => {
-                    eprintln!("page {} media box element {} is not a float but {:?}", page_number, elem_index, other);
-                    dimensions_ok = false;
-                    break;
-                },
-            }
-        }
-        if !dimensions_ok {
-            continue;
-        }
-
-        if dimensions[0] != 0.0 || dimensions[1] != 0.0 {
-            eprintln!("page {} media box is not anchored at (0, 0) but at ({}, {})", page_number, dimensions[0], dimensions[1]);
-            continue;
-        }
 
         let width_pt = dimensions[2];
         let height_pt = dimensions[3];
@@ -96,3 +102,37 @@ fn main() {
         );
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use lopdf::Object;
+
+    use super::media_box_dimensions;
+
+    #[test]
+    fn accepts_valid_media_box() {
+        let media_box = Object::Array(vec![0.into(), 0.into(), 595.into(), Object::Real(841.89)]);
+        let dimensions = media_box_dimensions(1, &media_box).unwrap();
+        assert_eq!(dimensions[2], 595.0);
+        assert!((dimensions[3] - 841.89).abs() < 0.001);
+    }
+
+    #[test]
+    fn reports_negative_media_box_element() {
+        let media_box = Object::Array(vec![0.into(), 0.into(), (-612).into(), 792.into()]);
+        assert_eq!(
+            media_box_dimensions(3, &media_box),
+            Err("page 3 media box element 2 is negative (-612)".to_owned()),
+        );
+    }
+
+    #[test]
+    fn reports_non_finite_media_box_element() {
+        let media_box = Object::Array(vec![0.into(), 0.into(), 612.into(), Object::Real(f32::NAN)]);
+        assert_eq!(
+            media_box_dimensions(1, &media_box),
+            Err("page 1 media box element 3 is not finite but NaN".to_owned()),
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use pdfmcr::image_path::ContentName;
+use pdfmcr::model::{File, JpegImage, JpegImageInfo, Page};
+use sha3::{Digest, Sha3_512};
+
+
+/// Recovers the state of a pdfmcr file from a PDF generated from it.
+#[derive(Parser)]
+struct Opts {
+    /// The number of directory levels into which images are sharded within the image directory.
+    #[arg(short, long, default_value = "0")]
+    pub shard_levels: u8,
+
+    /// The PDF file from which to recover the state.
+    pub pdf_file: PathBuf,
+
+    /// The directory into which to store the scanned images.
+    pub image_dir: PathBuf,
+
+    /// The state file to write. If its name ends in `.json`, it is written as JSON, otherwise as
+    /// CBOR.
+    pub state_file: PathBuf,
+}
+
+
+fn main() -> ExitCode {
+    let opts = Opts::parse();
+
+    if opts.state_file.exists() {
+        eprintln!("state file {} already exists; refusing to overwrite it", opts.state_file.display());
+        return ExitCode::FAILURE;
+    }
+
+    let pdf_data = std::fs::read(&opts.pdf_file)
+        .expect("failed to read PDF file");
+    let recovered = match pdfmcr::recover::recover_pages(&pdf_data) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("failed to recover pages: {}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let mut file = File {
+        pages: Vec::with_capacity(recovered.pages.len()),
+        default_language: recovered.default_language,
+        title: recovered.title,
+        visible_text: false,
+    };
+    for (page_index, recovered_page) in recovered.pages.into_iter().enumerate() {
+        let page_number = page_index + 1;
+        let mut page = match recovered_page.jpeg_data {
+            Some(jpeg_data) => {
+                let jpeg_image = match pdfmcr::jpeg::Image::try_read(jpeg_data.as_slice()) {
+                    Ok(ji) => ji,
+                    Err(e) => {
+                        eprintln!("page {} image is not a readable JPEG image: {}", page_number, e);
+                        return ExitCode::FAILURE;
+                    },
+                };
+                let info = match JpegImageInfo::try_from(&jpeg_image) {
+                    Ok(i) => i,
+                    Err(e) => {
+                        eprintln!("page {} image is not usable: {}", page_number, e);
+                        return ExitCode::FAILURE;
+                    },
+                };
+
+                let digest: [u8; 64] = Sha3_512::digest(&jpeg_data).into();
+                let content_name = ContentName {
+                    digest,
+                    size: jpeg_data.len().try_into().unwrap(),
+                };
+                let image_path = content_name.to_sharded_path(opts.shard_levels.into());
+                let os_image_path = image_path.to_os_path(&opts.image_dir);
+                if let Some(parent) = os_image_path.parent() {
+                    std::fs::create_dir_all(parent)
+                        .expect("failed to create image directory");
+                }
+                std::fs::write(&os_image_path, &jpeg_data)
+                    .expect("failed to write image");
+
+                let mut page = Page::new(JpegImage {
+                    info,
+                    file_path: image_path,
+                    data_filters: None,
+                });
+                page.media_box_pt = recovered_page.size_pt
+                    .map(|(width_pt, height_pt)| (width_pt as f64, height_pt as f64));
+                page
+            },
+            None => {
+                // recover_pages ensures that pages without an image have a media box
+                let (width_pt, height_pt) = recovered_page.size_pt.unwrap();
+                Page::new_text_only(width_pt as f64, height_pt as f64)
+            },
+        };
+        println!(
+            "page {}: {} annotations, {} artifacts",
+            page_number, recovered_page.annotations.len(), recovered_page.artifacts.len(),
+        );
+        page.annotations = recovered_page.annotations;
+        page.artifacts = recovered_page.artifacts;
+        file.pages.push(page);
+    }
+
+    let is_json = opts.state_file.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let mut state_data = Vec::new();
+    if is_json {
+        serde_json::to_writer_pretty(&mut state_data, &file)
+            .expect("failed to encode state as JSON");
+    } else {
+        ciborium::into_writer(&file, &mut state_data)
+            .expect("failed to encode state as CBOR");
+    }
+    std::fs::write(&opts.state_file, &state_data)
+        .expect("failed to write state file");
+
+    ExitCode::SUCCESS
+}
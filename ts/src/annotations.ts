@@ -109,6 +109,8 @@ export namespace Annotations {
             font_variant: "Regular",
             character_spacing: 0,
             word_spacing: 0,
+            horizontal_scale: null,
+            text_rise: null,
             language: null,
             alternate_text: null,
             actual_text: null,
@@ -125,6 +127,8 @@ export namespace Annotations {
             elements: [
                 createDefaultTextChunk(initialText),
             ],
+            order: null,
+            block_role: null,
         };
     }
 
@@ -147,6 +151,14 @@ export namespace Annotations {
         if (textChunk.expansion !== null) {
             annoTSpanElem.setAttribute("data-expansion", textChunk.expansion);
         }
+        if (textChunk.horizontal_scale !== null) {
+            annoTSpanElem.setAttribute("data-horizontal-scale", "" + textChunk.horizontal_scale);
+        }
+        if (textChunk.text_rise !== null) {
+            annoTSpanElem.setAttribute("data-text-rise", "" + textChunk.text_rise);
+            // raises the baseline in SVG just like in PDF
+            annoTSpanElem.setAttribute("baseline-shift", `${textChunk.text_rise}pt`);
+        }
 
         const annoTextNode = document.createTextNode(textChunk.text);
         annoTSpanElem.appendChild(annoTextNode);
@@ -173,6 +185,12 @@ export namespace Annotations {
 
         const annoGroup = document.createElementNS(SVG_NS, "g");
         annoGroup.classList.add("annotation");
+        if (annotation.order !== null && annotation.order !== undefined) {
+            annoGroup.setAttribute("data-order", "" + annotation.order);
+        }
+        if (annotation.block_role !== null && annotation.block_role !== undefined) {
+            annoGroup.setAttribute("data-block-role", annotation.block_role);
+        }
         const transform = svgRoot.createSVGTransform();
         transform.setTranslate(xPx, yPx);
         annoGroup.transform.baseVal.initialize(transform);
@@ -16,8 +16,15 @@ export interface Annotation {
     font_size: number;
     leading: number;
     elements: TextChunk[];
+    order: number|null;
+    block_role: BlockRole|null;
 }
 
+export type BlockRole =
+    "Paragraph"
+    |"Heading1"|"Heading2"|"Heading3"|"Heading4"|"Heading5"|"Heading6"
+    |"ListItem"|"BlockQuote"|"Caption";
+
 export type FontVariant = "Regular"|"Italic"|"Bold"|"BoldItalic";
 
 export interface TextChunk {
@@ -25,6 +32,8 @@ export interface TextChunk {
     font_variant: FontVariant;
     character_spacing: number;
     word_spacing: number;
+    horizontal_scale: number|null;
+    text_rise: number|null;
     language: string|null;
     alternate_text: string|null;
     actual_text: string|null;
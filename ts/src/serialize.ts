@@ -1,5 +1,5 @@
 import { getImageHeightPt, pointsValue, positionFromTranslate, SVG_NS } from "./common";
-import { Annotation, ArtifactKind, PageAnnotations, TextChunk } from "./model";
+import { Annotation, ArtifactKind, BlockRole, PageAnnotations, TextChunk } from "./model";
 
 
 // keep this in sync with src/model.rs, obviously
@@ -74,12 +74,18 @@ export namespace Serialize {
             const alternate_text = tspan.getAttribute("data-alt-text");
             const actual_text = tspan.getAttribute("data-actual-text");
             const expansion = tspan.getAttribute("data-expansion");
+            const horizontalScaleString = tspan.getAttribute("data-horizontal-scale");
+            const horizontal_scale = (horizontalScaleString === null) ? null : +horizontalScaleString;
+            const textRiseString = tspan.getAttribute("data-text-rise");
+            const text_rise = (textRiseString === null) ? null : +textRiseString;
 
             elements.push({
                 text,
                 font_variant: fontVariant,
                 character_spacing: characterSpacingPt,
                 word_spacing: wordSpacingPt,
+                horizontal_scale,
+                text_rise,
                 language,
                 alternate_text,
                 actual_text,
@@ -87,12 +93,18 @@ export namespace Serialize {
             });
         }
 
+        const orderString = annotationGroup.getAttribute("data-order");
+        const order = (orderString === null) ? null : +orderString;
+        const block_role = annotationGroup.getAttribute("data-block-role") as BlockRole|null;
+
         return {
             left: Math.round(pos.x),
             bottom: Math.round(imageHeightPt - pos.y),
             font_size: fontSizePt,
             leading: leadingPt,
             elements,
+            order,
+            block_role,
         };
     }
 